@@ -0,0 +1,10 @@
+#![no_main]
+use compiler::lexer::Lexer;
+use libfuzzer_sys::fuzz_target;
+
+// bad input is an error, never a panic
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = Lexer::scan_source(text, 0, &[]);
+    }
+});
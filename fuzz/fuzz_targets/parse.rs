@@ -0,0 +1,17 @@
+#![no_main]
+use compiler::lexer::Lexer;
+use compiler::parser::Parser;
+use libfuzzer_sys::fuzz_target;
+
+// Syntax errors are returned, any panic goes to libfuzzer's hook, which
+// aborts. cargo fuzz run parse fuzz/corpus/parse tests/regressions starts
+// from the inputs that crashed before
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    if let Ok(lexer) = Lexer::scan_source(text, 0, &[]) {
+        let _ = Parser::new(lexer).parse();
+    }
+});
@@ -0,0 +1,798 @@
+use super::lexer::{Lexer, Token};
+use super::natives::Natives;
+use super::parser::{Fields, Parser, SExpr};
+use super::query::Database;
+use super::source::Span;
+use super::typeck::{collect_types, Type, TypeDefs};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const KEYWORDS: &[&str] = &[
+    "if", "else", "while", "fn", "struct", "enum", "match", "mod", "import", "pub", "void", "true",
+    "false",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefKind {
+    Function,
+    Global,
+    // a function's parameter with its index
+    Param(usize),
+    // a function's local or a name a match pattern binds
+    Local,
+    Struct,
+    Field,
+    Enum,
+    Variant,
+}
+
+// a declaration of a name
+#[derive(Debug)]
+pub struct Def {
+    pub name: String,
+    pub kind: DefKind,
+    // the name where it's declared
+    pub span: Span,
+    scope: usize,
+    // the struct of a field or the enum of a variant
+    owner: Option<usize>,
+}
+
+// a function's body, where its parameters and locals are declared, scope 0
+// is the top level
+#[derive(Debug)]
+struct Scope {
+    parent: Option<usize>,
+    // the declaration of the function, None for a lambda
+    function: Option<usize>,
+    // from the fn to the end of the body
+    span: Span,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: DefKind,
+    // the whole declaration and its name
+    pub span: Span,
+    pub name_span: Span,
+    // the functions declared inside a function
+    pub children: Vec<Symbol>,
+}
+
+#[derive(Debug)]
+pub struct Completion {
+    pub label: String,
+    // None for a keyword
+    pub kind: Option<DefKind>,
+    pub detail: Option<String>,
+}
+
+// What the editor knows about a file: its errors and the names declared in
+// it. Every offset is a character offset into the file's text.
+//
+// Names are resolved on the tokens, so they resolve in a file that doesn't
+// parse too. A function's parameters and every name assigned in its body
+// are its locals, other names are looked up in the functions around it and
+// then at the top level.
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    tokens: Vec<(Token, Span)>,
+    defs: Vec<Def>,
+    scopes: Vec<Scope>,
+    // the innermost scope of every token
+    token_scopes: Vec<usize>,
+    // the declaration every IDENT token declares or refers to, by the token's
+    // index
+    refs: HashMap<usize, usize>,
+    // the types of the globals of the part of the program that type checks
+    globals: HashMap<String, Type>,
+    types: Option<TypeDefs>,
+    natives: Natives,
+}
+
+impl Analysis {
+    // `dir` is where the file's modules are loaded from
+    pub fn new(name: &str, text: &str, dir: Option<PathBuf>) -> Self {
+        let mut db = Database::new(name, dir, Natives::prelude());
+        Self::with_database(name, text, &mut db)
+    }
+
+    // like new, the program is checked by a database whose root is the file,
+    // which only checks again the statements the file's last text didn't
+    // have
+    pub fn with_database(name: &str, text: &str, db: &mut Database) -> Self {
+        match Lexer::scan_source(text, 0, &[]) {
+            Ok(lexer) => Self::with_lexer(name, text, &lexer, db),
+            Err(err) => {
+                let mut analysis = Self::empty();
+                let message = err.to_string();
+                let offset = error_offset(text, &message);
+                analysis.error(offset, offset + 1, message);
+                analysis
+            }
+        }
+    }
+
+    // like with_database, with the text already lexed, nothing must have
+    // been read from the lexer
+    pub fn with_lexer(name: &str, text: &str, lexer: &Lexer, db: &mut Database) -> Self {
+        let mut analysis = Self::empty();
+        let mut tokens = lexer.clone();
+        while let Some(span) = tokens.span() {
+            let token = tokens.next().unwrap();
+            analysis.tokens.push((token, span));
+        }
+        analysis.resolve(text.chars().count());
+
+        let mut parser = Parser::new(lexer.clone());
+        let program = match parser.parse_program() {
+            Ok(program) => program,
+            Err(err) => {
+                let span = err.span.unwrap_or(Span { start: 0, end: 0 });
+                analysis.error(span.start, span.end, err.to_string());
+                return analysis;
+            }
+        };
+        analysis.types = collect_types(&program).ok();
+
+        let stmts = match program {
+            SExpr::Block(stmts) => stmts,
+            _ => unreachable!(),
+        };
+        db.set_source(name, text.to_string());
+        if stmts
+            .iter()
+            .any(|stmt| matches!(stmt, SExpr::Mod(..) | SExpr::Import(..)))
+        {
+            analysis.check_modules(name, text, db);
+        } else {
+            analysis.check(parser.statement_spans(), db);
+        }
+        analysis
+    }
+
+    fn empty() -> Self {
+        Self {
+            diagnostics: vec![],
+            tokens: vec![],
+            defs: vec![],
+            scopes: vec![],
+            token_scopes: vec![],
+            refs: HashMap::new(),
+            globals: HashMap::new(),
+            types: None,
+            natives: Natives::prelude(),
+        }
+    }
+
+    fn error(&mut self, start: usize, end: usize, message: String) {
+        self.diagnostics.push(Diagnostic {
+            span: Span { start, end },
+            message,
+        });
+    }
+
+    // type checks a program without modules, a type error is reported on
+    // the first top level statement that doesn't check
+    fn check(&mut self, spans: &[Span], db: &Database) {
+        let err = match db.globals() {
+            Ok(globals) => {
+                self.globals = (*globals).clone();
+                return;
+            }
+            Err(err) => err,
+        };
+
+        // the statements are checked one after the other, every one after
+        // the first that doesn't check fails too
+        let failing = (0..spans.len())
+            .find(|&i| db.globals_before(i + 1).is_err())
+            .unwrap_or(0);
+        self.globals = db
+            .globals_before(failing)
+            .map(|globals| (*globals).clone())
+            .unwrap_or_default();
+        let span = spans
+            .get(failing)
+            .copied()
+            .unwrap_or(Span { start: 0, end: 0 });
+        self.error(span.start, span.end, err.to_string());
+    }
+
+    // checks the program the file's modules make up, the errors go on the
+    // first line unless they say where they are in it
+    fn check_modules(&mut self, name: &str, text: &str, db: &Database) {
+        match db.globals() {
+            Ok(globals) => self.globals = (*globals).clone(),
+            Err(err) => {
+                let message = err.to_string();
+                let offset = message
+                    .strip_prefix(name)
+                    .and_then(|rest| rest.strip_prefix(':'))
+                    .and_then(|rest| {
+                        let mut parts = rest.splitn(3, ':');
+                        let line = parts.next()?.parse().ok()?;
+                        let column = parts.next()?.parse().ok()?;
+                        Some(offset_of(text, line, column))
+                    })
+                    .unwrap_or(0);
+                self.error(offset, offset, message);
+            }
+        }
+    }
+
+    fn declare(
+        &mut self,
+        token: usize,
+        kind: DefKind,
+        scope: usize,
+        owner: Option<usize>,
+    ) -> usize {
+        let (name, span) = match &self.tokens[token] {
+            (Token::IDENT(name), span) => (name.clone(), *span),
+            _ => unreachable!(),
+        };
+        self.defs.push(Def {
+            name,
+            kind,
+            span,
+            scope,
+            owner,
+        });
+        self.refs.insert(token, self.defs.len() - 1);
+        self.defs.len() - 1
+    }
+
+    fn token(&self, i: usize) -> Option<&Token> {
+        self.tokens.get(i).map(|(token, _)| token)
+    }
+
+    fn ident(&self, i: usize) -> Option<&str> {
+        match self.token(i) {
+            Some(Token::IDENT(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn prev(&self, i: usize) -> Option<&Token> {
+        i.checked_sub(1).and_then(|i| self.token(i))
+    }
+
+    // finds the declarations, then what every other name refers to
+    fn resolve(&mut self, len: usize) {
+        self.scopes.push(Scope {
+            parent: None,
+            function: None,
+            span: Span { start: 0, end: len },
+        });
+        let mut stack = vec![0];
+        // the brace depth each function body on the stack ends at
+        let mut bodies = vec![];
+        // the { of the bodies of the functions whose parameters have been
+        // seen, with their scopes
+        let mut pending: Vec<(usize, usize)> = vec![];
+        let mut depth = 0;
+        // the tokens before this are part of a type, where fn is a function
+        // type
+        let mut types_until = 0;
+
+        for i in 0..self.tokens.len() {
+            let scope = *stack.last().unwrap();
+            self.token_scopes.push(scope);
+            match &self.tokens[i].0 {
+                Token::FN if i >= types_until => {
+                    types_until = self.function(i, scope, &mut pending);
+                }
+                Token::STRUCT | Token::ENUM => {
+                    types_until = types_until.max(self.type_def(i, scope));
+                }
+                Token::LCBRACE => {
+                    depth += 1;
+                    if let Some(&(_, body)) = pending.last().filter(|(brace, _)| *brace == i) {
+                        pending.pop();
+                        stack.push(body);
+                        bodies.push(depth);
+                    }
+                }
+                Token::RCBRACE => {
+                    if bodies.last() == Some(&depth) && stack.len() > 1 {
+                        bodies.pop();
+                        let body = stack.pop().unwrap();
+                        self.scopes[body].span.end = self.tokens[i].1.end;
+                    }
+                    depth -= 1;
+                }
+                Token::IDENT(name)
+                    if self.token(i + 1) == Some(&Token::ASSIGN)
+                        && !matches!(self.prev(i), Some(Token::DOT) | Some(Token::COLONCOLON))
+                        && !self.refs.contains_key(&i) =>
+                {
+                    let declared = self
+                        .defs
+                        .iter()
+                        .any(|def| def.scope == scope && def.name == *name && def.owner.is_none());
+                    if !declared {
+                        let kind = if scope == 0 {
+                            DefKind::Global
+                        } else {
+                            DefKind::Local
+                        };
+                        self.declare(i, kind, scope, None);
+                    }
+                }
+                Token::FATARROW => self.pattern(i, scope),
+                _ => {}
+            }
+        }
+
+        for i in 0..self.tokens.len() {
+            if self.ident(i).is_none() || self.refs.contains_key(&i) {
+                continue;
+            }
+            if let Some(def) = self.lookup(i) {
+                self.refs.insert(i, def);
+            }
+        }
+    }
+
+    // declares a function's name and parameters, returns the index of the
+    // { of its body
+    fn function(&mut self, i: usize, scope: usize, pending: &mut Vec<(usize, usize)>) -> usize {
+        let function = self
+            .ident(i + 1)
+            .is_some()
+            .then(|| self.declare(i + 1, DefKind::Function, scope, None));
+        let body = self.scopes.len();
+        self.scopes.push(Scope {
+            parent: Some(scope),
+            function,
+            span: self.tokens[i].1,
+        });
+
+        let mut k = i + 1;
+        while k < self.tokens.len() && self.token(k) != Some(&Token::LPAREN) {
+            k += 1;
+        }
+        let (mut parens, mut index) = (0, 0);
+        while k < self.tokens.len() {
+            match self.token(k) {
+                Some(Token::LPAREN) => parens += 1,
+                Some(Token::RPAREN) => {
+                    parens -= 1;
+                    if parens == 0 {
+                        break;
+                    }
+                }
+                Some(Token::IDENT(_))
+                    if parens == 1
+                        && matches!(self.prev(k), Some(Token::LPAREN) | Some(Token::COMMA))
+                        && matches!(
+                            self.token(k + 1),
+                            Some(Token::COLON) | Some(Token::COMMA) | Some(Token::RPAREN)
+                        ) =>
+                {
+                    self.declare(k, DefKind::Param(index), body, None);
+                    index += 1;
+                }
+                _ => {}
+            }
+            k += 1;
+        }
+        while k < self.tokens.len() && self.token(k) != Some(&Token::LCBRACE) {
+            k += 1;
+        }
+        pending.push((k, body));
+        k
+    }
+
+    // declares a struct and its fields or an enum and its variants, returns
+    // the index of the } that ends it
+    fn type_def(&mut self, i: usize, scope: usize) -> usize {
+        if self.ident(i + 1).is_none() {
+            return i;
+        }
+        let is_struct = self.token(i) == Some(&Token::STRUCT);
+        let (kind, member) = if is_struct {
+            (DefKind::Struct, DefKind::Field)
+        } else {
+            (DefKind::Enum, DefKind::Variant)
+        };
+        let owner = self.declare(i + 1, kind, scope, None);
+
+        let mut k = i + 2;
+        while k < self.tokens.len() && self.token(k) != Some(&Token::LCBRACE) {
+            k += 1;
+        }
+        let mut depth = 0;
+        while k < self.tokens.len() {
+            match self.token(k) {
+                Some(Token::LCBRACE) | Some(Token::LPAREN) | Some(Token::LBRACE) => depth += 1,
+                Some(Token::RCBRACE) | Some(Token::RPAREN) | Some(Token::RBRACE) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                Some(Token::IDENT(_))
+                    if depth == 1
+                        && matches!(self.prev(k), Some(Token::LCBRACE) | Some(Token::COMMA))
+                        && (!is_struct || self.token(k + 1) == Some(&Token::COLON)) =>
+                {
+                    self.declare(k, member, scope, Some(owner));
+                }
+                _ => {}
+            }
+            k += 1;
+        }
+        k
+    }
+
+    // declares the names bound by the pattern before the => at i
+    fn pattern(&mut self, i: usize, scope: usize) {
+        // a variant pattern's payload, then its path
+        let mut start = i;
+        if let Some(Token::RPAREN) | Some(Token::RCBRACE) = self.prev(i) {
+            let mut depth = 0;
+            while start > 0 {
+                start -= 1;
+                match self.token(start) {
+                    Some(Token::RPAREN) | Some(Token::RCBRACE) => depth += 1,
+                    Some(Token::LPAREN) | Some(Token::LCBRACE) => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+        while start > 0 && self.ident(start - 1).is_some() {
+            start -= 1;
+            if start > 0 && self.token(start - 1) == Some(&Token::COLONCOLON) {
+                start -= 1;
+            } else {
+                break;
+            }
+        }
+
+        for k in start..i {
+            let binds = match self.ident(k) {
+                Some(name) => name != "_",
+                None => false,
+            };
+            if binds
+                && !matches!(self.prev(k), Some(Token::COLONCOLON))
+                && !matches!(
+                    self.token(k + 1),
+                    Some(Token::COLONCOLON) | Some(Token::COLON)
+                )
+            {
+                self.declare(k, DefKind::Local, scope, None);
+            }
+        }
+    }
+
+    // what the name at token i refers to
+    fn lookup(&self, i: usize) -> Option<usize> {
+        let name = self.ident(i)?;
+        let members = |kind: DefKind, owner: Option<usize>| {
+            self.defs.iter().position(|def| {
+                def.kind == kind && def.name == name && (owner.is_none() || def.owner == owner)
+            })
+        };
+        match self.prev(i) {
+            Some(Token::DOT) => return members(DefKind::Field, None),
+            Some(Token::COLONCOLON) => {
+                let owner = i.checked_sub(2).and_then(|k| self.refs.get(&k).copied());
+                let owner = owner.or_else(|| i.checked_sub(2).and_then(|k| self.lookup(k)))?;
+                return members(DefKind::Variant, Some(owner));
+            }
+            _ => {}
+        }
+
+        // a local declared more than once, by several match arms, is the
+        // last declaration before it
+        let position = self.tokens[i].1.start;
+        let mut scope = Some(self.token_scopes[i]);
+        while let Some(s) = scope {
+            let found: Vec<usize> = (0..self.defs.len())
+                .filter(|&d| {
+                    let def = &self.defs[d];
+                    def.scope == s && def.name == name && def.owner.is_none()
+                })
+                .collect();
+            let before = found
+                .iter()
+                .rev()
+                .find(|&&d| self.defs[d].span.start <= position);
+            if let Some(&def) = before.or_else(|| found.first()) {
+                return Some(def);
+            }
+            scope = self.scopes[s].parent;
+        }
+        None
+    }
+
+    // the IDENT token the offset is in or just after
+    fn token_at(&self, offset: usize) -> Option<usize> {
+        self.tokens.iter().position(|(token, span)| {
+            matches!(token, Token::IDENT(_)) && span.start <= offset && offset <= span.end
+        })
+    }
+
+    pub fn definition(&self, offset: usize) -> Option<Span> {
+        let token = self.token_at(offset)?;
+        self.refs.get(&token).map(|&def| self.defs[def].span)
+    }
+
+    // the name at the offset and a description of it with its type
+    pub fn hover(&self, offset: usize) -> Option<(Span, String)> {
+        let token = self.token_at(offset)?;
+        let (name, span) = match &self.tokens[token] {
+            (Token::IDENT(name), span) => (name, *span),
+            _ => unreachable!(),
+        };
+        let def = match self.refs.get(&token) {
+            Some(&def) => &self.defs[def],
+            None => {
+                let native = self.natives.get(name)?;
+                return Some((span, format!("fn {}: {}", name, native.ty)));
+            }
+        };
+
+        let text = match def.kind {
+            DefKind::Function | DefKind::Global => match self.globals.get(name) {
+                Some(t) if def.kind == DefKind::Function => format!("fn {}: {}", name, t),
+                Some(t) => format!("{}: {}", name, t),
+                None if def.kind == DefKind::Function => format!("fn {}", name),
+                None => name.to_string(),
+            },
+            // the types of the parameters of top level functions are known
+            DefKind::Param(index) => {
+                let t = self.scopes[def.scope]
+                    .function
+                    .and_then(|f| self.globals.get(&self.defs[f].name))
+                    .and_then(|t| match t {
+                        Type::Fn(params, _) => params.get(index).cloned(),
+                        Type::Generic(_, t) => match &**t {
+                            Type::Fn(params, _) => params.get(index).cloned(),
+                            _ => None,
+                        },
+                        _ => None,
+                    });
+                match t {
+                    Some(t) => format!("{}: {}", name, t),
+                    None => format!("parameter {}", name),
+                }
+            }
+            DefKind::Local => format!("local {}", name),
+            DefKind::Struct => {
+                let def = self.struct_def(name);
+                match def {
+                    Some(fields) => format!("struct {} {{ {} }}", name, fields),
+                    None => format!("struct {}", name),
+                }
+            }
+            DefKind::Field => {
+                let owner = &self.defs[def.owner.unwrap()].name;
+                let t = self.types.as_ref().and_then(|types| {
+                    types
+                        .structs
+                        .iter()
+                        .find(|s| s.name == *owner)
+                        .and_then(|s| s.fields.iter().find(|(f, _)| f == name))
+                        .map(|(_, t)| t.to_string())
+                });
+                match t {
+                    Some(t) => format!("{}.{}: {}", owner, name, t),
+                    None => format!("{}.{}", owner, name),
+                }
+            }
+            DefKind::Enum => {
+                let variants = self.types.as_ref().and_then(|types| {
+                    let def = types.enums.iter().find(|e| e.name == *name)?;
+                    let variants: Vec<String> = def
+                        .variants
+                        .iter()
+                        .map(|(variant, fields)| format!("{}{}", variant, fmt_fields(fields)))
+                        .collect();
+                    Some(variants.join(", "))
+                });
+                match variants {
+                    Some(variants) => format!("enum {} {{ {} }}", name, variants),
+                    None => format!("enum {}", name),
+                }
+            }
+            DefKind::Variant => {
+                let owner = &self.defs[def.owner.unwrap()].name;
+                let fields = self.types.as_ref().and_then(|types| {
+                    let def = types.enums.iter().find(|e| e.name == *owner)?;
+                    let index = def.variant(name)?;
+                    Some(fmt_fields(&def.variants[index].1))
+                });
+                format!("{}::{}{}", owner, name, fields.unwrap_or_default())
+            }
+        };
+        Some((span, text))
+    }
+
+    fn struct_def(&self, name: &str) -> Option<String> {
+        let def = self
+            .types
+            .as_ref()?
+            .structs
+            .iter()
+            .find(|s| s.name == name)?;
+        let fields: Vec<String> = def
+            .fields
+            .iter()
+            .map(|(field, t)| format!("{}: {}", field, t))
+            .collect();
+        Some(fields.join(", "))
+    }
+
+    // the functions, structs and enums, with the functions declared inside
+    // a function as its children
+    pub fn symbols(&self) -> Vec<Symbol> {
+        self.symbols_in(0)
+    }
+
+    fn symbols_in(&self, scope: usize) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        for (d, def) in self.defs.iter().enumerate() {
+            if def.scope != scope {
+                continue;
+            }
+            let symbol = match def.kind {
+                DefKind::Function => {
+                    let body = self
+                        .scopes
+                        .iter()
+                        .position(|scope| scope.function == Some(d))
+                        .unwrap();
+                    Symbol {
+                        name: def.name.clone(),
+                        kind: def.kind,
+                        span: self.scopes[body].span,
+                        name_span: def.span,
+                        children: self.symbols_in(body),
+                    }
+                }
+                DefKind::Struct | DefKind::Enum => Symbol {
+                    name: def.name.clone(),
+                    kind: def.kind,
+                    span: def.span,
+                    name_span: def.span,
+                    children: vec![],
+                },
+                _ => continue,
+            };
+            symbols.push(symbol);
+        }
+        symbols
+    }
+
+    // the names that can go at the offset, only fields after a . and only
+    // variants after Enum::
+    pub fn completions(&self, offset: usize) -> Vec<Completion> {
+        // the token before the name being typed
+        let before = self.tokens.iter().rposition(|(token, span)| {
+            span.end <= offset && !(matches!(token, Token::IDENT(_)) && span.end == offset)
+        });
+        let members = |kind: DefKind, owner: Option<usize>| -> Vec<Completion> {
+            let mut completions: Vec<Completion> = vec![];
+            for def in &self.defs {
+                if def.kind == kind
+                    && (owner.is_none() || def.owner == owner)
+                    && !completions.iter().any(|c| c.label == def.name)
+                {
+                    completions.push(Completion {
+                        label: def.name.clone(),
+                        kind: Some(kind),
+                        detail: None,
+                    });
+                }
+            }
+            completions
+        };
+        match before.map(|i| (i, &self.tokens[i].0)) {
+            Some((_, Token::DOT)) => return members(DefKind::Field, None),
+            Some((i, Token::COLONCOLON)) => {
+                let owner = i.checked_sub(1).and_then(|k| self.refs.get(&k)).copied();
+                return match owner {
+                    Some(owner) if self.defs[owner].kind == DefKind::Enum => {
+                        members(DefKind::Variant, Some(owner))
+                    }
+                    _ => vec![],
+                };
+            }
+            _ => {}
+        }
+
+        let mut completions: Vec<Completion> = vec![];
+        let mut scope = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.span.start < offset && offset < scope.span.end);
+        while let Some(s) = scope {
+            for def in &self.defs {
+                if def.scope == s
+                    && def.owner.is_none()
+                    && !completions.iter().any(|c| c.label == def.name)
+                {
+                    completions.push(Completion {
+                        label: def.name.clone(),
+                        kind: Some(def.kind),
+                        detail: self.globals.get(&def.name).map(Type::to_string),
+                    });
+                }
+            }
+            scope = self.scopes[s].parent;
+        }
+        for native in self.natives.iter() {
+            if !completions.iter().any(|c| c.label == native.name) {
+                completions.push(Completion {
+                    label: native.name.clone(),
+                    kind: Some(DefKind::Function),
+                    detail: Some(native.ty.to_string()),
+                });
+            }
+        }
+        for keyword in KEYWORDS {
+            completions.push(Completion {
+                label: keyword.to_string(),
+                kind: None,
+                detail: None,
+            });
+        }
+        completions
+    }
+}
+
+fn fmt_fields(fields: &Fields<Type>) -> String {
+    match fields {
+        Fields::Unit => String::new(),
+        Fields::Tuple(types) => {
+            let types: Vec<String> = types.iter().map(Type::to_string).collect();
+            format!("({})", types.join(", "))
+        }
+        Fields::Struct(fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, t)| format!("{}: {}", field, t))
+                .collect();
+            format!(" {{ {} }}", fields.join(", "))
+        }
+    }
+}
+
+// the offset of a 1-based line and column
+fn offset_of(text: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = text
+        .split('\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.chars().count() + 1)
+        .sum();
+    line_start + column.saturating_sub(1)
+}
+
+// where a lexer error says it is, "... AT: Ln 3, Col 7"
+fn error_offset(text: &str, message: &str) -> usize {
+    let numbers: Vec<usize> = message
+        .rsplit("AT:")
+        .next()
+        .unwrap_or("")
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|n| n.parse().ok())
+        .collect();
+    match numbers[..] {
+        [line, column] => offset_of(text, line, column),
+        _ => 0,
+    }
+}
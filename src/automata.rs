@@ -0,0 +1,269 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Error, ErrorKind};
+
+// A scanner generator: a regular expression becomes an NFA by Thompson's
+// construction, and the NFA a DFA by the subset construction. States are
+// indexes rather than shared cells, so the automata can be walked and
+// drawn.
+
+#[derive(Debug, Clone, Default)]
+pub struct NfaState {
+    pub moves: Vec<(char, usize)>,
+    // the states reached without reading a character
+    pub empty: Vec<usize>,
+}
+
+// every NFA built here has one start and one accepting state
+#[derive(Debug, Clone)]
+pub struct Nfa {
+    pub states: Vec<NfaState>,
+    pub start: usize,
+    pub accept: usize,
+}
+
+impl Nfa {
+    pub fn char(c: char) -> Self {
+        let mut nfa = Nfa::with_states(2);
+        nfa.states[0].moves.push((c, 1));
+        nfa
+    }
+
+    // matches the empty string
+    pub fn empty() -> Self {
+        let mut nfa = Nfa::with_states(2);
+        nfa.states[0].empty.push(1);
+        nfa
+    }
+
+    pub fn or(first: Nfa, second: Nfa) -> Self {
+        let mut nfa = Nfa::with_states(2);
+        let first = nfa.embed(first);
+        let second = nfa.embed(second);
+        nfa.states[0].empty.extend([first.0, second.0]);
+        nfa.states[first.1].empty.push(1);
+        nfa.states[second.1].empty.push(1);
+        nfa
+    }
+
+    pub fn seq(first: Nfa, second: Nfa) -> Self {
+        let mut nfa = Nfa::with_states(2);
+        let first = nfa.embed(first);
+        let second = nfa.embed(second);
+        nfa.states[0].empty.push(first.0);
+        nfa.states[first.1].empty.push(second.0);
+        nfa.states[second.1].empty.push(1);
+        nfa
+    }
+
+    pub fn star(inner: Nfa) -> Self {
+        let mut nfa = Nfa::with_states(2);
+        let inner = nfa.embed(inner);
+        nfa.states[0].empty.extend([inner.0, 1]);
+        nfa.states[inner.1].empty.extend([inner.0, 1]);
+        nfa
+    }
+
+    // Characters, `|`, `*`, `+`, `?` and parentheses, a backslash makes the
+    // character after it literal
+    pub fn from_regex(pattern: &str) -> Result<Nfa, Error> {
+        let mut parser = RegexParser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let nfa = parser.alternation()?;
+        match parser.chars.get(parser.pos) {
+            None => Ok(nfa),
+            Some(c) => Err(parser.error(&format!("unexpected {}", c))),
+        }
+    }
+
+    // the states reachable from the given ones without reading a character
+    pub fn closure(&self, states: impl IntoIterator<Item = usize>) -> BTreeSet<usize> {
+        let mut closure = BTreeSet::new();
+        let mut stack: Vec<usize> = states.into_iter().collect();
+        while let Some(state) = stack.pop() {
+            if closure.insert(state) {
+                stack.extend(&self.states[state].empty);
+            }
+        }
+        closure
+    }
+
+    fn with_states(n: usize) -> Self {
+        Nfa {
+            states: vec![NfaState::default(); n],
+            start: 0,
+            accept: 1,
+        }
+    }
+
+    // copies another NFA's states into this one, returns where its start and
+    // accepting states went
+    fn embed(&mut self, other: Nfa) -> (usize, usize) {
+        let base = self.states.len();
+        for mut state in other.states {
+            for (_, target) in &mut state.moves {
+                *target += base;
+            }
+            for target in &mut state.empty {
+                *target += base;
+            }
+            self.states.push(state);
+        }
+        (base + other.start, base + other.accept)
+    }
+}
+
+struct RegexParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl RegexParser {
+    fn error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("REGEX ERROR: {} at {}", message, self.pos),
+        )
+    }
+
+    fn alternation(&mut self) -> Result<Nfa, Error> {
+        let mut nfa = self.sequence()?;
+        while self.chars.get(self.pos) == Some(&'|') {
+            self.pos += 1;
+            nfa = Nfa::or(nfa, self.sequence()?);
+        }
+        Ok(nfa)
+    }
+
+    fn sequence(&mut self) -> Result<Nfa, Error> {
+        let mut nfa: Option<Nfa> = None;
+        while let Some(&c) = self.chars.get(self.pos) {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let next = self.repetition()?;
+            nfa = Some(match nfa {
+                Some(nfa) => Nfa::seq(nfa, next),
+                None => next,
+            });
+        }
+        Ok(nfa.unwrap_or_else(Nfa::empty))
+    }
+
+    fn repetition(&mut self) -> Result<Nfa, Error> {
+        let mut nfa = self.atom()?;
+        while let Some(&c) = self.chars.get(self.pos) {
+            nfa = match c {
+                '*' => Nfa::star(nfa),
+                '+' => Nfa::seq(nfa.clone(), Nfa::star(nfa)),
+                '?' => Nfa::or(nfa, Nfa::empty()),
+                _ => break,
+            };
+            self.pos += 1;
+        }
+        Ok(nfa)
+    }
+
+    fn atom(&mut self) -> Result<Nfa, Error> {
+        let c = self.chars[self.pos];
+        self.pos += 1;
+        match c {
+            '(' => {
+                let nfa = self.alternation()?;
+                if self.chars.get(self.pos) != Some(&')') {
+                    return Err(self.error("expected )"));
+                }
+                self.pos += 1;
+                Ok(nfa)
+            }
+            '*' | '+' | '?' => Err(self.error(&format!("nothing to repeat with {}", c))),
+            '\\' => match self.chars.get(self.pos) {
+                Some(&c) => {
+                    self.pos += 1;
+                    Ok(Nfa::char(c))
+                }
+                None => Err(self.error("expected a character after \\")),
+            },
+            c => Ok(Nfa::char(c)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DfaState {
+    // the NFA states it stands for
+    pub nfa_states: BTreeSet<usize>,
+    pub accepting: bool,
+    pub moves: Vec<(char, usize)>,
+}
+
+// a DFA whose start is its first state, a character without a move is
+// rejected
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    pub states: Vec<DfaState>,
+}
+
+impl Dfa {
+    pub fn from_nfa(nfa: &Nfa) -> Self {
+        let mut alphabet: Vec<char> = nfa
+            .states
+            .iter()
+            .flat_map(|state| state.moves.iter().map(|(c, _)| *c))
+            .collect();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+
+        let start = nfa.closure([nfa.start]);
+        let mut ids = HashMap::new();
+        ids.insert(start.clone(), 0);
+        let mut states = vec![DfaState {
+            accepting: start.contains(&nfa.accept),
+            nfa_states: start,
+            moves: vec![],
+        }];
+        let mut work = vec![0];
+        while let Some(id) = work.pop() {
+            for &c in &alphabet {
+                let reached = states[id].nfa_states.iter().flat_map(|&state| {
+                    nfa.states[state]
+                        .moves
+                        .iter()
+                        .filter(move |(on, _)| *on == c)
+                        .map(|(_, target)| *target)
+                });
+                let next = nfa.closure(reached);
+                if next.is_empty() {
+                    continue;
+                }
+                let target = match ids.get(&next) {
+                    Some(&target) => target,
+                    None => {
+                        ids.insert(next.clone(), states.len());
+                        work.push(states.len());
+                        states.push(DfaState {
+                            accepting: next.contains(&nfa.accept),
+                            nfa_states: next,
+                            moves: vec![],
+                        });
+                        states.len() - 1
+                    }
+                };
+                states[id].moves.push((c, target));
+            }
+        }
+        Dfa { states }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let mut state = 0;
+        for c in text.chars() {
+            match self.states[state].moves.iter().find(|(on, _)| *on == c) {
+                Some((_, next)) => state = *next,
+                None => return false,
+            }
+        }
+        self.states[state].accepting
+    }
+}
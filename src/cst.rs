@@ -0,0 +1,874 @@
+use super::lexer::{Comment, Lexer, Token};
+use super::parser::Parser;
+use super::source::{Edit, Span};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::Error;
+use std::rc::Rc;
+
+// A concrete syntax tree keeps every character of the text it was parsed
+// from, whitespace and comments included, so printing it gives the text back
+// exactly. The green tree holds the text and only knows the widths of its
+// parts, so an unchanged subtree can be shared between versions of a file.
+// The red tree on top of it is made as it's walked and knows where each node
+// is and what its parent is. The typed views at the bottom give the nodes of
+// each kind accessors for their parts.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeKind {
+    SourceFile,
+    Block,
+    FnDecl,
+    Lambda,
+    Param,
+    StructDecl,
+    FieldDecl,
+    EnumDecl,
+    VariantDecl,
+    Mod,
+    Import,
+    Pub,
+    IfExpr,
+    WhileExpr,
+    MatchExpr,
+    MatchArm,
+    Pattern,
+    BinExpr,
+    PrefixExpr,
+    PostfixExpr,
+    CallExpr,
+    IndexExpr,
+    SliceExpr,
+    FieldExpr,
+    ParenExpr,
+    ArrayExpr,
+    StructLit,
+    FieldInit,
+    VariantExpr,
+    Literal,
+    Name,
+    TypeRef,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Whitespace,
+    Comment,
+    Token(Token),
+}
+
+impl TokenKind {
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::Comment)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GreenToken {
+    kind: TokenKind,
+    text: String,
+}
+
+impl GreenToken {
+    pub fn new(kind: TokenKind, text: String) -> Rc<Self> {
+        Rc::new(Self { kind, text })
+    }
+
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // in characters, like every offset in the compiler
+    pub fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len(),
+            GreenElement::Token(token) => token.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GreenNode {
+    kind: NodeKind,
+    len: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: NodeKind, children: Vec<GreenElement>) -> Rc<Self> {
+        let len = children.iter().map(GreenElement::len).sum();
+        Rc::new(Self {
+            kind,
+            len,
+            children,
+        })
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+impl Display for GreenNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => write!(f, "{}", node)?,
+                GreenElement::Token(token) => write!(f, "{}", token.text)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// a node of the red tree, cloning one is cheap
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+struct NodeData {
+    green: Rc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    // where the node starts in the text
+    offset: usize,
+}
+
+#[derive(Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    parent: SyntaxNode,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        Self(Rc::new(NodeData {
+            green,
+            parent: None,
+            offset: 0,
+        }))
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.0.offset,
+            end: self.0.offset + self.0.green.len,
+        }
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    pub fn text(&self) -> String {
+        self.0.green.to_string()
+    }
+
+    pub fn children_with_tokens(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        let mut children = vec![];
+        for child in &self.0.green.children {
+            children.push(match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    parent: Some(self.clone()),
+                    offset,
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    parent: self.clone(),
+                    offset,
+                }),
+            });
+            offset += child.len();
+        }
+        children
+    }
+
+    pub fn children(&self) -> Vec<SyntaxNode> {
+        self.children_with_tokens()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    // the tokens directly in this node that aren't trivia
+    pub fn child_tokens(&self) -> Vec<SyntaxToken> {
+        self.children_with_tokens()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Token(token) if !token.kind().is_trivia() => Some(token),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // the node and every node inside it, each before its children
+    pub fn descendants(&self) -> Vec<SyntaxNode> {
+        let mut nodes = vec![self.clone()];
+        for child in self.children() {
+            nodes.extend(child.descendants());
+        }
+        nodes
+    }
+
+    // every token in the node in order, trivia included
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = vec![];
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    // the token offset is in, None past the end
+    pub fn token_at(&self, offset: usize) -> Option<SyntaxToken> {
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) if node.span().end > offset => {
+                    return node.token_at(offset)
+                }
+                SyntaxElement::Token(token) if token.span().end > offset => return Some(token),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    // the green tree of the root with this node swapped for `green`, only
+    // the nodes around it are made again
+    pub fn replace(&self, green: Rc<GreenNode>) -> Rc<GreenNode> {
+        let parent = match self.parent() {
+            Some(parent) => parent,
+            None => return green,
+        };
+        let mut offset = parent.0.offset;
+        let mut children = parent.green().children().to_vec();
+        for child in children.iter_mut() {
+            if offset == self.0.offset {
+                if let GreenElement::Node(node) = child {
+                    if Rc::ptr_eq(node, self.green()) {
+                        *child = GreenElement::Node(green);
+                        break;
+                    }
+                }
+            }
+            offset += child.len();
+        }
+        parent.replace(GreenNode::new(parent.kind(), children))
+    }
+
+    // an indented line per node and token, for looking at the tree
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_into(&mut out, 0);
+        out
+    }
+
+    fn dump_into(&self, out: &mut String, depth: usize) {
+        let span = self.span();
+        out.push_str(&format!(
+            "{}{:?}@{}..{}\n",
+            "  ".repeat(depth),
+            self.kind(),
+            span.start,
+            span.end
+        ));
+        for child in self.children_with_tokens() {
+            match child {
+                SyntaxElement::Node(node) => node.dump_into(out, depth + 1),
+                SyntaxElement::Token(token) => {
+                    out.push_str(&format!("{}{:?}\n", "  ".repeat(depth + 1), token))
+                }
+            }
+        }
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+// printing a node gives back its text exactly
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.green)
+    }
+}
+
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(f, "{:?}@{}..{}", self.kind(), span.start, span.end)
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> &TokenKind {
+        &self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.green.len(),
+        }
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        let kind = match self.kind() {
+            TokenKind::Token(token) => format!("{:?}", token),
+            kind => format!("{:?}", kind),
+        };
+        write!(f, "{}@{}..{} {:?}", kind, span.start, span.end, self.text())
+    }
+}
+
+// Parses a file into its concrete syntax tree. The parser notes the tokens
+// each node covers as it goes, the tree is put together from those and the
+// tokens with the text between them, which is whitespace and comments.
+pub fn parse(text: &str) -> Result<SyntaxNode, Error> {
+    let (lexer, tokens) = lex(text)?;
+    let mut parser = Parser::new(lexer.clone());
+    parser.parse_program()?;
+
+    let chars: Vec<char> = text.chars().collect();
+    let green = build(&chars, &tokens, lexer.comments(), parser.nodes());
+    Ok(SyntaxNode::new_root(green))
+}
+
+// the lexer for a text and every token in it with its span
+fn lex(text: &str) -> Result<(Lexer, Vec<(Token, Span)>), Error> {
+    let lexer = Lexer::scan_source(text, 0, &[])?;
+    let mut tokens = vec![];
+    let mut token_lexer = lexer.clone();
+    while let Some(token) = token_lexer.next() {
+        tokens.push((token, token_lexer.last_span().unwrap()));
+    }
+    Ok((lexer, tokens))
+}
+
+// The tree for the text of `old` with the edit made to it. The innermost
+// block the edit is inside is parsed again on its own and put in place of
+// the old one, the rest of the tree is shared with the old one. When that
+// block doesn't parse on its own, because the edit added a brace say, the
+// block around it is tried and then the whole file, which still shares
+// every block whose text didn't change. It's for tools that keep the
+// concrete tree of a file being edited; the language server only relexes,
+// since its checker works on the parser's syntax tree.
+pub fn reparse(old: &SyntaxNode, edit: &Edit) -> Result<SyntaxNode, Error> {
+    let text = edit.apply(&old.to_string());
+    let chars: Vec<char> = text.chars().collect();
+
+    // the blocks the edit is strictly inside, innermost last
+    let mut blocks = vec![];
+    let mut node = old.clone();
+    while let Some(child) = node
+        .children()
+        .into_iter()
+        .find(|child| child.span().start < edit.span.start && edit.span.end < child.span().end)
+    {
+        if child.kind() == NodeKind::Block {
+            blocks.push(child.clone());
+        }
+        node = child;
+    }
+    for block in blocks.iter().rev() {
+        let span = block.span();
+        let end = (span.end as isize + edit.delta()) as usize;
+        if let Some(green) = parse_block(&chars[span.start..end]) {
+            return Ok(SyntaxNode::new_root(block.replace(green)));
+        }
+    }
+
+    let mut unchanged = HashMap::new();
+    for block in old.descendants() {
+        if block.kind() == NodeKind::Block {
+            unchanged.insert(block.to_string(), block.green().clone());
+        }
+    }
+    let new = parse(&text)?;
+    Ok(SyntaxNode::new_root(share(new.green(), &unchanged)))
+}
+
+// the green node for text that is a single block, None if it isn't one
+fn parse_block(chars: &[char]) -> Option<Rc<GreenNode>> {
+    let text: String = chars.iter().collect();
+    let (lexer, tokens) = lex(&text).ok()?;
+    let mut parser = Parser::new(lexer.clone());
+    parser.parse_lone_block().ok()?;
+    let root = build(chars, &tokens, lexer.comments(), parser.nodes());
+    match root.children() {
+        [GreenElement::Node(block)] if block.kind() == NodeKind::Block => Some(block.clone()),
+        _ => None,
+    }
+}
+
+// the node with the blocks in it whose text is in `unchanged` swapped for
+// the green nodes there
+fn share(green: &Rc<GreenNode>, unchanged: &HashMap<String, Rc<GreenNode>>) -> Rc<GreenNode> {
+    if green.kind() == NodeKind::Block {
+        if let Some(old) = unchanged.get(&green.to_string()) {
+            return old.clone();
+        }
+    }
+    let children: Vec<GreenElement> = green
+        .children()
+        .iter()
+        .map(|child| match child {
+            GreenElement::Node(node) => GreenElement::Node(share(node, unchanged)),
+            token => token.clone(),
+        })
+        .collect();
+    let same = children
+        .iter()
+        .zip(green.children())
+        .all(|pair| match pair {
+            (GreenElement::Node(new), GreenElement::Node(old)) => Rc::ptr_eq(new, old),
+            _ => true,
+        });
+    if same {
+        return green.clone();
+    }
+    GreenNode::new(green.kind(), children)
+}
+
+// the nodes being built, innermost last, each with where it ends
+struct Builder {
+    stack: Vec<(NodeKind, usize, Vec<GreenElement>)>,
+}
+
+impl Builder {
+    fn push(&mut self, element: GreenElement) {
+        self.stack.last_mut().unwrap().2.push(element);
+    }
+
+    fn close(&mut self) {
+        let (kind, _, children) = self.stack.pop().unwrap();
+        self.push(GreenElement::Node(GreenNode::new(kind, children)));
+    }
+
+    // the whitespace and comments from start to end
+    fn trivia(&mut self, chars: &[char], comments: &[Comment], start: usize, end: usize) {
+        let mut pos = start;
+        let first = comments.partition_point(|c| c.span.start < start);
+        for comment in comments[first..].iter().take_while(|c| c.span.end <= end) {
+            self.text(TokenKind::Whitespace, &chars[pos..comment.span.start]);
+            self.text(
+                TokenKind::Comment,
+                &chars[comment.span.start..comment.span.end],
+            );
+            pos = comment.span.end;
+        }
+        self.text(TokenKind::Whitespace, &chars[pos..end]);
+    }
+
+    fn text(&mut self, kind: TokenKind, chars: &[char]) {
+        if !chars.is_empty() {
+            let text = chars.iter().collect();
+            self.push(GreenElement::Token(GreenToken::new(kind, text)));
+        }
+    }
+}
+
+fn build(
+    chars: &[char],
+    tokens: &[(Token, Span)],
+    comments: &[Comment],
+    nodes: &[(NodeKind, usize, usize)],
+) -> Rc<GreenNode> {
+    // the nodes by where they start, outer ones first. A node ends after
+    // the nodes inside it, so of two covering the same tokens the one noted
+    // later is the outer one
+    let mut nodes: Vec<(usize, &(NodeKind, usize, usize))> = nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, start, end))| start < end)
+        .collect();
+    nodes.sort_by_key(|(i, (_, start, end))| (*start, Reverse(*end), Reverse(*i)));
+
+    let mut builder = Builder {
+        stack: vec![(NodeKind::SourceFile, tokens.len(), vec![])],
+    };
+    let mut next = 0;
+    let mut pos = 0;
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        while builder.stack.len() > 1 && builder.stack.last().unwrap().1 <= i {
+            builder.close();
+        }
+        builder.trivia(chars, comments, pos, span.start);
+        while next < nodes.len() && nodes[next].1 .1 == i {
+            let (kind, _, end) = *nodes[next].1;
+            builder.stack.push((kind, end, vec![]));
+            next += 1;
+        }
+        builder.text(
+            TokenKind::Token(token.clone()),
+            &chars[span.start..span.end],
+        );
+        pos = span.end;
+    }
+    while builder.stack.len() > 1 {
+        builder.close();
+    }
+    builder.trivia(chars, comments, pos, chars.len());
+
+    let (kind, _, children) = builder.stack.pop().unwrap();
+    GreenNode::new(kind, children)
+}
+
+// A typed view of a node of one kind.
+pub trait AstNode: Sized {
+    fn cast(node: SyntaxNode) -> Option<Self>;
+    fn syntax(&self) -> &SyntaxNode;
+}
+
+macro_rules! ast_nodes {
+    ($($kind:ident),*) => {
+        $(
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct $kind(SyntaxNode);
+
+            impl AstNode for $kind {
+                fn cast(node: SyntaxNode) -> Option<Self> {
+                    match node.kind() {
+                        NodeKind::$kind => Some(Self(node)),
+                        _ => None,
+                    }
+                }
+
+                fn syntax(&self) -> &SyntaxNode {
+                    &self.0
+                }
+            }
+        )*
+    };
+}
+
+ast_nodes!(
+    SourceFile,
+    Block,
+    FnDecl,
+    Lambda,
+    Param,
+    StructDecl,
+    FieldDecl,
+    EnumDecl,
+    VariantDecl,
+    IfExpr,
+    WhileExpr,
+    MatchExpr,
+    MatchArm,
+    BinExpr,
+    PrefixExpr,
+    CallExpr,
+    FieldExpr,
+    IndexExpr,
+    Literal,
+    Name,
+    TypeRef
+);
+
+fn child<T: AstNode>(node: &SyntaxNode) -> Option<T> {
+    node.children().into_iter().find_map(T::cast)
+}
+
+fn children<T: AstNode>(node: &SyntaxNode) -> Vec<T> {
+    node.children().into_iter().filter_map(T::cast).collect()
+}
+
+fn nth_child(node: &SyntaxNode, n: usize) -> Option<SyntaxNode> {
+    node.children().into_iter().nth(n)
+}
+
+// the first identifier directly in the node
+fn name(node: &SyntaxNode) -> Option<SyntaxToken> {
+    node.child_tokens()
+        .into_iter()
+        .find(|token| matches!(token.kind(), TokenKind::Token(Token::IDENT(_))))
+}
+
+// the first token directly in the node that isn't trivia
+fn first_token(node: &SyntaxNode) -> Option<SyntaxToken> {
+    node.child_tokens().into_iter().next()
+}
+
+impl SourceFile {
+    pub fn statements(&self) -> Vec<SyntaxNode> {
+        self.0.children()
+    }
+}
+
+impl Block {
+    pub fn statements(&self) -> Vec<SyntaxNode> {
+        self.0.children()
+    }
+}
+
+impl FnDecl {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+
+    pub fn params(&self) -> Vec<Param> {
+        children(&self.0)
+    }
+
+    pub fn ret_type(&self) -> Option<TypeRef> {
+        child(&self.0)
+    }
+
+    pub fn body(&self) -> Option<Block> {
+        child(&self.0)
+    }
+}
+
+impl Lambda {
+    pub fn params(&self) -> Vec<Param> {
+        children(&self.0)
+    }
+
+    // a block for fn(x) { }, any expression for |x| x
+    pub fn body(&self) -> Option<SyntaxNode> {
+        self.0
+            .children()
+            .into_iter()
+            .rfind(|node| !matches!(node.kind(), NodeKind::Param | NodeKind::TypeRef))
+    }
+}
+
+impl Param {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+
+    pub fn ty(&self) -> Option<TypeRef> {
+        child(&self.0)
+    }
+}
+
+impl StructDecl {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+
+    pub fn fields(&self) -> Vec<FieldDecl> {
+        children(&self.0)
+    }
+}
+
+impl FieldDecl {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+
+    pub fn ty(&self) -> Option<TypeRef> {
+        child(&self.0)
+    }
+}
+
+impl EnumDecl {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+
+    pub fn variants(&self) -> Vec<VariantDecl> {
+        children(&self.0)
+    }
+}
+
+impl VariantDecl {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+
+    // the types of a tuple variant
+    pub fn types(&self) -> Vec<TypeRef> {
+        children(&self.0)
+    }
+
+    // the fields of a struct variant
+    pub fn fields(&self) -> Vec<FieldDecl> {
+        children(&self.0)
+    }
+}
+
+impl IfExpr {
+    pub fn condition(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn then_branch(&self) -> Option<Block> {
+        nth_child(&self.0, 1).and_then(Block::cast)
+    }
+
+    // a block, or an IfExpr for else if
+    pub fn else_branch(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 2)
+    }
+}
+
+impl WhileExpr {
+    pub fn condition(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn body(&self) -> Option<Block> {
+        nth_child(&self.0, 1).and_then(Block::cast)
+    }
+}
+
+impl MatchExpr {
+    pub fn value(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn arms(&self) -> Vec<MatchArm> {
+        children(&self.0)
+    }
+}
+
+impl MatchArm {
+    pub fn pattern(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn body(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 1)
+    }
+}
+
+impl BinExpr {
+    pub fn lhs(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn op(&self) -> Option<SyntaxToken> {
+        first_token(&self.0)
+    }
+
+    pub fn rhs(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 1)
+    }
+}
+
+impl PrefixExpr {
+    pub fn op(&self) -> Option<SyntaxToken> {
+        first_token(&self.0)
+    }
+
+    pub fn operand(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+}
+
+impl CallExpr {
+    pub fn callee(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn args(&self) -> Vec<SyntaxNode> {
+        self.0.children().into_iter().skip(1).collect()
+    }
+}
+
+impl FieldExpr {
+    pub fn value(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn field(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+}
+
+impl IndexExpr {
+    pub fn value(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 0)
+    }
+
+    pub fn index(&self) -> Option<SyntaxNode> {
+        nth_child(&self.0, 1)
+    }
+}
+
+impl Literal {
+    pub fn token(&self) -> Option<SyntaxToken> {
+        first_token(&self.0)
+    }
+}
+
+impl Name {
+    pub fn token(&self) -> Option<SyntaxToken> {
+        name(&self.0)
+    }
+}
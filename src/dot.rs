@@ -0,0 +1,177 @@
+use super::automata::{Dfa, Nfa};
+use super::cst::{SyntaxElement, SyntaxNode};
+use super::ir::{Function, Module};
+
+// Graphviz drawings of the compiler's structures, each a digraph of its own
+// that `dot -Tsvg` can render. Several can go in one file, dot draws one
+// page per graph.
+
+// a string as a quoted dot ID
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// the nodes and tokens of a concrete syntax tree, whitespace left out
+pub fn syntax_tree(root: &SyntaxNode) -> String {
+    let mut out = String::from("digraph syntax {\n    node [shape=box, fontname=monospace];\n");
+    let mut next = 0;
+    syntax_node(root, &mut out, &mut next);
+    out.push_str("}\n");
+    out
+}
+
+// writes the node and everything under it, returns its ID
+fn syntax_node(node: &SyntaxNode, out: &mut String, next: &mut usize) -> usize {
+    let id = *next;
+    *next += 1;
+    let span = node.span();
+    let label = format!("{:?} {}..{}", node.kind(), span.start, span.end);
+    out.push_str(&format!("    n{} [label={}];\n", id, quote(&label)));
+    for child in node.children_with_tokens() {
+        let child_id = match child {
+            SyntaxElement::Node(child) => syntax_node(&child, out, next),
+            SyntaxElement::Token(token) if token.kind().is_trivia() => continue,
+            SyntaxElement::Token(token) => {
+                let child_id = *next;
+                *next += 1;
+                out.push_str(&format!(
+                    "    n{} [label={}, shape=plaintext];\n",
+                    child_id,
+                    quote(token.text())
+                ));
+                child_id
+            }
+        };
+        out.push_str(&format!("    n{} -> n{};\n", id, child_id));
+    }
+    id
+}
+
+fn graph_name(func: &Function) -> String {
+    func.name.replace(|c: char| !c.is_alphanumeric(), "_")
+}
+
+// a function's blocks with their instructions, and the jumps between them
+pub fn cfg(func: &Function) -> String {
+    let mut out = format!(
+        "digraph cfg_{} {{\n    label={};\n    node [shape=box, fontname=monospace];\n",
+        graph_name(func),
+        quote(&format!("fn {}", func.name))
+    );
+    for (id, block) in func.blocks.iter().enumerate() {
+        // \l ends a line aligned left
+        let mut label = format!("b{}:\\l", id);
+        for instr in &block.instrs {
+            label.push_str(&escape_line(&format!("{:?}", instr)));
+        }
+        label.push_str(&escape_line(&format!("{:?}", block.terminator)));
+        out.push_str(&format!("    b{} [label=\"{}\"];\n", id, label));
+        let succs = block.terminator.successors();
+        for (i, succ) in succs.iter().enumerate() {
+            let label = match (succs.len(), i) {
+                (2, 0) => " [label=true]",
+                (2, _) => " [label=false]",
+                _ => "",
+            };
+            out.push_str(&format!("    b{} -> b{}{};\n", id, succ, label));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// a line of a left aligned label, without its quotes
+fn escape_line(line: &str) -> String {
+    let quoted = quote(line);
+    format!("    {}\\l", &quoted[1..quoted.len() - 1])
+}
+
+// each block under its immediate dominator, unreachable blocks left out
+pub fn dominator_tree(func: &Function) -> String {
+    let mut out = format!(
+        "digraph dom_{} {{\n    label={};\n    node [shape=circle];\n    b0;\n",
+        graph_name(func),
+        quote(&format!("dominators of fn {}", func.name))
+    );
+    for (block, idom) in func.dominators().iter().enumerate() {
+        if let Some(idom) = idom {
+            out.push_str(&format!("    b{} -> b{};\n", idom, block));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// the control flow graph and dominator tree of every function
+pub fn module(module: &Module) -> String {
+    let mut out = String::new();
+    for func in &module.functions {
+        out.push_str(&cfg(func));
+        out.push_str(&dominator_tree(func));
+    }
+    out
+}
+
+pub fn nfa(nfa: &Nfa) -> String {
+    let mut out = String::from("digraph nfa {\n    rankdir=LR;\n    node [shape=circle];\n");
+    out.push_str(&format!(
+        "    start [shape=point];\n    start -> s{};\n    s{} [shape=doublecircle];\n",
+        nfa.start, nfa.accept
+    ));
+    for (id, state) in nfa.states.iter().enumerate() {
+        for (c, target) in &state.moves {
+            out.push_str(&format!(
+                "    s{} -> s{} [label={}];\n",
+                id,
+                target,
+                quote(&c.to_string())
+            ));
+        }
+        for target in &state.empty {
+            out.push_str(&format!("    s{} -> s{} [label=\"ε\"];\n", id, target));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+// each state is labelled with the NFA states it stands for
+pub fn dfa(dfa: &Dfa) -> String {
+    let mut out = String::from(
+        "digraph dfa {\n    rankdir=LR;\n    node [shape=circle];\n    start [shape=point];\n    start -> d0;\n",
+    );
+    for (id, state) in dfa.states.iter().enumerate() {
+        let nfa_states: Vec<String> = state.nfa_states.iter().map(|s| s.to_string()).collect();
+        let shape = match state.accepting {
+            true => "doublecircle",
+            false => "circle",
+        };
+        out.push_str(&format!(
+            "    d{} [label=\"d{}\\n{{{}}}\", shape={}];\n",
+            id,
+            id,
+            nfa_states.join(","),
+            shape
+        ));
+        for (c, target) in &state.moves {
+            out.push_str(&format!(
+                "    d{} -> d{} [label={}];\n",
+                id,
+                target,
+                quote(&c.to_string())
+            ));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
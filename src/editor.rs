@@ -0,0 +1,201 @@
+use std::io::{self, BufRead, Error, ErrorKind, IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+
+// Reads lines from the terminal, with the arrow keys moving along the line
+// and through the history. The terminal is put in raw mode with stty while a
+// line is read, when stdin isn't a terminal lines are read as they are.
+#[derive(Debug, Default)]
+pub struct Editor {
+    history: Vec<String>,
+}
+
+// the line being edited and where the cursor is in it
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    // a line the same as the last one isn't added again
+    pub fn add_history(&mut self, line: &str) {
+        if !line.trim().is_empty() && self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+        }
+    }
+
+    // the line without its line break, None at the end of the input and an
+    // Interrupted error if the user pressed ctrl-c
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut stdout = io::stdout();
+        stdout.write_all(prompt.as_bytes())?;
+        stdout.flush()?;
+
+        if !io::stdin().is_terminal() {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            return Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()));
+        }
+
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        let result = self.edit(prompt);
+        stty(&[saved.trim()])?;
+        result
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut line = Line {
+            chars: vec![],
+            cursor: 0,
+        };
+        // the history entry being shown, history.len() for the line being
+        // typed, which is kept in draft while an older one is shown
+        let mut shown = self.history.len();
+        let mut draft = vec![];
+
+        loop {
+            match read_byte()? {
+                None => return Ok(None),
+                Some(b'\r') | Some(b'\n') => {
+                    print!("\r\n");
+                    io::stdout().flush()?;
+                    return Ok(Some(line.chars.into_iter().collect()));
+                }
+                // ctrl-c
+                Some(3) => {
+                    print!("^C\r\n");
+                    io::stdout().flush()?;
+                    return Err(Error::new(ErrorKind::Interrupted, "interrupted"));
+                }
+                // ctrl-d ends the input on an empty line
+                Some(4) => {
+                    if line.chars.is_empty() {
+                        print!("\r\n");
+                        io::stdout().flush()?;
+                        return Ok(None);
+                    }
+                    line.delete();
+                }
+                // backspace
+                Some(127) | Some(8) => {
+                    if line.cursor > 0 {
+                        line.cursor -= 1;
+                        line.delete();
+                    }
+                }
+                // ctrl-a and ctrl-e
+                Some(1) => line.cursor = 0,
+                Some(5) => line.cursor = line.chars.len(),
+                Some(27) => match (read_byte()?, read_byte()?) {
+                    (Some(b'['), Some(b'A')) if shown > 0 => {
+                        if shown == self.history.len() {
+                            draft = line.chars.clone();
+                        }
+                        shown -= 1;
+                        line.set(self.history[shown].chars().collect());
+                    }
+                    (Some(b'['), Some(b'B')) if shown < self.history.len() => {
+                        shown += 1;
+                        match self.history.get(shown) {
+                            Some(entry) => line.set(entry.chars().collect()),
+                            None => line.set(draft.clone()),
+                        }
+                    }
+                    (Some(b'['), Some(b'C')) => {
+                        line.cursor = (line.cursor + 1).min(line.chars.len());
+                    }
+                    (Some(b'['), Some(b'D')) => line.cursor = line.cursor.saturating_sub(1),
+                    (Some(b'['), Some(b'H')) => line.cursor = 0,
+                    (Some(b'['), Some(b'F')) => line.cursor = line.chars.len(),
+                    // the delete key
+                    (Some(b'['), Some(b'3')) if read_byte()? == Some(b'~') => line.delete(),
+                    _ => {}
+                },
+                Some(byte) if byte >= 0x20 => {
+                    if let Some(c) = read_char(byte)? {
+                        line.chars.insert(line.cursor, c);
+                        line.cursor += 1;
+                    }
+                }
+                Some(_) => {}
+            }
+            line.draw(prompt)?;
+        }
+    }
+}
+
+impl Line {
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn set(&mut self, chars: Vec<char>) {
+        self.cursor = chars.len();
+        self.chars = chars;
+    }
+
+    // redraws the whole line and puts the cursor back where it is
+    fn draw(&self, prompt: &str) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        let text: String = self.chars.iter().collect();
+        write!(stdout, "\r{}{}\x1b[K", prompt, text)?;
+        if self.cursor < self.chars.len() {
+            write!(stdout, "\x1b[{}D", self.chars.len() - self.cursor)?;
+        }
+        stdout.flush()
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match io::stdin().lock().read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// the character a UTF-8 sequence starting with first encodes, None if it
+// isn't valid
+fn read_char(first: u8) -> io::Result<Option<char>> {
+    let len = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(None),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte()? {
+            Some(byte) => bytes.push(byte),
+            None => return Ok(None),
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next()))
+}
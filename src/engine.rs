@@ -0,0 +1,341 @@
+use super::heap::{GcConfig, GcStats};
+use super::natives::{Io, Natives};
+use super::operators::OperatorTable;
+use super::query::Database;
+use super::typeck::Type;
+use super::vm::{Limits, Value, VM};
+use std::cell::RefCell;
+use std::io::{self, Error, ErrorKind};
+use std::rc::Rc;
+
+// Runs programs for a Rust application. Every eval is a program of its own
+// that can call the prelude and the functions the host registered. It can't
+// load modules, and its stdin and stdout are buffers the host fills and
+// reads instead of the process's.
+pub struct Engine {
+    natives: Natives,
+    operators: OperatorTable,
+    io: Rc<RefCell<Sandbox>>,
+    limits: Limits,
+    gc: GcConfig,
+    // the collector's statistics for the last program that ran
+    gc_stats: GcStats,
+}
+
+// the stdin and stdout of the programs an engine runs
+#[derive(Debug, Default)]
+struct Sandbox {
+    input: String,
+    output: String,
+}
+
+impl Io for Sandbox {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.output.push_str(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let end = self.input.find('\n').map_or(self.input.len(), |i| i + 1);
+        Ok(self.input.drain(..end).collect())
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let io = Rc::new(RefCell::new(Sandbox::default()));
+        Self {
+            natives: Natives::prelude_with(io.clone()),
+            operators: OperatorTable::new(),
+            io,
+            limits: Limits::default(),
+            gc: GcConfig::default(),
+            gc_stats: GcStats::default(),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn set_gc(&mut self, config: GcConfig) {
+        self.gc = config;
+    }
+
+    // the operators programs are parsed with, a custom one calls the
+    // registered function or function of the program it names
+    pub fn set_operators(&mut self, operators: OperatorTable) {
+        self.operators = operators;
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_stats
+    }
+
+    // what read_line reads from, after what's left of the previous input
+    pub fn push_input(&mut self, input: &str) {
+        self.io.borrow_mut().input.push_str(input);
+    }
+
+    // what the programs printed since the last call
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.io.borrow_mut().output)
+    }
+
+    // makes a Rust function callable by programs, with the types of its
+    // arguments and result checked like a function of the language's
+    pub fn register_fn<Args, F: HostFn<Args> + 'static>(&mut self, name: &str, func: F) {
+        self.natives
+            .register(name, F::ty(), move |args| func.call(args));
+    }
+
+    // a Rust function that can fail with a runtime error, with the type
+    // given by the host
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        ty: Type,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.natives.register(name, ty, func);
+    }
+
+    // the value of the program's last expression
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let mut db = Database::new("<eval>", None, self.natives.clone())
+            .with_operators(self.operators.clone());
+        db.set_source("<eval>", source.to_string());
+        let module = db
+            .globals()
+            .and_then(|_| db.module())
+            .map_err(|err| db.located(err))?;
+        let mut vm = VM::new(&module, &self.natives)
+            .with_limits(self.limits)
+            .with_gc(self.gc);
+        let result = vm.run();
+        self.gc_stats = vm.gc_stats();
+        result.map_err(|err| Error::other(err.render(&db.source_map())))
+    }
+
+    pub fn eval_as<T: FromValue>(&mut self, source: &str) -> Result<T, Error> {
+        let value = self.eval(source)?;
+        T::from_value(&value).map_err(|message| Error::new(ErrorKind::InvalidData, message))
+    }
+}
+
+// a Rust type with a counterpart in the language
+pub trait HasType {
+    fn ty() -> Type;
+}
+
+pub trait FromValue: HasType + Sized {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+pub trait IntoValue: HasType {
+    fn into_value(self) -> Value;
+}
+
+fn mismatch<T: HasType>(value: &Value) -> String {
+    format!("expected {} but found {}", T::ty(), value.type_name())
+}
+
+// any value, left unchecked
+impl HasType for Value {
+    fn ty() -> Type {
+        Type::Unknown
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl HasType for () {
+    fn ty() -> Type {
+        Type::Void
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Void => Ok(()),
+            other => Err(mismatch::<Self>(other)),
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Void
+    }
+}
+
+impl HasType for bool {
+    fn ty() -> Type {
+        Type::Bool
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            other => Err(mismatch::<Self>(other)),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl HasType for i32 {
+    fn ty() -> Type {
+        Type::Int
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Int(n) => Ok(*n),
+            other => Err(mismatch::<Self>(other)),
+        }
+    }
+}
+
+impl IntoValue for i32 {
+    fn into_value(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl HasType for f32 {
+    fn ty() -> Type {
+        Type::Float
+    }
+}
+
+// ints widen to floats like they do in arithmetic
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Float(n) => Ok(*n),
+            Value::Int(n) => Ok(*n as f32),
+            other => Err(mismatch::<Self>(other)),
+        }
+    }
+}
+
+impl IntoValue for f32 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl HasType for String {
+    fn ty() -> Type {
+        Type::Str
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Str(s) => Ok(s.to_string()),
+            other => Err(mismatch::<Self>(other)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(Rc::from(self))
+    }
+}
+
+impl HasType for &str {
+    fn ty() -> Type {
+        Type::Str
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(Rc::from(self))
+    }
+}
+
+impl<T: HasType> HasType for Vec<T> {
+    fn ty() -> Type {
+        Type::Array(Box::new(T::ty()))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Array(elems) => elems.borrow().iter().map(T::from_value).collect(),
+            other => Err(mismatch::<Self>(other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let elems = self.into_iter().map(T::into_value).collect();
+        Value::Array(Rc::new(RefCell::new(elems)))
+    }
+}
+
+// a Rust closure a program can call, Args are the types of its arguments
+pub trait HostFn<Args> {
+    fn ty() -> Type;
+    fn call(&self, args: &[Value]) -> Result<Value, String>;
+}
+
+macro_rules! host_fn {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> HostFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R,
+            R: IntoValue,
+            $($arg: FromValue),*
+        {
+            fn ty() -> Type {
+                Type::Fn(vec![$($arg::ty()),*], Box::new(R::ty()))
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, args: &[Value]) -> Result<Value, String> {
+                let mut args = args.iter();
+                $(let $arg = $arg::from_value(args.next().expect("ERROR: too few arguments"))?;)*
+                Ok(self($($arg),*).into_value())
+            }
+        }
+    };
+}
+
+host_fn!();
+host_fn!(A);
+host_fn!(A, B);
+host_fn!(A, B, C);
+host_fn!(A, B, C, D);
@@ -0,0 +1,888 @@
+use super::lexer::{Comment, Lexer, Token};
+use super::operators::{Assoc, Fixity, OperatorTable};
+use super::parser::{sub_exprs, BlockSpans, Fields, FnSig, Parser, Pattern, SExpr, TypeExpr};
+use std::collections::HashMap;
+use std::io::Error;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatOptions {
+    // the spaces a block is indented by
+    pub indent: usize,
+    // the column lists are broken over several lines after
+    pub width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            width: 100,
+        }
+    }
+}
+
+// Formats a file. The text is printed back from its syntax tree, with the
+// parentheses it needs and nothing else, and a list that doesn't fit on its
+// line gets one item per line. Comments stay before or after the statement
+// or list item they were next to, which breaks the list. A comment between
+// the } of an if and its else stays after the }. Any other comment inside a
+// statement goes before it. A blank line between two statements is kept.
+pub fn format_source(text: &str, options: FormatOptions) -> Result<String, Error> {
+    format_with_operators(text, options, &OperatorTable::new())
+}
+
+// formats a file that uses the custom operators in `operators`
+pub fn format_with_operators(
+    text: &str,
+    options: FormatOptions,
+    operators: &OperatorTable,
+) -> Result<String, Error> {
+    let mut text = text.to_string();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    let lexer = Lexer::scan_source(&text, 0, &operators.custom_symbols())?;
+    let comments = lexer.comments().to_vec();
+    let mut parser = Parser::with_operators(lexer, operators.clone());
+    let program = parser.parse_program()?;
+
+    // the parser lists the blocks and matches in the order they start,
+    // which is the order they're found in the tree
+    let mut nodes = vec![];
+    blocks_in(&program, &mut nodes);
+    let blocks = nodes
+        .into_iter()
+        .zip(parser.blocks().iter().cloned())
+        .collect();
+    // and the lists in the order their opening brackets come
+    let mut nodes = vec![];
+    lists_in(&program, &mut nodes);
+    let lists = nodes
+        .into_iter()
+        .zip(parser.lists().iter().cloned())
+        .collect();
+
+    let mut formatter = Formatter {
+        options,
+        operators: operators.clone(),
+        text: text.chars().collect(),
+        comments,
+        next_comment: 0,
+        blocks,
+        lists,
+    };
+    let lines = match &program {
+        SExpr::Block(stmts) => formatter.statements(&program, stmts, true),
+        _ => unreachable!(),
+    };
+
+    let mut out = String::new();
+    if !lines.is_empty() {
+        formatter.render(&join_lines(lines), 0, false, &mut out, &mut 0, &mut None);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn blocks_in(expr: &SExpr, nodes: &mut Vec<*const SExpr>) {
+    if let SExpr::Block(_) = expr {
+        nodes.push(expr as *const SExpr);
+    }
+    match expr {
+        SExpr::Fn(_, _, body) => blocks_in(body, nodes),
+        // a match's arms are listed after its value
+        SExpr::Match(value, arms, _) => {
+            blocks_in(value, nodes);
+            nodes.push(expr as *const SExpr);
+            for (_, arm) in arms {
+                blocks_in(arm, nodes);
+            }
+        }
+        _ => {
+            for sub in sub_exprs(expr) {
+                blocks_in(sub, nodes);
+            }
+        }
+    }
+}
+
+fn lists_in(expr: &SExpr, nodes: &mut Vec<*const SExpr>) {
+    match expr {
+        // the callee comes before the arguments' (
+        SExpr::Call(callee, args, _) => {
+            lists_in(callee, nodes);
+            nodes.push(expr as *const SExpr);
+            for arg in args {
+                lists_in(arg, nodes);
+            }
+        }
+        SExpr::Fn(_, _, body) => {
+            nodes.push(expr as *const SExpr);
+            lists_in(body, nodes);
+        }
+        SExpr::Array(_) | SExpr::StructLit(..) => {
+            nodes.push(expr as *const SExpr);
+            for sub in sub_exprs(expr) {
+                lists_in(sub, nodes);
+            }
+        }
+        SExpr::Variant(_, _, fields, _) => {
+            if !matches!(fields, Fields::Unit) {
+                nodes.push(expr as *const SExpr);
+            }
+            for sub in sub_exprs(expr) {
+                lists_in(sub, nodes);
+            }
+        }
+        _ => {
+            for sub in sub_exprs(expr) {
+                lists_in(sub, nodes);
+            }
+        }
+    }
+}
+
+// what the formatted text is laid out from, a group is printed on one line
+// if it fits and has its lines broken otherwise
+#[derive(Debug, Clone)]
+enum Doc {
+    Text(String),
+    // a space, or a line break when its group is broken
+    Line,
+    // nothing, or a line break when its group is broken
+    SoftLine,
+    // always a line break, its group never fits
+    HardLine,
+    // text only written when its group is broken, like a trailing comma
+    IfBroken(String),
+    // the lines inside are indented one more level
+    Nest(Vec<Doc>),
+    Group(Vec<Doc>),
+    Concat(Vec<Doc>),
+}
+
+fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+// the lines of a block, an empty Text is a blank line
+fn join_lines(lines: Vec<Doc>) -> Doc {
+    let mut docs = vec![];
+    for (i, line) in lines.into_iter().enumerate() {
+        if i != 0 {
+            docs.push(Doc::HardLine);
+        }
+        docs.push(line);
+    }
+    Doc::Concat(docs)
+}
+
+// an item of a list with the comments on the lines before it and the one
+// after it on its line
+struct Item {
+    doc: Doc,
+    before: Vec<String>,
+    after: Option<String>,
+}
+
+// open, the items separated by commas and close, broken over a line per
+// item with a trailing comma when they don't fit or have comments. `end`
+// are the comments after the last item. `space` pads the items inside the
+// brackets when they're on one line
+fn list(open: &str, items: Vec<Item>, end: Vec<String>, close: &str, space: bool) -> Doc {
+    if items.is_empty() && end.is_empty() {
+        return text(format!("{}{}", open, close));
+    }
+    // a comment runs to the end of its line
+    let commented = !end.is_empty()
+        || items
+            .iter()
+            .any(|item| !item.before.is_empty() || item.after.is_some());
+    let line = || if space { Doc::Line } else { Doc::SoftLine };
+    let mut inner = vec![line()];
+    let count = items.len();
+    for (i, item) in items.into_iter().enumerate() {
+        for comment in item.before {
+            inner.push(text(comment));
+            inner.push(Doc::HardLine);
+        }
+        inner.push(item.doc);
+        if i + 1 < count {
+            inner.push(text(","));
+        } else {
+            inner.push(Doc::IfBroken(String::from(",")));
+        }
+        if let Some(comment) = item.after {
+            inner.push(text(format!(" {}", comment)));
+        }
+        if i + 1 < count {
+            inner.push(Doc::Line);
+        }
+    }
+    for (i, comment) in end.into_iter().enumerate() {
+        if count > 0 || i > 0 {
+            inner.push(Doc::HardLine);
+        }
+        inner.push(text(comment));
+    }
+    let last = if commented { Doc::HardLine } else { line() };
+    Doc::Group(vec![text(open), Doc::Nest(inner), last, text(close)])
+}
+
+fn parens(doc: Doc) -> Doc {
+    Doc::Concat(vec![text("("), doc, text(")")])
+}
+
+// the width of the doc on one line, None if it has to be broken
+fn flat_width(doc: &Doc) -> Option<usize> {
+    match doc {
+        Doc::Text(s) => Some(s.chars().count()),
+        Doc::Line => Some(1),
+        Doc::SoftLine | Doc::IfBroken(_) => Some(0),
+        Doc::HardLine => None,
+        Doc::Nest(docs) | Doc::Group(docs) | Doc::Concat(docs) => docs.iter().map(flat_width).sum(),
+    }
+}
+
+// block-like statements end without a ;
+fn ends_with_block(stmt: &SExpr) -> bool {
+    match stmt {
+        SExpr::Block(_)
+        | SExpr::If(..)
+        | SExpr::While(..)
+        | SExpr::StructDef(..)
+        | SExpr::EnumDef(..)
+        | SExpr::Match(..) => true,
+        SExpr::Fn(_, _, body) => matches!(**body, SExpr::Block(_)),
+        SExpr::Pub(item) => !matches!(**item, SExpr::Mod(..)),
+        _ => false,
+    }
+}
+
+// a lambda without braces takes in everything after it, so an expression
+// ending in one can't have anything after it
+fn ends_open(expr: &SExpr) -> bool {
+    match expr {
+        SExpr::Fn(None, _, body) => !matches!(**body, SExpr::Block(_)),
+        SExpr::Cons(_, operands, _) => operands.last().is_some_and(ends_open),
+        _ => false,
+    }
+}
+
+// whether a struct literal in the expression would be taken for the body
+// of the if, while or match it's the condition of
+fn has_struct_literal(expr: &SExpr) -> bool {
+    match expr {
+        SExpr::StructLit(..) | SExpr::Variant(_, _, Fields::Struct(_), _) => true,
+        SExpr::Cons(_, operands, _) => operands.iter().any(has_struct_literal),
+        SExpr::Call(target, ..)
+        | SExpr::Index(target, _, _)
+        | SExpr::Slice(target, ..)
+        | SExpr::Field(target, _, _) => has_struct_literal(target),
+        SExpr::Fn(None, _, body) => !matches!(**body, SExpr::Block(_)) && has_struct_literal(body),
+        _ => false,
+    }
+}
+
+// floats always have a fraction so they scan as floats again
+fn float_text(n: f32) -> String {
+    let s = n.to_string();
+    if s.contains('.') {
+        s
+    } else {
+        format!("{}.0", s)
+    }
+}
+
+fn pattern_text(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Wildcard => String::from("_"),
+        Pattern::Binding(name) => name.clone(),
+        Pattern::Literal(Token::FLOAT(n)) => float_text(*n),
+        Pattern::Literal(Token::STR(s)) => format!("\"{}\"", s),
+        Pattern::Literal(Token::INT(n)) => n.to_string(),
+        Pattern::Literal(Token::BOOL(b)) => b.to_string(),
+        Pattern::Literal(token) => format!("{:?}", token),
+        Pattern::Variant(name, variant, fields, rest) => {
+            let mut s = format!("{}::{}", name, variant);
+            match fields {
+                Fields::Unit => {}
+                Fields::Tuple(patterns) => {
+                    let patterns: Vec<String> = patterns.iter().map(pattern_text).collect();
+                    s.push_str(&format!("({})", patterns.join(", ")));
+                }
+                Fields::Struct(patterns) => {
+                    // `field: field` is written as `field`
+                    let mut fields: Vec<String> = patterns
+                        .iter()
+                        .map(|(field, pattern)| match pattern {
+                            Pattern::Binding(name) if name == field => field.clone(),
+                            pattern => format!("{}: {}", field, pattern_text(pattern)),
+                        })
+                        .collect();
+                    if *rest {
+                        fields.push(String::from(".."));
+                    }
+                    if fields.is_empty() {
+                        s.push_str(" {}");
+                    } else {
+                        s.push_str(&format!(" {{ {} }}", fields.join(", ")));
+                    }
+                }
+            }
+            s
+        }
+    }
+}
+
+struct Formatter {
+    options: FormatOptions,
+    operators: OperatorTable,
+    text: Vec<char>,
+    comments: Vec<Comment>,
+    // the first comment that hasn't been written yet
+    next_comment: usize,
+    blocks: HashMap<*const SExpr, BlockSpans>,
+    // the calls, arrays, struct literals, variants and functions with the
+    // spans of their lists
+    lists: HashMap<*const SExpr, BlockSpans>,
+}
+
+impl Formatter {
+    // the comments left before offset, they're written now
+    fn comments_before(&mut self, offset: usize) -> Vec<Comment> {
+        let start = self.next_comment;
+        while self.next_comment < self.comments.len()
+            && self.comments[self.next_comment].span.start < offset
+        {
+            self.next_comment += 1;
+        }
+        self.comments[start..self.next_comment].to_vec()
+    }
+
+    // the comment after offset on the same line, if nothing else comes
+    // before it from `next` on
+    fn trailing_comment(&mut self, offset: usize, next: usize) -> Option<Comment> {
+        let comment = self.comments.get(self.next_comment)?;
+        if comment.span.start >= next || self.text[offset..comment.span.start].contains(&'\n') {
+            return None;
+        }
+        self.next_comment += 1;
+        Some(comment.clone())
+    }
+
+    // where the token after offset starts, past whitespace and comments
+    fn next_token(&self, mut offset: usize) -> usize {
+        loop {
+            while self.text.get(offset).is_some_and(|c| c.is_whitespace()) {
+                offset += 1;
+            }
+            match self.comments.iter().find(|c| c.span.start == offset) {
+                Some(comment) => offset = comment.span.end,
+                None => return offset,
+            }
+        }
+    }
+
+    // a line per statement of a block or arm of a match and per comment
+    // among them, `item` lays out one with its ; or , given whether it's the
+    // last
+    fn lines<T>(
+        &mut self,
+        node: &SExpr,
+        items: &[T],
+        top_level: bool,
+        mut item: impl FnMut(&mut Self, &T, bool) -> Doc,
+    ) -> Vec<Doc> {
+        let spans = self.blocks[&(node as *const SExpr)].clone();
+        let end = if top_level {
+            self.text.len()
+        } else {
+            spans.span.end
+        };
+        let mut lines = vec![];
+        let mut prev_end = None;
+        for (i, value) in items.iter().enumerate() {
+            let span = spans.statements[i];
+            for comment in self.comments_before(span.start) {
+                self.blank_line(&mut lines, prev_end, comment.span.start);
+                prev_end = Some(comment.span.end);
+                lines.push(text(comment.text));
+            }
+            self.blank_line(&mut lines, prev_end, span.start);
+
+            let mut doc = vec![item(self, value, i + 1 == items.len())];
+            let inside = self.comments_before(span.end);
+            lines.extend(inside.into_iter().map(|c| text(c.text)));
+            prev_end = Some(span.end);
+            let next = spans.statements.get(i + 1).map_or(end, |next| next.start);
+            if let Some(comment) = self.trailing_comment(span.end, next) {
+                prev_end = Some(comment.span.end);
+                doc.push(text(format!(" {}", comment.text)));
+            }
+            lines.push(Doc::Concat(doc));
+        }
+
+        for comment in self.comments_before(end) {
+            self.blank_line(&mut lines, prev_end, comment.span.start);
+            prev_end = Some(comment.span.end);
+            lines.push(text(comment.text));
+        }
+        lines
+    }
+
+    // keeps a blank line between what ended at prev_end and what starts at
+    // start
+    fn blank_line(&self, lines: &mut Vec<Doc>, prev_end: Option<usize>, start: usize) {
+        if let Some(end) = prev_end {
+            if end < start && self.text[end..start].iter().filter(|c| **c == '\n').count() > 1 {
+                lines.push(text(""));
+            }
+        }
+    }
+
+    fn statements(&mut self, block: &SExpr, stmts: &[SExpr], top_level: bool) -> Vec<Doc> {
+        self.lines(block, stmts, top_level, |formatter, stmt, last| {
+            let doc = formatter.statement(stmt);
+            if !ends_with_block(stmt) && (top_level || !last) {
+                Doc::Concat(vec![doc, text(";")])
+            } else {
+                doc
+            }
+        })
+    }
+
+    fn block(&mut self, block: &SExpr) -> Doc {
+        let lines = match block {
+            SExpr::Block(stmts) => self.statements(block, stmts, false),
+            _ => unreachable!(),
+        };
+        if lines.is_empty() {
+            return text("{}");
+        }
+        Doc::Concat(vec![
+            text("{"),
+            Doc::Nest(vec![Doc::HardLine, join_lines(lines)]),
+            Doc::HardLine,
+            text("}"),
+        ])
+    }
+
+    fn statement(&mut self, stmt: &SExpr) -> Doc {
+        match stmt {
+            SExpr::StructDef(name, generics, fields) => {
+                let mut head = format!("struct {}", name);
+                if !generics.is_empty() {
+                    head.push_str(&format!("<{}>", generics.join(", ")));
+                }
+                let fields = fields
+                    .iter()
+                    .map(|(field, t)| format!("{}: {},", field, t))
+                    .collect();
+                decl_body(head, fields)
+            }
+            SExpr::EnumDef(name, variants) => {
+                let variants = variants
+                    .iter()
+                    .map(|(variant, fields)| {
+                        let fields = match fields {
+                            Fields::Unit => String::new(),
+                            Fields::Tuple(types) => {
+                                let types: Vec<String> =
+                                    types.iter().map(|t| t.to_string()).collect();
+                                format!("({})", types.join(", "))
+                            }
+                            Fields::Struct(fields) => {
+                                let fields: Vec<String> = fields
+                                    .iter()
+                                    .map(|(field, t)| format!("{}: {}", field, t))
+                                    .collect();
+                                format!(" {{ {} }}", fields.join(", "))
+                            }
+                        };
+                        format!("{}{},", variant, fields)
+                    })
+                    .collect();
+                decl_body(format!("enum {}", name), variants)
+            }
+            SExpr::Mod(name, _) => text(format!("mod {}", name)),
+            SExpr::Import(path, _) => text(format!("import {}", path.join("::"))),
+            SExpr::Pub(item) => Doc::Concat(vec![text("pub "), self.statement(item)]),
+            expr => self.expr(expr),
+        }
+    }
+
+    fn expr(&mut self, expr: &SExpr) -> Doc {
+        match expr {
+            SExpr::Atom(token) => text(match token {
+                Token::INT(n) => n.to_string(),
+                Token::FLOAT(n) => float_text(*n),
+                Token::STR(s) => format!("\"{}\"", s),
+                Token::BOOL(b) => b.to_string(),
+                Token::IDENT(name) => name.clone(),
+                Token::VOID => String::from("void"),
+                token => format!("{:?}", token),
+            }),
+            SExpr::Cons(op, operands, _) => self.operation(op, operands),
+            SExpr::Block(_) => self.block(expr),
+            SExpr::If(cond, then, els, _) => {
+                let mut docs = vec![text("if "), self.cond(cond), text(" "), self.block(then)];
+                if let Some(els) = els {
+                    // comments between the } and the else stay there
+                    let end = self.blocks[&(&**then as *const SExpr)].span.end;
+                    let comments = self.comments_before(self.next_token(end));
+                    for (i, comment) in comments.iter().enumerate() {
+                        if i == 0 && !self.text[end..comment.span.start].contains(&'\n') {
+                            docs.push(text(format!(" {}", comment.text)));
+                        } else {
+                            docs.push(Doc::HardLine);
+                            docs.push(text(comment.text.clone()));
+                        }
+                    }
+                    docs.push(match comments.is_empty() {
+                        true => text(" else "),
+                        false => Doc::Concat(vec![Doc::HardLine, text("else ")]),
+                    });
+                    docs.push(match **els {
+                        SExpr::If(..) => self.expr(els),
+                        _ => self.block(els),
+                    });
+                }
+                Doc::Concat(docs)
+            }
+            SExpr::While(cond, body, _) => Doc::Concat(vec![
+                text("while "),
+                self.cond(cond),
+                text(" "),
+                self.block(body),
+            ]),
+            SExpr::Match(value, arms, _) => {
+                let value = self.cond(value);
+                let lines = self.lines(expr, arms, false, |formatter, (pattern, arm), _| {
+                    let mut line = vec![text(format!("{} => ", pattern_text(pattern)))];
+                    line.push(formatter.statement(arm));
+                    if !matches!(
+                        arm,
+                        SExpr::Block(_) | SExpr::If(..) | SExpr::While(..) | SExpr::Match(..)
+                    ) {
+                        line.push(text(","));
+                    }
+                    Doc::Concat(line)
+                });
+                let mut docs = vec![text("match "), value, text(" {")];
+                if !lines.is_empty() {
+                    docs.push(Doc::Nest(vec![Doc::HardLine, join_lines(lines)]));
+                    docs.push(Doc::HardLine);
+                }
+                docs.push(text("}"));
+                Doc::Concat(docs)
+            }
+            SExpr::Array(elems) => {
+                let (elems, end) = self.items(expr, elems, |formatter, elem| formatter.expr(elem));
+                list("[", elems, end, "]", false)
+            }
+            SExpr::Index(array, index, _) => Doc::Concat(vec![
+                self.target(array, false),
+                text("["),
+                self.expr(index),
+                text("]"),
+            ]),
+            SExpr::Slice(array, lo, hi, _) => {
+                let mut docs = vec![self.target(array, false), text("[")];
+                if let Some(lo) = lo {
+                    docs.push(self.expr(lo));
+                }
+                docs.push(text(".."));
+                if let Some(hi) = hi {
+                    docs.push(self.expr(hi));
+                }
+                docs.push(text("]"));
+                Doc::Concat(docs)
+            }
+            SExpr::Field(value, field, _) => {
+                Doc::Concat(vec![self.target(value, false), text(format!(".{}", field))])
+            }
+            SExpr::StructLit(name, fields) => {
+                let (fields, end) = self.fields(expr, fields);
+                Doc::Concat(vec![
+                    text(format!("{} ", name)),
+                    list("{", fields, end, "}", true),
+                ])
+            }
+            SExpr::Variant(name, variant, fields, _) => {
+                let path = text(format!("{}::{}", name, variant));
+                match fields {
+                    Fields::Unit => path,
+                    Fields::Tuple(values) => {
+                        let (values, end) =
+                            self.items(expr, values, |formatter, value| formatter.expr(value));
+                        Doc::Concat(vec![path, list("(", values, end, ")", false)])
+                    }
+                    Fields::Struct(values) => {
+                        let (values, end) = self.fields(expr, values);
+                        Doc::Concat(vec![path, text(" "), list("{", values, end, "}", true)])
+                    }
+                }
+            }
+            SExpr::Fn(name, sig, body) => match (name, &**body) {
+                (Some(name), _) => self.function(expr, &format!("fn {}", name), sig, body),
+                (None, SExpr::Block(_)) => self.function(expr, "fn", sig, body),
+                (None, _) => {
+                    let params: Vec<String> = sig.params.iter().map(param_text).collect();
+                    let head = match params.is_empty() {
+                        true => String::from("|| "),
+                        false => format!("|{}| ", params.join(", ")),
+                    };
+                    Doc::Concat(vec![text(head), self.expr(body)])
+                }
+            },
+            SExpr::Call(callee, args, _) => {
+                let callee = self.target(callee, false);
+                let (args, end) = self.items(expr, args, |formatter, arg| formatter.expr(arg));
+                Doc::Concat(vec![callee, list("(", args, end, ")", false)])
+            }
+            SExpr::StructDef(..)
+            | SExpr::EnumDef(..)
+            | SExpr::Mod(..)
+            | SExpr::Import(..)
+            | SExpr::Pub(_) => self.statement(expr),
+        }
+    }
+
+    fn fields(&mut self, node: &SExpr, fields: &[(String, SExpr)]) -> (Vec<Item>, Vec<String>) {
+        self.items(node, fields, |formatter, (field, value)| {
+            Doc::Concat(vec![text(format!("{}: ", field)), formatter.expr(value)])
+        })
+    }
+
+    // the items of the list `node` has laid out by `item`, with the comments
+    // among them and the ones after the last
+    fn items<T>(
+        &mut self,
+        node: &SExpr,
+        values: &[T],
+        mut item: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> (Vec<Item>, Vec<String>) {
+        let texts = |comments: Vec<Comment>| comments.into_iter().map(|c| c.text).collect();
+        let spans = self.lists.get(&(node as *const SExpr)).cloned();
+        let mut items = vec![];
+        for (i, value) in values.iter().enumerate() {
+            let span = spans
+                .as_ref()
+                .and_then(|spans| spans.statements.get(i).copied());
+            let mut before: Vec<String> = match span {
+                Some(span) => texts(self.comments_before(span.start)),
+                None => vec![],
+            };
+            let doc = item(self, value);
+            let mut after = None;
+            if let (Some(span), Some(spans)) = (span, &spans) {
+                // one inside the item that isn't in one of its blocks or
+                // lists goes before it
+                before.extend(texts(self.comments_before(span.end)));
+                let next = spans
+                    .statements
+                    .get(i + 1)
+                    .map_or(spans.span.end, |next| next.start);
+                after = self.trailing_comment(span.end, next).map(|c| c.text);
+            }
+            items.push(Item { doc, before, after });
+        }
+        let end = match spans {
+            Some(spans) => texts(self.comments_before(spans.span.end)),
+            None => vec![],
+        };
+        (items, end)
+    }
+
+    fn function(&mut self, node: &SExpr, head: &str, sig: &FnSig, body: &SExpr) -> Doc {
+        let mut head = head.to_string();
+        if !sig.generics.is_empty() {
+            head.push_str(&format!("<{}>", sig.generics.join(", ")));
+        }
+        let (params, end) = self.items(node, &sig.params, |_, p| text(param_text(p)));
+        let mut docs = vec![text(head), list("(", params, end, ")", false)];
+        if let Some(ret) = &sig.ret {
+            docs.push(text(format!(" -> {}", ret)));
+        }
+        docs.push(text(" "));
+        docs.push(self.block(body));
+        Doc::Concat(docs)
+    }
+
+    // the condition of an if, while or match
+    fn cond(&mut self, cond: &SExpr) -> Doc {
+        let doc = self.expr(cond);
+        match has_struct_literal(cond) {
+            true => parens(doc),
+            false => doc,
+        }
+    }
+
+    // what an index, field access, call or postfix operator is applied to,
+    // anything looser than those is put in parentheses
+    fn target(&mut self, expr: &SExpr, postfix: bool) -> Doc {
+        let tight = match expr {
+            // the lexer doesn't allow a number right before ( or [, and 1.x
+            // would scan as a float
+            SExpr::Atom(Token::INT(_)) | SExpr::Atom(Token::FLOAT(_)) => postfix,
+            SExpr::Cons(op, operands, _) => {
+                operands.len() == 1 && self.operators.get(op, Fixity::Postfix).is_some()
+            }
+            SExpr::Atom(_)
+            | SExpr::Call(..)
+            | SExpr::Index(..)
+            | SExpr::Slice(..)
+            | SExpr::Field(..)
+            | SExpr::Array(_)
+            | SExpr::StructLit(..)
+            | SExpr::Variant(..) => true,
+            _ => false,
+        };
+        let doc = self.expr(expr);
+        match tight {
+            true => doc,
+            false => parens(doc),
+        }
+    }
+
+    // how tightly an operation holds its operands, the highest value for
+    // anything that isn't one
+    fn precedence(&self, expr: &SExpr) -> u8 {
+        let (op, fixity) = match expr {
+            SExpr::Cons(op, operands, _) if operands.len() == 2 => (op, Fixity::Infix),
+            SExpr::Cons(op, _, _) => (op, Fixity::Prefix),
+            _ => return u8::MAX,
+        };
+        self.operators
+            .get(op, fixity)
+            .map_or(u8::MAX, |op| op.precedence)
+    }
+
+    fn operation(&mut self, op: &Token, operands: &[SExpr]) -> Doc {
+        let symbol = op.symbol().unwrap_or_default().to_string();
+        match operands {
+            [call] if matches!(op, Token::TAIL) => {
+                Doc::Concat(vec![text("#[tail] "), self.expr(call)])
+            }
+            [lhs, rhs] => {
+                let (precedence, assoc) = match self.operators.get(op, Fixity::Infix) {
+                    Some(op) => (op.precedence, op.assoc),
+                    None => (0, Assoc::Left),
+                };
+                // a block-like expression on the left would end a statement
+                // it starts
+                let left = self.precedence(lhs);
+                let lhs_parens = left < precedence
+                    || (left == precedence && assoc == Assoc::Right)
+                    || ends_with_block(lhs)
+                    || ends_open(lhs);
+                let right = self.precedence(rhs);
+                let rhs_parens =
+                    right < precedence || (right == precedence && assoc == Assoc::Left);
+
+                let mut lhs = self.expr(lhs);
+                if lhs_parens {
+                    lhs = parens(lhs);
+                }
+                let mut rhs = self.expr(rhs);
+                if rhs_parens {
+                    rhs = parens(rhs);
+                }
+                Doc::Concat(vec![lhs, text(format!(" {} ", symbol)), rhs])
+            }
+            [operand] if self.operators.get(op, Fixity::Prefix).is_some() => {
+                let precedence = self.operators.get(op, Fixity::Prefix).unwrap().precedence;
+                // -(-x) rather than --x
+                let mut doc = self.expr(operand);
+                if self.precedence(operand) <= precedence {
+                    doc = parens(doc);
+                }
+                Doc::Concat(vec![text(symbol), doc])
+            }
+            [operand] => Doc::Concat(vec![self.target(operand, true), text(symbol)]),
+            _ => unreachable!(),
+        }
+    }
+
+    // writes the doc with its groups on one line where they fit, `pending`
+    // is the indentation of a line nothing has been written on yet, so
+    // blank lines are left empty
+    fn render(
+        &self,
+        doc: &Doc,
+        indent: usize,
+        flat: bool,
+        out: &mut String,
+        col: &mut usize,
+        pending: &mut Option<usize>,
+    ) {
+        match doc {
+            Doc::Text(s) => {
+                if s.is_empty() {
+                    return;
+                }
+                if let Some(indent) = pending.take() {
+                    out.push_str(&" ".repeat(indent));
+                    *col = indent;
+                }
+                out.push_str(s);
+                *col += s.chars().count();
+            }
+            Doc::Line | Doc::SoftLine | Doc::HardLine if !flat || matches!(doc, Doc::HardLine) => {
+                out.push('\n');
+                *col = 0;
+                *pending = Some(indent);
+            }
+            Doc::Line => self.render(&text(" "), indent, flat, out, col, pending),
+            Doc::SoftLine => {}
+            Doc::HardLine => unreachable!(),
+            Doc::IfBroken(s) => {
+                if !flat {
+                    self.render(&text(s.clone()), indent, flat, out, col, pending);
+                }
+            }
+            Doc::Nest(docs) => {
+                for doc in docs {
+                    self.render(doc, indent + self.options.indent, flat, out, col, pending);
+                }
+            }
+            Doc::Group(docs) => {
+                let start = pending.unwrap_or(*col);
+                let fits = flat
+                    || flat_width(doc).is_some_and(|width| start + width <= self.options.width);
+                for doc in docs {
+                    self.render(doc, indent, fits, out, col, pending);
+                }
+            }
+            Doc::Concat(docs) => {
+                for doc in docs {
+                    self.render(doc, indent, flat, out, col, pending);
+                }
+            }
+        }
+    }
+}
+
+fn param_text((name, t): &(String, Option<TypeExpr>)) -> String {
+    match t {
+        Some(t) => format!("{}: {}", name, t),
+        None => name.clone(),
+    }
+}
+
+// a struct or enum declaration with a line per field or variant
+fn decl_body(head: String, lines: Vec<String>) -> Doc {
+    if lines.is_empty() {
+        return text(format!("{} {{}}", head));
+    }
+    Doc::Concat(vec![
+        text(format!("{} {{", head)),
+        Doc::Nest(vec![
+            Doc::HardLine,
+            join_lines(lines.into_iter().map(text).collect()),
+        ]),
+        Doc::HardLine,
+        text("}"),
+    ])
+}
@@ -0,0 +1,305 @@
+use super::vm::{Cell, Closure, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::rc::{Rc, Weak};
+
+// The objects a running program allocated: its strings, arrays, structs,
+// closures and the variables closures capture. Values stay reference
+// counted, so most objects are freed as soon as nothing uses them, but an
+// array holding itself or a closure stored in a variable it captures never
+// is. A collection marks every object reachable from the roots the VM gives
+// it, its stack, globals and the variables of the calls being run, and
+// sweeps the others by emptying them, which breaks the cycles they're in.
+// The heap only keeps weak references and a size for every object, to
+// know what's still alive and how much memory it takes up.
+//
+// Collections happen between instructions, when everything the program
+// can still use is reachable from the roots. What a native returns is
+// tracked when it returns, and the arrays it was given are measured again
+// since it may have grown them.
+
+// what the first collection waits for, in bytes allocated since the
+// program started
+const FIRST_COLLECTION: usize = 1 << 20;
+
+// the memory an object takes up besides its contents, its reference counts
+const HEADER: usize = 2 * mem::size_of::<usize>();
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcConfig {
+    // collects at every instruction that allocated something, to find the
+    // objects the VM forgets to give as roots
+    pub stress: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub collections: usize,
+    pub allocated: usize,
+    // the objects found freed at collections, because nothing used them
+    // any more or by the collection itself
+    pub freed: usize,
+    // the objects a collection emptied, which were only kept alive by
+    // cycles
+    pub swept: usize,
+    pub live: usize,
+    pub bytes: usize,
+    pub peak_bytes: usize,
+}
+
+#[derive(Debug)]
+enum Object {
+    Str(Weak<str>),
+    Array(Weak<RefCell<Vec<Value>>>),
+    Struct(Weak<RefCell<Vec<Value>>>),
+    Closure(Weak<Closure>),
+    Cell(Weak<RefCell<Option<Value>>>),
+}
+
+impl Object {
+    // where it is, None once it's been freed
+    fn address(&self) -> Option<usize> {
+        match self {
+            Object::Str(s) => s.upgrade().map(|s| Rc::as_ptr(&s) as *const u8 as usize),
+            Object::Array(elems) | Object::Struct(elems) => {
+                elems.upgrade().map(|elems| Rc::as_ptr(&elems) as usize)
+            }
+            Object::Closure(closure) => closure.upgrade().map(|c| Rc::as_ptr(&c) as usize),
+            Object::Cell(cell) => cell.upgrade().map(|cell| Rc::as_ptr(&cell) as usize),
+        }
+    }
+
+    fn size(&self) -> usize {
+        let contents = match self {
+            Object::Str(s) => s.upgrade().map_or(0, |s| s.len()),
+            Object::Array(elems) | Object::Struct(elems) => elems
+                .upgrade()
+                .map_or(0, |elems| elems.borrow().len() * mem::size_of::<Value>()),
+            Object::Closure(closure) => closure
+                .upgrade()
+                .map_or(0, |c| c.env.len() * mem::size_of::<Cell>()),
+            Object::Cell(_) => mem::size_of::<RefCell<Option<Value>>>(),
+        };
+        HEADER + contents
+    }
+
+    // empties it, so the objects it holds are no longer kept alive by it
+    fn sweep(&self) {
+        match self {
+            Object::Array(elems) => {
+                if let Some(elems) = elems.upgrade() {
+                    elems.borrow_mut().clear();
+                }
+            }
+            Object::Struct(fields) => {
+                if let Some(fields) = fields.upgrade() {
+                    for field in fields.borrow_mut().iter_mut() {
+                        *field = Value::Void;
+                    }
+                }
+            }
+            Object::Cell(cell) => {
+                if let Some(cell) = cell.upgrade() {
+                    *cell.borrow_mut() = None;
+                }
+            }
+            // their captured variables are swept instead
+            Object::Str(_) | Object::Closure(_) => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<Object>,
+    // the size each object was last measured at, by its address
+    sizes: HashMap<usize, usize>,
+    config: GcConfig,
+    stats: GcStats,
+    // the bytes allocated since the last collection
+    allocated_bytes: usize,
+    next_collection: usize,
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Self {
+        Self {
+            objects: vec![],
+            sizes: HashMap::new(),
+            config,
+            stats: GcStats::default(),
+            allocated_bytes: 0,
+            next_collection: FIRST_COLLECTION,
+        }
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    // an estimate of the bytes the objects alive take up
+    pub fn bytes(&self) -> usize {
+        self.stats.bytes
+    }
+
+    // starts tracking the object the value points to, values that aren't
+    // objects are left alone
+    pub fn track(&mut self, value: &Value) {
+        let object = match value {
+            Value::Str(s) => Object::Str(Rc::downgrade(s)),
+            Value::Array(elems) => Object::Array(Rc::downgrade(elems)),
+            Value::Struct(_, fields) => Object::Struct(Rc::downgrade(fields)),
+            Value::Closure(closure) => Object::Closure(Rc::downgrade(closure)),
+            _ => return,
+        };
+        self.add(object);
+    }
+
+    // a variable a closure captured, which is what closures form cycles
+    // through
+    pub fn track_cell(&mut self, cell: &Cell) {
+        self.add(Object::Cell(Rc::downgrade(cell)));
+    }
+
+    // a value a native returned, with the objects in it, which it may have
+    // made too
+    pub fn track_all(&mut self, value: &Value) {
+        self.track(value);
+        match value {
+            Value::Array(elems) | Value::Struct(_, elems) => {
+                for elem in elems.borrow().iter() {
+                    self.track_all(elem);
+                }
+            }
+            Value::Enum(_, _, payload) => {
+                for value in payload.iter() {
+                    self.track_all(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // measures an object again after it changed size
+    pub fn resize(&mut self, value: &Value) {
+        let object = match value {
+            Value::Array(elems) => Object::Array(Rc::downgrade(elems)),
+            _ => return,
+        };
+        let address = object.address().unwrap();
+        if let Some(old) = self.sizes.get(&address).copied() {
+            let size = object.size();
+            self.sizes.insert(address, size);
+            self.stats.bytes = self.stats.bytes + size - old;
+            self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.bytes);
+            self.allocated_bytes += size.saturating_sub(old);
+        }
+    }
+
+    fn add(&mut self, object: Object) {
+        let size = object.size();
+        // tracked again, or an object freed since had the same address
+        if let Some(old) = self.sizes.insert(object.address().unwrap(), size) {
+            self.stats.bytes -= old;
+        }
+        self.objects.push(object);
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        self.stats.bytes += size;
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.stats.bytes);
+        self.allocated_bytes += size;
+    }
+
+    pub fn wants_collection(&self) -> bool {
+        match self.config.stress {
+            true => self.allocated_bytes > 0,
+            false => self.allocated_bytes >= self.next_collection,
+        }
+    }
+
+    // marks what the roots and the variables reach and sweeps the rest,
+    // then counts what's alive again
+    pub fn collect(&mut self, roots: Vec<Value>, cells: &[Cell]) {
+        let marked = mark(roots, cells);
+        let mut seen = HashSet::new();
+        let mut bytes = 0;
+        let stats = &mut self.stats;
+        let sizes = &mut self.sizes;
+        sizes.clear();
+        self.objects.retain(|object| {
+            let address = match object.address() {
+                Some(address) => address,
+                None => {
+                    stats.freed += 1;
+                    return false;
+                }
+            };
+            // a variable captured by several closures is tracked by each
+            if !seen.insert(address) {
+                stats.allocated -= 1;
+                return false;
+            }
+            if !marked.contains(&address) {
+                object.sweep();
+                // strings and closures are freed once the cycles they're in
+                // are broken
+                if !matches!(object, Object::Str(_) | Object::Closure(_)) {
+                    stats.swept += 1;
+                    stats.freed += 1;
+                    return false;
+                }
+            }
+            let size = object.size();
+            sizes.insert(address, size);
+            bytes += size;
+            true
+        });
+        self.stats.collections += 1;
+        self.stats.live = self.objects.len();
+        self.stats.bytes = bytes;
+        self.allocated_bytes = 0;
+        self.next_collection = FIRST_COLLECTION.max(bytes * 2);
+    }
+}
+
+// the addresses of every object reachable from the roots and the variables
+fn mark(roots: Vec<Value>, cells: &[Cell]) -> HashSet<usize> {
+    let mut marked = HashSet::new();
+    let mut work = roots;
+    for cell in cells {
+        mark_cell(cell, &mut marked, &mut work);
+    }
+    while let Some(value) = work.pop() {
+        match &value {
+            Value::Str(s) => {
+                marked.insert(Rc::as_ptr(s) as *const u8 as usize);
+            }
+            Value::Array(elems) | Value::Struct(_, elems) => {
+                if marked.insert(Rc::as_ptr(elems) as usize) {
+                    work.extend(elems.borrow().iter().cloned());
+                }
+            }
+            Value::Enum(_, _, payload) => {
+                if marked.insert(Rc::as_ptr(payload) as usize) {
+                    work.extend(payload.iter().cloned());
+                }
+            }
+            Value::Closure(closure) => {
+                if marked.insert(Rc::as_ptr(closure) as usize) {
+                    for cell in &closure.env {
+                        mark_cell(cell, &mut marked, &mut work);
+                    }
+                }
+            }
+            Value::Void | Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::Native(_) => {}
+        }
+    }
+    marked
+}
+
+fn mark_cell(cell: &Cell, marked: &mut HashSet<usize>, work: &mut Vec<Value>) {
+    if marked.insert(Rc::as_ptr(cell) as usize) {
+        work.extend(cell.borrow().clone());
+    }
+}
@@ -0,0 +1,1485 @@
+use super::layout::*;
+use super::lexer::*;
+use super::operators::OperatorTable;
+use super::parser::*;
+use super::patterns::{self, Ctor, Decision, Occurrence};
+use super::source::{Site, Span};
+use super::typeck::{self, EnumDef, Instances, StructDef, Type};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::{Error, ErrorKind};
+use std::rc::Rc;
+
+pub type BlockId = usize;
+
+#[derive(Debug, Clone)]
+pub enum Const {
+    Void,
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Exp,
+    Mod,
+    BitAnd,
+    BitOr,
+    Xor,
+    Shl,
+    Shr,
+    Eq,
+    Neq,
+    Less,
+    Leq,
+    Greater,
+    Geq,
+}
+
+// a stack machine instruction, every instruction pops its operands and
+// pushes its result
+#[derive(Debug, Clone)]
+pub enum Instr {
+    Const(Const),
+    LoadLocal(usize),
+    StoreLocal(usize),
+    LoadGlobal(usize),
+    StoreGlobal(usize),
+    Dup,
+    // duplicates the top two values, keeping their order
+    Dup2,
+    Pop,
+    Unary(UnOp),
+    Binary(BinOp),
+    // pops n elements, the last one pushed becomes the last element
+    MakeArray(usize),
+    // array, index -> element
+    Index,
+    // array, index, value -> value
+    StoreIndex,
+    // array, [lo], [hi] -> new array, the flags say which bounds were pushed
+    Slice(bool, bool),
+    // pops one value per field of the struct, the slots say which field
+    // each value goes into in the order they were pushed
+    MakeStruct(usize, Vec<usize>),
+    // struct -> field value, fields are looked up by name since the struct
+    // isn't always known before running
+    GetField(String),
+    // struct, value -> value
+    SetField(String),
+    // like MakeStruct for a variant of an enum, the variant is its index
+    MakeVariant(usize, usize, Vec<usize>),
+    // value of the enum -> the index of its variant
+    Tag(usize),
+    // enum value -> the value in a slot of its payload
+    VariantField(usize),
+    // loads or stores a variable captured by the running closure
+    LoadEnv(usize),
+    StoreEnv(usize),
+    // creates a closure of a function, sharing the variables it captures
+    // with the function creating it
+    MakeClosure(usize, Vec<Capture>),
+    // callee, n arguments -> result
+    Call(usize),
+}
+
+// where a closure finds a variable it captures when it's created
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    Local(usize),
+    Env(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Jump(BlockId),
+    // pops the condition
+    Branch(BlockId, BlockId),
+    // pops the return value
+    Return,
+    // stops with a runtime error
+    Fail(String),
+    // pops n arguments and the callee and returns what calling it returns,
+    // the callee runs in place of the function
+    TailCall(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub instrs: Vec<Instr>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    // params occupy the first locals
+    pub params: usize,
+    pub locals: Vec<String>,
+    // the variables of enclosing functions it uses, in environment order
+    pub captures: Vec<String>,
+    pub blocks: Vec<Block>,
+    // the line table, where in the source each instruction is by its block
+    // and index in it, for those in an expression with a site. A tail
+    // call's, a branch's or a failing terminator's index is one past the
+    // block's instructions
+    pub lines: HashMap<(BlockId, usize), Span>,
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub globals: Vec<String>,
+    pub structs: Vec<Rc<StructLayout>>,
+    pub enums: Vec<Rc<EnumDef>>,
+    pub main: usize,
+}
+
+// The code of one top level statement, lowered on its own so it can be kept
+// while the other statements change. Its functions, globals and layouts are
+// numbered within it until it's linked.
+#[derive(Debug)]
+pub struct Unit {
+    // the statement's top level code, which returns its value
+    pub main: Function,
+    pub functions: Vec<Function>,
+    pub globals: Vec<String>,
+    // the layouts of generic structs specialised in it, which come after the
+    // program's own
+    pub structs: Vec<Rc<StructLayout>>,
+    // for each of its functions, the generic function it's a copy of by its
+    // index among the ones the statement was lowered with, None for the
+    // statement's own. The spans of their lines are from where the
+    // statement they come from starts if it was placed
+    pub origins: Vec<Option<usize>>,
+}
+
+impl Terminator {
+    // the blocks control can go to next
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(then, other) => vec![*then, *other],
+            Terminator::Return | Terminator::Fail(_) | Terminator::TailCall(_) => vec![],
+        }
+    }
+}
+
+impl Function {
+    // where the instruction at the index came from, if it can fail
+    pub fn site(&self, block: BlockId, index: usize) -> Option<Span> {
+        self.lines.get(&(block, index)).copied()
+    }
+
+    // The immediate dominator of every block, None for the entry block and
+    // for blocks that can't be reached. Found by the iterative algorithm of
+    // Cooper, Harvey and Kennedy over the blocks in reverse postorder.
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let order = self.reverse_postorder();
+        let mut position = vec![usize::MAX; self.blocks.len()];
+        for (i, &block) in order.iter().enumerate() {
+            position[block] = i;
+        }
+        let mut preds = vec![vec![]; self.blocks.len()];
+        for &block in &order {
+            for succ in self.blocks[block].terminator.successors() {
+                preds[succ].push(block);
+            }
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &order[1..] {
+                let mut done = preds[block].iter().filter(|&&pred| idom[pred].is_some());
+                let mut new = *done.next().unwrap();
+                for &pred in done {
+                    new = intersect(&idom, &position, pred, new);
+                }
+                if idom[block] != Some(new) {
+                    idom[block] = Some(new);
+                    changed = true;
+                }
+            }
+        }
+        idom[0] = None;
+        idom
+    }
+
+    fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = vec![];
+        // a block with the successors it has left to visit
+        let mut stack = vec![(0, self.blocks[0].terminator.successors())];
+        visited[0] = true;
+        while let Some((block, succs)) = stack.last_mut() {
+            match succs.pop() {
+                Some(succ) if !visited[succ] => {
+                    visited[succ] = true;
+                    stack.push((succ, self.blocks[succ].terminator.successors()));
+                }
+                Some(_) => {}
+                None => {
+                    order.push(*block);
+                    stack.pop();
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+}
+
+// the closest block dominating both, walking up from whichever is later in
+// reverse postorder
+fn intersect(idom: &[Option<BlockId>], position: &[usize], a: BlockId, b: BlockId) -> BlockId {
+    let (mut a, mut b) = (a, b);
+    while a != b {
+        while position[a] > position[b] {
+            a = idom[a].unwrap();
+        }
+        while position[b] > position[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for layout in &self.structs {
+            writeln!(
+                f,
+                "struct {} (size {}, align {}):",
+                Type::Struct(layout.name.clone(), layout.args.clone()),
+                layout.size,
+                layout.align
+            )?;
+            for field in &layout.fields {
+                writeln!(
+                    f,
+                    "  {}: {} (slot {}, offset {})",
+                    field.name, field.ty, field.slot, field.offset
+                )?;
+            }
+        }
+        for def in &self.enums {
+            writeln!(f, "enum {}:", def.name)?;
+            for (tag, (variant, fields)) in def.variants.iter().enumerate() {
+                write!(f, "  {}", variant)?;
+                match fields {
+                    Fields::Unit => {}
+                    Fields::Tuple(types) => {
+                        let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+                        write!(f, "({})", types.join(", "))?;
+                    }
+                    Fields::Struct(fields) => {
+                        let fields: Vec<String> = fields
+                            .iter()
+                            .map(|(f, t)| format!("{}: {}", f, t))
+                            .collect();
+                        write!(f, " {{ {} }}", fields.join(", "))?;
+                    }
+                }
+                writeln!(f, " (tag {})", tag)?;
+            }
+        }
+        for func in &self.functions {
+            write!(
+                f,
+                "fn {}({})",
+                func.name,
+                func.locals[..func.params].join(", ")
+            )?;
+            if !func.captures.is_empty() {
+                write!(f, " captures {}", func.captures.join(", "))?;
+            }
+            writeln!(f, ":")?;
+            for (id, block) in func.blocks.iter().enumerate() {
+                writeln!(f, "  b{}:", id)?;
+                for instr in &block.instrs {
+                    writeln!(f, "    {:?}", instr)?;
+                }
+                writeln!(f, "    {:?}", block.terminator)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// where a name used in a function lives
+enum Var {
+    Local(usize),
+    Env(usize),
+    Global,
+}
+
+struct FunctionBuilder {
+    name: String,
+    params: usize,
+    locals: Vec<String>,
+    captures: Vec<String>,
+    blocks: Vec<Block>,
+    current: BlockId,
+    // every variable at the top level is a global
+    top_level: bool,
+    // the slots of names bound by the patterns of the match arms being
+    // lowered, innermost last, they shadow every other variable
+    scopes: Vec<Vec<(String, usize)>>,
+    // the types of the type parameters in a specialised generic function
+    subst: HashMap<String, Type>,
+    // the calls marked #[tail], by their block and index in it, with what
+    // the error names them
+    tail_calls: Vec<(BlockId, usize, String)>,
+    lines: HashMap<(BlockId, usize), Span>,
+    // the innermost expression being lowered that has a site, which is
+    // where the instructions and branches it emits are
+    site: Site,
+}
+
+impl FunctionBuilder {
+    fn new(name: String, params: Vec<String>) -> Self {
+        Self {
+            name,
+            params: params.len(),
+            locals: params,
+            captures: vec![],
+            blocks: vec![Block {
+                instrs: vec![],
+                terminator: Terminator::Return,
+            }],
+            current: 0,
+            top_level: true,
+            scopes: vec![],
+            subst: HashMap::new(),
+            tail_calls: vec![],
+            lines: HashMap::new(),
+            site: Site::default(),
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(Block {
+            instrs: vec![],
+            terminator: Terminator::Return,
+        });
+        self.blocks.len() - 1
+    }
+
+    // emits an instruction at the site of the expression being lowered
+    fn emit(&mut self, instr: Instr) {
+        let site = self.site;
+        self.emit_at(instr, &site);
+    }
+
+    // emits an instruction that can fail, with where it came from
+    fn emit_at(&mut self, instr: Instr, site: &Site) {
+        self.blocks[self.current].instrs.push(instr);
+        let index = self.blocks[self.current].instrs.len() - 1;
+        self.mark(index, site);
+    }
+
+    fn mark(&mut self, index: usize, site: &Site) {
+        if let Site(Some(span)) = site {
+            self.lines.insert((self.current, index), *span);
+        }
+    }
+
+    // ends the current block, code emitted afterwards goes to `next`
+    fn terminate(&mut self, terminator: Terminator, next: BlockId) {
+        // a branch fails if its condition isn't a bool
+        if let Terminator::Branch(..) = terminator {
+            let site = self.site;
+            self.mark(self.blocks[self.current].instrs.len(), &site);
+        }
+        self.blocks[self.current].terminator = terminator;
+        self.current = next;
+    }
+
+    // a slot for a value that only lives as long as some expression, its
+    // name can't clash with a variable
+    fn temp(&mut self, name: String) -> usize {
+        self.locals.push(name);
+        self.locals.len() - 1
+    }
+
+    fn resolve(&self, name: &str) -> Var {
+        let scoped = self.scopes.iter().rev().flatten();
+        if let Some((_, slot)) = scoped.into_iter().find(|(n, _)| n == name) {
+            return Var::Local(*slot);
+        }
+        if !self.top_level {
+            if let Some(slot) = self.locals.iter().position(|l| l == name) {
+                return Var::Local(slot);
+            }
+        }
+        match self.captures.iter().position(|c| c == name) {
+            Some(i) => Var::Env(i),
+            None => Var::Global,
+        }
+    }
+
+    // Turns each call whose value the function returns right away into a
+    // tail call, which is one ending a block that returns or that jumps to
+    // one through empty blocks. Not done for main, link turns its returns
+    // into jumps to the next statement
+    fn mark_tail_calls(&mut self) {
+        for block in 0..self.blocks.len() {
+            if !matches!(self.blocks[block].instrs.last(), Some(Instr::Call(_)))
+                || !self.returns(block)
+            {
+                continue;
+            }
+            if let Some(Instr::Call(argc)) = self.blocks[block].instrs.pop() {
+                self.blocks[block].terminator = Terminator::TailCall(argc);
+            }
+        }
+    }
+
+    fn returns(&self, mut block: BlockId) -> bool {
+        // empty blocks could jump in a circle
+        for _ in 0..self.blocks.len() {
+            match self.blocks[block].terminator {
+                Terminator::Return => return true,
+                Terminator::Jump(target) if self.blocks[target].instrs.is_empty() => block = target,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    // every call marked #[tail] has to have become a tail call
+    fn check_tail_calls(&self) -> Result<(), Error> {
+        for (block, index, call) in &self.tail_calls {
+            let block = &self.blocks[*block];
+            if !matches!(block.terminator, Terminator::TailCall(_)) || block.instrs.len() != *index
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("ERROR: {} is not in tail position", call),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Function {
+        Function {
+            name: self.name,
+            params: self.params,
+            locals: self.locals,
+            captures: self.captures,
+            blocks: self.blocks,
+            lines: self.lines,
+        }
+    }
+}
+
+// Generic functions are lowered once with their type parameters erased, and
+// every call of a generic function declared at the top level whose type
+// arguments are known calls a copy of it specialised for them instead. Literals
+// of generic structs get a layout for their type arguments the same way.
+pub struct Lowerer<'a> {
+    functions: Vec<Function>,
+    globals: Vec<String>,
+    structs: Vec<Rc<StructLayout>>,
+    enums: Vec<Rc<EnumDef>>,
+    struct_defs: Vec<StructDef>,
+    instances: &'a Instances,
+    // what the custom operators call
+    operators: &'a OperatorTable,
+    generic_fns: HashMap<&'a str, (usize, &'a FnSig, &'a SExpr)>,
+    // the specialised functions and layouts made so far, by names like
+    // id<int>
+    specialized_fns: HashMap<String, usize>,
+    specialized_structs: HashMap<String, usize>,
+    // the generic function whose copy is being lowered, and that of every
+    // function lowered so far
+    origin: Option<usize>,
+    origins: Vec<Option<usize>>,
+}
+
+impl<'a> Lowerer<'a> {
+    pub fn lower(
+        program: &'a SExpr,
+        instances: &'a Instances,
+        operators: &'a OperatorTable,
+    ) -> Result<Module, Error> {
+        let stmts = match program {
+            SExpr::Block(stmts) => &stmts[..],
+            _ => &[],
+        };
+        let generics: Vec<&SExpr> = generic_fns(stmts).into_iter().map(|i| &stmts[i]).collect();
+        let mut lowerer = Self::new(program, &generics, instances, operators)?;
+
+        let mut main = FunctionBuilder::new(String::from("main"), vec![]);
+        lowerer.lower_expr(&mut main, program)?;
+        main.terminate(Terminator::Return, 0);
+        main.check_tail_calls()?;
+        lowerer.functions.push(main.finish());
+
+        Ok(Module {
+            main: lowerer.functions.len() - 1,
+            functions: lowerer.functions,
+            globals: lowerer.globals,
+            structs: lowerer.structs,
+            enums: lowerer.enums,
+        })
+    }
+
+    // lowers a top level statement of a program whose type declarations are
+    // `types`, `generics` are the statements declaring its generic functions
+    // and the instances have to cover them as well as the statement
+    pub fn lower_statement(
+        stmt: &'a SExpr,
+        types: &'a SExpr,
+        generics: &[&'a SExpr],
+        instances: &'a Instances,
+        operators: &'a OperatorTable,
+    ) -> Result<Unit, Error> {
+        let mut lowerer = Self::new(types, generics, instances, operators)?;
+        let base = lowerer.structs.len();
+        let mut main = FunctionBuilder::new(String::from("main"), vec![]);
+        lowerer.lower_expr(&mut main, stmt)?;
+        main.terminate(Terminator::Return, 0);
+        main.check_tail_calls()?;
+
+        Ok(Unit {
+            main: main.finish(),
+            functions: lowerer.functions,
+            globals: lowerer.globals,
+            structs: lowerer.structs.split_off(base),
+            origins: lowerer.origins,
+        })
+    }
+
+    fn new(
+        types: &SExpr,
+        generics: &[&'a SExpr],
+        instances: &'a Instances,
+        operators: &'a OperatorTable,
+    ) -> Result<Self, Error> {
+        let defs = typeck::collect_types(types)?;
+        let structs = compute_layouts(&defs.structs)?;
+        let mut generic_fns = HashMap::new();
+        for (i, stmt) in generics.iter().enumerate() {
+            if let SExpr::Fn(Some(name), sig, body) = stmt {
+                generic_fns.insert(name.as_str(), (i, sig, &**body));
+            }
+        }
+        Ok(Self {
+            functions: vec![],
+            globals: vec![],
+            structs: structs.into_iter().map(Rc::new).collect(),
+            enums: defs.enums,
+            struct_defs: defs.structs,
+            instances,
+            operators,
+            generic_fns,
+            specialized_fns: HashMap::new(),
+            specialized_structs: HashMap::new(),
+            origin: None,
+            origins: vec![],
+        })
+    }
+
+    fn global(&mut self, name: &str) -> usize {
+        match self.globals.iter().position(|g| g == name) {
+            Some(slot) => slot,
+            None => {
+                self.globals.push(name.to_string());
+                self.globals.len() - 1
+            }
+        }
+    }
+
+    fn load(&mut self, func: &mut FunctionBuilder, name: &str) {
+        match func.resolve(name) {
+            Var::Local(slot) => func.emit(Instr::LoadLocal(slot)),
+            Var::Env(i) => func.emit(Instr::LoadEnv(i)),
+            Var::Global => {
+                let slot = self.global(name);
+                func.emit(Instr::LoadGlobal(slot));
+            }
+        }
+    }
+
+    fn store(&mut self, func: &mut FunctionBuilder, name: &str) {
+        match func.resolve(name) {
+            Var::Local(slot) => func.emit(Instr::StoreLocal(slot)),
+            Var::Env(i) => func.emit(Instr::StoreEnv(i)),
+            Var::Global => {
+                let slot = self.global(name);
+                func.emit(Instr::StoreGlobal(slot));
+            }
+        }
+    }
+
+    // emits code leaving exactly one value on the stack
+    fn lower_expr(&mut self, func: &mut FunctionBuilder, expr: &SExpr) -> Result<(), Error> {
+        let outer = func.site;
+        if let Some(site) = own_site(expr) {
+            func.site = *site;
+        }
+        let lowered = self.lower_node(func, expr);
+        func.site = outer;
+        lowered
+    }
+
+    fn lower_node(&mut self, func: &mut FunctionBuilder, expr: &SExpr) -> Result<(), Error> {
+        match expr {
+            SExpr::Atom(token) => match token {
+                Token::INT(n) => func.emit(Instr::Const(Const::Int(*n))),
+                Token::FLOAT(n) => func.emit(Instr::Const(Const::Float(*n))),
+                Token::STR(s) => func.emit(Instr::Const(Const::Str(s.clone()))),
+                Token::BOOL(b) => func.emit(Instr::Const(Const::Bool(*b))),
+                Token::VOID => func.emit(Instr::Const(Const::Void)),
+                Token::IDENT(name) => self.load(func, name),
+                _ => return Err(unsupported(expr)),
+            },
+
+            SExpr::Fn(name, sig, body) => {
+                self.lower_fn(func, name.as_deref(), sig, body)?;
+                if let Some(name) = name {
+                    self.store(func, name);
+                    func.emit(Instr::Const(Const::Void));
+                }
+            }
+
+            SExpr::Call(callee, args, site) => {
+                match self.specialize_call(func, expr, callee)? {
+                    Some(id) => func.emit(Instr::MakeClosure(id, vec![])),
+                    None => self.lower_expr(func, callee)?,
+                }
+                self.lower_call(func, args, site)?;
+            }
+
+            // a custom operator calls its function, the global even where a
+            // local has the same name
+            SExpr::Cons(op @ Token::OP(_), cons, site) => {
+                let function = match self.operators.function(op, cons.len()) {
+                    Some(function) => function.to_string(),
+                    None => return Err(unsupported(expr)),
+                };
+                let callee = SExpr::Atom(Token::IDENT(function.clone()));
+                match self.specialize_call(func, expr, &callee)? {
+                    Some(id) => func.emit(Instr::MakeClosure(id, vec![])),
+                    None => {
+                        let slot = self.global(&function);
+                        func.emit(Instr::LoadGlobal(slot));
+                    }
+                }
+                self.lower_call(func, cons, site)?;
+            }
+
+            // whether the call ended up in tail position is only known once
+            // the whole function is lowered
+            SExpr::Cons(Token::TAIL, cons, _) => {
+                let call = match &cons[0] {
+                    SExpr::Call(callee, ..) => match &**callee {
+                        SExpr::Atom(Token::IDENT(name)) => format!("the call to {}", name),
+                        _ => "the call".to_string(),
+                    },
+                    _ => return Err(unsupported(expr)),
+                };
+                self.lower_expr(func, &cons[0])?;
+                let index = func.blocks[func.current].instrs.len() - 1;
+                func.tail_calls.push((func.current, index, call));
+            }
+
+            SExpr::Cons(Token::ASSIGN, cons, _) => match &cons[0] {
+                SExpr::Atom(Token::IDENT(name)) => {
+                    self.lower_expr(func, &cons[1])?;
+                    func.emit(Instr::Dup);
+                    self.store(func, name);
+                }
+                SExpr::Index(array, index, site) => {
+                    self.lower_expr(func, array)?;
+                    self.lower_expr(func, index)?;
+                    self.lower_expr(func, &cons[1])?;
+                    func.emit_at(Instr::StoreIndex, site);
+                }
+                SExpr::Field(target, field, site) => {
+                    self.lower_expr(func, target)?;
+                    self.lower_expr(func, &cons[1])?;
+                    func.emit_at(Instr::SetField(field.clone()), site);
+                }
+                _ => return Err(unsupported(expr)),
+            },
+
+            // the rhs gets its own block so it only runs when the lhs doesn't
+            // already decide the result
+            SExpr::Cons(op @ Token::AND, cons, _) | SExpr::Cons(op @ Token::OR, cons, _) => {
+                let rhs_block = func.new_block();
+                let short_block = func.new_block();
+                let join = func.new_block();
+
+                self.lower_expr(func, &cons[0])?;
+                let (branch, short_value) = match op {
+                    Token::AND => (Terminator::Branch(rhs_block, short_block), false),
+                    _ => (Terminator::Branch(short_block, rhs_block), true),
+                };
+                func.terminate(branch, rhs_block);
+
+                self.lower_expr(func, &cons[1])?;
+                func.terminate(Terminator::Jump(join), short_block);
+
+                func.emit(Instr::Const(Const::Bool(short_value)));
+                func.terminate(Terminator::Jump(join), join);
+            }
+
+            SExpr::Cons(op, cons, site) if cons.len() == 1 => {
+                let op = match op {
+                    Token::SUB => UnOp::Neg,
+                    Token::ADD => UnOp::Plus,
+                    Token::NOT => UnOp::Not,
+                    Token::BITNOT => UnOp::BitNot,
+                    _ => return Err(unsupported(expr)),
+                };
+                self.lower_expr(func, &cons[0])?;
+                func.emit_at(Instr::Unary(op), site);
+            }
+
+            // the target of a compound assignment is only evaluated once
+            SExpr::Cons(op, cons, site) if op.compound_op().is_some() => {
+                let bin_op = match op.compound_op().as_ref().and_then(bin_op) {
+                    Some(bin_op) => bin_op,
+                    None => return Err(unsupported(expr)),
+                };
+                match &cons[0] {
+                    SExpr::Atom(Token::IDENT(name)) => {
+                        self.load(func, name);
+                        self.lower_expr(func, &cons[1])?;
+                        func.emit_at(Instr::Binary(bin_op), site);
+                        func.emit(Instr::Dup);
+                        self.store(func, name);
+                    }
+                    SExpr::Index(array, index, target) => {
+                        self.lower_expr(func, array)?;
+                        self.lower_expr(func, index)?;
+                        func.emit(Instr::Dup2);
+                        func.emit_at(Instr::Index, target);
+                        self.lower_expr(func, &cons[1])?;
+                        func.emit_at(Instr::Binary(bin_op), site);
+                        func.emit_at(Instr::StoreIndex, target);
+                    }
+                    SExpr::Field(value, field, target) => {
+                        self.lower_expr(func, value)?;
+                        func.emit(Instr::Dup);
+                        func.emit_at(Instr::GetField(field.clone()), target);
+                        self.lower_expr(func, &cons[1])?;
+                        func.emit_at(Instr::Binary(bin_op), site);
+                        func.emit_at(Instr::SetField(field.clone()), target);
+                    }
+                    _ => return Err(unsupported(expr)),
+                }
+            }
+
+            SExpr::Cons(op, cons, site) => {
+                let op = match bin_op(op) {
+                    Some(op) => op,
+                    None => return Err(unsupported(expr)),
+                };
+                self.lower_expr(func, &cons[0])?;
+                self.lower_expr(func, &cons[1])?;
+                func.emit_at(Instr::Binary(op), site);
+            }
+
+            SExpr::Block(stmts) => {
+                if stmts.is_empty() {
+                    func.emit(Instr::Const(Const::Void));
+                }
+                for (i, stmt) in stmts.iter().enumerate() {
+                    self.lower_expr(func, stmt)?;
+                    if i != stmts.len() - 1 {
+                        func.emit(Instr::Pop);
+                    }
+                }
+            }
+
+            SExpr::If(cond, then, els, _) => {
+                let then_block = func.new_block();
+                let else_block = func.new_block();
+                let join = func.new_block();
+
+                self.lower_expr(func, cond)?;
+                func.terminate(Terminator::Branch(then_block, else_block), then_block);
+
+                self.lower_expr(func, then)?;
+                func.terminate(Terminator::Jump(join), else_block);
+
+                match els {
+                    Some(els) => self.lower_expr(func, els)?,
+                    None => func.emit(Instr::Const(Const::Void)),
+                }
+                func.terminate(Terminator::Jump(join), join);
+            }
+
+            SExpr::While(cond, body, _) => {
+                let cond_block = func.new_block();
+                let body_block = func.new_block();
+                let exit = func.new_block();
+
+                func.terminate(Terminator::Jump(cond_block), cond_block);
+                self.lower_expr(func, cond)?;
+                func.terminate(Terminator::Branch(body_block, exit), body_block);
+
+                self.lower_expr(func, body)?;
+                func.emit(Instr::Pop);
+                func.terminate(Terminator::Jump(cond_block), exit);
+
+                func.emit(Instr::Const(Const::Void));
+            }
+
+            SExpr::Array(elems) => {
+                for elem in elems {
+                    self.lower_expr(func, elem)?;
+                }
+                func.emit(Instr::MakeArray(elems.len()));
+            }
+
+            SExpr::Index(array, index, site) => {
+                self.lower_expr(func, array)?;
+                self.lower_expr(func, index)?;
+                func.emit_at(Instr::Index, site);
+            }
+
+            SExpr::Slice(array, lo, hi, site) => {
+                self.lower_expr(func, array)?;
+                if let Some(lo) = lo {
+                    self.lower_expr(func, lo)?;
+                }
+                if let Some(hi) = hi {
+                    self.lower_expr(func, hi)?;
+                }
+                func.emit_at(Instr::Slice(lo.is_some(), hi.is_some()), site);
+            }
+
+            SExpr::StructDef(..) => func.emit(Instr::Const(Const::Void)),
+
+            SExpr::StructLit(name, values) => {
+                let id = match self.structs.iter().position(|s| &s.name == name) {
+                    Some(id) => self.specialize_struct(func, expr, id)?,
+                    None => return Err(unsupported(expr)),
+                };
+                let layout = self.structs[id].clone();
+                if values.len() != layout.fields.len() {
+                    return Err(unsupported(expr));
+                }
+
+                let mut slots = vec![];
+                for (field, value) in values {
+                    match layout.field(field) {
+                        Some(field) => slots.push(field.slot),
+                        None => return Err(unsupported(expr)),
+                    }
+                    self.lower_expr(func, value)?;
+                }
+                func.emit(Instr::MakeStruct(id, slots));
+            }
+
+            SExpr::Field(target, field, site) => {
+                self.lower_expr(func, target)?;
+                func.emit_at(Instr::GetField(field.clone()), site);
+            }
+
+            SExpr::EnumDef(..) => func.emit(Instr::Const(Const::Void)),
+
+            SExpr::Variant(name, variant, values, _) => {
+                let id = match self.enums.iter().position(|def| &def.name == name) {
+                    Some(id) => id,
+                    None => return Err(unsupported(expr)),
+                };
+                let def = self.enums[id].clone();
+                let index = match def.variant(variant) {
+                    Some(index) => index,
+                    None => return Err(unsupported(expr)),
+                };
+
+                let mut slots = vec![];
+                match (&def.variants[index].1, values) {
+                    (Fields::Unit, Fields::Unit) => {}
+                    (Fields::Tuple(types), Fields::Tuple(values))
+                        if types.len() == values.len() =>
+                    {
+                        for (slot, value) in values.iter().enumerate() {
+                            self.lower_expr(func, value)?;
+                            slots.push(slot);
+                        }
+                    }
+                    (Fields::Struct(fields), Fields::Struct(values))
+                        if fields.len() == values.len() =>
+                    {
+                        for (field, value) in values {
+                            match fields.iter().position(|(f, _)| f == field) {
+                                Some(slot) => slots.push(slot),
+                                None => return Err(unsupported(expr)),
+                            }
+                            self.lower_expr(func, value)?;
+                        }
+                    }
+                    _ => return Err(unsupported(expr)),
+                }
+                func.emit(Instr::MakeVariant(id, index, slots));
+            }
+
+            SExpr::Match(value, arms, site) => self.lower_match(func, value, arms, site)?,
+
+            SExpr::Mod(..) | SExpr::Import(..) | SExpr::Pub(_) => return Err(unsupported(expr)),
+        }
+
+        Ok(())
+    }
+
+    // the value is kept in a temporary that the decision tree tests, the
+    // arms' bodies get one block each that every leaf for them jumps to
+    fn lower_match(
+        &mut self,
+        func: &mut FunctionBuilder,
+        value: &SExpr,
+        arms: &[(Pattern, SExpr)],
+        site: &Site,
+    ) -> Result<(), Error> {
+        self.lower_expr(func, value)?;
+        let scrutinee = func.temp(String::from("%match"));
+        func.emit(Instr::StoreLocal(scrutinee));
+
+        let mut pats = vec![];
+        let mut arm_scopes = vec![];
+        for (pattern, _) in arms {
+            let pat = match patterns::normalize(pattern, &self.enums) {
+                Some(pat) => pat,
+                None => return Err(unsupported(value)),
+            };
+            let mut names = vec![];
+            bound_names(&pat, &mut names);
+            let scope: Vec<(String, usize)> = names
+                .into_iter()
+                .map(|name| {
+                    let slot = func.temp(format!("%{}", name));
+                    (name, slot)
+                })
+                .collect();
+            arm_scopes.push(scope);
+            pats.push(pat);
+        }
+
+        let arm_blocks: Vec<BlockId> = arms.iter().map(|_| func.new_block()).collect();
+        let join = func.new_block();
+
+        let decision = patterns::compile(pats, &self.enums);
+        self.lower_decision(func, &decision, scrutinee, &arm_blocks, &arm_scopes, site);
+
+        for (i, (_, arm)) in arms.iter().enumerate() {
+            func.current = arm_blocks[i];
+            func.scopes.push(arm_scopes[i].clone());
+            let result = self.lower_expr(func, arm);
+            func.scopes.pop();
+            result?;
+            func.terminate(Terminator::Jump(join), join);
+        }
+        func.current = join;
+        if arms.is_empty() {
+            func.emit(Instr::Const(Const::Void));
+        }
+        Ok(())
+    }
+
+    // ends the current block, and every block it creates, by jumping to an
+    // arm or failing
+    fn lower_decision(
+        &mut self,
+        func: &mut FunctionBuilder,
+        decision: &Decision,
+        scrutinee: usize,
+        arm_blocks: &[BlockId],
+        arm_scopes: &[Vec<(String, usize)>],
+        site: &Site,
+    ) {
+        let load = |func: &mut FunctionBuilder, occurrence: &Occurrence| {
+            func.emit(Instr::LoadLocal(scrutinee));
+            for slot in occurrence {
+                func.emit_at(Instr::VariantField(*slot), site);
+            }
+        };
+
+        match decision {
+            Decision::Leaf(arm, bindings) => {
+                for (name, occurrence) in bindings {
+                    let (_, slot) = arm_scopes[*arm].iter().find(|(n, _)| n == name).unwrap();
+                    load(func, occurrence);
+                    func.emit(Instr::StoreLocal(*slot));
+                }
+                func.terminate(Terminator::Jump(arm_blocks[*arm]), func.current);
+            }
+            Decision::Switch(occurrence, cases, fallback) => {
+                for (i, (ctor, decision)) in cases.iter().enumerate() {
+                    // the last constructor of a complete set needs no test
+                    if i == cases.len() - 1 && fallback.is_none() {
+                        self.lower_decision(
+                            func, decision, scrutinee, arm_blocks, arm_scopes, site,
+                        );
+                        return;
+                    }
+
+                    let matched = func.new_block();
+                    let next = func.new_block();
+                    load(func, occurrence);
+                    match ctor {
+                        Ctor::Variant(id, index) => {
+                            func.emit_at(Instr::Tag(*id), site);
+                            func.emit(Instr::Const(Const::Int(*index as i32)));
+                        }
+                        Ctor::Bool(b) => func.emit(Instr::Const(Const::Bool(*b))),
+                        Ctor::Int(n) => func.emit(Instr::Const(Const::Int(*n))),
+                        Ctor::Float(n) => func.emit(Instr::Const(Const::Float(*n))),
+                        Ctor::Str(s) => func.emit(Instr::Const(Const::Str(s.clone()))),
+                    }
+                    func.emit(Instr::Binary(BinOp::Eq));
+                    func.terminate(Terminator::Branch(matched, next), matched);
+
+                    self.lower_decision(func, decision, scrutinee, arm_blocks, arm_scopes, site);
+                    func.current = next;
+                }
+                match fallback {
+                    Some(fallback) => {
+                        self.lower_decision(func, fallback, scrutinee, arm_blocks, arm_scopes, site)
+                    }
+                    None => self.lower_decision(
+                        func,
+                        &Decision::Fail,
+                        scrutinee,
+                        arm_blocks,
+                        arm_scopes,
+                        site,
+                    ),
+                }
+            }
+            Decision::Fail => {
+                let message = String::from("no match arm matches the value");
+                func.mark(func.blocks[func.current].instrs.len(), site);
+                func.terminate(Terminator::Fail(message), func.current);
+            }
+        }
+    }
+
+    // closure conversion, the function gets the variables it uses from
+    // enclosing functions through its environment and the closure is left
+    // on the stack
+    fn lower_fn(
+        &mut self,
+        func: &mut FunctionBuilder,
+        name: Option<&str>,
+        sig: &FnSig,
+        body: &SExpr,
+    ) -> Result<(), Error> {
+        let params = sig.param_names();
+        let mut captures = vec![];
+        let mut sources = vec![];
+        let outer = |name: &str| !matches!(func.resolve(name), Var::Global);
+        for free in free_names(&params, body, &outer) {
+            let source = match func.resolve(&free) {
+                Var::Local(slot) => Capture::Local(slot),
+                Var::Env(i) => Capture::Env(i),
+                Var::Global => continue,
+            };
+            captures.push(free);
+            sources.push(source);
+        }
+
+        // its own type parameters shadow the enclosing function's
+        let mut subst = func.subst.clone();
+        subst.retain(|var, _| !sig.generics.contains(var));
+
+        let name = name.unwrap_or("<lambda>").to_string();
+        let inner = self.lower_fn_body(name, sig, body, captures, subst)?;
+        self.functions.push(inner);
+        self.origins.push(self.origin);
+        func.emit(Instr::MakeClosure(self.functions.len() - 1, sources));
+        Ok(())
+    }
+
+    fn lower_fn_body(
+        &mut self,
+        name: String,
+        sig: &FnSig,
+        body: &SExpr,
+        captures: Vec<String>,
+        subst: HashMap<String, Type>,
+    ) -> Result<Function, Error> {
+        // names assigned anywhere in the body are locals unless they're
+        // captured, the rest are captured or globals
+        let params = sig.param_names();
+        let locals = local_names(&params, body, &|name| captures.iter().any(|c| c == name));
+
+        let mut inner = FunctionBuilder::new(name, params);
+        inner.locals = locals;
+        inner.captures = captures;
+        inner.top_level = false;
+        inner.subst = subst;
+
+        self.lower_expr(&mut inner, body)?;
+        inner.terminate(Terminator::Return, 0);
+        inner.mark_tail_calls();
+        inner.check_tail_calls()?;
+        Ok(inner.finish())
+    }
+
+    // the type arguments inferred for a generic call or literal, if they are
+    // all known in the function being lowered
+    fn type_args(&self, func: &FunctionBuilder, expr: &SExpr) -> Option<Vec<Type>> {
+        let args: Vec<Type> = self
+            .instances
+            .get(&(expr as *const SExpr))?
+            .iter()
+            .map(|t| t.substitute(&func.subst))
+            .collect();
+        args.iter().all(Type::is_concrete).then_some(args)
+    }
+
+    // the arguments and the call of a function that's already on the stack
+    fn lower_call(
+        &mut self,
+        func: &mut FunctionBuilder,
+        args: &[SExpr],
+        site: &Site,
+    ) -> Result<(), Error> {
+        for arg in args {
+            self.lower_expr(func, arg)?;
+        }
+        func.emit_at(Instr::Call(args.len()), site);
+        Ok(())
+    }
+
+    // the specialised copy of the generic function a call calls, if it calls
+    // one declared at the top level
+    fn specialize_call(
+        &mut self,
+        func: &FunctionBuilder,
+        call: &SExpr,
+        callee: &SExpr,
+    ) -> Result<Option<usize>, Error> {
+        let name = match callee {
+            SExpr::Atom(Token::IDENT(name)) => name,
+            _ => return Ok(None),
+        };
+        let (origin, sig, body) = match self.generic_fns.get(name.as_str()) {
+            Some(decl) if matches!(func.resolve(name), Var::Global) => *decl,
+            _ => return Ok(None),
+        };
+        let args = match self.type_args(func, call) {
+            Some(args) => args,
+            None => return Ok(None),
+        };
+
+        let key = Type::Struct(name.clone(), args.clone()).to_string();
+        if let Some(id) = self.specialized_fns.get(&key) {
+            return Ok(Some(*id));
+        }
+        // the id is taken before lowering the body so it can call itself
+        self.functions.push(Function {
+            name: key.clone(),
+            params: 0,
+            locals: vec![],
+            captures: vec![],
+            blocks: vec![],
+            lines: HashMap::new(),
+        });
+        self.origins.push(Some(origin));
+        let id = self.functions.len() - 1;
+        self.specialized_fns.insert(key.clone(), id);
+
+        let subst = sig.generics.iter().cloned().zip(args).collect();
+        let outer = self.origin.replace(origin);
+        let copy = self.lower_fn_body(key, sig, body, vec![], subst);
+        self.origin = outer;
+        self.functions[id] = copy?;
+        Ok(Some(id))
+    }
+
+    // the layout for the type arguments of a generic struct literal, or the
+    // erased one with id `erased` if they aren't known
+    fn specialize_struct(
+        &mut self,
+        func: &FunctionBuilder,
+        lit: &SExpr,
+        erased: usize,
+    ) -> Result<usize, Error> {
+        let args = match self.type_args(func, lit) {
+            Some(args) => args,
+            None => return Ok(erased),
+        };
+        let name = self.structs[erased].name.clone();
+        let key = Type::Struct(name.clone(), args.clone()).to_string();
+        if let Some(id) = self.specialized_structs.get(&key) {
+            return Ok(*id);
+        }
+
+        let layout = instantiate(&self.struct_defs, &name, args)?;
+        self.structs.push(Rc::new(layout));
+        self.specialized_structs.insert(key, self.structs.len() - 1);
+        Ok(self.structs.len() - 1)
+    }
+}
+
+// the indexes of the generic functions declared at the top level, except
+// ones declared more than once since calls can't tell which of them they call
+pub fn generic_fns(stmts: &[SExpr]) -> Vec<usize> {
+    let mut fns = vec![];
+    let mut declared = vec![];
+    for (i, stmt) in stmts.iter().enumerate() {
+        if let SExpr::Fn(Some(name), sig, _) = stmt {
+            if declared.contains(&name.as_str()) {
+                fns.retain(|&j| !matches!(&stmts[j], SExpr::Fn(Some(other), ..) if other == name));
+            } else if !sig.generics.is_empty() {
+                fns.push(i);
+            }
+            declared.push(name.as_str());
+        }
+    }
+    fns
+}
+
+// Puts the units of a program's statements together into one module, its
+// main runs their code in order and returns the last one's value like the
+// whole program lowered at once does. `types` has to be the program's type
+// declarations the units were lowered with.
+pub fn link(types: &SExpr, units: &[&Unit]) -> Result<Module, Error> {
+    link_units(types, &[], units, &|_, _| 0)
+}
+
+// Links the units of placed statements, whose sites are from where the
+// statement starts. `starts` has where each unit's statement starts and
+// `generic_starts` where each generic function they were lowered with does
+pub fn link_placed(
+    types: &SExpr,
+    units: &[&Unit],
+    starts: &[usize],
+    generic_starts: &[usize],
+) -> Result<Module, Error> {
+    link_units(types, &[], units, &|unit, origin| match origin {
+        Some(generic) => generic_starts[generic],
+        None => starts[unit],
+    })
+}
+
+// Links units after ones whose code already ran, as a REPL's earlier
+// entries did. Their functions and globals are numbered as they were when
+// they ran, so the closures they made still call the right function, but
+// only the new units' code goes in main.
+pub fn link_after(types: &SExpr, ran: &[&Unit], units: &[&Unit]) -> Result<Module, Error> {
+    link_units(types, ran, units, &|_, _| 0)
+}
+
+// `start` says how far to move the spans of a unit's function, by the
+// unit's index and the function's origin
+fn link_units(
+    types: &SExpr,
+    ran: &[&Unit],
+    units: &[&Unit],
+    start: &dyn Fn(usize, Option<usize>) -> usize,
+) -> Result<Module, Error> {
+    let moved = |lines: &HashMap<(BlockId, usize), Span>, by: usize, blocks: usize| {
+        lines
+            .iter()
+            .map(|((block, index), span)| {
+                let span = Span {
+                    start: span.start + by,
+                    end: span.end + by,
+                };
+                ((block + blocks, *index), span)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    let defs = typeck::collect_types(types)?;
+    let mut structs: Vec<Rc<StructLayout>> = compute_layouts(&defs.structs)?
+        .into_iter()
+        .map(Rc::new)
+        .collect();
+    let base = structs.len();
+    let mut functions = vec![];
+    let mut globals: Vec<String> = vec![];
+    let mut main = Function {
+        name: String::from("main"),
+        params: 0,
+        locals: vec![],
+        captures: vec![],
+        blocks: vec![],
+        lines: HashMap::new(),
+    };
+    for (i, unit) in ran.iter().chain(units).enumerate() {
+        let slots = unit
+            .globals
+            .iter()
+            .map(|name| match globals.iter().position(|g| g == name) {
+                Some(slot) => slot,
+                None => {
+                    globals.push(name.clone());
+                    globals.len() - 1
+                }
+            })
+            .collect();
+        let mut relink = Relink {
+            functions: functions.len(),
+            base,
+            structs: structs.len(),
+            globals: slots,
+            locals: 0,
+            blocks: 0,
+        };
+        for (function, origin) in unit.functions.iter().zip(&unit.origins) {
+            functions.push(Function {
+                name: function.name.clone(),
+                params: function.params,
+                locals: function.locals.clone(),
+                captures: function.captures.clone(),
+                blocks: function.blocks.iter().map(|b| relink.block(b)).collect(),
+                lines: moved(&function.lines, start(i, *origin), 0),
+            });
+        }
+
+        structs.extend(unit.structs.iter().cloned());
+        if i < ran.len() {
+            continue;
+        }
+
+        // returning from a statement that isn't the last goes on to the next
+        relink.locals = main.locals.len();
+        relink.blocks = main.blocks.len();
+        main.lines
+            .extend(moved(&unit.main.lines, start(i, None), relink.blocks));
+        let next = main.blocks.len() + unit.main.blocks.len();
+        for block in &unit.main.blocks {
+            let mut block = relink.block(block);
+            if matches!(block.terminator, Terminator::Return) && i != ran.len() + units.len() - 1 {
+                block.instrs.push(Instr::Pop);
+                block.terminator = Terminator::Jump(next);
+            }
+            main.blocks.push(block);
+        }
+        main.locals.extend(unit.main.locals.iter().cloned());
+    }
+    if units.is_empty() {
+        main.blocks.push(Block {
+            instrs: vec![Instr::Const(Const::Void)],
+            terminator: Terminator::Return,
+        });
+    }
+    functions.push(main);
+
+    Ok(Module {
+        main: functions.len() - 1,
+        functions,
+        globals,
+        structs,
+        enums: defs.enums,
+    })
+}
+
+// where a unit's numbers start in the module it's linked into
+struct Relink {
+    functions: usize,
+    // the number of the program's own layouts, which every unit shares
+    base: usize,
+    structs: usize,
+    // the module's slot for each of the unit's globals
+    globals: Vec<usize>,
+    locals: usize,
+    blocks: usize,
+}
+
+impl Relink {
+    fn block(&self, block: &Block) -> Block {
+        let instrs = block.instrs.iter().map(|instr| self.instr(instr)).collect();
+        let terminator = match &block.terminator {
+            Terminator::Jump(to) => Terminator::Jump(to + self.blocks),
+            Terminator::Branch(then, els) => {
+                Terminator::Branch(then + self.blocks, els + self.blocks)
+            }
+            terminator => terminator.clone(),
+        };
+        Block { instrs, terminator }
+    }
+
+    fn instr(&self, instr: &Instr) -> Instr {
+        match instr {
+            Instr::LoadLocal(slot) => Instr::LoadLocal(slot + self.locals),
+            Instr::StoreLocal(slot) => Instr::StoreLocal(slot + self.locals),
+            Instr::LoadGlobal(slot) => Instr::LoadGlobal(self.globals[*slot]),
+            Instr::StoreGlobal(slot) => Instr::StoreGlobal(self.globals[*slot]),
+            Instr::MakeStruct(id, slots) if *id >= self.base => {
+                Instr::MakeStruct(id - self.base + self.structs, slots.clone())
+            }
+            Instr::MakeClosure(id, captures) => {
+                let captures = captures
+                    .iter()
+                    .map(|capture| match capture {
+                        Capture::Local(slot) => Capture::Local(slot + self.locals),
+                        capture => *capture,
+                    })
+                    .collect();
+                Instr::MakeClosure(id + self.functions, captures)
+            }
+            instr => instr.clone(),
+        }
+    }
+}
+
+// the names a pattern binds, in the order they appear
+fn bound_names(pat: &patterns::Pat, names: &mut Vec<String>) {
+    match pat {
+        patterns::Pat::Wild(Some(name)) => names.push(name.clone()),
+        patterns::Pat::Wild(None) => {}
+        patterns::Pat::Ctor(_, args) => {
+            for arg in args {
+                bound_names(arg, names);
+            }
+        }
+    }
+}
+
+fn bin_op(token: &Token) -> Option<BinOp> {
+    match token {
+        Token::ADD => Some(BinOp::Add),
+        Token::SUB => Some(BinOp::Sub),
+        Token::MUL => Some(BinOp::Mul),
+        Token::DIV => Some(BinOp::Div),
+        Token::EXP => Some(BinOp::Exp),
+        Token::MOD => Some(BinOp::Mod),
+        Token::BITAND => Some(BinOp::BitAnd),
+        Token::BITOR => Some(BinOp::BitOr),
+        Token::XOR => Some(BinOp::Xor),
+        Token::SHL => Some(BinOp::Shl),
+        Token::SHR => Some(BinOp::Shr),
+        Token::EQ => Some(BinOp::Eq),
+        Token::NEQ => Some(BinOp::Neq),
+        Token::LESS => Some(BinOp::Less),
+        Token::LEQ => Some(BinOp::Leq),
+        Token::GREATER => Some(BinOp::Greater),
+        Token::GEQ => Some(BinOp::Geq),
+        _ => None,
+    }
+}
+
+fn unsupported(expr: &SExpr) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("ERROR: Can't lower {}", expr),
+    )
+}
@@ -0,0 +1,353 @@
+use std::fmt::{self, Display};
+use std::io::{Error, ErrorKind};
+
+// A JSON value. Objects keep their fields in order, which is the order they
+// are written in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, Error> {
+        let mut parser = JsonParser {
+            chars: text.chars().collect(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    // the field of an object, None for anything else
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64()
+            .filter(|n| *n >= 0.0 && n.fract() == 0.0)
+            .map(|n| n as usize)
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(elems) => Some(elems),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Self {
+        Json::Number(n)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::Str(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::Str(s)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(elems: Vec<Json>) -> Self {
+        Json::Array(elems)
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// written without any whitespace
+impl Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // integers are written without a fraction, JSON has no NaN or
+            // infinity
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::Str(s) => write_str(f, s),
+            Json::Array(elems) => {
+                write!(f, "[")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+    // how many arrays and objects the value being parsed is in
+    depth: usize,
+}
+
+// deeper nesting is an error rather than a stack overflow, here or in what
+// reads the value
+const MAX_DEPTH: usize = 512;
+
+impl JsonParser {
+    fn error(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("JSON ERROR: {} at {}", message, self.pos),
+        )
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(
+            self.peek(),
+            Some(' ') | Some('\n') | Some('\r') | Some('\t')
+        ) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        match self.next() {
+            Some(found) if found == c => Ok(()),
+            _ => Err(self.error(&format!("expected {}", c))),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, Error> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::Str),
+            Some('[') | Some('{') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error(&format!("nested more than {} deep", MAX_DEPTH)));
+                }
+                self.depth += 1;
+                let value = self.nested();
+                self.depth -= 1;
+                value
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    // an array or an object
+    fn nested(&mut self) -> Result<Json, Error> {
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let mut elems = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(elems));
+                }
+                loop {
+                    elems.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Json::Array(elems)),
+                        _ => return Err(self.error("expected , or ]")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(':')?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => {}
+                        Some('}') => return Ok(Json::Object(fields)),
+                        _ => return Err(self.error("expected , or }")),
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, Error> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let unit = self.hex()?;
+                        // characters outside the BMP are written as a
+                        // surrogate pair
+                        let c = if (0xd800..0xdc00).contains(&unit) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(self.error("invalid surrogate pair"));
+                            }
+                            char::from_u32(0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00))
+                        } else {
+                            char::from_u32(unit)
+                        };
+                        s.push(c.unwrap_or('\u{fffd}'));
+                    }
+                    _ => return Err(self.error("invalid escape")),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, Error> {
+        let mut unit = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid \\u escape"))?;
+            unit = unit * 16 + digit;
+        }
+        Ok(unit)
+    }
+}
@@ -0,0 +1,159 @@
+use super::typeck::*;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+#[derive(Debug)]
+pub struct FieldLayout {
+    pub name: String,
+    pub ty: Type,
+    // the field's index in a VM struct value
+    pub slot: usize,
+    // the field's byte offset in a natively laid out struct
+    pub offset: usize,
+}
+
+// Structs are laid out like C structs for native code, fields stay in
+// declaration order and are padded to their alignment. The VM only needs the
+// slot of every field.
+#[derive(Debug)]
+pub struct StructLayout {
+    pub name: String,
+    // the type arguments the layout is for, erased ones are type parameters
+    pub args: Vec<Type>,
+    pub fields: Vec<FieldLayout>,
+    pub size: usize,
+    pub align: usize,
+}
+
+impl StructLayout {
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+// the layout of every struct with its type parameters erased
+pub fn compute_layouts(structs: &[StructDef]) -> Result<Vec<StructLayout>, Error> {
+    let mut sizes = HashMap::new();
+    structs
+        .iter()
+        .map(|def| {
+            let args = def.generics.iter().cloned().map(Type::Param).collect();
+            compute(&def.name, args, structs, &mut sizes, &mut vec![])
+        })
+        .collect()
+}
+
+// the layout of a generic struct with concrete type arguments
+pub fn instantiate(
+    structs: &[StructDef],
+    name: &str,
+    args: Vec<Type>,
+) -> Result<StructLayout, Error> {
+    compute(name, args, structs, &mut HashMap::new(), &mut vec![])
+}
+
+// `visiting` holds the structs whose layout depends on the one being
+// computed, with the size of their type arguments. A struct can only contain
+// an instance of itself with smaller type arguments, like a Pair<int, int>
+// in a Pair<Pair<int, int>, int>, anything else has infinite size. `sizes`
+// remembers the size and alignment of every instance computed so far
+fn compute(
+    name: &str,
+    args: Vec<Type>,
+    structs: &[StructDef],
+    sizes: &mut HashMap<String, (usize, usize)>,
+    visiting: &mut Vec<(String, usize)>,
+) -> Result<StructLayout, Error> {
+    let args_size = args.iter().map(type_size).sum();
+    if visiting
+        .iter()
+        .any(|(v, size)| v == name && *size <= args_size)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "TYPE ERROR: struct {} contains itself and has infinite size",
+                name
+            ),
+        ));
+    }
+    visiting.push((name.to_string(), args_size));
+
+    let def = structs.iter().find(|def| def.name == name).unwrap();
+    let subst = def
+        .generics
+        .iter()
+        .cloned()
+        .zip(args.iter().cloned())
+        .collect();
+    let mut field_layouts = vec![];
+    let mut offset = 0;
+    let mut align = 1;
+    for (slot, (field, ty)) in def.fields.iter().enumerate() {
+        let ty = ty.substitute(&subst);
+        let (field_size, field_align) = match &ty {
+            Type::Struct(inner, inner_args) => {
+                let key = ty.to_string();
+                match sizes.get(&key) {
+                    Some(size) => *size,
+                    None => {
+                        let inner = compute(inner, inner_args.clone(), structs, sizes, visiting)?;
+                        sizes.insert(key, (inner.size, inner.align));
+                        (inner.size, inner.align)
+                    }
+                }
+            }
+            ty => native_size(ty),
+        };
+
+        offset = round_up(offset, field_align);
+        field_layouts.push(FieldLayout {
+            name: field.clone(),
+            ty,
+            slot,
+            offset,
+        });
+        offset += field_size;
+        align = align.max(field_align);
+    }
+
+    visiting.pop();
+    Ok(StructLayout {
+        name: name.to_string(),
+        args,
+        fields: field_layouts,
+        size: round_up(offset, align),
+        align,
+    })
+}
+
+// size and alignment of everything but structs, strings and arrays are a
+// pointer and a length, enums are boxed since they may contain themselves,
+// functions are a code pointer and an environment pointer and values of an
+// erased type parameter are boxed
+fn native_size(ty: &Type) -> (usize, usize) {
+    match ty {
+        Type::Void => (0, 1),
+        Type::Bool => (1, 1),
+        Type::Int | Type::Float => (4, 4),
+        Type::Str | Type::Array(_) | Type::Fn(..) => (16, 8),
+        Type::Enum(_) | Type::Param(_) => (8, 8),
+        Type::Generic(..) => (16, 8),
+        Type::Struct(..) | Type::Unknown | Type::Var(_) => unreachable!(),
+    }
+}
+
+// the number of types a type is made of
+fn type_size(ty: &Type) -> usize {
+    match ty {
+        Type::Array(elem) => 1 + type_size(elem),
+        Type::Struct(_, args) => 1 + args.iter().map(type_size).sum::<usize>(),
+        Type::Fn(params, ret) => 1 + params.iter().map(type_size).sum::<usize>() + type_size(ret),
+        Type::Generic(_, t) => type_size(t),
+        _ => 1,
+    }
+}
+
+fn round_up(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
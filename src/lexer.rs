@@ -1,12 +1,27 @@
+use super::source::{Edit, Span};
 use std::collections::VecDeque;
 use std::io::{Error, ErrorKind};
-#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     IF,
     ELSE,
     WHILE,
     FN,
+    STRUCT,
+    ENUM,
+    MATCH,
+    MODULE,
+    IMPORT,
+    PUB,
     SEMI,
+    COLON,
+    COLONCOLON,
+    FATARROW,
+    ARROW,
+    COMMA,
+    DOT,
+    DOTDOT,
     LPAREN,
     RPAREN,
     LBRACE,
@@ -18,8 +33,28 @@ pub enum Token {
     MUL,
     DIV,
     EXP,
+    MOD,
+    BITAND,
+    BITOR,
+    XOR,
+    SHL,
+    SHR,
+    BITNOT,
     ASSIGN,
+    ADDASSIGN,
+    SUBASSIGN,
+    MULASSIGN,
+    DIVASSIGN,
+    MODASSIGN,
+    EXPASSIGN,
+    BITANDASSIGN,
+    BITORASSIGN,
+    XORASSIGN,
+    SHLASSIGN,
+    SHRASSIGN,
     NOT,
+    AND,
+    OR,
     NEQ,
     EQ,
     LESS,
@@ -27,72 +62,285 @@ pub enum Token {
     GREATER,
     GEQ,
     VOID,
+    // the #[tail] attribute on a call
+    TAIL,
     IDENT(String),
+    // an operator registered at runtime, see OperatorTable
+    OP(String),
     STR(String),
+    BOOL(bool),
     INT(i32),
     FLOAT(f32),
 }
 
-#[derive(Debug)]
+// every symbol Token::from_symbol knows
+pub const SYMBOLS: [&str; 47] = [
+    ";", ",", ":", "::", "=>", "->", ".", "..", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/",
+    "**", "%", "&", "|", "^", "<<", ">>", "~", "=", "+=", "-=", "*=", "/=", "%=", "**=", "&=",
+    "|=", "^=", "<<=", ">>=", "!", "&&", "||", "!=", "==", "<", "<=", ">", ">=",
+];
+
+impl Token {
+    // the token the lexer produces for an operator or bracket symbol
+    pub fn from_symbol(symbol: &str) -> Option<Token> {
+        let token = match symbol {
+            ";" => Token::SEMI,
+            "," => Token::COMMA,
+            ":" => Token::COLON,
+            "::" => Token::COLONCOLON,
+            "=>" => Token::FATARROW,
+            "->" => Token::ARROW,
+            "." => Token::DOT,
+            ".." => Token::DOTDOT,
+            "(" => Token::LPAREN,
+            ")" => Token::RPAREN,
+            "[" => Token::LBRACE,
+            "]" => Token::RBRACE,
+            "{" => Token::LCBRACE,
+            "}" => Token::RCBRACE,
+            "+" => Token::ADD,
+            "-" => Token::SUB,
+            "*" => Token::MUL,
+            "/" => Token::DIV,
+            "**" => Token::EXP,
+            "%" => Token::MOD,
+            "&" => Token::BITAND,
+            "|" => Token::BITOR,
+            "^" => Token::XOR,
+            "<<" => Token::SHL,
+            ">>" => Token::SHR,
+            "~" => Token::BITNOT,
+            "=" => Token::ASSIGN,
+            "+=" => Token::ADDASSIGN,
+            "-=" => Token::SUBASSIGN,
+            "*=" => Token::MULASSIGN,
+            "/=" => Token::DIVASSIGN,
+            "%=" => Token::MODASSIGN,
+            "**=" => Token::EXPASSIGN,
+            "&=" => Token::BITANDASSIGN,
+            "|=" => Token::BITORASSIGN,
+            "^=" => Token::XORASSIGN,
+            "<<=" => Token::SHLASSIGN,
+            ">>=" => Token::SHRASSIGN,
+            "!" => Token::NOT,
+            "&&" => Token::AND,
+            "||" => Token::OR,
+            "!=" => Token::NEQ,
+            "==" => Token::EQ,
+            "<" => Token::LESS,
+            "<=" => Token::LEQ,
+            ">" => Token::GREATER,
+            ">=" => Token::GEQ,
+            _ => return None,
+        };
+        Some(token)
+    }
+
+    // the symbol from_symbol makes this token from, the text of a custom
+    // operator and None for the other tokens
+    pub fn symbol(&self) -> Option<&str> {
+        if let Token::OP(symbol) = self {
+            return Some(symbol);
+        }
+        SYMBOLS
+            .iter()
+            .find(|symbol| Token::from_symbol(symbol).as_ref() == Some(self))
+            .copied()
+    }
+
+    // the operator a compound assignment like += applies before assigning
+    pub fn compound_op(&self) -> Option<Token> {
+        match self {
+            Token::ADDASSIGN => Some(Token::ADD),
+            Token::SUBASSIGN => Some(Token::SUB),
+            Token::MULASSIGN => Some(Token::MUL),
+            Token::DIVASSIGN => Some(Token::DIV),
+            Token::MODASSIGN => Some(Token::MOD),
+            Token::EXPASSIGN => Some(Token::EXP),
+            Token::BITANDASSIGN => Some(Token::BITAND),
+            Token::BITORASSIGN => Some(Token::BITOR),
+            Token::XORASSIGN => Some(Token::XOR),
+            Token::SHLASSIGN => Some(Token::SHL),
+            Token::SHRASSIGN => Some(Token::SHR),
+            _ => None,
+        }
+    }
+}
+
+// a // comment, the text includes the //
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Lexer {
     token_list: VecDeque<Token>,
+    // the span of every token in token_list
+    spans: VecDeque<Span>,
+    // the span of the token next returned last
+    last: Option<Span>,
+    // every comment in the text, in order
+    comments: Vec<Comment>,
+    // how many tokens next has returned
+    read: usize,
 }
 
 impl Lexer {
-    pub fn scan(file_name: String) -> Result<Lexer, Error> {
-        let buf = std::fs::read(file_name)?;
+    // lexes a file's text, `start` is the offset of its first character in
+    // the source map and every token's span is relative to it. The given
+    // operator symbols are recognised too, longer symbols win over shorter
+    // ones and over the built in operators
+    pub fn scan_source(text: &str, start: usize, symbols: &[String]) -> Result<Lexer, Error> {
+        let s: Vec<char> = text.chars().collect();
+        Self::scan_chars(&s, 0, start, symbols, |_| false).map(|(lexer, _)| lexer)
+    }
+
+    // lexes s from `from`, which must be where a token or whitespace starts,
+    // until the first token start `stop` is true for. Returns the tokens
+    // before it and where it was, None when the end was reached. Line
+    // numbers in errors are only right when `from` is 0
+    fn scan_chars(
+        s: &[char],
+        from: usize,
+        start: usize,
+        symbols: &[String],
+        mut stop: impl FnMut(usize) -> bool,
+    ) -> Result<(Lexer, Option<usize>), Error> {
         let mut token_list = VecDeque::new();
+        let mut spans = VecDeque::new();
+        let mut comments = vec![];
         let mut ln_num = 0;
-
-        let s: Vec<char> = String::from_utf8(buf)
-            .expect("from_utf8 failed")
-            .chars()
-            .collect();
+        // where the line being scanned starts, for the columns of errors
+        let mut line_start = from;
+        let mut stopped = None;
 
         let mut peek;
         let mut current;
-        let mut i = 0;
-        let mut j = 0;
+        let mut i = from;
+        let mut j;
         ln_num += 1;
-        let file_bound = s.len() - 1;
-        while i < file_bound {
+        // the character at k, \0 past the end of the text
+        let at = |k: usize| s.get(k).copied().unwrap_or('\0');
+        while i < s.len() {
+            // every token pushed below spans from here to the new i
+            let token_start = i;
             current = &s[i];
             j = i + 1;
-            peek = s[j];
-            if current == &' ' || current == &'\n' {
+            peek = at(j);
+            // a \r of a \r\n line ending is whitespace like a tab
+            if matches!(current, ' ' | '\t' | '\r' | '\n') {
+                if current == &'\n' {
+                    ln_num += 1;
+                    line_start = i + 1;
+                }
                 i += 1;
                 continue;
             }
+            if stop(i) {
+                stopped = Some(i);
+                break;
+            }
+
+            let custom = symbols
+                .iter()
+                .filter(|symbol| starts_with(s, i, symbol))
+                .max_by_key(|symbol| symbol.len());
+            if let Some(symbol) = custom {
+                token_list.push_back(Token::OP(symbol.clone()));
+                i += symbol.chars().count();
+                spans.push_back(Span {
+                    start: start + token_start,
+                    end: start + i,
+                });
+                continue;
+            }
 
             match current {
                 '+' => {
-                    token_list.push_back(Token::ADD);
-                    i += 1;
+                    if peek == '=' {
+                        token_list.push_back(Token::ADDASSIGN);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::ADD);
+                        i += 1;
+                    }
                 }
 
                 '-' => {
-                    token_list.push_back(Token::SUB);
-                    i += 1;
+                    if peek == '=' {
+                        token_list.push_back(Token::SUBASSIGN);
+                        i += 2;
+                    } else if peek == '>' {
+                        token_list.push_back(Token::ARROW);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::SUB);
+                        i += 1;
+                    }
                 }
 
                 '*' => {
-                    if peek == '*' {
+                    if peek == '*' && at(j + 1) == '=' {
+                        token_list.push_back(Token::EXPASSIGN);
+                        i += 3;
+                    } else if peek == '*' {
                         token_list.push_back(Token::EXP);
                         i += 2;
+                    } else if peek == '=' {
+                        token_list.push_back(Token::MULASSIGN);
+                        i += 2;
                     } else {
                         token_list.push_back(Token::MUL);
                         i += 1;
                     }
                 }
                 '/' => {
-                    token_list.push_back(Token::DIV);
-                    i += 1;
+                    // a comment runs to the end of the line, it's kept apart
+                    // from the tokens
+                    if peek == '/' {
+                        while j < s.len() && s[j] != '\n' {
+                            j += 1;
+                        }
+                        // the \r of a \r\n stays whitespace
+                        if s[j - 1] == '\r' {
+                            j -= 1;
+                        }
+                        comments.push(Comment {
+                            span: Span {
+                                start: start + i,
+                                end: start + j,
+                            },
+                            text: s[i..j].iter().collect::<String>().trim_end().to_string(),
+                        });
+                        i = j;
+                    } else if peek == '=' {
+                        token_list.push_back(Token::DIVASSIGN);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::DIV);
+                        i += 1;
+                    }
+                }
+
+                '%' => {
+                    if peek == '=' {
+                        token_list.push_back(Token::MODASSIGN);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::MOD);
+                        i += 1;
+                    }
                 }
 
                 '=' => {
                     if peek == '=' {
                         token_list.push_back(Token::EQ);
                         i += 2;
+                    } else if peek == '>' {
+                        token_list.push_back(Token::FATARROW);
+                        i += 2;
                     } else {
                         token_list.push_back(Token::ASSIGN);
                         i += 1;
@@ -109,8 +357,61 @@ impl Lexer {
                     }
                 }
 
-                '<' => {
+                '&' => {
+                    if peek == '&' {
+                        token_list.push_back(Token::AND);
+                        i += 2;
+                    } else if peek == '=' {
+                        token_list.push_back(Token::BITANDASSIGN);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::BITAND);
+                        i += 1;
+                    }
+                }
+
+                '|' => {
+                    if peek == '|' {
+                        token_list.push_back(Token::OR);
+                        i += 2;
+                    } else if peek == '=' {
+                        token_list.push_back(Token::BITORASSIGN);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::BITOR);
+                        i += 1;
+                    }
+                }
+
+                '^' => {
                     if peek == '=' {
+                        token_list.push_back(Token::XORASSIGN);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::XOR);
+                        i += 1;
+                    }
+                }
+
+                '~' => {
+                    token_list.push_back(Token::BITNOT);
+                    i += 1;
+                }
+
+                // #[tail] is the only attribute there is
+                '#' if starts_with(s, i, "#[tail]") => {
+                    token_list.push_back(Token::TAIL);
+                    i += 7;
+                }
+
+                '<' => {
+                    if peek == '<' && at(j + 1) == '=' {
+                        token_list.push_back(Token::SHLASSIGN);
+                        i += 3;
+                    } else if peek == '<' {
+                        token_list.push_back(Token::SHL);
+                        i += 2;
+                    } else if peek == '=' {
                         token_list.push_back(Token::LEQ);
                         i += 2;
                     } else {
@@ -120,7 +421,13 @@ impl Lexer {
                 }
 
                 '>' => {
-                    if peek == '=' {
+                    if peek == '>' && at(j + 1) == '=' {
+                        token_list.push_back(Token::SHRASSIGN);
+                        i += 3;
+                    } else if peek == '>' {
+                        token_list.push_back(Token::SHR);
+                        i += 2;
+                    } else if peek == '=' {
                         token_list.push_back(Token::GEQ);
                         i += 2;
                     } else {
@@ -130,9 +437,18 @@ impl Lexer {
                 }
 
                 '"' => {
-                    while peek != '"' {
+                    while at(j) != '"' {
+                        if j >= s.len() {
+                            return Err(std::io::Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "UNTERMINATED STRING AT: Ln {}, Col {}",
+                                    ln_num,
+                                    i - line_start + 1
+                                ),
+                            ));
+                        }
                         j += 1;
-                        peek = s[j];
                     }
                     if j - i == 1 {
                         token_list.push_back(Token::STR(String::new()));
@@ -178,46 +494,104 @@ impl Lexer {
                     i += 1;
                 }
 
+                ',' => {
+                    token_list.push_back(Token::COMMA);
+                    i += 1;
+                }
+
+                ':' => {
+                    if peek == ':' {
+                        token_list.push_back(Token::COLONCOLON);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::COLON);
+                        i += 1;
+                    }
+                }
+
+                '.' => {
+                    if peek == '.' {
+                        token_list.push_back(Token::DOTDOT);
+                        i += 2;
+                    } else {
+                        token_list.push_back(Token::DOT);
+                        i += 1;
+                    }
+                }
+
                 _ => {
-                    if current.is_digit(10) {
+                    if current.is_ascii_digit() {
                         let mut float = false;
-                        while peek.is_digit(10) || (peek == '.' && float == false) {
+                        while peek.is_ascii_digit() || (peek == '.' && !float) {
+                            // 1..3 is a range, not the float 1. followed by .3
+                            if peek == '.' && at(j + 1) == '.' {
+                                break;
+                            }
                             if peek == '.' {
                                 float = true;
                             }
                             j += 1;
-                            peek = s[j];
+                            peek = at(j);
                         }
                         if peek != ' '
+                            && peek != '\t'
+                            && peek != '\r'
                             && peek != ';'
                             && peek != ')'
                             && peek != ']'
+                            && peek != '}'
+                            && peek != ','
+                            && peek != '='
+                            && peek != '.'
                             && peek != '\n'
                             && peek != '+'
                             && peek != '-'
                             && peek != '*'
                             && peek != '/'
-                            && j != file_bound
+                            && peek != '&'
+                            && peek != '|'
+                            && peek != '%'
+                            && peek != '^'
+                            && peek != '<'
+                            && peek != '>'
+                            && !symbols.iter().any(|symbol| starts_with(s, j, symbol))
+                            && j < s.len()
                         {
                             return Err(std::io::Error::new(
                                 ErrorKind::InvalidInput,
-                                format!("INVALID TOKEN AT: Ln {}, Col {}", ln_num, j),
+                                format!(
+                                    "INVALID TOKEN AT: Ln {}, Col {}",
+                                    ln_num,
+                                    j - line_start + 1
+                                ),
                             ));
                         }
 
                         let num = s[i..j].iter().collect::<String>();
 
+                        // an int too big for 32 bits
+                        let too_big = || {
+                            std::io::Error::new(
+                                ErrorKind::InvalidInput,
+                                format!(
+                                    "NUMBER TOO LARGE AT: Ln {}, Col {}",
+                                    ln_num,
+                                    i - line_start + 1
+                                ),
+                            )
+                        };
                         if float {
                             token_list.push_back(Token::FLOAT(num.parse::<f32>().unwrap()));
                         } else {
-                            token_list.push_back(Token::INT(num.parse::<i32>().unwrap()));
+                            let n = num.parse::<i32>().map_err(|_| too_big())?;
+                            token_list.push_back(Token::INT(n));
                         }
 
                         i = j;
                     } else if current.is_alphabetic() || current == &'_' {
-                        while (peek.is_alphanumeric() || peek == '_') && j != file_bound {
+                        while peek.is_alphanumeric() || peek == '_' {
                             j += 1;
-                            peek = s[j];
+                            peek = at(j);
                         }
 
                         let word = &s[i..j];
@@ -230,8 +604,24 @@ impl Lexer {
                             token_list.push_back(Token::ELSE)
                         } else if word == ['f', 'n'] {
                             token_list.push_back(Token::FN);
+                        } else if word == ['s', 't', 'r', 'u', 'c', 't'] {
+                            token_list.push_back(Token::STRUCT);
+                        } else if word == ['e', 'n', 'u', 'm'] {
+                            token_list.push_back(Token::ENUM);
+                        } else if word == ['m', 'a', 't', 'c', 'h'] {
+                            token_list.push_back(Token::MATCH);
+                        } else if word == ['m', 'o', 'd'] {
+                            token_list.push_back(Token::MODULE);
+                        } else if word == ['i', 'm', 'p', 'o', 'r', 't'] {
+                            token_list.push_back(Token::IMPORT);
+                        } else if word == ['p', 'u', 'b'] {
+                            token_list.push_back(Token::PUB);
                         } else if word == ['v', 'o', 'i', 'd'] {
                             token_list.push_back(Token::VOID);
+                        } else if word == ['t', 'r', 'u', 'e'] {
+                            token_list.push_back(Token::BOOL(true));
+                        } else if word == ['f', 'a', 'l', 's', 'e'] {
+                            token_list.push_back(Token::BOOL(false));
                         } else {
                             token_list.push_back(Token::IDENT(word.iter().collect()));
                         }
@@ -239,20 +629,162 @@ impl Lexer {
                     } else {
                         return Err(std::io::Error::new(
                             ErrorKind::InvalidInput,
-                            format!("INVALID TOKEN AT: Ln {}, Col {}", ln_num, j),
+                            format!(
+                                "INVALID TOKEN AT: Ln {}, Col {}",
+                                ln_num,
+                                i - line_start + 1
+                            ),
                         ));
                     }
                 }
             }
+            if spans.len() < token_list.len() {
+                spans.push_back(Span {
+                    start: start + token_start,
+                    end: start + i,
+                });
+            }
+        }
+        let lexer = Self {
+            token_list,
+            spans,
+            last: None,
+            comments,
+            read: 0,
+        };
+        Ok((lexer, stopped))
+    }
+
+    // lexes `text`, which is the text this lexer scanned with the edit made
+    // to it, without scanning it all again. Scanning restarts a couple of
+    // tokens before the edit, since a token can depend on the character
+    // after it, and stops at the first token past the edit that starts
+    // where an old one did; from there on the text is the same, so the old
+    // tokens are kept and moved by how much longer the text got. Nothing
+    // must have been read from this lexer. Also returns how many tokens were
+    // scanned
+    pub fn relex(
+        &self,
+        text: &str,
+        start: usize,
+        symbols: &[String],
+        edit: &Edit,
+    ) -> Result<(Lexer, usize), Error> {
+        let s: Vec<char> = text.chars().collect();
+        let delta = edit.delta();
+        let moved = |span: &Span| Span {
+            start: (span.start as isize + delta) as usize,
+            end: (span.end as isize + delta) as usize,
+        };
+        let before = self
+            .spans
+            .partition_point(|span| span.start < start + edit.span.start);
+        let restart = before.saturating_sub(2);
+        let from = match before {
+            0 => 0,
+            _ => self.spans[restart].start - start,
+        };
+        let edit_end = edit.span.start + edit.text.chars().count();
+        let old_start = |i: usize| {
+            let old = (i as isize - delta) as usize + start;
+            self.spans.binary_search_by_key(&old, |span| span.start)
+        };
+        let (scanned, stopped) = match Self::scan_chars(&s, from, start, symbols, |i| {
+            i >= edit_end && old_start(i).is_ok()
+        }) {
+            Ok(scanned) => scanned,
+            // scanned again from the start for the line number
+            Err(_) => return Self::scan_source(text, start, symbols).map(|lexer| (lexer, 0)),
+        };
+        let rescanned = scanned.token_list.len();
+
+        let mut token_list: VecDeque<Token> =
+            self.token_list.iter().take(restart).cloned().collect();
+        let mut spans: VecDeque<Span> = self.spans.iter().take(restart).copied().collect();
+        let mut comments: Vec<Comment> = self
+            .comments
+            .iter()
+            .take_while(|comment| comment.span.end <= start + from)
+            .cloned()
+            .collect();
+        token_list.extend(scanned.token_list);
+        spans.extend(scanned.spans);
+        comments.extend(scanned.comments);
+        if let Some(Ok(old)) = stopped.map(old_start) {
+            let old_offset = self.spans[old].start;
+            token_list.extend(self.token_list.iter().skip(old).cloned());
+            spans.extend(self.spans.iter().skip(old).map(moved));
+            comments.extend(
+                self.comments
+                    .iter()
+                    .filter(|comment| comment.span.start > old_offset)
+                    .map(|comment| Comment {
+                        span: moved(&comment.span),
+                        text: comment.text.clone(),
+                    }),
+            );
         }
-        Ok(Self { token_list })
+        let lexer = Self {
+            token_list,
+            spans,
+            last: None,
+            comments,
+            read: 0,
+        };
+        Ok((lexer, rescanned))
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Token> {
-        self.token_list.pop_front()
+        self.last = self.spans.pop_front();
+        let token = self.token_list.pop_front();
+        if token.is_some() {
+            self.read += 1;
+        }
+        token
+    }
+
+    // how many tokens next has returned, which is the index of the next
+    // one, the halves of a token split by push_front share its index
+    pub fn tokens_read(&self) -> usize {
+        self.read
+    }
+
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
     }
 
-    pub fn peek(&mut self) -> Option<&Token> {
+    // the span of the token next returned last
+    pub fn last_span(&self) -> Option<Span> {
+        self.last
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
         self.token_list.front()
     }
+
+    // the span of the token peek returns
+    pub fn span(&self) -> Option<Span> {
+        self.spans.front().copied()
+    }
+
+    // puts a token back, for when the parser splits one token into two, it
+    // gets the end of the span of the token next returned last
+    pub fn push_front(&mut self, token: Token) {
+        let span = match self.last {
+            Some(last) => Span {
+                start: last.end - 1,
+                end: last.end,
+            },
+            None => Span { start: 0, end: 0 },
+        };
+        self.spans.push_front(span);
+        self.token_list.push_front(token);
+        self.read -= 1;
+    }
+}
+
+fn starts_with(s: &[char], i: usize, symbol: &str) -> bool {
+    let len = symbol.chars().count();
+    i + len <= s.len() && s[i..i + len].iter().copied().eq(symbol.chars())
 }
@@ -0,0 +1,30 @@
+// The compiler's stages, an Engine that runs programs inside a Rust
+// application, an interactive REPL, a language server and a formatter.
+pub mod analysis;
+pub mod automata;
+pub mod cst;
+pub mod dot;
+pub mod editor;
+pub mod engine;
+pub mod format;
+pub mod heap;
+pub mod ir;
+pub mod json;
+pub mod layout;
+pub mod lexer;
+pub mod lsp;
+pub mod modules;
+pub mod natives;
+pub mod operators;
+pub mod parser;
+pub mod patterns;
+pub mod query;
+pub mod repl;
+pub mod serialize;
+pub mod source;
+pub mod typeck;
+pub mod vm;
+
+pub use engine::{Engine, FromValue, HasType, HostFn, IntoValue};
+pub use heap::{GcConfig, GcStats};
+pub use vm::{Limits, RuntimeError, Value};
@@ -0,0 +1,511 @@
+use super::analysis::{Analysis, Completion, DefKind, Symbol};
+use super::json::Json;
+use super::lexer::Lexer;
+use super::natives::Natives;
+use super::query::Database;
+use super::source::{Edit, Span};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Error, ErrorKind, Write};
+use std::path::PathBuf;
+
+// the JSON-RPC error codes the server answers with
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+// A language server speaking the Language Server Protocol over stdin and
+// stdout. Every open document is analysed again whenever it changes, edits
+// arrive as ranges of it that are replaced. Only the tokens around an edit
+// are lexed again, the program is parsed whole since the checker needs all
+// of its syntax tree.
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+struct Document {
+    name: String,
+    text: String,
+    // the offset of the first character of every line
+    lines: Vec<usize>,
+    // the text's tokens, None if it doesn't lex
+    lexer: Option<Lexer>,
+    analysis: Analysis,
+    // kept between edits so only what they changed is checked again
+    db: Database,
+}
+
+impl Document {
+    fn new(uri: &str, text: String) -> Self {
+        let path = uri_path(uri);
+        let name = path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| uri.to_string());
+        let dir = path.and_then(|path| path.parent().map(PathBuf::from));
+        let mut db = Database::new(&name, dir, Natives::prelude());
+        let lexer = Lexer::scan_source(&text, 0, &[]).ok();
+        let analysis = match &lexer {
+            Some(lexer) => Analysis::with_lexer(&name, &text, lexer, &mut db),
+            None => Analysis::with_database(&name, &text, &mut db),
+        };
+        Self {
+            name,
+            lines: line_starts(&text),
+            text,
+            lexer,
+            analysis,
+            db,
+        }
+    }
+
+    // LSP positions count columns in UTF-16 code units
+    fn position(&self, offset: usize) -> Json {
+        let line = self.lines.partition_point(|&start| start <= offset) - 1;
+        let character: usize = self
+            .text
+            .chars()
+            .skip(self.lines[line])
+            .take(offset - self.lines[line])
+            .map(char::len_utf16)
+            .sum();
+        Json::object(vec![("line", line.into()), ("character", character.into())])
+    }
+
+    fn range(&self, span: Span) -> Json {
+        Json::object(vec![
+            ("start", self.position(span.start)),
+            ("end", self.position(span.end.max(span.start))),
+        ])
+    }
+
+    fn offset(&self, position: &Json) -> Option<usize> {
+        let line = position.get("line")?.as_usize()?;
+        let character = position.get("character")?.as_usize()?;
+        let start = match self.lines.get(line) {
+            Some(&start) => start,
+            None => return Some(self.text.chars().count()),
+        };
+        let mut units = 0;
+        let mut offset = start;
+        for c in self.text.chars().skip(start) {
+            if units >= character || c == '\n' {
+                break;
+            }
+            units += c.len_utf16();
+            offset += 1;
+        }
+        Some(offset)
+    }
+
+    // replaces the range with the text, all of it without a range, the
+    // document is only analysed again by `analyse`
+    fn edit(&mut self, change: &Json) -> Option<()> {
+        let text = change.get("text")?.as_str()?;
+        let text = match change.get("range") {
+            Some(range) => {
+                let start = self.offset(range.get("start")?)?;
+                let end = self.offset(range.get("end")?)?;
+                let edit = Edit {
+                    span: Span {
+                        start,
+                        end: end.max(start),
+                    },
+                    text: text.to_string(),
+                };
+                let text = edit.apply(&self.text);
+                self.lexer = match &self.lexer {
+                    Some(lexer) => lexer.relex(&text, 0, &[], &edit).map(|(lexer, _)| lexer),
+                    None => Lexer::scan_source(&text, 0, &[]),
+                }
+                .ok();
+                text
+            }
+            None => {
+                self.lexer = Lexer::scan_source(text, 0, &[]).ok();
+                text.to_string()
+            }
+        };
+        self.lines = line_starts(&text);
+        self.text = text;
+        Some(())
+    }
+
+    fn analyse(&mut self) {
+        self.analysis = match &self.lexer {
+            Some(lexer) => Analysis::with_lexer(&self.name, &self.text, lexer, &mut self.db),
+            None => Analysis::with_database(&self.name, &self.text, &mut self.db),
+        };
+    }
+}
+
+// the offset of the first character of every line
+fn line_starts(text: &str) -> Vec<usize> {
+    let mut lines = vec![0];
+    for (i, c) in text.chars().enumerate() {
+        if c == '\n' {
+            lines.push(i + 1);
+        }
+    }
+    lines
+}
+
+// the file a file:// URI names
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        if b == b'%' {
+            let hex: Vec<u8> = chars.by_ref().take(2).collect();
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    // serves until the client sends exit or closes stdin
+    pub fn run(&mut self) -> io::Result<()> {
+        self.serve(&mut io::stdin().lock(), &mut io::stdout())
+    }
+
+    // serves messages from `input` until exit or its end, writing to `output`
+    pub fn serve(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<()> {
+        while let Some(body) = read_message(input)? {
+            let message = match Json::parse(&body) {
+                Ok(message) => message,
+                Err(err) => {
+                    send(
+                        output,
+                        &error_response(Json::Null, PARSE_ERROR, &err.to_string()),
+                    )?;
+                    continue;
+                }
+            };
+            let method = message.get("method").and_then(Json::as_str);
+            let params = message.get("params").cloned().unwrap_or(Json::Null);
+            match (message.get("id"), method) {
+                (_, Some("exit")) => break,
+                (Some(id), Some(method)) => {
+                    let response = match self.request(method, &params) {
+                        Ok(result) => Json::object(vec![
+                            ("jsonrpc", "2.0".into()),
+                            ("id", id.clone()),
+                            ("result", result),
+                        ]),
+                        Err((code, message)) => error_response(id.clone(), code, &message),
+                    };
+                    send(output, &response)?;
+                }
+                (None, Some(method)) => self.notification(method, &params, output)?,
+                // a response to a request of the server's, it sends none
+                (Some(_), None) if message.get("result").is_some() => {}
+                (id, None) => send(
+                    output,
+                    &error_response(
+                        id.cloned().unwrap_or(Json::Null),
+                        INVALID_REQUEST,
+                        "a request needs a method",
+                    ),
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        if method == "initialize" {
+            return Ok(capabilities());
+        }
+        if method == "shutdown" {
+            self.shutdown = true;
+            return Ok(Json::Null);
+        }
+        if self.shutdown {
+            return Err((INVALID_REQUEST, String::from("the server is shutting down")));
+        }
+
+        let invalid = || (INVALID_PARAMS, format!("invalid params for {}", method));
+        let handled = matches!(
+            method,
+            "textDocument/hover"
+                | "textDocument/definition"
+                | "textDocument/documentSymbol"
+                | "textDocument/completion"
+        );
+        if !handled {
+            return Err((METHOD_NOT_FOUND, format!("unknown method {}", method)));
+        }
+        let uri = params
+            .get("textDocument")
+            .and_then(|doc| doc.get("uri"))
+            .and_then(Json::as_str)
+            .ok_or_else(invalid)?;
+        let doc = match self.documents.get(uri) {
+            Some(doc) => doc,
+            None => return Ok(Json::Null),
+        };
+        if method == "textDocument/documentSymbol" {
+            let symbols = doc.analysis.symbols();
+            return Ok(Json::Array(
+                symbols
+                    .iter()
+                    .map(|symbol| symbol_json(doc, symbol))
+                    .collect(),
+            ));
+        }
+
+        let offset = params
+            .get("position")
+            .and_then(|position| doc.offset(position))
+            .ok_or_else(invalid)?;
+        let result = match method {
+            "textDocument/hover" => match doc.analysis.hover(offset) {
+                Some((span, text)) => Json::object(vec![
+                    (
+                        "contents",
+                        Json::object(vec![("kind", "plaintext".into()), ("value", text.into())]),
+                    ),
+                    ("range", doc.range(span)),
+                ]),
+                None => Json::Null,
+            },
+            "textDocument/definition" => match doc.analysis.definition(offset) {
+                Some(span) => Json::object(vec![("uri", uri.into()), ("range", doc.range(span))]),
+                None => Json::Null,
+            },
+            _ => Json::Array(
+                doc.analysis
+                    .completions(offset)
+                    .iter()
+                    .map(completion_json)
+                    .collect(),
+            ),
+        };
+        Ok(result)
+    }
+
+    fn notification(
+        &mut self,
+        method: &str,
+        params: &Json,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let uri = match params
+            .get("textDocument")
+            .and_then(|doc| doc.get("uri"))
+            .and_then(Json::as_str)
+        {
+            Some(uri) => uri.to_string(),
+            None => return Ok(()),
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|doc| doc.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                self.documents
+                    .insert(uri.clone(), Document::new(&uri, text.to_string()));
+            }
+            "textDocument/didChange" => {
+                let changes = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .unwrap_or(&[]);
+                if let Some(doc) = self.documents.get_mut(&uri) {
+                    for change in changes {
+                        doc.edit(change);
+                    }
+                    doc.analyse();
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+            }
+            _ => return Ok(()),
+        }
+        self.publish_diagnostics(&uri, output)
+    }
+
+    fn publish_diagnostics(&self, uri: &str, output: &mut impl Write) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(doc) => doc
+                .analysis
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    Json::object(vec![
+                        ("range", doc.range(diagnostic.span)),
+                        // an error
+                        ("severity", 1.into()),
+                        ("source", "compiler".into()),
+                        ("message", diagnostic.message.clone().into()),
+                    ])
+                })
+                .collect(),
+            // a closed document's diagnostics are cleared
+            None => vec![],
+        };
+        send(
+            output,
+            &Json::object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("method", "textDocument/publishDiagnostics".into()),
+                (
+                    "params",
+                    Json::object(vec![
+                        ("uri", uri.into()),
+                        ("diagnostics", Json::Array(diagnostics)),
+                    ]),
+                ),
+            ]),
+        )
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        (
+            "capabilities",
+            Json::object(vec![
+                (
+                    "textDocumentSync",
+                    // incremental changes
+                    Json::object(vec![("openClose", true.into()), ("change", 2.into())]),
+                ),
+                ("hoverProvider", true.into()),
+                ("definitionProvider", true.into()),
+                ("documentSymbolProvider", true.into()),
+                (
+                    "completionProvider",
+                    Json::object(vec![(
+                        "triggerCharacters",
+                        Json::Array(vec![".".into(), ":".into()]),
+                    )]),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object(vec![("name", env!("CARGO_PKG_NAME").into())]),
+        ),
+    ])
+}
+
+// the SymbolKind and CompletionItemKind numbers of the protocol
+fn symbol_kind(kind: DefKind) -> i32 {
+    match kind {
+        DefKind::Function => 12,
+        DefKind::Struct => 23,
+        DefKind::Enum => 10,
+        DefKind::Field => 8,
+        DefKind::Variant => 22,
+        DefKind::Global | DefKind::Param(_) | DefKind::Local => 13,
+    }
+}
+
+fn completion_kind(kind: Option<DefKind>) -> i32 {
+    match kind {
+        Some(DefKind::Function) => 3,
+        Some(DefKind::Struct) => 22,
+        Some(DefKind::Enum) => 13,
+        Some(DefKind::Field) => 5,
+        Some(DefKind::Variant) => 20,
+        Some(DefKind::Global) | Some(DefKind::Param(_)) | Some(DefKind::Local) => 6,
+        None => 14,
+    }
+}
+
+fn symbol_json(doc: &Document, symbol: &Symbol) -> Json {
+    Json::object(vec![
+        ("name", symbol.name.as_str().into()),
+        ("kind", symbol_kind(symbol.kind).into()),
+        ("range", doc.range(symbol.span)),
+        ("selectionRange", doc.range(symbol.name_span)),
+        (
+            "children",
+            Json::Array(
+                symbol
+                    .children
+                    .iter()
+                    .map(|child| symbol_json(doc, child))
+                    .collect(),
+            ),
+        ),
+    ])
+}
+
+fn completion_json(completion: &Completion) -> Json {
+    let mut fields = vec![
+        ("label", completion.label.as_str().into()),
+        ("kind", completion_kind(completion.kind).into()),
+    ];
+    if let Some(detail) = &completion.detail {
+        fields.push(("detail", detail.as_str().into()));
+    }
+    Json::object(fields)
+}
+
+fn error_response(id: Json, code: i32, message: &str) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            Json::object(vec![("code", code.into()), ("message", message.into())]),
+        ),
+    ])
+}
+
+// the body of the next message, None once the input ends
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "a message without a Content-Length"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+}
+
+fn send(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
@@ -1,17 +1,208 @@
-mod lexer;
-mod parser;
+use compiler::ir::Lowerer;
+use compiler::json::Json;
+use compiler::lexer::Lexer;
+use compiler::operators::OperatorTable;
+use compiler::typeck::TypeChecker;
+use compiler::{automata, cst, dot, format, lsp, natives, parser, query, repl, serialize, vm};
+use std::io::{self, Read};
+use std::path::PathBuf;
 
 fn main() {
-    let tokens = lexer::Lexer::scan(String::from("input"));
-    let parser = match tokens {
-        Ok(tokens) => {
-            println!("{:?}", tokens);
-            parser::Parser::new(tokens)
+    // `compiler repl` starts an interactive session instead, `compiler lsp`
+    // a language server on stdin and stdout, `compiler fmt` formats files,
+    // `compiler --emit=KIND` prints a stage's output and `compiler
+    // --from=ast-json FILE` runs a syntax tree another tool wrote
+    match std::env::args().nth(1).as_deref() {
+        Some("repl") => {
+            if let Err(err) = repl::Repl::new().run() {
+                fail(format!("Issue reading input: {}", err));
+            }
+            return;
         }
-        Err(err) => panic!("Issue lexing: {}", err),
+        Some("lsp") => {
+            if let Err(err) = lsp::Server::new().run() {
+                fail(format!("Issue serving the language server: {}", err));
+            }
+            return;
+        }
+        Some("fmt") => {
+            if let Err(err) = fmt(std::env::args().skip(2).collect()) {
+                fail(format!("Issue formatting: {}", err));
+            }
+            return;
+        }
+        Some(arg) if arg.starts_with("--emit=") => {
+            if let Err(err) = emit(&arg["--emit=".len()..], std::env::args().skip(2).collect()) {
+                fail(format!("Issue emitting {}: {}", arg, err));
+            }
+            return;
+        }
+        Some("--from=ast-json") => {
+            if let Err(err) = run_json(std::env::args().nth(2)) {
+                fail(format!("Issue running the syntax tree: {}", err));
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    // the root file and every module it declares, merged into one program,
+    // checked and lowered a statement at a time
+    let db = query::Database::new("input", Some(PathBuf::new()), natives::Natives::prelude());
+    let module = match db.globals().and_then(|_| db.module()) {
+        Ok(module) => module,
+        Err(err) => fail(db.located(err).to_string()),
     };
 
-    let s_exp = parser.parse();
+    match vm::VM::new(&module, db.natives()).run() {
+        Ok(value) => println!("{}", value),
+        Err(err) => fail(err.render(&db.source_map())),
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1)
+}
+
+// compiler --emit=KIND [FILE] prints a stage's output for the file, input
+// when there's none. KIND is dot, tokens-json or ast-json, see emit_dot and
+// the serialize module
+fn emit(kind: &str, args: Vec<String>) -> io::Result<()> {
+    let file = args.first().map_or("input", String::as_str);
+    match kind {
+        "dot" => emit_dot(args),
+        "tokens-json" => {
+            let text = std::fs::read_to_string(file)?;
+            println!(
+                "{}",
+                serialize::tokens_to_json(Lexer::scan_source(&text, 0, &[])?)
+            );
+            Ok(())
+        }
+        "ast-json" => {
+            let text = std::fs::read_to_string(file)?;
+            let lexer = Lexer::scan_source(&text, 0, &[])?;
+            let program = parser::Parser::new(lexer).parse()?;
+            println!("{}", serialize::ast_to_json(&program));
+            Ok(())
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown output {}", kind),
+        )),
+    }
+}
+
+// a program from an ast-json document, checked and run like one parsed from
+// text. It can't declare modules
+fn run_json(file: Option<String>) -> io::Result<()> {
+    let file = file.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file"))?;
+    let program = serialize::ast_from_json(&Json::parse(&std::fs::read_to_string(file)?)?)?;
+    let natives = natives::Natives::prelude();
+    let operators = OperatorTable::new();
+    let (_, instances) = TypeChecker::check(&program, &natives, &operators)?;
+    let module = Lowerer::lower(&program, &instances, &operators)?;
+    match vm::VM::new(&module, &natives).run() {
+        Ok(value) => println!("{}", value),
+        Err(err) => fail(err.to_string()),
+    }
+    Ok(())
+}
 
-    println!("{}", s_exp);
+// compiler --emit=dot [FILE] draws the syntax tree of the file and the
+// control flow graph and dominator tree of each of its functions. compiler
+// --emit=dot --regex PATTERN draws the NFA and DFA of a regular expression
+// instead
+fn emit_dot(args: Vec<String>) -> io::Result<()> {
+    if let [flag, pattern] = &args[..] {
+        if flag == "--regex" {
+            let nfa = automata::Nfa::from_regex(pattern)?;
+            print!("{}", dot::nfa(&nfa));
+            print!("{}", dot::dfa(&automata::Dfa::from_nfa(&nfa)));
+            return Ok(());
+        }
+    }
+
+    let file = PathBuf::from(args.first().map_or("input", String::as_str));
+    let text = std::fs::read_to_string(&file)?;
+    let dir = file.parent().map(PathBuf::from);
+    let db = query::Database::new(
+        &file.display().to_string(),
+        dir,
+        natives::Natives::prelude(),
+    );
+
+    let tree = cst::parse(&text)?;
+    let module = db.module()?;
+    print!("{}{}", dot::syntax_tree(&tree), dot::module(&module));
+    Ok(())
+}
+
+// compiler fmt [--check] [--indent N] [--width N] [FILE]...
+// formats the files in place, or stdin to stdout when there are none. With
+// --check nothing is written, the files that aren't formatted are listed and
+// the exit code is 1 if there are any
+fn fmt(args: Vec<String>) -> io::Result<()> {
+    let mut options = format::FormatOptions::default();
+    let mut check = false;
+    let mut files = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--indent" | "--width" => {
+                let value = args.next().and_then(|value| value.parse().ok());
+                let value = value.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} needs a number", arg),
+                    )
+                })?;
+                match arg.as_str() {
+                    "--indent" => options.indent = value,
+                    _ => options.width = value,
+                }
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    if !fmt_files(&files, options, check)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// whether every file was formatted already
+fn fmt_files(files: &[String], options: format::FormatOptions, check: bool) -> io::Result<bool> {
+    if files.is_empty() {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        let formatted = format::format_source(&text, options)?;
+        if !check {
+            print!("{}", formatted);
+        } else if formatted != text {
+            println!("<stdin>");
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+
+    let mut formatted_already = true;
+    for file in files {
+        let text = std::fs::read_to_string(file)?;
+        let formatted = format::format_source(&text, options)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", file, err)))?;
+        if formatted == text {
+            continue;
+        }
+        if check {
+            println!("{}", file);
+            formatted_already = false;
+        } else {
+            std::fs::write(file, formatted)?;
+        }
+    }
+    Ok(formatted_already)
 }
@@ -0,0 +1,808 @@
+use super::lexer::*;
+use super::operators::OperatorTable;
+use super::parser::*;
+use super::source::{Site, SourceMap, Span};
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+// the extension of module files, `mod b;` in module a loads a/b.lang from
+// the root file's directory, the root file itself can be named anything
+pub const EXTENSION: &str = "lang";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    // a global or a function
+    Value,
+    // a struct or an enum
+    Type,
+    // a child module, by its index
+    Module(usize),
+}
+
+#[derive(Debug)]
+struct Item {
+    name: String,
+    kind: Kind,
+    public: bool,
+}
+
+#[derive(Debug)]
+struct Module {
+    // the names of the modules from the root down to it, empty for the root
+    path: Vec<String>,
+    file: String,
+    // where the file starts in the source map
+    start: usize,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    stmts: Vec<SExpr>,
+    items: Vec<Item>,
+    imports: Vec<(Vec<String>, Span)>,
+    // what its imports bring into scope, by the name they're used with:
+    // the module owning the item and the item's kind
+    imported: Vec<(String, usize, Kind)>,
+    // the other modules it uses, which have to run before it
+    uses: Vec<usize>,
+}
+
+impl Module {
+    fn name(&self) -> String {
+        if self.path.is_empty() {
+            self.file.clone()
+        } else {
+            self.path.join("::")
+        }
+    }
+}
+
+// Loads the module tree starting at the root file and merges it into one
+// program. The items of module a::b are renamed to a::b::item, names are
+// resolved to those, and every module's top level runs after the modules it
+// uses so their functions and globals are defined before it needs them.
+pub fn load(root: &Path, operators: &OperatorTable, map: &mut SourceMap) -> Result<SExpr, Error> {
+    let text = std::fs::read_to_string(root)?;
+    let dir = root.parent().map(Path::to_path_buf).unwrap_or_default();
+    load_source(&root.display().to_string(), text, Some(dir), operators, map)
+}
+
+// loads a program from its root's text, without a directory to load its
+// modules from it can't declare any. The files are parsed with the custom
+// operators in `operators`
+pub fn load_source(
+    name: &str,
+    text: String,
+    dir: Option<PathBuf>,
+    operators: &OperatorTable,
+    map: &mut SourceMap,
+) -> Result<SExpr, Error> {
+    let mut files = Disk {
+        root: (name.to_string(), text),
+        operators: operators.clone(),
+    };
+    load_files(name, dir, map, &mut files)
+}
+
+// where the loader gets a program's files from
+pub trait Files {
+    // the text of a file
+    fn read(&mut self, name: &str) -> Result<String, Error>;
+    // the statements of a file whose text was read, their spans start at 0
+    // for the file's first character
+    fn parse(&mut self, name: &str, text: &str) -> Result<Vec<SExpr>, Error>;
+}
+
+// the root's text and the other files read from disk
+struct Disk {
+    root: (String, String),
+    operators: OperatorTable,
+}
+
+impl Files for Disk {
+    fn read(&mut self, name: &str) -> Result<String, Error> {
+        if name == self.root.0 {
+            return Ok(self.root.1.clone());
+        }
+        std::fs::read_to_string(name)
+    }
+
+    fn parse(&mut self, name: &str, text: &str) -> Result<Vec<SExpr>, Error> {
+        let lexer = Lexer::scan_source(text, 0, &self.operators.custom_symbols())
+            .map_err(|err| Error::new(err.kind(), format!("{}: {}", name, err)))?;
+        match Parser::with_operators(lexer, self.operators.clone()).parse() {
+            Ok(SExpr::Block(stmts)) => Ok(stmts),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(syntax_error(name, text, err)),
+        }
+    }
+}
+
+// a syntax error in a file with where in the file it is, when it's not at
+// the end
+pub fn syntax_error(name: &str, text: &str, err: SyntaxError) -> Error {
+    let mut map = SourceMap::new();
+    map.add(name.to_string(), text.to_string());
+    let location = err.span.and_then(|span| map.location(span.start));
+    let message = match location {
+        Some(location) => format!("{}: {}", location, err),
+        None => format!("{}: {}", name, err),
+    };
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+// loads the program whose root file is named `root` with its files from
+// `files`, modules are loaded from `dir`
+pub fn load_files(
+    root: &str,
+    dir: Option<PathBuf>,
+    map: &mut SourceMap,
+    files: &mut dyn Files,
+) -> Result<SExpr, Error> {
+    let text = files.read(root)?;
+    let mut loader = Loader {
+        map,
+        files,
+        dir,
+        modules: vec![],
+        current: 0,
+        generics: vec![],
+    };
+    loader.load_module(root.to_string(), text, vec![], None)?;
+
+    for i in 0..loader.modules.len() {
+        loader.resolve_imports(i)?;
+    }
+    let mut stmts_by_module = vec![];
+    for i in 0..loader.modules.len() {
+        loader.current = i;
+        let stmts = std::mem::take(&mut loader.modules[i].stmts);
+        let mut resolved = vec![];
+        for stmt in stmts {
+            resolved.push(loader.expr(stmt, &mut vec![])?);
+        }
+        stmts_by_module.push(resolved);
+    }
+
+    // children run before their parent unless they use it
+    let mut tree = vec![];
+    loader.children_first(0, &mut tree);
+    let mut order = vec![];
+    for id in tree {
+        loader.order(id, &mut order, &mut vec![])?;
+    }
+    let mut program = vec![];
+    for i in order {
+        program.append(&mut stmts_by_module[i]);
+    }
+    Ok(SExpr::Block(program))
+}
+
+struct Loader<'a> {
+    map: &'a mut SourceMap,
+    files: &'a mut dyn Files,
+    // where module files are loaded from, None if they can't be
+    dir: Option<PathBuf>,
+    modules: Vec<Module>,
+    // the module whose statements are being resolved
+    current: usize,
+    // the type parameters of the declarations around the type being resolved
+    generics: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn load_module(
+        &mut self,
+        name: String,
+        text: String,
+        path: Vec<String>,
+        parent: Option<usize>,
+    ) -> Result<usize, Error> {
+        let start = self.map.add(name.clone(), text.clone());
+        let stmts = self.files.parse(&name, &text)?;
+
+        let id = self.modules.len();
+        self.modules.push(Module {
+            path: path.clone(),
+            file: name,
+            start,
+            parent,
+            children: vec![],
+            stmts: vec![],
+            items: vec![],
+            imports: vec![],
+            imported: vec![],
+            uses: vec![],
+        });
+
+        let mut kept = vec![];
+        for stmt in stmts {
+            let stmt = moved(stmt, start);
+            let (stmt, public) = match stmt {
+                SExpr::Pub(item) => (*item, true),
+                stmt => (stmt, false),
+            };
+            let (name, kind) = match &stmt {
+                SExpr::Mod(name, span) => {
+                    if self.modules[id]
+                        .children
+                        .iter()
+                        .any(|c| self.modules[*c].path.last() == Some(name))
+                    {
+                        return Err(self.error(*span, format!("module {} is declared twice", name)));
+                    }
+                    let mut child_path = path.clone();
+                    child_path.push(name.clone());
+                    let mut child_file = match &self.dir {
+                        Some(dir) => dir.clone(),
+                        None => {
+                            return Err(self.error(
+                                *span,
+                                format!("can't load module {} without a directory", name),
+                            ))
+                        }
+                    };
+                    child_file.extend(&child_path);
+                    child_file.set_extension(EXTENSION);
+                    let child_name = child_file.display().to_string();
+                    let text = self.files.read(&child_name).map_err(|err| {
+                        self.error(
+                            *span,
+                            format!(
+                                "can't read module {} from {}: {}",
+                                child_path.join("::"),
+                                child_name,
+                                err
+                            ),
+                        )
+                    })?;
+
+                    let child = self.load_module(child_name, text, child_path, Some(id))?;
+                    self.modules[id].children.push(child);
+                    (name.clone(), Kind::Module(child))
+                }
+                SExpr::Import(import, span) => {
+                    self.modules[id].imports.push((import.clone(), *span));
+                    continue;
+                }
+                SExpr::StructDef(name, ..) | SExpr::EnumDef(name, _) => (name.clone(), Kind::Type),
+                _ => {
+                    let mut names = vec![];
+                    assigned_names(&stmt, &mut names);
+                    for name in names {
+                        let items = &mut self.modules[id].items;
+                        match items
+                            .iter_mut()
+                            .find(|item| item.name == name && item.kind == Kind::Value)
+                        {
+                            Some(item) => item.public |= public,
+                            None => items.push(Item {
+                                name,
+                                kind: Kind::Value,
+                                public,
+                            }),
+                        }
+                    }
+                    kept.push(stmt);
+                    continue;
+                }
+            };
+            self.modules[id].items.push(Item { name, kind, public });
+            if !matches!(stmt, SExpr::Mod(..)) {
+                kept.push(stmt);
+            }
+        }
+        self.modules[id].stmts = kept;
+        Ok(id)
+    }
+
+    fn error(&self, span: Span, message: String) -> Error {
+        let location = match self.map.location(span.start) {
+            Some(location) => format!("{}: ", location),
+            None => String::new(),
+        };
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{}MODULE ERROR: {}", location, message),
+        )
+    }
+
+    fn resolve_imports(&mut self, id: usize) -> Result<(), Error> {
+        for (path, span) in std::mem::take(&mut self.modules[id].imports) {
+            let (name, prefix) = path.split_last().unwrap();
+            let owner = if prefix.is_empty() {
+                // import a; brings the root's a into scope
+                0
+            } else {
+                self.module_path(id, prefix)
+                    .map_err(|message| self.error(span, message))?
+            };
+
+            let mut found = false;
+            for kind in [Kind::Value, Kind::Type, Kind::Module(0)] {
+                if let Some(kind) = self
+                    .own_item(id, owner, name, kind)
+                    .map_err(|message| self.error(span, message))?
+                {
+                    found = true;
+                    if self.in_scope(id, name, kind).is_some() {
+                        return Err(self.error(
+                            span,
+                            format!(
+                                "import of {} clashes with another item named {}",
+                                path.join("::"),
+                                name
+                            ),
+                        ));
+                    }
+                    self.modules[id].imported.push((name.clone(), owner, kind));
+                    self.add_use(id, owner);
+                }
+            }
+            if !found {
+                return Err(self.error(
+                    span,
+                    format!("module {} has no item {}", self.modules[owner].name(), name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // the item named `name` of the kind `kind` (any module for a Module kind)
+    // that module `owner` declares, Err if module `from` can't see it
+    fn own_item(
+        &self,
+        from: usize,
+        owner: usize,
+        name: &str,
+        kind: Kind,
+    ) -> Result<Option<Kind>, String> {
+        let item = self.modules[owner]
+            .items
+            .iter()
+            .find(|item| item.name == name && same_kind(item.kind, kind));
+        match item {
+            Some(item) if item.public || self.is_within(from, owner) => Ok(Some(item.kind)),
+            Some(_) => Err(format!(
+                "{} is private to module {}",
+                name,
+                self.modules[owner].name()
+            )),
+            None => Ok(None),
+        }
+    }
+
+    // where a name used in module `id` comes from: its own items, then what
+    // it imports, as the owning module and the item's kind
+    fn in_scope(&self, id: usize, name: &str, kind: Kind) -> Option<(usize, Kind)> {
+        let module = &self.modules[id];
+        if let Some(item) = module
+            .items
+            .iter()
+            .find(|item| item.name == name && same_kind(item.kind, kind))
+        {
+            return Some((id, item.kind));
+        }
+        module
+            .imported
+            .iter()
+            .find(|(n, _, k)| n == name && same_kind(*k, kind))
+            .map(|(_, owner, kind)| (*owner, *kind))
+    }
+
+    // the module a path names, its first name is looked up in module `from`
+    // and then in the root
+    fn module_path(&self, from: usize, path: &[String]) -> Result<usize, String> {
+        let first = self
+            .in_scope(from, &path[0], Kind::Module(0))
+            .or_else(|| self.in_scope(0, &path[0], Kind::Module(0)));
+        let mut module = match first {
+            Some((_, Kind::Module(module))) => module,
+            _ => return Err(format!("there is no module {}", path[0])),
+        };
+        for name in &path[1..] {
+            module = match self.own_item(from, module, name, Kind::Module(0))? {
+                Some(Kind::Module(child)) => child,
+                _ => {
+                    return Err(format!(
+                        "module {} has no module {}",
+                        self.modules[module].name(),
+                        name
+                    ))
+                }
+            };
+        }
+        Ok(module)
+    }
+
+    // private items are visible in their module and the modules inside it
+    fn is_within(&self, id: usize, ancestor: usize) -> bool {
+        let mut module = Some(id);
+        while let Some(m) = module {
+            if m == ancestor {
+                return true;
+            }
+            module = self.modules[m].parent;
+        }
+        false
+    }
+
+    fn add_use(&mut self, id: usize, used: usize) {
+        if id != used && !self.modules[id].uses.contains(&used) {
+            self.modules[id].uses.push(used);
+        }
+    }
+
+    // the name an item of module `owner` has in the merged program
+    fn mangle(&self, owner: usize, name: &str) -> String {
+        let path = &self.modules[owner].path;
+        if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}::{}", path.join("::"), name)
+        }
+    }
+
+    // the item a qualified path like a::b::item names, errors are put at
+    // the site when the path has one
+    fn qualified(
+        &mut self,
+        path: &[String],
+        name: &str,
+        kind: Kind,
+        site: &Site,
+    ) -> Result<String, Error> {
+        let id = self.current;
+        let message = match self.module_path(id, path) {
+            Ok(owner) => match self.own_item(id, owner, name, kind) {
+                Ok(Some(_)) => {
+                    self.add_use(id, owner);
+                    return Ok(self.mangle(owner, name));
+                }
+                Ok(None) => format!("module {} has no item {}", self.modules[owner].name(), name),
+                Err(message) => message,
+            },
+            Err(message) => message,
+        };
+        let message = format!("in module {}: {}", self.modules[id].name(), message);
+        Err(match site.0 {
+            Some(span) => self.error(span, message),
+            None => Error::new(
+                ErrorKind::InvalidInput,
+                format!("MODULE ERROR: {}", message),
+            ),
+        })
+    }
+
+    // an unqualified name, names nothing in scope declares are left alone
+    fn unqualified(&mut self, name: &str, kind: Kind) -> String {
+        match self.in_scope(self.current, name, kind) {
+            Some((owner, _)) => {
+                self.add_use(self.current, owner);
+                self.mangle(owner, name)
+            }
+            None => name.to_string(),
+        }
+    }
+
+    fn value(&mut self, name: String, bound: &[String]) -> String {
+        if bound.contains(&name) {
+            name
+        } else {
+            self.unqualified(&name, Kind::Value)
+        }
+    }
+
+    // a type name, which may be a path
+    fn type_name(&mut self, name: &str) -> Result<String, Error> {
+        let path: Vec<String> = name.split("::").map(String::from).collect();
+        match path.split_last() {
+            Some((name, [])) if self.generics.contains(name) => Ok(name.clone()),
+            Some((name, [])) => Ok(self.unqualified(name, Kind::Type)),
+            Some((name, prefix)) => self.qualified(prefix, name, Kind::Type, &Site::default()),
+            None => unreachable!(),
+        }
+    }
+
+    fn type_expr(&mut self, t: TypeExpr) -> Result<TypeExpr, Error> {
+        Ok(match t {
+            TypeExpr::Named(name, args) => TypeExpr::Named(
+                self.type_name(&name)?,
+                args.into_iter()
+                    .map(|t| self.type_expr(t))
+                    .collect::<Result<_, _>>()?,
+            ),
+            TypeExpr::Array(elem) => TypeExpr::Array(Box::new(self.type_expr(*elem)?)),
+            TypeExpr::Fn(params, ret) => TypeExpr::Fn(
+                params
+                    .into_iter()
+                    .map(|t| self.type_expr(t))
+                    .collect::<Result<_, _>>()?,
+                Box::new(self.type_expr(*ret)?),
+            ),
+        })
+    }
+
+    fn fields<T>(
+        &mut self,
+        fields: Fields<T>,
+        mut resolve: impl FnMut(&mut Self, T) -> Result<T, Error>,
+    ) -> Result<Fields<T>, Error> {
+        Ok(match fields {
+            Fields::Unit => Fields::Unit,
+            Fields::Tuple(values) => Fields::Tuple(
+                values
+                    .into_iter()
+                    .map(|value| resolve(self, value))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Fields::Struct(values) => Fields::Struct(
+                values
+                    .into_iter()
+                    .map(|(field, value)| Ok((field, resolve(self, value)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+        })
+    }
+
+    fn pattern(&mut self, pattern: Pattern) -> Result<Pattern, Error> {
+        Ok(match pattern {
+            Pattern::Variant(name, variant, fields, rest) => Pattern::Variant(
+                self.type_name(&name)?,
+                variant,
+                self.fields(fields, Self::pattern)?,
+                rest,
+            ),
+            pattern => pattern,
+        })
+    }
+
+    fn exprs(&mut self, exprs: Vec<SExpr>, bound: &mut Vec<String>) -> Result<Vec<SExpr>, Error> {
+        exprs
+            .into_iter()
+            .map(|expr| self.expr(expr, bound))
+            .collect()
+    }
+
+    // a site in the file being resolved moved to where it is in the map
+    fn moved(&self, site: Site) -> Site {
+        site.moved(self.modules[self.current].start)
+    }
+
+    fn boxed(&mut self, expr: SExpr, bound: &mut Vec<String>) -> Result<Box<SExpr>, Error> {
+        Ok(Box::new(self.expr(expr, bound)?))
+    }
+
+    // renames every use of a module item in expr, `bound` holds the locals
+    // and match bindings in scope, which shadow items
+    fn expr(&mut self, expr: SExpr, bound: &mut Vec<String>) -> Result<SExpr, Error> {
+        Ok(match expr {
+            SExpr::Atom(Token::IDENT(name)) => SExpr::Atom(Token::IDENT(self.value(name, bound))),
+            SExpr::Atom(token) => SExpr::Atom(token),
+            SExpr::Cons(op, cons, site) => {
+                SExpr::Cons(op, self.exprs(cons, bound)?, self.moved(site))
+            }
+            SExpr::Block(stmts) => SExpr::Block(self.exprs(stmts, bound)?),
+            SExpr::If(cond, then, els, site) => SExpr::If(
+                self.boxed(*cond, bound)?,
+                self.boxed(*then, bound)?,
+                match els {
+                    Some(els) => Some(self.boxed(*els, bound)?),
+                    None => None,
+                },
+                self.moved(site),
+            ),
+            SExpr::While(cond, body, site) => SExpr::While(
+                self.boxed(*cond, bound)?,
+                self.boxed(*body, bound)?,
+                self.moved(site),
+            ),
+            SExpr::Array(elems) => SExpr::Array(self.exprs(elems, bound)?),
+            SExpr::Index(array, index, site) => SExpr::Index(
+                self.boxed(*array, bound)?,
+                self.boxed(*index, bound)?,
+                self.moved(site),
+            ),
+            SExpr::Slice(array, lo, hi, site) => SExpr::Slice(
+                self.boxed(*array, bound)?,
+                match lo {
+                    Some(lo) => Some(self.boxed(*lo, bound)?),
+                    None => None,
+                },
+                match hi {
+                    Some(hi) => Some(self.boxed(*hi, bound)?),
+                    None => None,
+                },
+                self.moved(site),
+            ),
+            SExpr::StructDef(name, generics, fields) => {
+                let depth = self.generics.len();
+                self.generics.extend(generics.iter().cloned());
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, t)| Ok((field, self.type_expr(t)?)))
+                    .collect::<Result<_, Error>>();
+                self.generics.truncate(depth);
+                SExpr::StructDef(self.type_name(&name)?, generics, fields?)
+            }
+            SExpr::StructLit(name, values) => SExpr::StructLit(
+                self.type_name(&name)?,
+                values
+                    .into_iter()
+                    .map(|(field, value)| Ok((field, self.expr(value, bound)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            SExpr::Field(value, field, site) => {
+                SExpr::Field(self.boxed(*value, bound)?, field, self.moved(site))
+            }
+            SExpr::EnumDef(name, variants) => SExpr::EnumDef(
+                self.type_name(&name)?,
+                variants
+                    .into_iter()
+                    .map(|(variant, fields)| Ok((variant, self.fields(fields, Self::type_expr)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            SExpr::Variant(path, variant, fields, site) => {
+                self.variant(path, variant, fields, self.moved(site), bound)?
+            }
+            SExpr::Match(value, arms, site) => {
+                let value = self.boxed(*value, bound)?;
+                let mut resolved = vec![];
+                for (pattern, arm) in arms {
+                    let outer = bound.len();
+                    pattern_names(&pattern, bound);
+                    let arm = self.expr(arm, bound);
+                    bound.truncate(outer);
+                    resolved.push((self.pattern(pattern)?, arm?));
+                }
+                SExpr::Match(value, resolved, self.moved(site))
+            }
+            SExpr::Fn(name, sig, body) => {
+                let name = name.map(|name| self.value(name, bound));
+
+                let depth = self.generics.len();
+                self.generics.extend(sig.generics.iter().cloned());
+                let sig = self.sig(sig);
+                let mut locals = bound.clone();
+                locals.extend(sig.as_ref().map(FnSig::param_names).unwrap_or_default());
+                assigned_names(&body, &mut locals);
+                let body = self.boxed(*body, &mut locals);
+                self.generics.truncate(depth);
+
+                SExpr::Fn(name, sig?, body?)
+            }
+            SExpr::Call(callee, args, site) => SExpr::Call(
+                self.boxed(*callee, bound)?,
+                self.exprs(args, bound)?,
+                self.moved(site),
+            ),
+            // the parser only allows these at the top level, where the
+            // loader has taken them out
+            SExpr::Mod(..) | SExpr::Import(..) | SExpr::Pub(_) => unreachable!(),
+        })
+    }
+
+    fn sig(&mut self, sig: FnSig) -> Result<FnSig, Error> {
+        let mut params = vec![];
+        for (param, t) in sig.params {
+            let t = match t {
+                Some(t) => Some(self.type_expr(t)?),
+                None => None,
+            };
+            params.push((param, t));
+        }
+        let ret = match sig.ret {
+            Some(t) => Some(self.type_expr(t)?),
+            None => None,
+        };
+        Ok(FnSig {
+            generics: sig.generics,
+            params,
+            ret,
+        })
+    }
+
+    // a::b(x) parses like an enum variant, it's a call when a names a module
+    fn variant(
+        &mut self,
+        path: String,
+        name: String,
+        fields: Fields<SExpr>,
+        site: Site,
+        bound: &mut Vec<String>,
+    ) -> Result<SExpr, Error> {
+        let path: Vec<String> = path.split("::").map(String::from).collect();
+        if self.module_path(self.current, &path).is_err() {
+            let fields = self.fields(fields, |loader, value| loader.expr(value, bound))?;
+            return Ok(SExpr::Variant(
+                self.type_name(&path.join("::"))?,
+                name,
+                fields,
+                site,
+            ));
+        }
+
+        Ok(match fields {
+            Fields::Unit => SExpr::Atom(Token::IDENT(self.qualified(
+                &path,
+                &name,
+                Kind::Value,
+                &site,
+            )?)),
+            Fields::Tuple(args) => {
+                let callee = SExpr::Atom(Token::IDENT(self.qualified(
+                    &path,
+                    &name,
+                    Kind::Value,
+                    &site,
+                )?));
+                SExpr::Call(Box::new(callee), self.exprs(args, bound)?, site)
+            }
+            Fields::Struct(values) => SExpr::StructLit(
+                self.qualified(&path, &name, Kind::Type, &site)?,
+                values
+                    .into_iter()
+                    .map(|(field, value)| Ok((field, self.expr(value, bound)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+        })
+    }
+
+    // puts module `id` after the modules it uses, `visiting` holds the
+    // modules whose uses are being ordered, meeting one again is a cycle
+    fn order(
+        &self,
+        id: usize,
+        order: &mut Vec<usize>,
+        visiting: &mut Vec<usize>,
+    ) -> Result<(), Error> {
+        if order.contains(&id) {
+            return Ok(());
+        }
+        if let Some(start) = visiting.iter().position(|m| *m == id) {
+            let mut cycle: Vec<String> = visiting[start..]
+                .iter()
+                .map(|m| self.modules[*m].name())
+                .collect();
+            cycle.push(self.modules[id].name());
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("MODULE ERROR: cyclic import {}", cycle.join(" -> ")),
+            ));
+        }
+
+        visiting.push(id);
+        for used in &self.modules[id].uses {
+            self.order(*used, order, visiting)?;
+        }
+        visiting.pop();
+        order.push(id);
+        Ok(())
+    }
+
+    fn children_first(&self, id: usize, tree: &mut Vec<usize>) {
+        for child in &self.modules[id].children {
+            self.children_first(*child, tree);
+        }
+        tree.push(id);
+    }
+}
+
+// a top level statement of a file with its spans moved to where the file
+// starts in the source map
+fn moved(stmt: SExpr, start: usize) -> SExpr {
+    let span = |span: Span| Span {
+        start: span.start + start,
+        end: span.end + start,
+    };
+    match stmt {
+        SExpr::Mod(name, s) => SExpr::Mod(name, span(s)),
+        SExpr::Import(path, s) => SExpr::Import(path, span(s)),
+        SExpr::Pub(item) => SExpr::Pub(Box::new(moved(*item, start))),
+        stmt => stmt,
+    }
+}
+
+// a Module kind matches any module
+fn same_kind(a: Kind, b: Kind) -> bool {
+    matches!((a, b), (Kind::Module(_), Kind::Module(_))) || a == b
+}
@@ -0,0 +1,226 @@
+use super::typeck::Type;
+use super::vm::Value;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+// the error a native function fails with is reported as a runtime error
+pub type NativeFn = Rc<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+// where print, println and read_line go, a host can give a program its own
+// instead of the process's stdin and stdout
+pub trait Io {
+    fn write(&mut self, text: &str) -> io::Result<()>;
+    // the next line with its line break, empty at the end of the input
+    fn read_line(&mut self) -> io::Result<String>;
+}
+
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        Ok(line)
+    }
+}
+
+// a built-in function implemented in Rust
+pub struct Native {
+    pub name: String,
+    // the type the checker gives it, a function type or a generic one
+    pub ty: Type,
+    pub func: NativeFn,
+}
+
+impl Native {
+    pub fn arity(&self) -> usize {
+        match &self.ty {
+            Type::Fn(params, _) => params.len(),
+            Type::Generic(_, t) => match &**t {
+                Type::Fn(params, _) => params.len(),
+                _ => 0,
+            },
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({}: {})", self.name, self.ty)
+    }
+}
+
+// The built-in functions a program can call. They are globals the program
+// may shadow, the type checker knows their types and the VM puts them in
+// the globals the program uses.
+#[derive(Debug, Default, Clone)]
+pub struct Natives {
+    natives: Vec<Rc<Native>>,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the standard library every program gets, using stdin and stdout
+    pub fn prelude() -> Self {
+        Self::prelude_with(Rc::new(RefCell::new(StdIo)))
+    }
+
+    pub fn prelude_with(io: Rc<RefCell<dyn Io>>) -> Self {
+        let mut natives = Self::new();
+        let out = io.clone();
+        natives.register(
+            "print",
+            func(vec![Type::Unknown], Type::Void),
+            move |args| {
+                let text = args[0].to_string();
+                out.borrow_mut()
+                    .write(&text)
+                    .map_err(|err| err.to_string())?;
+                Ok(Value::Void)
+            },
+        );
+        let out = io.clone();
+        natives.register(
+            "println",
+            func(vec![Type::Unknown], Type::Void),
+            move |args| {
+                let text = format!("{}\n", args[0]);
+                out.borrow_mut()
+                    .write(&text)
+                    .map_err(|err| err.to_string())?;
+                Ok(Value::Void)
+            },
+        );
+        // the line without its line break
+        natives.register("read_line", func(vec![], Type::Str), move |_| {
+            let line = io.borrow_mut().read_line().map_err(|err| err.to_string())?;
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            Ok(Value::Str(Rc::from(line)))
+        });
+        natives.register("len", func(vec![Type::Unknown], Type::Int), |args| {
+            let len = match &args[0] {
+                Value::Array(elems) => elems.borrow().len(),
+                Value::Str(s) => s.chars().count(),
+                other => {
+                    return Err(format!(
+                        "a value of type {} has no length",
+                        other.type_name()
+                    ))
+                }
+            };
+            Ok(Value::Int(len as i32))
+        });
+        natives.register(
+            "push",
+            generic(
+                vec![Type::Array(Box::new(param("T"))), param("T")],
+                Type::Void,
+            ),
+            |args| match &args[0] {
+                Value::Array(elems) => {
+                    elems.borrow_mut().push(args[1].clone());
+                    Ok(Value::Void)
+                }
+                other => Err(format!(
+                    "can't push onto a value of type {}",
+                    other.type_name()
+                )),
+            },
+        );
+        natives.register(
+            "sqrt",
+            func(vec![Type::Float], Type::Float),
+            |args| match &args[0] {
+                Value::Float(n) => Ok(Value::Float(n.sqrt())),
+                Value::Int(n) => Ok(Value::Float((*n as f32).sqrt())),
+                other => Err(format!(
+                    "can't take the square root of a value of type {}",
+                    other.type_name()
+                )),
+            },
+        );
+        natives.register(
+            "abs",
+            generic(vec![param("T")], param("T")),
+            |args| match &args[0] {
+                Value::Int(n) => n
+                    .checked_abs()
+                    .map(Value::Int)
+                    .ok_or_else(|| String::from("integer overflow")),
+                Value::Float(n) => Ok(Value::Float(n.abs())),
+                other => Err(format!(
+                    "can't take the absolute value of a value of type {}",
+                    other.type_name()
+                )),
+            },
+        );
+        natives.register(
+            "to_int",
+            func(vec![Type::Str], Type::Int),
+            |args| match &args[0] {
+                Value::Str(s) => s
+                    .trim()
+                    .parse()
+                    .map(Value::Int)
+                    .map_err(|_| format!("can't convert {:?} to an int", s)),
+                other => Err(format!(
+                    "can't convert a value of type {} to an int",
+                    other.type_name()
+                )),
+            },
+        );
+        natives.register("to_string", func(vec![Type::Unknown], Type::Str), |args| {
+            Ok(Value::Str(Rc::from(args[0].to_string())))
+        });
+        natives
+    }
+
+    // a function registered again replaces the old one
+    pub fn register(
+        &mut self,
+        name: &str,
+        ty: Type,
+        func: impl Fn(&[Value]) -> Result<Value, String> + 'static,
+    ) {
+        self.natives.retain(|native| native.name != name);
+        self.natives.push(Rc::new(Native {
+            name: name.to_string(),
+            ty,
+            func: Rc::new(func),
+        }));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Rc<Native>> {
+        self.natives.iter().find(|native| native.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<Native>> {
+        self.natives.iter()
+    }
+}
+
+fn func(params: Vec<Type>, ret: Type) -> Type {
+    Type::Fn(params, Box::new(ret))
+}
+
+// a function generic over T
+fn generic(params: Vec<Type>, ret: Type) -> Type {
+    Type::Generic(vec![String::from("T")], Box::new(func(params, ret)))
+}
+
+fn param(name: &str) -> Type {
+    Type::Param(name.to_string())
+}
@@ -0,0 +1,270 @@
+use super::lexer::*;
+use std::io::{Error, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fixity {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub token: Token,
+    pub fixity: Fixity,
+    // higher levels bind tighter
+    pub precedence: u8,
+    pub assoc: Assoc,
+    // the global function a custom operator calls with its operands, a
+    // native or one of the root file's
+    pub function: Option<String>,
+}
+
+// the highest precedence level whose binding powers still fit in a u8
+pub const MAX_PRECEDENCE: u8 = 126;
+
+// The built in levels, from loosest to tightest:
+//   0  = += -= *= /= %= **= &= |= ^= <<= >>=   (right)
+//   1  ||
+//   2  &&
+//   3  == != < <= > >=
+//   4  |
+//   5  ^
+//   6  &
+//   7  << >>
+//   8  + -
+//   9  * / %
+//   10 **
+//   11 prefix + - ** ! ~
+//   12 postfix [ . (
+#[derive(Debug, Clone)]
+pub struct OperatorTable {
+    operators: Vec<Operator>,
+}
+
+impl Default for OperatorTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OperatorTable {
+    pub fn new() -> Self {
+        let mut table = Self { operators: vec![] };
+        let mut builtin = |symbol: &str, fixity, precedence, assoc| {
+            let token = Token::from_symbol(symbol).unwrap();
+            table.insert(token, fixity, precedence, assoc, None);
+        };
+
+        let infix = [
+            (
+                0,
+                Assoc::Right,
+                vec!["=", "+=", "-=", "*=", "/=", "%=", "**="],
+            ),
+            (0, Assoc::Right, vec!["&=", "|=", "^=", "<<=", ">>="]),
+            (1, Assoc::Left, vec!["||"]),
+            (2, Assoc::Left, vec!["&&"]),
+            (3, Assoc::Left, vec!["==", "!=", "<", "<=", ">", ">="]),
+            (4, Assoc::Left, vec!["|"]),
+            (5, Assoc::Left, vec!["^"]),
+            (6, Assoc::Left, vec!["&"]),
+            (7, Assoc::Left, vec!["<<", ">>"]),
+            (8, Assoc::Left, vec!["+", "-"]),
+            (9, Assoc::Left, vec!["*", "/", "%"]),
+            (10, Assoc::Left, vec!["**"]),
+        ];
+        for (precedence, assoc, symbols) in infix.iter() {
+            for symbol in symbols {
+                builtin(symbol, Fixity::Infix, *precedence, *assoc);
+            }
+        }
+
+        for symbol in ["+", "-", "**", "!", "~"].iter() {
+            builtin(symbol, Fixity::Prefix, 11, Assoc::Right);
+        }
+        builtin("[", Fixity::Postfix, 12, Assoc::Left);
+        builtin(".", Fixity::Postfix, 12, Assoc::Left);
+        builtin("(", Fixity::Postfix, 12, Assoc::Left);
+
+        table
+    }
+
+    pub fn register_infix(
+        &mut self,
+        symbol: &str,
+        precedence: u8,
+        assoc: Assoc,
+    ) -> Result<(), Error> {
+        self.register(symbol, Fixity::Infix, precedence, assoc, None)
+    }
+
+    pub fn register_prefix(&mut self, symbol: &str, precedence: u8) -> Result<(), Error> {
+        self.register(symbol, Fixity::Prefix, precedence, Assoc::Right, None)
+    }
+
+    pub fn register_postfix(&mut self, symbol: &str, precedence: u8) -> Result<(), Error> {
+        self.register(symbol, Fixity::Postfix, precedence, Assoc::Left, None)
+    }
+
+    // a custom operator, `a |> b` calls `function` with a and b
+    pub fn define_infix(
+        &mut self,
+        symbol: &str,
+        precedence: u8,
+        assoc: Assoc,
+        function: &str,
+    ) -> Result<(), Error> {
+        self.define(symbol, Fixity::Infix, precedence, assoc, function)
+    }
+
+    pub fn define_prefix(
+        &mut self,
+        symbol: &str,
+        precedence: u8,
+        function: &str,
+    ) -> Result<(), Error> {
+        self.define(symbol, Fixity::Prefix, precedence, Assoc::Right, function)
+    }
+
+    pub fn define_postfix(
+        &mut self,
+        symbol: &str,
+        precedence: u8,
+        function: &str,
+    ) -> Result<(), Error> {
+        self.define(symbol, Fixity::Postfix, precedence, Assoc::Left, function)
+    }
+
+    // the built in operators and symbols keep their own meaning
+    fn define(
+        &mut self,
+        symbol: &str,
+        fixity: Fixity,
+        precedence: u8,
+        assoc: Assoc,
+        function: &str,
+    ) -> Result<(), Error> {
+        if Token::from_symbol(symbol).is_some() {
+            return Err(operator_error(format!("{:?} is a built in symbol", symbol)));
+        }
+        let function = Some(function.to_string());
+        self.register(symbol, fixity, precedence, assoc, function)
+    }
+
+    fn register(
+        &mut self,
+        symbol: &str,
+        fixity: Fixity,
+        precedence: u8,
+        assoc: Assoc,
+        function: Option<String>,
+    ) -> Result<(), Error> {
+        if precedence > MAX_PRECEDENCE {
+            return Err(operator_error(format!(
+                "Precedence {} is above the maximum of {}",
+                precedence, MAX_PRECEDENCE
+            )));
+        }
+        let token = match Token::from_symbol(symbol) {
+            Some(token) => token,
+            None if is_operator_symbol(symbol) => Token::OP(symbol.to_string()),
+            None => {
+                return Err(operator_error(format!(
+                    "{:?} can't be used as an operator",
+                    symbol
+                )))
+            }
+        };
+        self.insert(token, fixity, precedence, assoc, function);
+        Ok(())
+    }
+
+    // registering a token again with the same fixity replaces the old entry
+    fn insert(
+        &mut self,
+        token: Token,
+        fixity: Fixity,
+        precedence: u8,
+        assoc: Assoc,
+        function: Option<String>,
+    ) {
+        self.operators
+            .retain(|op| !(op.token == token && op.fixity == fixity));
+        self.operators.push(Operator {
+            token,
+            fixity,
+            precedence,
+            assoc,
+            function,
+        });
+    }
+
+    pub fn get(&self, token: &Token, fixity: Fixity) -> Option<&Operator> {
+        self.operators
+            .iter()
+            .find(|op| &op.token == token && op.fixity == fixity)
+    }
+
+    // the function an operation with a custom operator calls given how many
+    // operands it has, a unary one is prefix when the symbol is both like
+    // the formatter prints it
+    pub fn function(&self, token: &Token, operands: usize) -> Option<&str> {
+        let fixity = match operands {
+            2 => Fixity::Infix,
+            _ if self.get(token, Fixity::Prefix).is_some() => Fixity::Prefix,
+            _ => Fixity::Postfix,
+        };
+        self.get(token, fixity)?.function.as_deref()
+    }
+
+    // the symbols the lexer has to know about on top of its own, for passing
+    // to Lexer::scan_source
+    pub fn custom_symbols(&self) -> Vec<String> {
+        self.operators
+            .iter()
+            .filter_map(|op| match &op.token {
+                Token::OP(symbol) => Some(symbol.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn infix_binding_power(&self, token: &Token) -> Option<(u8, u8)> {
+        let op = self.get(token, Fixity::Infix)?;
+        let bp = op.precedence * 2 + 1;
+        match op.assoc {
+            Assoc::Left => Some((bp, bp + 1)),
+            Assoc::Right => Some((bp + 1, bp)),
+        }
+    }
+
+    pub fn prefix_binding_power(&self, token: &Token) -> Option<u8> {
+        self.get(token, Fixity::Prefix)
+            .map(|op| op.precedence * 2 + 1)
+    }
+
+    pub fn postfix_binding_power(&self, token: &Token) -> Option<u8> {
+        self.get(token, Fixity::Postfix)
+            .map(|op| op.precedence * 2 + 1)
+    }
+}
+
+// custom operators are runs of punctuation that can't be confused with
+// brackets, strings, separators or identifiers
+fn is_operator_symbol(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_punctuation() && !"()[]{}\",;_".contains(c))
+}
+
+fn operator_error(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("ERROR: {}", message))
+}
@@ -1,89 +1,1652 @@
+use super::cst::NodeKind;
 use super::lexer::*;
+use super::operators::*;
+use super::source::{Site, Span};
 use std::fmt::{self, Display};
+use std::io::{Error, ErrorKind};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SExpr {
     Atom(Token),
-    Cons(Token, Vec<SExpr>),
+    // an operation and where it is, as for the other expressions that can
+    // fail at runtime
+    Cons(Token, Vec<SExpr>, Site),
+    Block(Vec<SExpr>),
+    // the site is the if or while and its condition, which fails if it
+    // isn't a bool
+    If(Box<SExpr>, Box<SExpr>, Option<Box<SExpr>>, Site),
+    While(Box<SExpr>, Box<SExpr>, Site),
+    Array(Vec<SExpr>),
+    Index(Box<SExpr>, Box<SExpr>, Site),
+    Slice(Box<SExpr>, Option<Box<SExpr>>, Option<Box<SExpr>>, Site),
+    // a struct's name, its type parameters and its fields
+    StructDef(String, Vec<String>, Vec<(String, TypeExpr)>),
+    StructLit(String, Vec<(String, SExpr)>),
+    Field(Box<SExpr>, String, Site),
+    EnumDef(String, Vec<(String, Fields<TypeExpr>)>),
+    // Enum::Variant with its payload, or module::item(args) before the
+    // module loader makes it a call at the site
+    Variant(String, String, Fields<SExpr>, Site),
+    // the site is the match and its value, which fails if no arm matches
+    Match(Box<SExpr>, Vec<(Pattern, SExpr)>, Site),
+    // a named function declaration or, without a name, a lambda
+    Fn(Option<String>, FnSig, Box<SExpr>),
+    // the callee, the arguments and where the call is
+    Call(Box<SExpr>, Vec<SExpr>, Site),
+    // mod name; declares a module in another file, import a::b; brings an
+    // item of another module into scope, both only appear at the top level
+    // and are resolved away by the module loader
+    Mod(String, Span),
+    Import(Vec<String>, Span),
+    // a top level fn, struct, enum or mod other modules may use
+    Pub(Box<SExpr>),
+}
+
+// the payload of an enum variant, its declaration or a pattern matching it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fields<T> {
+    Unit,
+    Tuple(Vec<T>),
+    Struct(Vec<(String, T)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Binding(String),
+    // an INT, FLOAT, STR or BOOL
+    Literal(Token),
+    // the flag is set when a struct pattern ends in .. and may leave out
+    // fields
+    Variant(String, String, Fields<Pattern>, bool),
+}
+
+impl<T: Display> Fields<T> {
+    fn fmt_fields(&self, f: &mut fmt::Formatter<'_>, rest: bool) -> fmt::Result {
+        match self {
+            Fields::Unit => Ok(()),
+            Fields::Tuple(elems) => {
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, ")")
+            }
+            Fields::Struct(fields) => {
+                write!(f, " {{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}: {}", name, value)?;
+                }
+                if rest {
+                    write!(f, "{} ..", if fields.is_empty() { "" } else { "," })?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Binding(name) => write!(f, "{}", name),
+            Pattern::Literal(token) => match token {
+                Token::INT(n) => write!(f, "{}", n),
+                Token::FLOAT(n) => write!(f, "{:?}", n),
+                Token::STR(s) => write!(f, "{:?}", s),
+                Token::BOOL(b) => write!(f, "{}", b),
+                token => write!(f, "{:?}", token),
+            },
+            Pattern::Variant(name, variant, fields, rest) => {
+                write!(f, "{}::{}", name, variant)?;
+                fields.fmt_fields(f, *rest)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeExpr {
+    // a name with its type arguments, if it has any
+    Named(String, Vec<TypeExpr>),
+    Array(Box<TypeExpr>),
+    Fn(Vec<TypeExpr>, Box<TypeExpr>),
+}
+
+impl Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeExpr::Named(name, args) if args.is_empty() => write!(f, "{}", name),
+            TypeExpr::Named(name, args) => write!(f, "{}<{}>", name, join(args)),
+            TypeExpr::Array(elem) => write!(f, "[{}]", elem),
+            TypeExpr::Fn(params, ret) => write!(f, "fn({}) -> {}", join(params), ret),
+        }
+    }
+}
+
+// the parts of a function that come before its body, parameters and the
+// return type may be left without a type
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnSig {
+    pub generics: Vec<String>,
+    pub params: Vec<(String, Option<TypeExpr>)>,
+    pub ret: Option<TypeExpr>,
+}
+
+impl FnSig {
+    pub fn param_names(&self) -> Vec<String> {
+        self.params.iter().map(|(name, _)| name.clone()).collect()
+    }
+}
+
+impl Display for FnSig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.generics.is_empty() {
+            write!(f, "<{}> ", self.generics.join(" "))?;
+        }
+        write!(f, "(")?;
+        for (i, (name, t)) in self.params.iter().enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+            match t {
+                Some(t) => write!(f, "({} {})", name, t)?,
+                None => write!(f, "{}", name)?,
+            }
+        }
+        write!(f, ")")?;
+        if let Some(ret) = &self.ret {
+            write!(f, " -> {}", ret)?;
+        }
+        Ok(())
+    }
+}
+
+fn join<T: Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    items.join(", ")
 }
 
 impl Display for SExpr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        Ok(match self {
+        match self {
             SExpr::Atom(token) => {
                 write!(f, "{:?}", token)?;
             }
-            SExpr::Cons(token, cons) => {
+            SExpr::Cons(token, cons, _) => {
                 write!(f, "({:?} ", token)?;
                 for s in cons {
                     write!(f, " {}", s)?;
                 }
                 write!(f, ")")?;
             }
-        })
+            SExpr::Block(stmts) => {
+                write!(f, "(BLOCK")?;
+                for s in stmts {
+                    write!(f, " {}", s)?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::If(cond, then, els, _) => {
+                write!(f, "(IF {} {}", cond, then)?;
+                if let Some(els) = els {
+                    write!(f, " {}", els)?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::While(cond, body, _) => {
+                write!(f, "(WHILE {} {})", cond, body)?;
+            }
+            SExpr::Array(elems) => {
+                write!(f, "(ARRAY")?;
+                for s in elems {
+                    write!(f, " {}", s)?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::Index(array, index, _) => {
+                write!(f, "(INDEX {} {})", array, index)?;
+            }
+            SExpr::Slice(array, lo, hi, _) => {
+                write!(f, "(SLICE {}", array)?;
+                for bound in [lo, hi].iter() {
+                    match bound {
+                        Some(bound) => write!(f, " {}", bound)?,
+                        None => write!(f, " _")?,
+                    }
+                }
+                write!(f, ")")?;
+            }
+            SExpr::StructDef(name, generics, fields) => {
+                write!(f, "(STRUCT {}", name)?;
+                if !generics.is_empty() {
+                    write!(f, "<{}>", generics.join(", "))?;
+                }
+                for (field, t) in fields {
+                    write!(f, " ({} {})", field, t)?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::StructLit(name, fields) => {
+                write!(f, "(NEW {}", name)?;
+                for (field, value) in fields {
+                    write!(f, " ({} {})", field, value)?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::Field(value, field, _) => {
+                write!(f, "(FIELD {} {})", value, field)?;
+            }
+            SExpr::EnumDef(name, variants) => {
+                write!(f, "(ENUM {}", name)?;
+                for (variant, fields) in variants {
+                    write!(f, " ({}", variant)?;
+                    fields.fmt_fields(f, false)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::Variant(name, variant, fields, _) => {
+                write!(f, "(VARIANT {}::{}", name, variant)?;
+                fields.fmt_fields(f, false)?;
+                write!(f, ")")?;
+            }
+            SExpr::Match(value, arms, _) => {
+                write!(f, "(MATCH {}", value)?;
+                for (pattern, arm) in arms {
+                    write!(f, " ({} {})", pattern, arm)?;
+                }
+                write!(f, ")")?;
+            }
+            SExpr::Fn(name, sig, body) => {
+                match name {
+                    Some(name) => write!(f, "(FN {}", name)?,
+                    None => write!(f, "(LAMBDA")?,
+                }
+                write!(f, " {} {})", sig, body)?;
+            }
+            SExpr::Mod(name, _) => {
+                write!(f, "(MOD {})", name)?;
+            }
+            SExpr::Import(path, _) => {
+                write!(f, "(IMPORT {})", path.join("::"))?;
+            }
+            SExpr::Pub(item) => {
+                write!(f, "(PUB {})", item)?;
+            }
+            SExpr::Call(callee, args, _) => {
+                write!(f, "(CALL {}", callee)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// every name assigned to inside a function body, with = or a compound
+// assignment since x += y is x = x + y, including the names of the
+// functions it declares
+pub fn assigned_names(expr: &SExpr, names: &mut Vec<String>) {
+    match expr {
+        SExpr::Atom(_) => {}
+        SExpr::Fn(Some(name), ..) => {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        SExpr::Fn(None, ..) => {}
+        SExpr::Mod(..) | SExpr::Import(..) => {}
+        SExpr::Pub(item) => assigned_names(item, names),
+        SExpr::Call(callee, args, _) => {
+            assigned_names(callee, names);
+            for arg in args {
+                assigned_names(arg, names);
+            }
+        }
+        SExpr::Cons(token, cons, _) => {
+            let assigns = matches!(token, Token::ASSIGN) || token.compound_op().is_some();
+            if let (true, SExpr::Atom(Token::IDENT(name))) = (assigns, &cons[0]) {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            for sub in cons {
+                assigned_names(sub, names);
+            }
+        }
+        SExpr::Block(stmts) | SExpr::Array(stmts) => {
+            for stmt in stmts {
+                assigned_names(stmt, names);
+            }
+        }
+        SExpr::If(cond, then, els, _) => {
+            assigned_names(cond, names);
+            assigned_names(then, names);
+            if let Some(els) = els {
+                assigned_names(els, names);
+            }
+        }
+        SExpr::While(cond, body, _) | SExpr::Index(cond, body, _) => {
+            assigned_names(cond, names);
+            assigned_names(body, names);
+        }
+        SExpr::Slice(array, lo, hi, _) => {
+            assigned_names(array, names);
+            for bound in [lo, hi].iter().copied().flatten() {
+                assigned_names(bound, names);
+            }
+        }
+        SExpr::StructDef(..) => {}
+        SExpr::StructLit(_, fields) => {
+            for (_, value) in fields {
+                assigned_names(value, names);
+            }
+        }
+        SExpr::Field(value, _, _) => assigned_names(value, names),
+        SExpr::EnumDef(..) => {}
+        SExpr::Variant(_, _, fields, _) => match fields {
+            Fields::Unit => {}
+            Fields::Tuple(values) => {
+                for value in values {
+                    assigned_names(value, names);
+                }
+            }
+            Fields::Struct(values) => {
+                for (_, value) in values {
+                    assigned_names(value, names);
+                }
+            }
+        },
+        // names bound by patterns are scoped to their arm rather than
+        // being locals of the function
+        SExpr::Match(value, arms, _) => {
+            assigned_names(value, names);
+            for (_, arm) in arms {
+                assigned_names(arm, names);
+            }
+        }
+    }
+}
+
+// The locals of a function are its parameters and the names it assigns,
+// except the variables of the functions around it, `outer` says which
+// names those have. Assigning one of those assigns the variable it
+// captured, like reading it reads it. Globals aren't one of them, a
+// function assigning a global's name has a local of its own instead.
+pub fn local_names(params: &[String], body: &SExpr, outer: &dyn Fn(&str) -> bool) -> Vec<String> {
+    let mut assigned = vec![];
+    assigned_names(body, &mut assigned);
+    let mut locals = params.to_vec();
+    for name in assigned {
+        if !locals.contains(&name) && !outer(&name) {
+            locals.push(name);
+        }
+    }
+    locals
+}
+
+// the names a function uses or assigns without them being its locals, in
+// the order they first appear, these are captured or globals
+pub fn free_names(params: &[String], body: &SExpr, outer: &dyn Fn(&str) -> bool) -> Vec<String> {
+    let mut bound = local_names(params, body, outer);
+    let mut free = vec![];
+    used_names(body, &mut bound, &mut free, outer);
+    free
+}
+
+// the names a pattern binds, in the order they appear
+pub fn pattern_names(pattern: &Pattern, names: &mut Vec<String>) {
+    match pattern {
+        Pattern::Wildcard | Pattern::Literal(_) => {}
+        Pattern::Binding(name) => names.push(name.clone()),
+        Pattern::Variant(_, _, fields, _) => match fields {
+            Fields::Unit => {}
+            Fields::Tuple(patterns) => {
+                for pattern in patterns {
+                    pattern_names(pattern, names);
+                }
+            }
+            Fields::Struct(patterns) => {
+                for (_, pattern) in patterns {
+                    pattern_names(pattern, names);
+                }
+            }
+        },
+    }
+}
+
+fn used_names(
+    expr: &SExpr,
+    bound: &mut Vec<String>,
+    free: &mut Vec<String>,
+    outer: &dyn Fn(&str) -> bool,
+) {
+    let add = |name: &String, bound: &[String], free: &mut Vec<String>| {
+        if !bound.contains(name) && !free.contains(name) {
+            free.push(name.clone());
+        }
+    };
+
+    match expr {
+        SExpr::Atom(Token::IDENT(name)) => add(name, bound, free),
+        SExpr::Atom(_) | SExpr::StructDef(..) | SExpr::EnumDef(..) => {}
+        SExpr::Fn(_, sig, body) => {
+            let around = |name: &str| bound.iter().any(|b| b == name) || outer(name);
+            for name in free_names(&sig.param_names(), body, &around) {
+                add(&name, bound, free);
+            }
+        }
+        SExpr::Match(value, arms, _) => {
+            used_names(value, bound, free, outer);
+            for (pattern, arm) in arms {
+                let depth = bound.len();
+                pattern_names(pattern, bound);
+                used_names(arm, bound, free, outer);
+                bound.truncate(depth);
+            }
+        }
+        _ => {
+            for sub in sub_exprs(expr) {
+                used_names(sub, bound, free, outer);
+            }
+        }
+    }
+}
+
+// the expressions directly inside expr, not counting function bodies
+pub fn sub_exprs(expr: &SExpr) -> Vec<&SExpr> {
+    match expr {
+        SExpr::Atom(_)
+        | SExpr::StructDef(..)
+        | SExpr::EnumDef(..)
+        | SExpr::Fn(..)
+        | SExpr::Mod(..)
+        | SExpr::Import(..) => vec![],
+        SExpr::Pub(item) => vec![item],
+        SExpr::Cons(_, subs, _) | SExpr::Block(subs) | SExpr::Array(subs) => subs.iter().collect(),
+        SExpr::If(cond, then, els, _) => {
+            let mut subs = vec![&**cond, &**then];
+            subs.extend(els.as_deref());
+            subs
+        }
+        SExpr::While(a, b, _) | SExpr::Index(a, b, _) => vec![a, b],
+        SExpr::Slice(array, lo, hi, _) => {
+            let mut subs = vec![&**array];
+            subs.extend(lo.as_deref());
+            subs.extend(hi.as_deref());
+            subs
+        }
+        SExpr::StructLit(_, fields) => fields.iter().map(|(_, value)| value).collect(),
+        SExpr::Field(value, _, _) => vec![value],
+        SExpr::Variant(_, _, fields, _) => match fields {
+            Fields::Unit => vec![],
+            Fields::Tuple(values) => values.iter().collect(),
+            Fields::Struct(values) => values.iter().map(|(_, value)| value).collect(),
+        },
+        SExpr::Match(value, arms, _) => {
+            let mut subs = vec![&**value];
+            subs.extend(arms.iter().map(|(_, arm)| arm));
+            subs
+        }
+        SExpr::Call(callee, args, _) => {
+            let mut subs = vec![&**callee];
+            subs.extend(args);
+            subs
+        }
+    }
+}
+
+// calls f on every site in expr, function bodies too
+pub fn sites_mut(expr: &mut SExpr, f: &mut dyn FnMut(&mut Site)) {
+    let each = |exprs: &mut [SExpr], f: &mut dyn FnMut(&mut Site)| {
+        for expr in exprs {
+            sites_mut(expr, f);
+        }
+    };
+    match expr {
+        SExpr::Atom(_)
+        | SExpr::StructDef(..)
+        | SExpr::EnumDef(..)
+        | SExpr::Mod(..)
+        | SExpr::Import(..) => {}
+        SExpr::Pub(item) | SExpr::Fn(_, _, item) => sites_mut(item, f),
+        SExpr::Block(subs) | SExpr::Array(subs) => each(subs, f),
+        SExpr::Cons(_, subs, site) => {
+            f(site);
+            each(subs, f);
+        }
+        SExpr::If(cond, then, els, site) => {
+            f(site);
+            sites_mut(cond, f);
+            sites_mut(then, f);
+            if let Some(els) = els {
+                sites_mut(els, f);
+            }
+        }
+        SExpr::While(a, b, site) => {
+            f(site);
+            sites_mut(a, f);
+            sites_mut(b, f);
+        }
+        SExpr::Index(array, index, site) => {
+            f(site);
+            sites_mut(array, f);
+            sites_mut(index, f);
+        }
+        SExpr::Slice(array, lo, hi, site) => {
+            f(site);
+            sites_mut(array, f);
+            for bound in lo.iter_mut().chain(hi.iter_mut()) {
+                sites_mut(bound, f);
+            }
+        }
+        SExpr::StructLit(_, fields) => {
+            for (_, value) in fields {
+                sites_mut(value, f);
+            }
+        }
+        SExpr::Field(value, _, site) => {
+            f(site);
+            sites_mut(value, f);
+        }
+        SExpr::Variant(_, _, fields, site) => {
+            f(site);
+            match fields {
+                Fields::Unit => {}
+                Fields::Tuple(values) => each(values, f),
+                Fields::Struct(values) => {
+                    for (_, value) in values {
+                        sites_mut(value, f);
+                    }
+                }
+            }
+        }
+        SExpr::Match(value, arms, site) => {
+            f(site);
+            sites_mut(value, f);
+            for (_, arm) in arms {
+                sites_mut(arm, f);
+            }
+        }
+        SExpr::Call(callee, args, site) => {
+            f(site);
+            sites_mut(callee, f);
+            each(args, f);
+        }
+    }
+}
+
+// the site of expr itself, if it has one
+pub fn own_site(expr: &SExpr) -> Option<&Site> {
+    match expr {
+        SExpr::Cons(.., site)
+        | SExpr::If(.., site)
+        | SExpr::While(.., site)
+        | SExpr::Index(.., site)
+        | SExpr::Slice(.., site)
+        | SExpr::Field(.., site)
+        | SExpr::Variant(.., site)
+        | SExpr::Match(.., site)
+        | SExpr::Call(.., site) => Some(site),
+        _ => None,
+    }
+}
+
+// where the first site in expr starts, function bodies included
+pub fn site_start(expr: &SExpr) -> Option<usize> {
+    let own = own_site(expr).and_then(|site| site.0.map(|span| span.start));
+    let subs = match expr {
+        SExpr::Fn(_, _, body) => vec![&**body],
+        _ => sub_exprs(expr),
+    };
+    own.into_iter()
+        .chain(subs.into_iter().filter_map(site_start))
+        .min()
+}
+
+// a top level statement with its sites moved to be from where its first
+// site starts, so moving the statement in the text leaves it the same
+pub fn placed(mut stmt: SExpr) -> SExpr {
+    let start = site_start(&stmt).unwrap_or(0);
+    sites_mut(&mut stmt, &mut |site| {
+        if let Site(Some(span)) = site {
+            *site = Site(Some(Span {
+                start: span.start - start,
+                end: span.end - start,
+            }));
+        }
+    });
+    stmt
+}
+
+// a token as an error message names it, its symbol if it has one
+fn found(token: Option<&Token>) -> String {
+    match token {
+        None => String::from("end of input"),
+        Some(token) => match token.symbol() {
+            Some(symbol) => symbol.to_string(),
+            None => format!("{:?}", token),
+        },
     }
 }
 
 #[derive(Debug)]
 pub struct Parser {
     lexer: Lexer,
+    operators: OperatorTable,
+    // set while parsing an if or while condition, where `x {` starts the
+    // body rather than a struct literal
+    no_struct_literal: bool,
+    // cleared inside blocks, only the top level may declare modules
+    top_level: bool,
+    // every block and match parsed so far in the order their { comes, the
+    // top level first
+    blocks: Vec<BlockSpans>,
+    // every list of arguments, parameters, array elements or struct literal
+    // fields parsed so far in the order their opening bracket comes
+    lists: Vec<BlockSpans>,
+    // the kind of every node of the concrete syntax tree parsed so far with
+    // the index of its first token and one past its last
+    nodes: Vec<(NodeKind, usize, usize)>,
+    // how many statements, expressions, patterns and types the one being
+    // parsed is in
+    depth: usize,
+}
+
+// deeper nesting is a syntax error rather than a stack overflow, here or in
+// the stages after parsing
+const MAX_DEPTH: usize = 64;
+
+// what went wrong and where, the span is missing for empty input
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ERROR: {}", self.message)
+    }
+}
+
+impl From<SyntaxError> for Error {
+    fn from(err: SyntaxError) -> Self {
+        Error::new(ErrorKind::InvalidInput, err.to_string())
+    }
+}
+
+pub type Parsed<T> = Result<T, SyntaxError>;
+
+// where a block and each of its statements are, the braces of a match and
+// each of its arms, or the brackets of a list and each of its items. The
+// top level's span goes from its first token to its last
+#[derive(Debug, Clone)]
+pub struct BlockSpans {
+    pub span: Span,
+    pub statements: Vec<Span>,
 }
 
 impl Parser {
     pub fn new(lexer: Lexer) -> Self {
-        Self { lexer }
+        Self::with_operators(lexer, OperatorTable::new())
     }
 
-    pub fn parse(mut self) -> SExpr {
-        self.parse_rec(0)
+    // the lexer should have been given the table's custom symbols
+    pub fn with_operators(lexer: Lexer, operators: OperatorTable) -> Self {
+        Self {
+            lexer,
+            operators,
+            no_struct_literal: false,
+            top_level: true,
+            blocks: vec![],
+            lists: vec![],
+            nodes: vec![],
+            depth: 0,
+        }
     }
 
-    fn parse_rec(&mut self, min_bp: u8) -> SExpr {
+    pub fn parse(mut self) -> Parsed<SExpr> {
+        self.parse_program()
+    }
+
+    // like parse, but the parser is still there afterwards for the blocks
+    // and nodes it found
+    pub fn parse_program(&mut self) -> Parsed<SExpr> {
+        let first = self.lexer.span();
+        let stmts = self.parse_statements()?;
+        if self.lexer.peek().is_some() {
+            return self.fail(format!("Unexpected token {:?}", self.lexer.peek()));
+        }
+        if let (Some(first), Some(last)) = (first, self.lexer.last_span()) {
+            self.blocks[0].span = Span {
+                start: first.start,
+                end: last.end,
+            };
+        }
+        Ok(SExpr::Block(stmts))
+    }
+
+    // parses input that is a single block, for parsing one again on its own
+    pub fn parse_lone_block(&mut self) -> Parsed<SExpr> {
+        self.top_level = false;
+        let block = self.parse_block()?;
+        if self.lexer.peek().is_some() {
+            return self.fail(format!("Unexpected token {:?}", self.lexer.peek()));
+        }
+        Ok(block)
+    }
+
+    // where parsing stopped, the token it couldn't parse or the last one at
+    // the end of the input
+    pub fn position(&self) -> Option<Span> {
+        self.lexer.span().or_else(|| self.lexer.last_span())
+    }
+
+    pub fn statement_spans(&self) -> &[Span] {
+        self.blocks
+            .first()
+            .map_or(&[], |block| block.statements.as_slice())
+    }
+
+    pub fn blocks(&self) -> &[BlockSpans] {
+        &self.blocks
+    }
+
+    pub fn lists(&self) -> &[BlockSpans] {
+        &self.lists
+    }
+
+    // the nodes in the order they end, see cst::parse
+    pub fn nodes(&self) -> &[(NodeKind, usize, usize)] {
+        &self.nodes
+    }
+
+    // notes a node from token `start` to the last one read
+    fn node(&mut self, kind: NodeKind, start: usize) {
+        self.nodes.push((kind, start, self.lexer.tokens_read()));
+    }
+
+    // the index of the token read last, for a node whose first token has
+    // been consumed already
+    fn last_token(&self) -> usize {
+        self.lexer.tokens_read() - 1
+    }
+
+    // where an expression starting at `first` and ending with the token
+    // read last is
+    fn site(&self, first: Option<Span>) -> Site {
+        match (first, self.lexer.last_span()) {
+            (Some(first), Some(last)) => Site(Some(Span {
+                start: first.start,
+                end: last.end,
+            })),
+            _ => Site::default(),
+        }
+    }
+
+    // parses statements until a } or EOF, neither of which is consumed
+    fn parse_statements(&mut self) -> Parsed<Vec<SExpr>> {
+        let mut stmts = vec![];
+        let block = self.blocks.len();
+        self.blocks.push(BlockSpans {
+            span: Span { start: 0, end: 0 },
+            statements: vec![],
+        });
+
+        loop {
+            match self.lexer.peek() {
+                None | Some(Token::RCBRACE) => break,
+                Some(Token::SEMI) => {
+                    self.lexer.next();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let start = self.lexer.span();
+            let stmt = self.parse_statement()?;
+            if let (Some(start), Some(end)) = (start, self.lexer.last_span()) {
+                self.blocks[block].statements.push(Span {
+                    start: start.start,
+                    end: end.end,
+                });
+            }
+            let ends_with_block = matches!(
+                stmt,
+                SExpr::Block(_)
+                    | SExpr::If(..)
+                    | SExpr::While(..)
+                    | SExpr::StructDef(..)
+                    | SExpr::EnumDef(..)
+                    | SExpr::Match(..)
+            ) || matches!(&stmt, SExpr::Fn(_, _, body) if matches!(**body, SExpr::Block(_)))
+                || matches!(&stmt, SExpr::Pub(item) if !matches!(**item, SExpr::Mod(..)));
+            stmts.push(stmt);
+
+            match self.lexer.peek() {
+                None | Some(Token::RCBRACE) => break,
+                Some(Token::SEMI) => {
+                    self.lexer.next();
+                }
+                Some(Token::ELSE) => return self.fail(String::from("else without a matching if")),
+                Some(token) => {
+                    if !ends_with_block {
+                        return self.fail(format!("Expected ; before {:?}", token));
+                    }
+                }
+            }
+        }
+
+        Ok(stmts)
+    }
+
+    // block-like expressions in statement position end the statement, so
+    // `if c { } -1` is two statements rather than a subtraction
+    fn parse_statement(&mut self) -> Parsed<SExpr> {
+        self.nested(Self::parse_statement_node)
+    }
+
+    fn parse_statement_node(&mut self) -> Parsed<SExpr> {
+        match self.lexer.peek() {
+            Some(Token::IF) | Some(Token::WHILE) | Some(Token::LCBRACE) | Some(Token::FN)
+            | Some(Token::MATCH) => {
+                let token = self.lexer.next().unwrap();
+                self.parse_block_like(token)
+            }
+            Some(Token::STRUCT) => {
+                self.lexer.next();
+                self.parse_struct_def()
+            }
+            Some(Token::ENUM) => {
+                self.lexer.next();
+                self.parse_enum_def()
+            }
+            Some(Token::MODULE) | Some(Token::IMPORT) | Some(Token::PUB) => self.parse_item(),
+            _ => self.parse_rec(0),
+        }
+    }
+
+    // parses a mod, an import or a pub item
+    fn parse_item(&mut self) -> Parsed<SExpr> {
+        let span = self.lexer.span().unwrap();
+        let start = self.lexer.tokens_read();
+        let token = self.lexer.next().unwrap();
+        if !self.top_level {
+            return self.fail(format!("{:?} is only allowed at the top level", token));
+        }
+        match token {
+            Token::MODULE => {
+                let name = self.parse_ident("module name")?;
+                self.node(NodeKind::Mod, start);
+                Ok(SExpr::Mod(name, span))
+            }
+            Token::IMPORT => {
+                let mut path = vec![self.parse_ident("module or item name")?];
+                while matches!(self.lexer.peek(), Some(Token::COLONCOLON)) {
+                    self.lexer.next();
+                    path.push(self.parse_ident("module or item name")?);
+                }
+                self.node(NodeKind::Import, start);
+                Ok(SExpr::Import(path, span))
+            }
+            _ => {
+                let item = match self.lexer.peek() {
+                    Some(Token::FN) | Some(Token::STRUCT) | Some(Token::ENUM)
+                    | Some(Token::MODULE) => self.parse_statement()?,
+                    token => {
+                        return self.fail(format!(
+                            "Expected fn, struct, enum or mod after pub, found {}",
+                            found(token)
+                        ))
+                    }
+                };
+                if matches!(&item, SExpr::Fn(None, ..)) {
+                    return self.fail(String::from("A lambda can't be pub"));
+                }
+                self.node(NodeKind::Pub, start);
+                Ok(SExpr::Pub(Box::new(item)))
+            }
+        }
+    }
+
+    fn parse_block_like(&mut self, token: Token) -> Parsed<SExpr> {
+        match token {
+            Token::IF => self.parse_if(),
+            Token::WHILE => self.parse_while(),
+            Token::LCBRACE => self.parse_block_body(),
+            Token::MATCH => self.parse_match(),
+            // fn name<T>(x: T) -> T { } declares a function, without the
+            // name it's a lambda
+            Token::FN => {
+                let start = self.last_token();
+                let name = match self.lexer.peek() {
+                    Some(Token::IDENT(_)) => Some(self.parse_ident("function name")?),
+                    _ => None,
+                };
+                let generics = self.parse_generics()?;
+                if !matches!(self.lexer.next(), Some(Token::LPAREN)) {
+                    return self.fail(format!(
+                        "Expected ( before the parameters of {}",
+                        name.as_deref().unwrap_or("a lambda")
+                    ));
+                }
+                let params = self.parse_noted_list(Token::RPAREN, |parser| parser.parse_param())?;
+                let ret = if matches!(self.lexer.peek(), Some(Token::ARROW)) {
+                    self.lexer.next();
+                    Some(self.parse_type()?)
+                } else {
+                    None
+                };
+                let body = self.parse_block()?;
+
+                let sig = FnSig {
+                    generics,
+                    params: self.check_params(params)?,
+                    ret,
+                };
+                let kind = match name {
+                    Some(_) => NodeKind::FnDecl,
+                    None => NodeKind::Lambda,
+                };
+                self.node(kind, start);
+                Ok(SExpr::Fn(name, sig, Box::new(body)))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_block(&mut self) -> Parsed<SExpr> {
+        if !matches!(self.lexer.next(), Some(Token::LCBRACE)) {
+            return self.fail(String::from("Expected a block"));
+        }
+        self.parse_block_body()
+    }
+
+    // parses the rest of a block whose { has already been consumed
+    fn parse_block_body(&mut self) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let open = self.lexer.last_span();
+        let block = self.blocks.len();
+        let top_level = std::mem::replace(&mut self.top_level, false);
+        let stmts = self.with_struct_literals(|parser| parser.parse_statements())?;
+        self.top_level = top_level;
+        if !matches!(self.lexer.next(), Some(Token::RCBRACE)) {
+            return self.fail(String::from("Unclosed block"));
+        }
+        if let (Some(open), Some(close)) = (open, self.lexer.last_span()) {
+            self.blocks[block].span = Span {
+                start: open.start,
+                end: close.end,
+            };
+        }
+        self.node(NodeKind::Block, start);
+        Ok(SExpr::Block(stmts))
+    }
+
+    fn parse_cond(&mut self) -> Parsed<SExpr> {
+        let outer = self.no_struct_literal;
+        self.no_struct_literal = true;
+        let cond = self.parse_rec(0)?;
+        self.no_struct_literal = outer;
+        Ok(cond)
+    }
+
+    // brackets lift the restriction on struct literals in conditions
+    fn with_struct_literals<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = self.no_struct_literal;
+        self.no_struct_literal = false;
+        let result = parse(self);
+        self.no_struct_literal = outer;
+        result
+    }
+
+    // parses the rest of an if whose IF has already been consumed
+    fn parse_if(&mut self) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let first = self.lexer.last_span();
+        let cond = self.parse_cond()?;
+        let site = self.site(first);
+        let then = self.parse_block()?;
+
+        let els = if matches!(self.lexer.peek(), Some(Token::ELSE)) {
+            self.lexer.next();
+            if matches!(self.lexer.peek(), Some(Token::IF)) {
+                self.lexer.next();
+                Some(Box::new(self.parse_if()?))
+            } else {
+                Some(Box::new(self.parse_block()?))
+            }
+        } else {
+            None
+        };
+
+        self.node(NodeKind::IfExpr, start);
+        Ok(SExpr::If(Box::new(cond), Box::new(then), els, site))
+    }
+
+    // parses the rest of a while whose WHILE has already been consumed
+    fn parse_while(&mut self) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let first = self.lexer.last_span();
+        let cond = self.parse_cond()?;
+        let site = self.site(first);
+        let body = self.parse_block()?;
+        self.node(NodeKind::WhileExpr, start);
+        Ok(SExpr::While(Box::new(cond), Box::new(body), site))
+    }
+
+    // parses the rest of a struct declaration whose STRUCT has already been
+    // consumed
+    fn parse_struct_def(&mut self) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let name = self.parse_ident("struct name")?;
+        let generics = self.parse_generics()?;
+        if !matches!(self.lexer.next(), Some(Token::LCBRACE)) {
+            return self.fail(format!("Expected {{ after struct {}", name));
+        }
+
+        let mut fields = vec![];
+        while !matches!(self.lexer.peek(), Some(Token::RCBRACE)) {
+            let field_start = self.lexer.tokens_read();
+            let field = self.parse_ident("field name")?;
+            if !matches!(self.lexer.next(), Some(Token::COLON)) {
+                return self.fail(format!("Expected : after field {}", field));
+            }
+            if fields.iter().any(|(f, _)| f == &field) {
+                return self.fail(format!(
+                    "Field {} is declared twice in struct {}",
+                    field, name
+                ));
+            }
+            fields.push((field, self.parse_type()?));
+            self.node(NodeKind::FieldDecl, field_start);
+            self.expect_separator("struct declaration")?;
+        }
+        self.lexer.next();
+
+        self.node(NodeKind::StructDecl, start);
+        Ok(SExpr::StructDef(name, generics, fields))
+    }
+
+    // parses the <T, U> of a generic declaration if there is one
+    fn parse_generics(&mut self) -> Parsed<Vec<String>> {
+        if !matches!(self.lexer.peek(), Some(Token::LESS)) {
+            return Ok(vec![]);
+        }
+        self.lexer.next();
+
+        let mut generics = vec![];
+        while !matches!(self.lexer.peek(), Some(Token::GREATER)) {
+            let generic = self.parse_ident("type parameter")?;
+            if generics.contains(&generic) {
+                return self.fail(format!("Type parameter {} is declared twice", generic));
+            }
+            generics.push(generic);
+            match self.lexer.peek() {
+                Some(Token::COMMA) => {
+                    self.lexer.next();
+                }
+                Some(Token::GREATER) => {}
+                token => {
+                    return self.fail(format!(
+                        "Expected , or > in type parameters, found {}",
+                        found(token)
+                    ))
+                }
+            }
+        }
+        self.lexer.next();
+        Ok(generics)
+    }
+
+    // a parameter name with an optional type
+    fn parse_param(&mut self) -> Parsed<(String, Option<TypeExpr>)> {
+        let start = self.lexer.tokens_read();
+        let name = self.parse_ident("parameter")?;
+        let param = if matches!(self.lexer.peek(), Some(Token::COLON)) {
+            self.lexer.next();
+            (name, Some(self.parse_type()?))
+        } else {
+            (name, None)
+        };
+        self.node(NodeKind::Param, start);
+        Ok(param)
+    }
+
+    // parses the rest of a struct literal whose name and { have already been
+    // consumed
+    fn parse_struct_lit(&mut self, name: String) -> Parsed<SExpr> {
+        let list = self.open_list();
+        let mut fields = vec![];
+        while !matches!(self.lexer.peek(), Some(Token::RCBRACE)) {
+            let first = self.lexer.span();
+            let start = self.lexer.tokens_read();
+            let field = self.parse_ident("field name")?;
+            if !matches!(self.lexer.next(), Some(Token::COLON)) {
+                return self.fail(format!("Expected : after field {}", field));
+            }
+            let value = self.with_struct_literals(|parser| parser.parse_rec(0))?;
+            self.node(NodeKind::FieldInit, start);
+            self.list_item(list, first);
+            fields.push((field, value));
+            self.expect_separator("struct literal")?;
+        }
+        self.lexer.next();
+        self.close_list(list);
+
+        Ok(SExpr::StructLit(name, fields))
+    }
+
+    // parses the rest of an enum declaration whose ENUM has already been
+    // consumed
+    fn parse_enum_def(&mut self) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let name = self.parse_ident("enum name")?;
+        if !matches!(self.lexer.next(), Some(Token::LCBRACE)) {
+            return self.fail(format!("Expected {{ after enum {}", name));
+        }
+
+        let mut variants = vec![];
+        while !matches!(self.lexer.peek(), Some(Token::RCBRACE)) {
+            let variant_start = self.lexer.tokens_read();
+            let variant = self.parse_ident("variant name")?;
+            if variants.iter().any(|(v, _)| v == &variant) {
+                return self.fail(format!(
+                    "Variant {} is declared twice in enum {}",
+                    variant, name
+                ));
+            }
+
+            let fields = match self.lexer.peek() {
+                Some(Token::LPAREN) => {
+                    self.lexer.next();
+                    Fields::Tuple(self.parse_list(Token::RPAREN, |parser| parser.parse_type())?)
+                }
+                Some(Token::LCBRACE) => {
+                    self.lexer.next();
+                    let mut fields: Vec<(String, TypeExpr)> = vec![];
+                    while !matches!(self.lexer.peek(), Some(Token::RCBRACE)) {
+                        let field_start = self.lexer.tokens_read();
+                        let field = self.parse_ident("field name")?;
+                        if !matches!(self.lexer.next(), Some(Token::COLON)) {
+                            return self.fail(format!("Expected : after field {}", field));
+                        }
+                        if fields.iter().any(|(f, _)| f == &field) {
+                            return self.fail(format!(
+                                "Field {} is declared twice in variant {}",
+                                field, variant
+                            ));
+                        }
+                        fields.push((field, self.parse_type()?));
+                        self.node(NodeKind::FieldDecl, field_start);
+                        self.expect_separator("variant declaration")?;
+                    }
+                    self.lexer.next();
+                    Fields::Struct(fields)
+                }
+                _ => Fields::Unit,
+            };
+
+            variants.push((variant, fields));
+            self.node(NodeKind::VariantDecl, variant_start);
+            self.expect_separator("enum declaration")?;
+        }
+        self.lexer.next();
+        self.node(NodeKind::EnumDecl, start);
+
+        Ok(SExpr::EnumDef(name, variants))
+    }
+
+    // parses the rest of Enum::Variant whose name and :: have already been
+    // consumed, a longer path like module::Enum::Variant or module::item
+    // keeps everything but the last name in the first part
+    fn parse_variant(&mut self, name: String, first: Option<Span>) -> Parsed<SExpr> {
+        // the name and :: have been read
+        let start = self.lexer.tokens_read() - 2;
+        let (name, variant) = self.parse_path(name)?;
+        let fields = match self.lexer.peek() {
+            Some(Token::LPAREN) => {
+                self.lexer.next();
+                let values = self.with_struct_literals(|parser| {
+                    parser.parse_noted_list(Token::RPAREN, |parser| parser.parse_rec(0))
+                })?;
+                Fields::Tuple(values)
+            }
+            Some(Token::LCBRACE) if !self.no_struct_literal => {
+                self.lexer.next();
+                match self.parse_struct_lit(variant.clone())? {
+                    SExpr::StructLit(_, values) => Fields::Struct(values),
+                    _ => unreachable!(),
+                }
+            }
+            _ => Fields::Unit,
+        };
+        self.node(NodeKind::VariantExpr, start);
+        Ok(SExpr::Variant(name, variant, fields, self.site(first)))
+    }
+
+    // parses the rest of a match whose MATCH has already been consumed
+    fn parse_match(&mut self) -> Parsed<SExpr> {
+        let match_start = self.last_token();
+        let first = self.lexer.last_span();
+        let value = self.parse_cond()?;
+        let site = self.site(first);
+        if !matches!(self.lexer.next(), Some(Token::LCBRACE)) {
+            return self.fail(format!("Expected {{ after match value {}", value));
+        }
+        let open = self.lexer.last_span();
+        let block = self.blocks.len();
+        self.blocks.push(BlockSpans {
+            span: Span { start: 0, end: 0 },
+            statements: vec![],
+        });
+
+        let mut arms = vec![];
+        while !matches!(self.lexer.peek(), Some(Token::RCBRACE)) {
+            let start = self.lexer.span();
+            let arm_start = self.lexer.tokens_read();
+            let pattern = self.parse_pattern()?;
+            if !matches!(self.lexer.next(), Some(Token::FATARROW)) {
+                return self.fail(format!("Expected => after pattern {}", pattern));
+            }
+
+            let arm = self.with_struct_literals(|parser| parser.parse_statement())?;
+            self.node(NodeKind::MatchArm, arm_start);
+            if let (Some(start), Some(end)) = (start, self.lexer.last_span()) {
+                self.blocks[block].statements.push(Span {
+                    start: start.start,
+                    end: end.end,
+                });
+            }
+            let ends_with_block = matches!(
+                arm,
+                SExpr::Block(_) | SExpr::If(..) | SExpr::While(..) | SExpr::Match(..)
+            );
+            arms.push((pattern, arm));
+
+            match self.lexer.peek() {
+                Some(Token::COMMA) => {
+                    self.lexer.next();
+                }
+                Some(Token::RCBRACE) => {}
+                token => {
+                    if !ends_with_block {
+                        return self.fail(format!(
+                            "Expected , or }} after match arm, found {}",
+                            found(token)
+                        ));
+                    }
+                }
+            }
+        }
+        self.lexer.next();
+        if let (Some(open), Some(close)) = (open, self.lexer.last_span()) {
+            self.blocks[block].span = Span {
+                start: open.start,
+                end: close.end,
+            };
+        }
+        self.node(NodeKind::MatchExpr, match_start);
+
+        Ok(SExpr::Match(Box::new(value), arms, site))
+    }
+
+    fn parse_pattern(&mut self) -> Parsed<Pattern> {
+        let start = self.lexer.tokens_read();
+        let pattern = self.nested(Self::parse_pattern_node)?;
+        self.node(NodeKind::Pattern, start);
+        Ok(pattern)
+    }
+
+    fn parse_pattern_node(&mut self) -> Parsed<Pattern> {
+        match self.lexer.next() {
+            Some(Token::IDENT(name)) if name == "_" => Ok(Pattern::Wildcard),
+            Some(Token::IDENT(name)) => {
+                if !matches!(self.lexer.peek(), Some(Token::COLONCOLON)) {
+                    return Ok(Pattern::Binding(name));
+                }
+                self.lexer.next();
+                let (name, variant) = self.parse_path(name)?;
+
+                match self.lexer.peek() {
+                    Some(Token::LPAREN) => {
+                        self.lexer.next();
+                        let fields =
+                            self.parse_list(Token::RPAREN, |parser| parser.parse_pattern())?;
+                        Ok(Pattern::Variant(
+                            name,
+                            variant,
+                            Fields::Tuple(fields),
+                            false,
+                        ))
+                    }
+                    Some(Token::LCBRACE) => {
+                        self.lexer.next();
+                        let mut fields = vec![];
+                        let mut rest = false;
+                        while !matches!(self.lexer.peek(), Some(Token::RCBRACE)) {
+                            if matches!(self.lexer.peek(), Some(Token::DOTDOT)) {
+                                self.lexer.next();
+                                rest = true;
+                                break;
+                            }
+                            let field = self.parse_ident("field name")?;
+                            // `field` alone is short for `field: field`
+                            let pattern = if matches!(self.lexer.peek(), Some(Token::COLON)) {
+                                self.lexer.next();
+                                self.parse_pattern()?
+                            } else {
+                                Pattern::Binding(field.clone())
+                            };
+                            fields.push((field, pattern));
+                            self.expect_separator("struct pattern")?;
+                        }
+                        if !matches!(self.lexer.next(), Some(Token::RCBRACE)) {
+                            return self
+                                .fail(String::from(".. must come last in a struct pattern"));
+                        }
+                        Ok(Pattern::Variant(
+                            name,
+                            variant,
+                            Fields::Struct(fields),
+                            rest,
+                        ))
+                    }
+                    _ => Ok(Pattern::Variant(name, variant, Fields::Unit, false)),
+                }
+            }
+            Some(token @ Token::INT(_))
+            | Some(token @ Token::FLOAT(_))
+            | Some(token @ Token::STR(_))
+            | Some(token @ Token::BOOL(_)) => Ok(Pattern::Literal(token)),
+            Some(Token::SUB) => match self.lexer.next() {
+                Some(Token::INT(n)) => Ok(Pattern::Literal(Token::INT(-n))),
+                Some(Token::FLOAT(n)) => Ok(Pattern::Literal(Token::FLOAT(-n))),
+                token => self.fail(format!(
+                    "Expected a number after - in pattern, found {}",
+                    found(token.as_ref())
+                )),
+            },
+            token => self.fail(format!(
+                "Expected a pattern, found {}",
+                found(token.as_ref())
+            )),
+        }
+    }
+
+    // parses comma separated items up to and including the closing token,
+    // a trailing comma is allowed
+    fn parse_list<T>(
+        &mut self,
+        close: Token,
+        mut parse: impl FnMut(&mut Self) -> Parsed<T>,
+    ) -> Parsed<Vec<T>> {
+        let mut items = vec![];
+        while self.lexer.peek() != Some(&close) {
+            items.push(parse(self)?);
+            match self.lexer.peek() {
+                Some(Token::COMMA) => {
+                    self.lexer.next();
+                }
+                Some(token) if token == &close => {}
+                token => {
+                    return self.fail(format!(
+                        "Expected , or {}, found {}",
+                        found(Some(&close)),
+                        found(token)
+                    ))
+                }
+            }
+        }
+        self.lexer.next();
+        Ok(items)
+    }
+
+    // like parse_list, noting where the list and its items are
+    fn parse_noted_list<T>(
+        &mut self,
+        close: Token,
+        mut parse: impl FnMut(&mut Self) -> Parsed<T>,
+    ) -> Parsed<Vec<T>> {
+        let list = self.open_list();
+        let items = self.parse_list(close, |parser| {
+            let start = parser.lexer.span();
+            let item = parse(parser)?;
+            parser.list_item(list, start);
+            Ok(item)
+        })?;
+        self.close_list(list);
+        Ok(items)
+    }
+
+    // starts noting a list whose opening bracket was the last token read
+    fn open_list(&mut self) -> usize {
+        let span = self.lexer.last_span().unwrap_or(Span { start: 0, end: 0 });
+        self.lists.push(BlockSpans {
+            span,
+            statements: vec![],
+        });
+        self.lists.len() - 1
+    }
+
+    // notes an item of the list that started at `start` and has just been
+    // parsed
+    fn list_item(&mut self, list: usize, start: Option<Span>) {
+        if let (Some(start), Some(end)) = (start, self.lexer.last_span()) {
+            self.lists[list].statements.push(Span {
+                start: start.start,
+                end: end.end,
+            });
+        }
+    }
+
+    // the list ends with the last token read
+    fn close_list(&mut self, list: usize) {
+        if let Some(close) = self.lexer.last_span() {
+            self.lists[list].span.end = close.end;
+        }
+    }
+
+    fn parse_type(&mut self) -> Parsed<TypeExpr> {
+        let start = self.lexer.tokens_read();
+        let ty = self.nested(Self::parse_type_node)?;
+        self.node(NodeKind::TypeRef, start);
+        Ok(ty)
+    }
+
+    fn parse_type_node(&mut self) -> Parsed<TypeExpr> {
+        match self.lexer.next() {
+            Some(Token::IDENT(name)) => {
+                let name = if matches!(self.lexer.peek(), Some(Token::COLONCOLON)) {
+                    self.lexer.next();
+                    let (module, name) = self.parse_path(name)?;
+                    format!("{}::{}", module, name)
+                } else {
+                    name
+                };
+                if !matches!(self.lexer.peek(), Some(Token::LESS)) {
+                    return Ok(TypeExpr::Named(name, vec![]));
+                }
+                self.lexer.next();
+
+                let mut args = vec![];
+                loop {
+                    args.push(self.parse_type()?);
+                    match self.lexer.next() {
+                        Some(Token::COMMA) => {}
+                        Some(Token::GREATER) => break,
+                        // the >> closing nested type arguments is two >
+                        Some(Token::SHR) => {
+                            self.lexer.push_front(Token::GREATER);
+                            break;
+                        }
+                        token => {
+                            return self.fail(format!(
+                                "Expected , or > in type arguments, found {}",
+                                found(token.as_ref())
+                            ))
+                        }
+                    }
+                }
+                Ok(TypeExpr::Named(name, args))
+            }
+            Some(Token::VOID) => Ok(TypeExpr::Named(String::from("void"), vec![])),
+            Some(Token::FN) => {
+                if !matches!(self.lexer.next(), Some(Token::LPAREN)) {
+                    return self.fail(String::from("Expected ( in function type"));
+                }
+                let params = self.parse_list(Token::RPAREN, |parser| parser.parse_type())?;
+                if !matches!(self.lexer.next(), Some(Token::ARROW)) {
+                    return self.fail(String::from("Expected -> in function type"));
+                }
+                Ok(TypeExpr::Fn(params, Box::new(self.parse_type()?)))
+            }
+            Some(Token::LBRACE) => {
+                let elem = self.parse_type()?;
+                if !matches!(self.lexer.next(), Some(Token::RBRACE)) {
+                    return self.fail(String::from("Expected ] after array element type"));
+                }
+                Ok(TypeExpr::Array(Box::new(elem)))
+            }
+            token => self.fail(format!("Expected a type, found {}", found(token.as_ref()))),
+        }
+    }
+
+    // parses the rest of a path whose first name and :: have already been
+    // consumed, returns everything but the last name joined by :: and the
+    // last name
+    fn parse_path(&mut self, first: String) -> Parsed<(String, String)> {
+        let mut prefix = first;
+        let mut last = self.parse_ident("name after ::")?;
+        while matches!(self.lexer.peek(), Some(Token::COLONCOLON)) {
+            self.lexer.next();
+            prefix = format!("{}::{}", prefix, last);
+            last = self.parse_ident("name after ::")?;
+        }
+        Ok((prefix, last))
+    }
+
+    fn parse_ident(&mut self, what: &str) -> Parsed<String> {
+        match self.lexer.next() {
+            Some(Token::IDENT(name)) => Ok(name),
+            token => self.fail(format!(
+                "Expected a {}, found {}",
+                what,
+                found(token.as_ref())
+            )),
+        }
+    }
+
+    // eats the , between items in braces, the last one is optional
+    fn expect_separator(&mut self, what: &str) -> Parsed<()> {
+        match self.lexer.peek() {
+            Some(Token::COMMA) => {
+                self.lexer.next();
+                Ok(())
+            }
+            Some(Token::RCBRACE) => Ok(()),
+            token => self.fail(format!(
+                "Expected , or }} in {}, found {}",
+                what,
+                found(token)
+            )),
+        }
+    }
+
+    fn parse_rec(&mut self, min_bp: u8) -> Parsed<SExpr> {
+        self.nested(|parser| parser.parse_rec_node(min_bp))
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Parsed<T>) -> Parsed<T> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return self.fail(format!("Nested more than {} deep", MAX_DEPTH));
+        }
+        let parsed = parse(self);
+        self.depth -= 1;
+        parsed
+    }
+
+    fn parse_rec_node(&mut self, min_bp: u8) -> Parsed<SExpr> {
+        let start = self.lexer.tokens_read();
+        let first = self.lexer.span();
         let mut lhs = match self.lexer.next() {
             Some(token) => {
-                if matches!(token, Token::INT(_))
+                if let (Token::IDENT(name), Some(Token::COLONCOLON)) = (&token, self.lexer.peek()) {
+                    let name = name.clone();
+                    self.lexer.next();
+                    self.parse_variant(name, first)?
+                } else if let (Token::IDENT(name), Some(Token::LCBRACE), false) =
+                    (&token, self.lexer.peek(), self.no_struct_literal)
+                {
+                    let name = name.clone();
+                    self.lexer.next();
+                    let lit = self.parse_struct_lit(name)?;
+                    self.node(NodeKind::StructLit, start);
+                    lit
+                } else if matches!(token, Token::INT(_))
                     || matches!(token, Token::FLOAT(_))
                     || matches!(token, Token::IDENT(_))
                     || matches!(token, Token::STR(_))
+                    || matches!(token, Token::BOOL(_))
                     || matches!(token, Token::VOID)
-                    || matches!(token, Token::LBRACE)
-                    || matches!(token, Token::LCBRACE)
                 {
+                    let kind = match token {
+                        Token::IDENT(_) => NodeKind::Name,
+                        _ => NodeKind::Literal,
+                    };
+                    self.node(kind, start);
                     SExpr::Atom(token)
-                } else if matches!(token, Token::ADD)
-                    || matches!(token, Token::SUB)
-                    || matches!(token, Token::EXP)
-                    || matches!(token, Token::NOT)
+                } else if matches!(token, Token::LBRACE) {
+                    self.with_struct_literals(|parser| parser.parse_array())?
+                } else if matches!(token, Token::BITOR) || matches!(token, Token::OR) {
+                    self.parse_lambda(token)?
+                } else if matches!(token, Token::IF)
+                    || matches!(token, Token::WHILE)
+                    || matches!(token, Token::LCBRACE)
                     || matches!(token, Token::FN)
-                    || matches!(token, Token::IF)
+                    || matches!(token, Token::MATCH)
                 {
-                    let right_bp = Parser::prefix_binding_power(&token);
-                    let rhs;
-
-                    if matches!(token, Token::FN) {
-                        let id = self.lexer.next();
-                        assert!(matches!(id, Some(Token::IDENT(_))));
-                        assert!(matches!(self.lexer.peek(), Some(Token::LPAREN)));
-                        rhs = self.parse_rec(right_bp);
-                        return SExpr::Cons(token, vec![SExpr::Atom(id.unwrap()), rhs]);
-                    } else {
-                        rhs = self.parse_rec(right_bp);
-                        return SExpr::Cons(token, vec![rhs]);
+                    self.parse_block_like(token)?
+                } else if matches!(token, Token::TAIL) {
+                    // binds as tightly as a call, so only the postfix
+                    // operators of the call itself are part of it
+                    let bp = self.operators.postfix_binding_power(&Token::LPAREN);
+                    let call = self.parse_rec(bp.unwrap_or(0))?;
+                    if !matches!(call, SExpr::Call(..)) {
+                        return self.fail(String::from("#[tail] can only go on a call"));
                     }
+                    self.node(NodeKind::PrefixExpr, start);
+                    SExpr::Cons(token, vec![call], self.site(first))
+                } else if let Some(right_bp) = self.operators.prefix_binding_power(&token) {
+                    let rhs = self.parse_rec(right_bp)?;
+                    self.node(NodeKind::PrefixExpr, start);
+                    SExpr::Cons(token, vec![rhs], self.site(first))
                 } else if matches!(token, Token::LPAREN) {
-                    let sub = self.parse_rec(0);
-                    assert!(matches!(self.lexer.next(), Some(Token::RPAREN)));
-                    sub
-                } else if matches!(token, Token::LCBRACE) {
-                    let sub = self.parse_rec(0);
-                    assert!(matches!(self.lexer.next(), Some(Token::RCBRACE)));
+                    let sub = self.with_struct_literals(|parser| parser.parse_rec(0))?;
+                    if !matches!(self.lexer.next(), Some(Token::RPAREN)) {
+                        return self.fail(String::from("Expected )"));
+                    }
+                    self.node(NodeKind::ParenExpr, start);
                     sub
+                } else if matches!(token, Token::ELSE) {
+                    return self.fail(String::from("else without a matching if"));
                 } else {
-                    println!("{:?}", token);
-                    panic!("BAD TOKEN");
+                    return self.fail(format!("Unexpected token {:?}", token));
                 }
             }
-            None => panic!("BAD TOKEN"),
+            None => return self.fail(String::from("Unexpected end of input")),
         };
 
         loop {
@@ -94,87 +1657,179 @@ impl Parser {
             }
             let op = op.unwrap().clone();
 
-            if let Some(left_bp) = Parser::postfix_binding_power(&op) {
+            if let Some(left_bp) = self.operators.postfix_binding_power(&op) {
+                if left_bp < min_bp {
+                    break;
+                }
+
                 self.lexer.next();
-                let rhs = self.parse_rec(left_bp);
-                if matches!(op, Token::LBRACE) {
-                    assert!(matches!(self.lexer.next(), Some(Token::RBRACE)));
-                } else if matches!(op, Token::LCBRACE) {
-                    println!("{:?}", rhs);
-                    assert!(matches!(self.lexer.next(), Some(Token::RCBRACE)));
-                }
-                lhs = SExpr::Cons(op, vec![lhs, rhs]);
+                lhs = if matches!(op, Token::LBRACE) {
+                    self.with_struct_literals(|parser| parser.parse_index(lhs, first))?
+                } else if matches!(op, Token::DOT) {
+                    let field = self.parse_ident("field name")?;
+                    SExpr::Field(Box::new(lhs), field, self.site(first))
+                } else if matches!(op, Token::LPAREN) {
+                    let args = self.with_struct_literals(|parser| {
+                        parser.parse_noted_list(Token::RPAREN, |parser| parser.parse_rec(0))
+                    })?;
+                    SExpr::Call(Box::new(lhs), args, self.site(first))
+                } else {
+                    SExpr::Cons(op, vec![lhs], self.site(first))
+                };
+                let kind = match lhs {
+                    SExpr::Index(..) => NodeKind::IndexExpr,
+                    SExpr::Slice(..) => NodeKind::SliceExpr,
+                    SExpr::Field(..) => NodeKind::FieldExpr,
+                    SExpr::Call(..) => NodeKind::CallExpr,
+                    _ => NodeKind::PostfixExpr,
+                };
+                self.node(kind, start);
                 continue;
             }
 
-            if let Some((left_bp, right_bp)) = Parser::infix_binding_power(&op) {
+            if let Some((left_bp, right_bp)) = self.operators.infix_binding_power(&op) {
                 if left_bp < min_bp {
                     break;
                 }
 
                 self.lexer.next();
-                let rhs = self.parse_rec(right_bp);
-
-                if matches!(op, Token::ASSIGN) {
-                    assert!(
-                        matches!(lhs, SExpr::Atom(Token::IDENT(_))),
-                        "ERROR: Can't assign to non-identifiers"
-                    );
+                let rhs = self.parse_rec(right_bp)?;
 
-                    assert!(
-                        !matches!(rhs, SExpr::Atom(Token::LBRACE)),
-                        "ERROR: Bad identifier assignment"
-                    );
+                let assigns = matches!(op, Token::ASSIGN) || op.compound_op().is_some();
+                if assigns
+                    && !matches!(
+                        lhs,
+                        SExpr::Atom(Token::IDENT(_)) | SExpr::Index(..) | SExpr::Field(..)
+                    )
+                {
+                    return self.fail(String::from("Can't assign to non-identifiers"));
                 }
 
-                lhs = SExpr::Cons(op, vec![lhs, rhs]);
+                lhs = SExpr::Cons(op, vec![lhs, rhs], self.site(first));
+                self.node(NodeKind::BinExpr, start);
                 continue;
             }
 
             // breaks if the operator isn't defined for any positional operations
-            // ie for ), }, EOF
+            // ie for ), }, ;, {, EOF
             break;
         }
 
-        lhs
+        Ok(lhs)
     }
 
-    fn infix_binding_power(token: &Token) -> Option<(u8, u8)> {
-        match token {
-            Token::ADD => Some((2, 3)),
-            Token::SUB => Some((2, 3)),
-            Token::MUL => Some((4, 5)),
-            Token::DIV => Some((4, 5)),
-            Token::EXP => Some((6, 7)),
-            Token::ASSIGN => Some((1, 1)),
-            Token::EQ => Some((1, 2)),
-            Token::LEQ => Some((1, 2)),
-            Token::GEQ => Some((1, 2)),
-            Token::NEQ => Some((1, 2)),
-            Token::LESS => Some((1, 2)),
-            Token::GREATER => Some((1, 2)),
-            Token::SEMI => Some((0, 0)),
-            _ => None,
-        }
-    }
-
-    fn prefix_binding_power(token: &Token) -> u8 {
-        match token {
-            Token::IF => 0,
-            Token::ADD => 9,
-            Token::SUB => 9,
-            Token::EXP => 9,
-            Token::NOT => 8,
-            Token::FN => 10,
-            _ => panic!("GIVEN A WRONG TOKEN FOR PREFIX: {:?}", token),
+    // parses the rest of |params| body whose first | has already been
+    // consumed, || is a lambda without parameters
+    fn parse_lambda(&mut self, token: Token) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let list = self.open_list();
+        let mut params = vec![];
+        if matches!(token, Token::BITOR) {
+            while !matches!(self.lexer.peek(), Some(Token::BITOR)) {
+                let first = self.lexer.span();
+                params.push(self.parse_param()?);
+                self.list_item(list, first);
+                match self.lexer.peek() {
+                    Some(Token::COMMA) => {
+                        self.lexer.next();
+                    }
+                    Some(Token::BITOR) => {}
+                    token => {
+                        return self
+                            .fail(format!("Expected , or | in lambda, found {}", found(token)))
+                    }
+                }
+            }
+            self.lexer.next();
         }
+        self.close_list(list);
+        let body = self.parse_rec(0)?;
+        let sig = FnSig {
+            generics: vec![],
+            params: self.check_params(params)?,
+            ret: None,
+        };
+        self.node(NodeKind::Lambda, start);
+        Ok(SExpr::Fn(None, sig, Box::new(body)))
     }
 
-    fn postfix_binding_power(token: &Token) -> Option<u8> {
-        match token {
-            Token::LBRACE => Some(0),
-            Token::LCBRACE => Some(0),
-            _ => None,
+    // parses the rest of an array literal whose [ has already been consumed
+    fn parse_array(&mut self) -> Parsed<SExpr> {
+        let start = self.last_token();
+        let list = self.open_list();
+        let mut elems = vec![];
+
+        while !matches!(self.lexer.peek(), Some(Token::RBRACE)) {
+            let first = self.lexer.span();
+            elems.push(self.parse_rec(0)?);
+            self.list_item(list, first);
+            match self.lexer.peek() {
+                Some(Token::COMMA) => {
+                    self.lexer.next();
+                }
+                Some(Token::RBRACE) => {}
+                token => {
+                    return self.fail(format!(
+                        "Expected , or ] in array literal, found {}",
+                        found(token)
+                    ))
+                }
+            }
         }
+        self.lexer.next();
+
+        self.close_list(list);
+        self.node(NodeKind::ArrayExpr, start);
+        Ok(SExpr::Array(elems))
+    }
+
+    // parses the rest of a[i] or a[lo..hi] whose [ has already been consumed,
+    // either bound of a slice may be left out
+    fn parse_index(&mut self, array: SExpr, first: Option<Span>) -> Parsed<SExpr> {
+        let index = if matches!(self.lexer.peek(), Some(Token::DOTDOT)) {
+            None
+        } else {
+            Some(Box::new(self.parse_rec(0)?))
+        };
+
+        let hi = if matches!(self.lexer.peek(), Some(Token::DOTDOT)) {
+            self.lexer.next();
+            if matches!(self.lexer.peek(), Some(Token::RBRACE)) {
+                Some(None)
+            } else {
+                Some(Some(Box::new(self.parse_rec(0)?)))
+            }
+        } else {
+            None
+        };
+
+        if !matches!(self.lexer.next(), Some(Token::RBRACE)) {
+            return self.fail(String::from("Expected ] after index"));
+        }
+        let site = self.site(first);
+        Ok(match hi {
+            Some(hi) => SExpr::Slice(Box::new(array), index, hi, site),
+            None => SExpr::Index(Box::new(array), index.unwrap(), site),
+        })
+    }
+
+    fn check_params(
+        &self,
+        params: Vec<(String, Option<TypeExpr>)>,
+    ) -> Parsed<Vec<(String, Option<TypeExpr>)>> {
+        for (i, (param, _)) in params.iter().enumerate() {
+            if params[..i].iter().any(|(p, _)| p == param) {
+                return self.fail(format!("Parameter {} is declared twice", param));
+            }
+        }
+        Ok(params)
+    }
+
+    // a syntax error at the token the parser stopped on
+    fn fail<T>(&self, message: String) -> Parsed<T> {
+        Err(SyntaxError {
+            message,
+            span: self.position(),
+        })
     }
 }
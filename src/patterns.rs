@@ -0,0 +1,366 @@
+use super::lexer::*;
+use super::parser::*;
+use super::typeck::*;
+use std::rc::Rc;
+
+// Patterns are checked and compiled in a normalized form where a struct
+// variant's fields are in declaration order and every field is present, so
+// all variants look like tuples. The analyses follow Maranget's "Warnings for
+// pattern matching" and "Compiling pattern matching to good decision trees".
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ctor {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Str(String),
+    // the enum's index in the declared enums and the variant's index in it
+    Variant(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum Pat {
+    // a wildcard, or a binding when it has a name
+    Wild(Option<String>),
+    Ctor(Ctor, Vec<Pat>),
+}
+
+// a path of payload slots from the matched value to a value inside it
+pub type Occurrence = Vec<usize>;
+
+#[derive(Debug)]
+pub enum Decision {
+    // runs the arm after binding names to the values at their occurrences
+    Leaf(usize, Vec<(String, Occurrence)>),
+    // tests the value at the occurrence against each constructor in turn,
+    // the fallback runs when none of them match
+    Switch(Occurrence, Vec<(Ctor, Decision)>, Option<Box<Decision>>),
+    // no arm matches
+    Fail,
+}
+
+// None if the pattern names an enum, variant or field that doesn't exist
+pub fn normalize(pattern: &Pattern, enums: &[Rc<EnumDef>]) -> Option<Pat> {
+    match pattern {
+        Pattern::Wildcard => Some(Pat::Wild(None)),
+        Pattern::Binding(name) => Some(Pat::Wild(Some(name.clone()))),
+        Pattern::Literal(token) => {
+            let ctor = match token {
+                Token::BOOL(b) => Ctor::Bool(*b),
+                Token::INT(n) => Ctor::Int(*n),
+                Token::FLOAT(n) => Ctor::Float(*n),
+                Token::STR(s) => Ctor::Str(s.clone()),
+                _ => return None,
+            };
+            Some(Pat::Ctor(ctor, vec![]))
+        }
+        Pattern::Variant(name, variant, patterns, _) => {
+            let id = enums.iter().position(|def| &def.name == name)?;
+            let index = enums[id].variant(variant)?;
+            let args = match (&enums[id].variants[index].1, patterns) {
+                (Fields::Unit, Fields::Unit) => vec![],
+                (Fields::Tuple(types), Fields::Tuple(patterns))
+                    if types.len() == patterns.len() =>
+                {
+                    patterns
+                        .iter()
+                        .map(|pattern| normalize(pattern, enums))
+                        .collect::<Option<_>>()?
+                }
+                (Fields::Struct(fields), Fields::Struct(patterns)) => fields
+                    .iter()
+                    .map(
+                        |(field, _)| match patterns.iter().find(|(f, _)| f == field) {
+                            Some((_, pattern)) => normalize(pattern, enums),
+                            None => Some(Pat::Wild(None)),
+                        },
+                    )
+                    .collect::<Option<_>>()?,
+                _ => return None,
+            };
+            Some(Pat::Ctor(Ctor::Variant(id, index), args))
+        }
+    }
+}
+
+// whether a value can match `pattern` without matching any of `rows`
+pub fn is_useful(rows: &[Pat], pattern: &Pat, enums: &[Rc<EnumDef>]) -> bool {
+    let matrix: Vec<Vec<Pat>> = rows.iter().map(|row| vec![row.clone()]).collect();
+    useful(&matrix, std::slice::from_ref(pattern), enums)
+}
+
+// a pattern for values none of the rows match, if there are any
+pub fn missing_pattern(rows: &[Pat], enums: &[Rc<EnumDef>]) -> Option<Pattern> {
+    let matrix: Vec<Vec<Pat>> = rows.iter().map(|row| vec![row.clone()]).collect();
+    missing(&matrix, 1, enums).map(|witness| to_pattern(&witness[0], enums))
+}
+
+pub fn compile(arms: Vec<Pat>, enums: &[Rc<EnumDef>]) -> Decision {
+    let rows = arms
+        .into_iter()
+        .enumerate()
+        .map(|(arm, pat)| Row {
+            pats: vec![pat],
+            bindings: vec![],
+            arm,
+        })
+        .collect();
+    compile_rows(rows, vec![vec![]], enums)
+}
+
+fn arity(ctor: &Ctor, enums: &[Rc<EnumDef>]) -> usize {
+    match ctor {
+        Ctor::Variant(id, index) => match &enums[*id].variants[*index].1 {
+            Fields::Unit => 0,
+            Fields::Tuple(types) => types.len(),
+            Fields::Struct(fields) => fields.len(),
+        },
+        _ => 0,
+    }
+}
+
+// every constructor of the type `ctors` belong to, if there are finitely many
+fn signature(ctors: &[Ctor], enums: &[Rc<EnumDef>]) -> Option<Vec<Ctor>> {
+    match ctors.first()? {
+        Ctor::Bool(_) => Some(vec![Ctor::Bool(true), Ctor::Bool(false)]),
+        Ctor::Variant(id, _) => Some(
+            (0..enums[*id].variants.len())
+                .map(|index| Ctor::Variant(*id, index))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn is_complete(ctors: &[Ctor], enums: &[Rc<EnumDef>]) -> bool {
+    match signature(ctors, enums) {
+        Some(all) => all.iter().all(|ctor| ctors.contains(ctor)),
+        None => false,
+    }
+}
+
+// the distinct constructors in the first column, in order of appearance
+fn head_ctors(matrix: &[Vec<Pat>]) -> Vec<Ctor> {
+    let mut ctors = vec![];
+    for row in matrix {
+        if let Pat::Ctor(ctor, _) = &row[0] {
+            if !ctors.contains(ctor) {
+                ctors.push(ctor.clone());
+            }
+        }
+    }
+    ctors
+}
+
+fn wilds(n: usize) -> Vec<Pat> {
+    vec![Pat::Wild(None); n]
+}
+
+// the rows that match `ctor`, with its arguments in place of the first column
+fn specialize(matrix: &[Vec<Pat>], ctor: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter_map(|row| {
+            let args = match &row[0] {
+                Pat::Ctor(c, args) if c == ctor => args.clone(),
+                Pat::Ctor(..) => return None,
+                Pat::Wild(_) => wilds(arity),
+            };
+            Some([args, row[1..].to_vec()].concat())
+        })
+        .collect()
+}
+
+// the rows that match any constructor missing from the first column
+fn default(matrix: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter(|row| matches!(row[0], Pat::Wild(_)))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn useful(matrix: &[Vec<Pat>], row: &[Pat], enums: &[Rc<EnumDef>]) -> bool {
+    let (head, rest) = match row.split_first() {
+        Some(split) => split,
+        None => return matrix.is_empty(),
+    };
+
+    match head {
+        Pat::Ctor(ctor, args) => {
+            let row = [args.clone(), rest.to_vec()].concat();
+            useful(&specialize(matrix, ctor, args.len()), &row, enums)
+        }
+        Pat::Wild(_) => {
+            let ctors = head_ctors(matrix);
+            if !is_complete(&ctors, enums) {
+                return useful(&default(matrix), rest, enums);
+            }
+            ctors.iter().any(|ctor| {
+                let arity = arity(ctor, enums);
+                let row = [wilds(arity), rest.to_vec()].concat();
+                useful(&specialize(matrix, ctor, arity), &row, enums)
+            })
+        }
+    }
+}
+
+// a row of n patterns none of the rows in the matrix match
+fn missing(matrix: &[Vec<Pat>], n: usize, enums: &[Rc<EnumDef>]) -> Option<Vec<Pat>> {
+    if n == 0 {
+        return if matrix.is_empty() {
+            Some(vec![])
+        } else {
+            None
+        };
+    }
+
+    let ctors = head_ctors(matrix);
+    if is_complete(&ctors, enums) {
+        return ctors.into_iter().find_map(|ctor| {
+            let arity = arity(&ctor, enums);
+            let mut witness = missing(&specialize(matrix, &ctor, arity), arity + n - 1, enums)?;
+            let rest = witness.split_off(arity);
+            Some([vec![Pat::Ctor(ctor, witness)], rest].concat())
+        });
+    }
+
+    let mut witness = missing(&default(matrix), n - 1, enums)?;
+    let head = match signature(&ctors, enums) {
+        Some(all) => {
+            let ctor = all.into_iter().find(|ctor| !ctors.contains(ctor)).unwrap();
+            let arity = arity(&ctor, enums);
+            Pat::Ctor(ctor, wilds(arity))
+        }
+        None => Pat::Wild(None),
+    };
+    witness.insert(0, head);
+    Some(witness)
+}
+
+fn to_pattern(pat: &Pat, enums: &[Rc<EnumDef>]) -> Pattern {
+    match pat {
+        Pat::Wild(_) => Pattern::Wildcard,
+        Pat::Ctor(Ctor::Bool(b), _) => Pattern::Literal(Token::BOOL(*b)),
+        Pat::Ctor(Ctor::Int(n), _) => Pattern::Literal(Token::INT(*n)),
+        Pat::Ctor(Ctor::Float(n), _) => Pattern::Literal(Token::FLOAT(*n)),
+        Pat::Ctor(Ctor::Str(s), _) => Pattern::Literal(Token::STR(s.clone())),
+        Pat::Ctor(Ctor::Variant(id, index), args) => {
+            let def = &enums[*id];
+            let (variant, fields) = &def.variants[*index];
+            let args = args.iter().map(|arg| to_pattern(arg, enums));
+            let fields = match fields {
+                Fields::Unit => Fields::Unit,
+                Fields::Tuple(_) => Fields::Tuple(args.collect()),
+                Fields::Struct(fields) => Fields::Struct(
+                    fields
+                        .iter()
+                        .map(|(field, _)| field.clone())
+                        .zip(args)
+                        .collect(),
+                ),
+            };
+            Pattern::Variant(def.name.clone(), variant.clone(), fields, false)
+        }
+    }
+}
+
+struct Row {
+    pats: Vec<Pat>,
+    bindings: Vec<(String, Occurrence)>,
+    arm: usize,
+}
+
+// `occurrences` says where the value each column tests comes from
+fn compile_rows(rows: Vec<Row>, occurrences: Vec<Occurrence>, enums: &[Rc<EnumDef>]) -> Decision {
+    let first = match rows.first() {
+        Some(first) => first,
+        None => return Decision::Fail,
+    };
+
+    // the first row matches once its remaining patterns are all wildcards
+    let column = match first
+        .pats
+        .iter()
+        .position(|pat| matches!(pat, Pat::Ctor(..)))
+    {
+        Some(column) => column,
+        None => {
+            let mut bindings = first.bindings.clone();
+            for (pat, occurrence) in first.pats.iter().zip(&occurrences) {
+                if let Pat::Wild(Some(name)) = pat {
+                    bindings.push((name.clone(), occurrence.clone()));
+                }
+            }
+            return Decision::Leaf(first.arm, bindings);
+        }
+    };
+
+    let occurrence = occurrences[column].clone();
+    let mut others = occurrences;
+    others.remove(column);
+
+    // takes the tested column out of a row, binding its name if it's a
+    // wildcard, and returns the column's pattern
+    let split = |row: &Row| {
+        let mut pats = row.pats.clone();
+        let head = pats.remove(column);
+        let mut bindings = row.bindings.clone();
+        if let Pat::Wild(Some(name)) = &head {
+            bindings.push((name.clone(), occurrence.clone()));
+        }
+        (head, pats, bindings)
+    };
+
+    let mut ctors = vec![];
+    for row in &rows {
+        if let Pat::Ctor(ctor, _) = &row.pats[column] {
+            if !ctors.contains(ctor) {
+                ctors.push(ctor.clone());
+            }
+        }
+    }
+
+    let mut cases = vec![];
+    for ctor in &ctors {
+        let arity = arity(ctor, enums);
+        let mut sub_rows = vec![];
+        for row in &rows {
+            let (head, pats, bindings) = split(row);
+            let args = match head {
+                Pat::Ctor(c, args) if &c == ctor => args,
+                Pat::Ctor(..) => continue,
+                Pat::Wild(_) => wilds(arity),
+            };
+            sub_rows.push(Row {
+                pats: [args, pats].concat(),
+                bindings,
+                arm: row.arm,
+            });
+        }
+
+        let sub_occurrences = (0..arity)
+            .map(|slot| [occurrence.clone(), vec![slot]].concat())
+            .chain(others.iter().cloned())
+            .collect();
+        cases.push((ctor.clone(), compile_rows(sub_rows, sub_occurrences, enums)));
+    }
+
+    let fallback = if is_complete(&ctors, enums) {
+        None
+    } else {
+        let mut default_rows = vec![];
+        for row in &rows {
+            if let (Pat::Wild(_), pats, bindings) = split(row) {
+                default_rows.push(Row {
+                    pats,
+                    bindings,
+                    arm: row.arm,
+                });
+            }
+        }
+        Some(Box::new(compile_rows(default_rows, others, enums)))
+    };
+
+    Decision::Switch(occurrence, cases, fallback)
+}
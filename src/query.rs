@@ -0,0 +1,581 @@
+use super::ir::{self, Module, Unit};
+use super::lexer::Lexer;
+use super::modules::{self, Files};
+use super::natives::Natives;
+use super::operators::OperatorTable;
+use super::parser::{self, Parser, SExpr};
+use super::source::SourceMap;
+use super::typeck::{self, Instances, Type, TypeChecker};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// A program's compilation split into queries, each computed from the ones it
+// asks for and remembered with them. Setting a file's text starts a new
+// revision, a query asked for again after that is only computed again if one
+// of the queries it used changed, and when it comes out the same as before
+// the queries using it don't change either. Top level statements are checked
+// one after the other with the globals before them and lowered one by one,
+// so editing a function's body only checks and lowers that function again,
+// and the statements after it too if its type changed.
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Query {
+    // the text of a file, read from disk unless it was set
+    Source(String),
+    // a file lexed
+    Tokens(String),
+    // a file's statements
+    Ast(String),
+    // the root file and its modules merged into one program, with every name
+    // resolved to the item it refers to
+    Program,
+    // a top level statement of the program, by index, placed so text
+    // moving it leaves it the same
+    Statement(usize),
+    // the program's struct and enum declarations
+    TypeDecls,
+    // the names the program assigns at the top level
+    GlobalNames,
+    // the number of top level statements
+    Count,
+    // the indexes of the statements declaring generic functions
+    GenericFns,
+    // a statement type checked with the globals assigned before it
+    Checked(usize),
+    // the types of the globals before a statement, the last index is past
+    // the end of the program
+    Globals(usize),
+    Instances(usize),
+    // a statement lowered on its own
+    Unit(usize),
+    // the units linked into a module the VM can run
+    Module,
+}
+
+// an error a query failed with, kept so it can be returned again
+#[derive(Debug, Clone, PartialEq)]
+struct Failure {
+    kind: ErrorKind,
+    message: String,
+}
+
+impl Failure {
+    fn error(self) -> Error {
+        Error::new(self.kind, self.message)
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        Self {
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+type Answer<T> = Result<Rc<T>, Failure>;
+
+type Globals = HashMap<String, Type>;
+
+#[derive(Debug, Clone)]
+enum Output {
+    Source(Answer<String>),
+    Tokens(Answer<Lexer>),
+    Ast(Answer<Vec<SExpr>>),
+    Program(Answer<Vec<SExpr>>),
+    Statement(Option<Rc<SExpr>>),
+    TypeDecls(Answer<SExpr>),
+    GlobalNames(Answer<Vec<String>>),
+    Count(Answer<usize>),
+    GenericFns(Answer<Vec<usize>>),
+    Checked(Answer<(Globals, Instances)>),
+    Globals(Answer<Globals>),
+    Instances(Answer<Instances>),
+    Unit(Answer<Unit>),
+    Module(Answer<Module>),
+}
+
+impl Output {
+    // whether the queries using it can keep what they computed from the
+    // other, code is never compared so units and modules are always new
+    fn same(&self, other: &Output) -> bool {
+        match (self, other) {
+            (Output::Source(a), Output::Source(b)) => a == b,
+            (Output::Tokens(a), Output::Tokens(b)) => a == b,
+            (Output::Ast(a), Output::Ast(b)) => a == b,
+            (Output::Program(a), Output::Program(b)) => a == b,
+            (Output::Statement(a), Output::Statement(b)) => a == b,
+            (Output::TypeDecls(a), Output::TypeDecls(b)) => a == b,
+            (Output::GlobalNames(a), Output::GlobalNames(b)) => a == b,
+            (Output::Count(a), Output::Count(b)) => a == b,
+            (Output::GenericFns(a), Output::GenericFns(b)) => a == b,
+            (Output::Checked(a), Output::Checked(b)) => a == b,
+            (Output::Globals(a), Output::Globals(b)) => a == b,
+            (Output::Instances(a), Output::Instances(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+struct Memo {
+    output: Output,
+    // the revision the output last changed in
+    changed: u64,
+    // the last revision it's known to be up to date in
+    verified: u64,
+    // the queries it was computed from, in the order it asked for them
+    deps: Vec<Query>,
+    // set for the texts given to set_source, which nothing computes
+    input: bool,
+}
+
+pub struct Database {
+    // the root file's name and where its modules are loaded from
+    root: String,
+    dir: Option<PathBuf>,
+    natives: Natives,
+    // the custom operators the files are parsed with and what they call
+    operators: OperatorTable,
+    revision: u64,
+    memos: RefCell<HashMap<Query, Memo>>,
+    // the queries being computed, innermost last, with the ones each has
+    // asked for so far
+    active: RefCell<Vec<(Query, Vec<Query>)>>,
+    // every query computed, in order
+    log: RefCell<Vec<Query>>,
+}
+
+impl Database {
+    pub fn new(root: &str, dir: Option<PathBuf>, natives: Natives) -> Self {
+        Self {
+            root: root.to_string(),
+            dir,
+            natives,
+            operators: OperatorTable::new(),
+            revision: 0,
+            memos: RefCell::new(HashMap::new()),
+            active: RefCell::new(vec![]),
+            log: RefCell::new(vec![]),
+        }
+    }
+
+    // parses the files with these operators instead of the built in ones
+    pub fn with_operators(mut self, operators: OperatorTable) -> Self {
+        self.operators = operators;
+        self
+    }
+
+    pub fn natives(&self) -> &Natives {
+        &self.natives
+    }
+
+    pub fn operators(&self) -> &OperatorTable {
+        &self.operators
+    }
+
+    // gives a file the text, it's no longer read from disk
+    pub fn set_source(&mut self, name: &str, text: String) {
+        let query = Query::Source(name.to_string());
+        let output = Output::Source(Ok(Rc::new(text)));
+        let mut memos = self.memos.borrow_mut();
+        if let Some(memo) = memos.get(&query) {
+            if memo.input && memo.output.same(&output) {
+                return;
+            }
+        }
+        self.revision += 1;
+        memos.insert(
+            query,
+            Memo {
+                output,
+                changed: self.revision,
+                verified: self.revision,
+                deps: vec![],
+                input: true,
+            },
+        );
+    }
+
+    // the queries computed since the last call, in the order they finished
+    pub fn take_log(&self) -> Vec<Query> {
+        std::mem::take(&mut self.log.borrow_mut())
+    }
+
+    pub fn tokens(&self, name: &str) -> Result<Rc<Lexer>, Error> {
+        self.tokens_of(name).map_err(Failure::error)
+    }
+
+    pub fn ast(&self, name: &str) -> Result<Rc<Vec<SExpr>>, Error> {
+        self.ast_of(name).map_err(Failure::error)
+    }
+
+    // the statements of the whole program
+    pub fn program(&self) -> Result<Rc<Vec<SExpr>>, Error> {
+        self.program_stmts().map_err(Failure::error)
+    }
+
+    // the types of the globals once the whole program has run
+    pub fn globals(&self) -> Result<Rc<HashMap<String, Type>>, Error> {
+        let len = self.program()?.len();
+        self.globals_before(len)
+    }
+
+    // the types of the globals before a statement, or the error checking a
+    // statement before it failed with
+    pub fn globals_before(&self, stmt: usize) -> Result<Rc<HashMap<String, Type>>, Error> {
+        self.globals_at(stmt).map_err(Failure::error)
+    }
+
+    pub fn unit(&self, stmt: usize) -> Result<Rc<Unit>, Error> {
+        self.unit_of(stmt).map_err(Failure::error)
+    }
+
+    pub fn module(&self) -> Result<Rc<Module>, Error> {
+        match self.fetch(Query::Module) {
+            Output::Module(module) => module.map_err(Failure::error),
+            _ => unreachable!(),
+        }
+    }
+
+    // an error loading, checking or lowering the program. Loading errors say
+    // where they are, the others are put where the first statement that
+    // fails to check or lower starts
+    pub fn located(&self, err: Error) -> Error {
+        let stmts = match self.program() {
+            Ok(stmts) => stmts,
+            Err(_) => return err,
+        };
+        let location = (0..stmts.len())
+            .find(|&i| self.globals_before(i + 1).is_err() || self.unit(i).is_err())
+            .and_then(|i| parser::site_start(&stmts[i]))
+            .and_then(|offset| self.source_map().location(offset));
+        match location {
+            Some(location) => Error::new(err.kind(), format!("{}: {}", location, err)),
+            None => err,
+        }
+    }
+
+    // the program's files at the offsets of the sites in its module, to
+    // show where a runtime error happened
+    pub fn source_map(&self) -> SourceMap {
+        let mut map = SourceMap::new();
+        let mut files = DatabaseFiles(self);
+        // a program that doesn't load has no module to run either
+        let _ = modules::load_files(&self.root, self.dir.clone(), &mut map, &mut files);
+        map
+    }
+
+    // the query's output, brought up to date
+    fn fetch(&self, query: Query) -> Output {
+        if let Some((_, deps)) = self.active.borrow_mut().last_mut() {
+            deps.push(query.clone());
+        }
+        self.update(&query);
+        self.memos.borrow()[&query].output.clone()
+    }
+
+    // brings the query up to date and returns the revision its output last
+    // changed in. A remembered output is kept if none of the queries it was
+    // computed from has changed since it was last known to be up to date,
+    // they're checked in the order it asked for them since the ones after a
+    // changed one may not be asked for anymore
+    fn update(&self, query: &Query) -> u64 {
+        let memo = self.memos.borrow().get(query).map(|memo| {
+            let current = memo.input || memo.verified == self.revision;
+            (current, memo.verified, memo.changed, memo.deps.clone())
+        });
+        if let Some((current, verified, changed, deps)) = memo {
+            if current || deps.iter().all(|dep| self.update(dep) <= verified) {
+                if let Some(memo) = self.memos.borrow_mut().get_mut(query) {
+                    memo.verified = self.revision;
+                }
+                return changed;
+            }
+        }
+        self.execute(query)
+    }
+
+    fn execute(&self, query: &Query) -> u64 {
+        if self
+            .active
+            .borrow()
+            .iter()
+            .any(|(active, _)| active == query)
+        {
+            panic!("QUERY ERROR: {:?} depends on itself", query);
+        }
+        self.active.borrow_mut().push((query.clone(), vec![]));
+        let output = self.compute(query);
+        let (_, deps) = self.active.borrow_mut().pop().unwrap();
+        self.log.borrow_mut().push(query.clone());
+
+        // an output equal to the old one keeps the old one, so the queries
+        // using it can keep theirs and addresses into it stay valid
+        let mut memos = self.memos.borrow_mut();
+        let (output, changed) = match memos.remove(query) {
+            Some(old) if old.output.same(&output) => (old.output, old.changed),
+            _ => (output, self.revision),
+        };
+        memos.insert(
+            query.clone(),
+            Memo {
+                output,
+                changed,
+                verified: self.revision,
+                deps,
+                input: false,
+            },
+        );
+        changed
+    }
+
+    fn compute(&self, query: &Query) -> Output {
+        match query {
+            Query::Source(name) => Output::Source(
+                std::fs::read_to_string(name)
+                    .map(Rc::new)
+                    .map_err(Failure::from),
+            ),
+            Query::Tokens(name) => Output::Tokens(self.source(name).and_then(|text| {
+                Lexer::scan_source(&text, 0, &self.operators.custom_symbols())
+                    .map(Rc::new)
+                    .map_err(|err| Error::new(err.kind(), format!("{}: {}", name, err)).into())
+            })),
+            Query::Ast(name) => Output::Ast(self.tokens_of(name).and_then(|lexer| {
+                let parser = Parser::with_operators((*lexer).clone(), self.operators.clone());
+                match parser.parse() {
+                    Ok(SExpr::Block(stmts)) => Ok(Rc::new(stmts)),
+                    Ok(_) => unreachable!(),
+                    Err(err) => self
+                        .source(name)
+                        .and_then(|text| Err(modules::syntax_error(name, &text, err).into())),
+                }
+            })),
+            Query::Program => {
+                let mut files = DatabaseFiles(self);
+                let program = modules::load_files(
+                    &self.root,
+                    self.dir.clone(),
+                    &mut SourceMap::new(),
+                    &mut files,
+                );
+                Output::Program(match program {
+                    Ok(SExpr::Block(stmts)) => Ok(Rc::new(stmts)),
+                    Ok(_) => unreachable!(),
+                    Err(err) => Err(err.into()),
+                })
+            }
+            Query::Statement(i) => Output::Statement(
+                self.program_stmts()
+                    .ok()
+                    .and_then(|stmts| stmts.get(*i).cloned())
+                    .map(|stmt| Rc::new(parser::placed(stmt))),
+            ),
+            Query::TypeDecls => Output::TypeDecls(self.program_stmts().map(|stmts| {
+                let types = stmts
+                    .iter()
+                    .filter(|stmt| matches!(stmt, SExpr::StructDef(..) | SExpr::EnumDef(..)))
+                    .cloned()
+                    .collect();
+                Rc::new(SExpr::Block(types))
+            })),
+            Query::GlobalNames => Output::GlobalNames(
+                self.program_stmts()
+                    .map(|stmts| Rc::new(typeck::global_names(&stmts))),
+            ),
+            Query::Count => Output::Count(self.program_stmts().map(|stmts| Rc::new(stmts.len()))),
+            Query::GenericFns => Output::GenericFns(
+                self.program_stmts()
+                    .map(|stmts| Rc::new(ir::generic_fns(&stmts))),
+            ),
+            Query::Checked(i) => Output::Checked(self.check(*i)),
+            Query::Globals(i) => Output::Globals(match i {
+                0 => Ok(Rc::new(HashMap::new())),
+                _ => self
+                    .checked_stmt(i - 1)
+                    .map(|checked| Rc::new(checked.0.clone())),
+            }),
+            Query::Instances(i) => Output::Instances(
+                self.checked_stmt(*i)
+                    .map(|checked| Rc::new(checked.1.clone())),
+            ),
+            Query::Unit(i) => Output::Unit(self.lower(*i)),
+            Query::Module => Output::Module(self.link()),
+        }
+    }
+
+    fn check(&self, i: usize) -> Answer<(Globals, Instances)> {
+        let stmt = self.statement(i)?;
+        let types = self.type_decls()?;
+        let names = self.global_names()?;
+        let globals = self.globals_at(i)?;
+        // the last statement's value is the program's
+        let last = i + 1 == *self.count()?;
+        let checked = TypeChecker::check_statement(
+            &stmt,
+            last,
+            &types,
+            &names,
+            (*globals).clone(),
+            &self.natives,
+            &self.operators,
+        )?;
+        Ok(Rc::new(checked))
+    }
+
+    // the instances of the generic functions' statements are needed too for
+    // the copies specialised for the calls in it
+    fn lower(&self, i: usize) -> Answer<Unit> {
+        let stmt = self.statement(i)?;
+        let types = self.type_decls()?;
+        let mut instances = (*self.instances(i)?).clone();
+        let mut generics = vec![];
+        for j in self.generic_fns()?.iter() {
+            instances.extend(self.instances(*j)?.iter().map(|(k, v)| (*k, v.clone())));
+            generics.push(self.statement(*j)?);
+        }
+        let generics: Vec<&SExpr> = generics.iter().map(|stmt| &**stmt).collect();
+        let unit =
+            ir::Lowerer::lower_statement(&stmt, &types, &generics, &instances, &self.operators)?;
+        Ok(Rc::new(unit))
+    }
+
+    // the units are kept while text before their statements moves, it's
+    // only here that their sites are put where the statements are now
+    fn link(&self) -> Answer<Module> {
+        let stmts = self.program_stmts()?;
+        let types = self.type_decls()?;
+        let units = (0..stmts.len())
+            .map(|i| self.unit_of(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        let units: Vec<&Unit> = units.iter().map(|unit| &**unit).collect();
+        let starts: Vec<usize> = stmts
+            .iter()
+            .map(|stmt| parser::site_start(stmt).unwrap_or(0))
+            .collect();
+        let generic_starts: Vec<usize> = self.generic_fns()?.iter().map(|j| starts[*j]).collect();
+        let module = ir::link_placed(&types, &units, &starts, &generic_starts)?;
+        Ok(Rc::new(module))
+    }
+
+    fn source(&self, name: &str) -> Answer<String> {
+        match self.fetch(Query::Source(name.to_string())) {
+            Output::Source(text) => text,
+            _ => unreachable!(),
+        }
+    }
+
+    fn tokens_of(&self, name: &str) -> Answer<Lexer> {
+        match self.fetch(Query::Tokens(name.to_string())) {
+            Output::Tokens(lexer) => lexer,
+            _ => unreachable!(),
+        }
+    }
+
+    fn ast_of(&self, name: &str) -> Answer<Vec<SExpr>> {
+        match self.fetch(Query::Ast(name.to_string())) {
+            Output::Ast(stmts) => stmts,
+            _ => unreachable!(),
+        }
+    }
+
+    fn program_stmts(&self) -> Answer<Vec<SExpr>> {
+        match self.fetch(Query::Program) {
+            Output::Program(stmts) => stmts,
+            _ => unreachable!(),
+        }
+    }
+
+    fn statement(&self, i: usize) -> Result<Rc<SExpr>, Failure> {
+        match self.fetch(Query::Statement(i)) {
+            Output::Statement(Some(stmt)) => Ok(stmt),
+            Output::Statement(None) => self.program_stmts().and_then(|_| {
+                Err(Failure {
+                    kind: ErrorKind::InvalidInput,
+                    message: format!("QUERY ERROR: the program has no statement {}", i),
+                })
+            }),
+            _ => unreachable!(),
+        }
+    }
+
+    fn type_decls(&self) -> Answer<SExpr> {
+        match self.fetch(Query::TypeDecls) {
+            Output::TypeDecls(types) => types,
+            _ => unreachable!(),
+        }
+    }
+
+    fn global_names(&self) -> Answer<Vec<String>> {
+        match self.fetch(Query::GlobalNames) {
+            Output::GlobalNames(names) => names,
+            _ => unreachable!(),
+        }
+    }
+
+    fn count(&self) -> Answer<usize> {
+        match self.fetch(Query::Count) {
+            Output::Count(count) => count,
+            _ => unreachable!(),
+        }
+    }
+
+    fn generic_fns(&self) -> Answer<Vec<usize>> {
+        match self.fetch(Query::GenericFns) {
+            Output::GenericFns(fns) => fns,
+            _ => unreachable!(),
+        }
+    }
+
+    fn checked_stmt(&self, i: usize) -> Answer<(Globals, Instances)> {
+        match self.fetch(Query::Checked(i)) {
+            Output::Checked(checked) => checked,
+            _ => unreachable!(),
+        }
+    }
+
+    fn globals_at(&self, i: usize) -> Answer<Globals> {
+        match self.fetch(Query::Globals(i)) {
+            Output::Globals(globals) => globals,
+            _ => unreachable!(),
+        }
+    }
+
+    fn instances(&self, i: usize) -> Answer<Instances> {
+        match self.fetch(Query::Instances(i)) {
+            Output::Instances(instances) => instances,
+            _ => unreachable!(),
+        }
+    }
+
+    fn unit_of(&self, i: usize) -> Answer<Unit> {
+        match self.fetch(Query::Unit(i)) {
+            Output::Unit(unit) => unit,
+            _ => unreachable!(),
+        }
+    }
+}
+
+// the module loader gets the program's files from the database, so the
+// program depends on the files it loaded
+struct DatabaseFiles<'a>(&'a Database);
+
+impl Files for DatabaseFiles<'_> {
+    fn read(&mut self, name: &str) -> Result<String, Error> {
+        self.0
+            .source(name)
+            .map(|text| (*text).clone())
+            .map_err(Failure::error)
+    }
+
+    fn parse(&mut self, name: &str, _text: &str) -> Result<Vec<SExpr>, Error> {
+        self.0
+            .ast_of(name)
+            .map(|stmts| (*stmts).clone())
+            .map_err(Failure::error)
+    }
+}
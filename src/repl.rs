@@ -0,0 +1,299 @@
+use super::editor::Editor;
+use super::ir::{self, Lowerer, Unit};
+use super::lexer::{Lexer, Token};
+use super::modules;
+use super::natives::Natives;
+use super::operators::OperatorTable;
+use super::parser::{Parser, SExpr};
+use super::source::SourceMap;
+use super::typeck::{self, Instances, Type, TypeChecker};
+use super::vm::{Value, VM};
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind};
+use std::rc::Rc;
+
+const PROMPT: &str = ">> ";
+// the prompt for the lines of an entry after the first
+const CONTINUE: &str = ".. ";
+
+const HELP: &str = "\
+:tokens CODE  show the tokens the code is scanned into
+:ast CODE     show the code's syntax tree
+:type EXPR    show the expression's type
+:history      show the lines entered so far
+:reset        forget every binding and function
+:quit         leave the REPL";
+
+// An interactive session. Every entry is checked with the types of the
+// globals the entries before it left behind and run with their values, so
+// their bindings and functions are still there without running them again.
+// Each entry is a file of its own in the session's source map, positions in
+// errors are within it.
+pub struct Repl {
+    editor: Editor,
+    natives: Natives,
+    operators: OperatorTable,
+    state: State,
+}
+
+// what the entries that ran without an error left behind
+#[derive(Default)]
+struct State {
+    // their struct and enum declarations
+    types: Vec<SExpr>,
+    globals: HashMap<String, Type>,
+    values: HashMap<String, Value>,
+    // their statements lowered, linked again before every entry's so their
+    // functions keep their numbers
+    units: Vec<Unit>,
+    // the statements declaring generic functions, which calls in later
+    // entries are specialised from, with the instances found in them
+    generics: Vec<(Rc<SExpr>, Instances)>,
+    source_map: SourceMap,
+    // how many entries have been read, which names the next one's file
+    read: usize,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self::with_natives(Natives::prelude())
+    }
+
+    pub fn with_natives(natives: Natives) -> Self {
+        Self {
+            editor: Editor::new(),
+            natives,
+            operators: OperatorTable::new(),
+            state: State::default(),
+        }
+    }
+
+    // the operators entries are parsed with, a custom one calls the native
+    // or function it names
+    pub fn with_operators(mut self, operators: OperatorTable) -> Self {
+        self.operators = operators;
+        self
+    }
+
+    // reads and runs entries until the input ends or the user quits
+    pub fn run(&mut self) -> io::Result<()> {
+        while let Some(input) = self.read_entry()? {
+            let input = input.trim();
+            if input == ":quit" || input == ":q" {
+                break;
+            }
+            if input.is_empty() {
+                continue;
+            }
+            if let Err(err) = self.entry(input) {
+                println!("{}", err);
+            }
+        }
+        Ok(())
+    }
+
+    // an entry goes on over the next lines while it has a bracket left
+    // open, an empty line ends it anyway
+    fn read_entry(&mut self) -> io::Result<Option<String>> {
+        let mut input = String::new();
+        let mut prompt = PROMPT;
+        loop {
+            let line = match self.editor.read_line(prompt) {
+                Ok(Some(line)) => line,
+                Ok(None) if input.is_empty() => return Ok(None),
+                Ok(None) => return Ok(Some(input)),
+                // ctrl-c drops the entry
+                Err(err) if err.kind() == ErrorKind::Interrupted => {
+                    input.clear();
+                    prompt = PROMPT;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            self.editor.add_history(&line);
+            if prompt == CONTINUE && line.trim().is_empty() {
+                return Ok(Some(input));
+            }
+            if !input.is_empty() {
+                input.push('\n');
+            }
+            input.push_str(&line);
+            if !is_incomplete(&input, &self.operators) {
+                return Ok(Some(input));
+            }
+            prompt = CONTINUE;
+        }
+    }
+
+    fn entry(&mut self, input: &str) -> Result<(), Error> {
+        let (command, arg) = match input.strip_prefix(':') {
+            Some(command) => match command.find(char::is_whitespace) {
+                Some(i) => (&command[..i], command[i..].trim()),
+                None => (command, ""),
+            },
+            None => {
+                match self.eval(input)? {
+                    Value::Void => {}
+                    value => println!("{}", value),
+                }
+                return Ok(());
+            }
+        };
+
+        match command {
+            "help" => println!("{}", HELP),
+            "tokens" => {
+                let mut lexer = scan(arg, &self.operators)?;
+                let mut tokens = vec![];
+                while let Some(token) = lexer.next() {
+                    tokens.push(format!("{:?}", token));
+                }
+                println!("{}", tokens.join(" "));
+            }
+            "ast" => {
+                let lexer = scan(arg, &self.operators)?;
+                let ast = Parser::with_operators(lexer, self.operators.clone()).parse()?;
+                println!("{}", ast);
+            }
+            "type" => {
+                let (ty, _) = self.check(arg)?;
+                println!("{}", ty);
+            }
+            "history" => {
+                for (i, line) in self.editor.history().iter().enumerate() {
+                    println!("{:4}  {}", i + 1, line);
+                }
+            }
+            "reset" => self.state = State::default(),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown command :{}, :help lists them", command),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    // runs the entry with what the earlier ones left behind, which it adds
+    // to if it succeeds
+    pub fn eval(&mut self, entry: &str) -> Result<Value, Error> {
+        let (_, checked) = self.check(entry)?;
+        let ran: Vec<&Unit> = self.state.units.iter().collect();
+        let units: Vec<&Unit> = checked.units.iter().collect();
+        let module = ir::link_after(&SExpr::Block(checked.types.clone()), &ran, &units)?;
+
+        let mut vm = VM::new(&module, &self.natives).with_globals(&self.state.values);
+        let value = vm
+            .run()
+            .map_err(|err| Error::other(err.render(&self.state.source_map)))?;
+        let values = vm.globals();
+
+        let state = &mut self.state;
+        state.values.extend(values);
+        state.types = checked.types;
+        state.globals = checked.globals;
+        state.units.extend(checked.units);
+        state.generics = checked.generics;
+        Ok(value)
+    }
+
+    // the entry's type and what it would leave behind, with its statements
+    // lowered
+    fn check(&mut self, entry: &str) -> Result<(Type, State), Error> {
+        let state = &mut self.state;
+        state.read += 1;
+        // modules are loaded from the directory the REPL was started in
+        let dir = std::env::current_dir().ok();
+        let name = format!("<entry {}>", state.read);
+        let text = entry.to_string();
+        let stmts =
+            match modules::load_source(&name, text, dir, &self.operators, &mut state.source_map)? {
+                SExpr::Block(stmts) => stmts.into_iter().map(Rc::new),
+                _ => unreachable!(),
+            };
+
+        let mut checked = State {
+            types: state.types.clone(),
+            globals: state.globals.clone(),
+            generics: state.generics.clone(),
+            ..State::default()
+        };
+        let stmts: Vec<Rc<SExpr>> = stmts.collect();
+        checked.types.extend(
+            stmts
+                .iter()
+                .filter(|stmt| matches!(***stmt, SExpr::StructDef(..) | SExpr::EnumDef(..)))
+                .map(|stmt| (**stmt).clone()),
+        );
+        let types = SExpr::Block(checked.types.clone());
+        // the globals of the entries before and the ones this one assigns
+        let mut names: Vec<String> = checked.globals.keys().cloned().collect();
+        for stmt in &stmts {
+            names.extend(typeck::global_names(std::slice::from_ref(&**stmt)));
+        }
+
+        let mut ty = Type::Void;
+        for (i, stmt) in stmts.iter().enumerate() {
+            let globals = std::mem::take(&mut checked.globals);
+            let (t, globals, mut instances) = TypeChecker::check_statement_type(
+                stmt,
+                i + 1 == stmts.len(),
+                &types,
+                &names,
+                globals,
+                &self.natives,
+                &self.operators,
+            )?;
+            ty = t;
+            checked.globals = globals;
+
+            // a function declared again replaces the generic one
+            if let SExpr::Fn(Some(name), sig, _) = &**stmt {
+                checked
+                    .generics
+                    .retain(|(g, _)| !matches!(&**g, SExpr::Fn(Some(other), ..) if other == name));
+                if !sig.generics.is_empty() {
+                    checked.generics.push((stmt.clone(), instances.clone()));
+                }
+            }
+
+            for (_, found) in &checked.generics {
+                instances.extend(found.iter().map(|(k, v)| (*k, v.clone())));
+            }
+            let generics: Vec<&SExpr> = checked.generics.iter().map(|(g, _)| &**g).collect();
+            let unit =
+                Lowerer::lower_statement(stmt, &types, &generics, &instances, &self.operators)?;
+            checked.units.push(unit);
+        }
+        Ok((ty, checked))
+    }
+}
+
+fn scan(code: &str, operators: &OperatorTable) -> Result<Lexer, Error> {
+    Lexer::scan_source(code, 0, &operators.custom_symbols())
+}
+
+// whether the input has a bracket left open, input that doesn't scan is
+// complete so its error is reported
+fn is_incomplete(input: &str, operators: &OperatorTable) -> bool {
+    let mut lexer = match scan(input, operators) {
+        Ok(lexer) => lexer,
+        Err(_) => return false,
+    };
+    let mut depth = 0;
+    while let Some(token) = lexer.next() {
+        match token {
+            Token::LPAREN | Token::LBRACE | Token::LCBRACE => depth += 1,
+            Token::RPAREN | Token::RBRACE | Token::RCBRACE => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
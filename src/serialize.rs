@@ -0,0 +1,630 @@
+use super::json::Json;
+use super::lexer::{Lexer, Token, SYMBOLS};
+use super::parser::{Fields, FnSig, Pattern, SExpr, TypeExpr};
+use super::source::{Site, Span};
+use std::io::{Error, ErrorKind};
+
+// The token stream and the syntax tree as JSON, for tools outside Rust. A
+// document is an object with the schema's version and either "tokens" or
+// "ast":
+//
+//   token     {"kind": "IDENT", "value": "x"}, kind is the Token variant and
+//             only IDENT, OP, STR, BOOL, INT and FLOAT have a value. In the
+//             token stream it also has "span": {"start": 0, "end": 1}, in
+//             characters from the start of the file
+//   node      {"node": "Cons", ...} with a field per part of the SExpr
+//             variant, see node below
+//   pattern   {"pattern": "Binding", ...}
+//   type      {"type": "Named", ...}
+//
+// A tree read back is only checked for its shape, it goes through the type
+// checker like any other program afterwards. The version goes up when a
+// document of the old version wouldn't be read the same way.
+pub const VERSION: usize = 1;
+
+const KEYWORDS: [Token; 12] = [
+    Token::IF,
+    Token::ELSE,
+    Token::WHILE,
+    Token::FN,
+    Token::STRUCT,
+    Token::ENUM,
+    Token::MATCH,
+    Token::MODULE,
+    Token::IMPORT,
+    Token::PUB,
+    Token::VOID,
+    Token::TAIL,
+];
+
+pub fn tokens_to_json(mut lexer: Lexer) -> Json {
+    let mut tokens = vec![];
+    while let Some(span) = lexer.span() {
+        let mut token = token(&lexer.next().unwrap());
+        if let Json::Object(fields) = &mut token {
+            fields.push((String::from("span"), span_to_json(span)));
+        }
+        tokens.push(token);
+    }
+    Json::object(vec![("version", VERSION.into()), ("tokens", tokens.into())])
+}
+
+pub fn ast_to_json(program: &SExpr) -> Json {
+    Json::object(vec![("version", VERSION.into()), ("ast", node(program))])
+}
+
+pub fn ast_from_json(json: &Json) -> Result<SExpr, Error> {
+    match json.get("version").and_then(Json::as_usize) {
+        Some(VERSION) => read_node(field(json, "ast")?),
+        Some(version) => Err(invalid(&format!("unsupported version {}", version))),
+        None => Err(invalid("missing version")),
+    }
+}
+
+fn span_to_json(span: Span) -> Json {
+    Json::object(vec![("start", span.start.into()), ("end", span.end.into())])
+}
+
+fn site(site: &Site) -> Json {
+    optional(site.0.map(span_to_json))
+}
+
+fn token(token: &Token) -> Json {
+    let value = match token {
+        Token::IDENT(s) | Token::OP(s) | Token::STR(s) => Some(Json::from(s.as_str())),
+        Token::BOOL(b) => Some(Json::from(*b)),
+        Token::INT(n) => Some(Json::from(*n)),
+        Token::FLOAT(n) => Some(Json::from(*n as f64)),
+        _ => None,
+    };
+    let debug = format!("{:?}", token);
+    let kind = debug.split('(').next().unwrap();
+    let mut fields = vec![("kind", Json::from(kind))];
+    fields.extend(value.map(|value| ("value", value)));
+    Json::object(fields)
+}
+
+fn optional(json: Option<Json>) -> Json {
+    json.unwrap_or(Json::Null)
+}
+
+fn strings(names: &[String]) -> Json {
+    names
+        .iter()
+        .map(|name| Json::from(name.as_str()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn nodes(exprs: &[SExpr]) -> Json {
+    exprs.iter().map(node).collect::<Vec<_>>().into()
+}
+
+fn named<T>(items: &[(String, T)], value: &str, to_json: impl Fn(&T) -> Json) -> Json {
+    items
+        .iter()
+        .map(|(name, item)| {
+            Json::object(vec![("name", name.as_str().into()), (value, to_json(item))])
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn fields<T>(fields: &Fields<T>, to_json: impl Fn(&T) -> Json) -> Json {
+    match fields {
+        Fields::Unit => Json::object(vec![("kind", "Unit".into())]),
+        Fields::Tuple(items) => Json::object(vec![
+            ("kind", "Tuple".into()),
+            (
+                "items",
+                items.iter().map(to_json).collect::<Vec<_>>().into(),
+            ),
+        ]),
+        Fields::Struct(items) => Json::object(vec![
+            ("kind", "Struct".into()),
+            ("fields", named(items, "value", to_json)),
+        ]),
+    }
+}
+
+fn sig(sig: &FnSig) -> Vec<(&'static str, Json)> {
+    let params = sig
+        .params
+        .iter()
+        .map(|(name, ty)| {
+            Json::object(vec![
+                ("name", name.as_str().into()),
+                ("type", optional(ty.as_ref().map(type_expr))),
+            ])
+        })
+        .collect::<Vec<_>>();
+    vec![
+        ("generics", strings(&sig.generics)),
+        ("params", params.into()),
+        ("ret", optional(sig.ret.as_ref().map(type_expr))),
+    ]
+}
+
+fn node(expr: &SExpr) -> Json {
+    let (kind, mut parts) = match expr {
+        SExpr::Atom(t) => ("Atom", vec![("token", token(t))]),
+        SExpr::Cons(op, operands, at) => (
+            "Cons",
+            vec![
+                ("op", token(op)),
+                ("operands", nodes(operands)),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::Block(stmts) => ("Block", vec![("statements", nodes(stmts))]),
+        SExpr::If(cond, then, other, at) => (
+            "If",
+            vec![
+                ("cond", node(cond)),
+                ("then", node(then)),
+                ("else", optional(other.as_deref().map(node))),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::While(cond, body, at) => (
+            "While",
+            vec![
+                ("cond", node(cond)),
+                ("body", node(body)),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::Array(elems) => ("Array", vec![("elements", nodes(elems))]),
+        SExpr::Index(array, index, at) => (
+            "Index",
+            vec![
+                ("array", node(array)),
+                ("index", node(index)),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::Slice(array, lo, hi, at) => (
+            "Slice",
+            vec![
+                ("array", node(array)),
+                ("lo", optional(lo.as_deref().map(node))),
+                ("hi", optional(hi.as_deref().map(node))),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::StructDef(name, generics, fields) => (
+            "StructDef",
+            vec![
+                ("name", name.as_str().into()),
+                ("generics", strings(generics)),
+                ("fields", named(fields, "type", type_expr)),
+            ],
+        ),
+        SExpr::StructLit(name, values) => (
+            "StructLit",
+            vec![
+                ("name", name.as_str().into()),
+                ("fields", named(values, "value", node)),
+            ],
+        ),
+        SExpr::Field(value, name, at) => (
+            "Field",
+            vec![
+                ("value", node(value)),
+                ("field", name.as_str().into()),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::EnumDef(name, variants) => {
+            let variants = variants
+                .iter()
+                .map(|(name, payload)| {
+                    Json::object(vec![
+                        ("name", name.as_str().into()),
+                        ("fields", fields(payload, type_expr)),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            (
+                "EnumDef",
+                vec![
+                    ("name", name.as_str().into()),
+                    ("variants", variants.into()),
+                ],
+            )
+        }
+        SExpr::Variant(name, variant, payload, at) => (
+            "Variant",
+            vec![
+                ("enum", name.as_str().into()),
+                ("variant", variant.as_str().into()),
+                ("fields", fields(payload, node)),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::Match(scrutinee, arms, at) => {
+            let arms = arms
+                .iter()
+                .map(|(p, body)| Json::object(vec![("pattern", pattern(p)), ("body", node(body))]))
+                .collect::<Vec<_>>();
+            (
+                "Match",
+                vec![
+                    ("scrutinee", node(scrutinee)),
+                    ("arms", arms.into()),
+                    ("site", site(at)),
+                ],
+            )
+        }
+        SExpr::Fn(name, fn_sig, body) => {
+            let mut parts = vec![("name", optional(name.as_deref().map(Json::from)))];
+            parts.extend(sig(fn_sig));
+            parts.push(("body", node(body)));
+            ("Fn", parts)
+        }
+        SExpr::Call(callee, args, at) => (
+            "Call",
+            vec![
+                ("callee", node(callee)),
+                ("args", nodes(args)),
+                ("site", site(at)),
+            ],
+        ),
+        SExpr::Mod(name, span) => (
+            "Mod",
+            vec![
+                ("name", name.as_str().into()),
+                ("span", span_to_json(*span)),
+            ],
+        ),
+        SExpr::Import(path, span) => (
+            "Import",
+            vec![("path", strings(path)), ("span", span_to_json(*span))],
+        ),
+        SExpr::Pub(item) => ("Pub", vec![("item", node(item))]),
+    };
+    parts.insert(0, ("node", kind.into()));
+    Json::object(parts)
+}
+
+fn pattern(pattern: &Pattern) -> Json {
+    let (kind, mut parts) = match pattern {
+        Pattern::Wildcard => ("Wildcard", vec![]),
+        Pattern::Binding(name) => ("Binding", vec![("name", name.as_str().into())]),
+        Pattern::Literal(t) => ("Literal", vec![("token", token(t))]),
+        Pattern::Variant(name, variant, payload, rest) => (
+            "Variant",
+            vec![
+                ("enum", name.as_str().into()),
+                ("variant", variant.as_str().into()),
+                ("fields", fields(payload, self::pattern)),
+                ("rest", (*rest).into()),
+            ],
+        ),
+    };
+    parts.insert(0, ("pattern", kind.into()));
+    Json::object(parts)
+}
+
+fn type_expr(ty: &TypeExpr) -> Json {
+    match ty {
+        TypeExpr::Named(name, args) => Json::object(vec![
+            ("type", "Named".into()),
+            ("name", name.as_str().into()),
+            (
+                "args",
+                args.iter().map(type_expr).collect::<Vec<_>>().into(),
+            ),
+        ]),
+        TypeExpr::Array(elem) => {
+            Json::object(vec![("type", "Array".into()), ("elem", type_expr(elem))])
+        }
+        TypeExpr::Fn(params, ret) => Json::object(vec![
+            ("type", "Fn".into()),
+            (
+                "params",
+                params.iter().map(type_expr).collect::<Vec<_>>().into(),
+            ),
+            ("ret", type_expr(ret)),
+        ]),
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("AST JSON ERROR: {}", message),
+    )
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Result<&'a Json, Error> {
+    json.get(key)
+        .ok_or_else(|| invalid(&format!("missing {} in {}", key, json)))
+}
+
+// a field that may be null or left out
+fn maybe<'a>(json: &'a Json, key: &str) -> Option<&'a Json> {
+    json.get(key).filter(|value| **value != Json::Null)
+}
+
+fn string(json: &Json, key: &str) -> Result<String, Error> {
+    field(json, key)?
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| invalid(&format!("{} isn't a string in {}", key, json)))
+}
+
+fn list<'a>(json: &'a Json, key: &str) -> Result<&'a [Json], Error> {
+    field(json, key)?
+        .as_array()
+        .ok_or_else(|| invalid(&format!("{} isn't an array in {}", key, json)))
+}
+
+fn read_strings(json: &Json, key: &str) -> Result<Vec<String>, Error> {
+    list(json, key)?
+        .iter()
+        .map(|name| {
+            name.as_str()
+                .map(String::from)
+                .ok_or_else(|| invalid(&format!("{} isn't a string", name)))
+        })
+        .collect()
+}
+
+fn read_nodes(json: &Json, key: &str) -> Result<Vec<SExpr>, Error> {
+    list(json, key)?.iter().map(read_node).collect()
+}
+
+fn read_box(json: &Json, key: &str) -> Result<Box<SExpr>, Error> {
+    read_node(field(json, key)?).map(Box::new)
+}
+
+fn read_maybe_box(json: &Json, key: &str) -> Result<Option<Box<SExpr>>, Error> {
+    maybe(json, key)
+        .map(read_node)
+        .transpose()
+        .map(|node| node.map(Box::new))
+}
+
+fn read_named<T>(
+    json: &Json,
+    key: &str,
+    value: &str,
+    read: impl Fn(&Json) -> Result<T, Error>,
+) -> Result<Vec<(String, T)>, Error> {
+    list(json, key)?
+        .iter()
+        .map(|item| Ok((string(item, "name")?, read(field(item, value)?)?)))
+        .collect()
+}
+
+fn read_fields<T>(
+    json: &Json,
+    read: impl Fn(&Json) -> Result<T, Error>,
+) -> Result<Fields<T>, Error> {
+    match string(json, "kind")?.as_str() {
+        "Unit" => Ok(Fields::Unit),
+        "Tuple" => list(json, "items")?
+            .iter()
+            .map(read)
+            .collect::<Result<_, _>>()
+            .map(Fields::Tuple),
+        "Struct" => read_named(json, "fields", "value", read).map(Fields::Struct),
+        kind => Err(invalid(&format!("unknown fields kind {}", kind))),
+    }
+}
+
+fn read_span(json: &Json) -> Result<Span, Error> {
+    let offset = |key| {
+        field(json, key)?
+            .as_usize()
+            .ok_or_else(|| invalid(&format!("{} isn't an offset in {}", key, json)))
+    };
+    Ok(Span {
+        start: offset("start")?,
+        end: offset("end")?,
+    })
+}
+
+// a node with no site, as one written by another tool, has none
+fn read_site(json: &Json) -> Result<Site, Error> {
+    Ok(Site(maybe(json, "site").map(read_span).transpose()?))
+}
+
+fn read_token(json: &Json) -> Result<Token, Error> {
+    let kind = string(json, "kind")?;
+    let value = || field(json, "value");
+    let bad_value = || invalid(&format!("bad value for {} in {}", kind, json));
+    let token = match kind.as_str() {
+        "IDENT" => Token::IDENT(string(json, "value")?),
+        "OP" => Token::OP(string(json, "value")?),
+        "STR" => Token::STR(string(json, "value")?),
+        "BOOL" => match value()? {
+            Json::Bool(b) => Token::BOOL(*b),
+            _ => return Err(bad_value()),
+        },
+        "INT" => {
+            let n = value()?.as_f64().ok_or_else(bad_value)?;
+            if n.fract() != 0.0 || n < i32::MIN as f64 || n > i32::MAX as f64 {
+                return Err(bad_value());
+            }
+            Token::INT(n as i32)
+        }
+        "FLOAT" => Token::FLOAT(value()?.as_f64().ok_or_else(bad_value)? as f32),
+        _ => KEYWORDS
+            .iter()
+            .cloned()
+            .chain(
+                SYMBOLS
+                    .iter()
+                    .filter_map(|symbol| Token::from_symbol(symbol)),
+            )
+            .find(|token| format!("{:?}", token) == kind)
+            .ok_or_else(|| invalid(&format!("unknown token kind {}", kind)))?,
+    };
+    Ok(token)
+}
+
+fn read_node(json: &Json) -> Result<SExpr, Error> {
+    let expr = match string(json, "node")?.as_str() {
+        "Atom" => SExpr::Atom(read_token(field(json, "token")?)?),
+        "Cons" => {
+            let op = read_token(field(json, "op")?)?;
+            let operands = read_nodes(json, "operands")?;
+            // the stages after parsing rely on every operator having the
+            // operands it takes
+            let arity: &[usize] = match op {
+                Token::TAIL | Token::NOT | Token::BITNOT => &[1],
+                Token::ADD | Token::SUB | Token::EXP | Token::OP(_) => &[1, 2],
+                _ => &[2],
+            };
+            if !arity.contains(&operands.len()) {
+                let arity: Vec<String> = arity.iter().map(|n| n.to_string()).collect();
+                return Err(invalid(&format!(
+                    "{:?} takes {} operands but has {}",
+                    op,
+                    arity.join(" or "),
+                    operands.len()
+                )));
+            }
+            SExpr::Cons(op, operands, read_site(json)?)
+        }
+        "Block" => SExpr::Block(read_nodes(json, "statements")?),
+        "If" => SExpr::If(
+            read_box(json, "cond")?,
+            read_box(json, "then")?,
+            read_maybe_box(json, "else")?,
+            read_site(json)?,
+        ),
+        "While" => SExpr::While(
+            read_box(json, "cond")?,
+            read_box(json, "body")?,
+            read_site(json)?,
+        ),
+        "Array" => SExpr::Array(read_nodes(json, "elements")?),
+        "Index" => SExpr::Index(
+            read_box(json, "array")?,
+            read_box(json, "index")?,
+            read_site(json)?,
+        ),
+        "Slice" => SExpr::Slice(
+            read_box(json, "array")?,
+            read_maybe_box(json, "lo")?,
+            read_maybe_box(json, "hi")?,
+            read_site(json)?,
+        ),
+        "StructDef" => SExpr::StructDef(
+            string(json, "name")?,
+            read_strings(json, "generics")?,
+            read_named(json, "fields", "type", read_type)?,
+        ),
+        "StructLit" => SExpr::StructLit(
+            string(json, "name")?,
+            read_named(json, "fields", "value", read_node)?,
+        ),
+        "Field" => SExpr::Field(
+            read_box(json, "value")?,
+            string(json, "field")?,
+            read_site(json)?,
+        ),
+        "EnumDef" => SExpr::EnumDef(
+            string(json, "name")?,
+            read_named(json, "variants", "fields", |fields| {
+                read_fields(fields, read_type)
+            })?,
+        ),
+        "Variant" => SExpr::Variant(
+            string(json, "enum")?,
+            string(json, "variant")?,
+            read_fields(field(json, "fields")?, read_node)?,
+            read_site(json)?,
+        ),
+        "Match" => {
+            let arms = list(json, "arms")?
+                .iter()
+                .map(|arm| {
+                    Ok((
+                        read_pattern(field(arm, "pattern")?)?,
+                        read_node(field(arm, "body")?)?,
+                    ))
+                })
+                .collect::<Result<_, Error>>()?;
+            SExpr::Match(read_box(json, "scrutinee")?, arms, read_site(json)?)
+        }
+        "Fn" => {
+            let name = maybe(json, "name")
+                .map(|name| {
+                    name.as_str()
+                        .map(String::from)
+                        .ok_or_else(|| invalid("name isn't a string"))
+                })
+                .transpose()?;
+            let params = list(json, "params")?
+                .iter()
+                .map(|param| {
+                    Ok((
+                        string(param, "name")?,
+                        maybe(param, "type").map(read_type).transpose()?,
+                    ))
+                })
+                .collect::<Result<_, Error>>()?;
+            let sig = FnSig {
+                generics: read_strings(json, "generics")?,
+                params,
+                ret: maybe(json, "ret").map(read_type).transpose()?,
+            };
+            SExpr::Fn(name, sig, read_box(json, "body")?)
+        }
+        "Call" => SExpr::Call(
+            read_box(json, "callee")?,
+            read_nodes(json, "args")?,
+            read_site(json)?,
+        ),
+        "Mod" => SExpr::Mod(string(json, "name")?, read_span(field(json, "span")?)?),
+        "Import" => SExpr::Import(
+            read_strings(json, "path")?,
+            read_span(field(json, "span")?)?,
+        ),
+        "Pub" => SExpr::Pub(read_box(json, "item")?),
+        kind => return Err(invalid(&format!("unknown node {}", kind))),
+    };
+    Ok(expr)
+}
+
+fn read_pattern(json: &Json) -> Result<Pattern, Error> {
+    let pattern = match string(json, "pattern")?.as_str() {
+        "Wildcard" => Pattern::Wildcard,
+        "Binding" => Pattern::Binding(string(json, "name")?),
+        "Literal" => Pattern::Literal(read_token(field(json, "token")?)?),
+        "Variant" => Pattern::Variant(
+            string(json, "enum")?,
+            string(json, "variant")?,
+            read_fields(field(json, "fields")?, read_pattern)?,
+            matches!(field(json, "rest")?, Json::Bool(true)),
+        ),
+        kind => return Err(invalid(&format!("unknown pattern {}", kind))),
+    };
+    Ok(pattern)
+}
+
+fn read_type(json: &Json) -> Result<TypeExpr, Error> {
+    let ty = match string(json, "type")?.as_str() {
+        "Named" => TypeExpr::Named(
+            string(json, "name")?,
+            list(json, "args")?
+                .iter()
+                .map(read_type)
+                .collect::<Result<_, _>>()?,
+        ),
+        "Array" => TypeExpr::Array(Box::new(read_type(field(json, "elem")?)?)),
+        "Fn" => TypeExpr::Fn(
+            list(json, "params")?
+                .iter()
+                .map(read_type)
+                .collect::<Result<_, _>>()?,
+            Box::new(read_type(field(json, "ret")?)?),
+        ),
+        kind => return Err(invalid(&format!("unknown type {}", kind))),
+    };
+    Ok(ty)
+}
@@ -0,0 +1,138 @@
+use std::fmt::{self, Display};
+
+// a range of characters in the source map, start inclusive and end exclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Where an expression that can fail at runtime is in the source, kept in
+// syntax trees for the errors it makes. Trees that weren't parsed from
+// text have no sites.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Site(pub Option<Span>);
+
+impl Site {
+    // the site in a file that starts at `start` in the source map
+    pub fn moved(self, start: usize) -> Site {
+        Site(self.0.map(|span| Span {
+            start: span.start + start,
+            end: span.end + start,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    // the offset of the file's first character in the map
+    pub start: usize,
+}
+
+// Every file of a program gets its own range of offsets, so a span alone
+// says which file it's in.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+// a file name with a 1-based line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds a file and returns the offset of its first character
+    pub fn add(&mut self, name: String, text: String) -> usize {
+        // one past the end of the last file, so a span at its end is still in it
+        let start = match self.files.last() {
+            Some(file) => file.start + file.text.chars().count() + 1,
+            None => 0,
+        };
+        self.files.push(SourceFile { name, text, start });
+        start
+    }
+
+    pub fn file(&self, offset: usize) -> Option<&SourceFile> {
+        self.files.iter().rev().find(|file| file.start <= offset)
+    }
+
+    pub fn location(&self, offset: usize) -> Option<Location> {
+        let file = self.file(offset)?;
+        let mut line = 1;
+        let mut column = 1;
+        for c in file.text.chars().take(offset - file.start) {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        Some(Location {
+            file: file.name.clone(),
+            line,
+            column,
+        })
+    }
+
+    // a message about a span with the line the span starts on, the span
+    // underlined up to the end of that line
+    pub fn render(&self, span: Span, message: &str) -> String {
+        let (file, location) = match (self.file(span.start), self.location(span.start)) {
+            (Some(file), Some(location)) => (file, location),
+            _ => return message.to_string(),
+        };
+        let line = file.text.lines().nth(location.line - 1).unwrap_or("");
+        let width = (span.end - span.start)
+            .min(line.chars().count() + 1 - location.column)
+            .max(1);
+        let number = location.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "{}: {}\n{} | {}\n{} | {}{}",
+            location,
+            message,
+            number,
+            line,
+            gutter,
+            " ".repeat(location.column - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+// a change to a text, the characters in the span are replaced with the text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub span: Span,
+    pub text: String,
+}
+
+impl Edit {
+    pub fn apply(&self, text: &str) -> String {
+        let mut chars: Vec<char> = text.chars().collect();
+        chars.splice(self.span.start..self.span.end, self.text.chars());
+        chars.into_iter().collect()
+    }
+
+    // how much longer the text is after the edit, in characters
+    pub fn delta(&self) -> isize {
+        self.text.chars().count() as isize - (self.span.end - self.span.start) as isize
+    }
+}
@@ -0,0 +1,1479 @@
+use super::layout;
+use super::lexer::*;
+use super::natives::Natives;
+use super::operators::OperatorTable;
+use super::parser::*;
+use super::patterns;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::{Error, ErrorKind};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,
+    Bool,
+    Int,
+    Float,
+    Str,
+    Array(Box<Type>),
+    // a struct with its type arguments
+    Struct(String, Vec<Type>),
+    Enum(String),
+    // a function's parameter types and return type
+    Fn(Vec<Type>, Box<Type>),
+    // a type parameter inside the declaration that introduces it, where it
+    // only matches itself
+    Param(String),
+    // the type of a generic function, the type parameters are replaced
+    // every time it's called
+    Generic(Vec<String>, Box<Type>),
+    // the type of anything whose type can't be known before running, like
+    // the arguments of print, it is compatible with every other type
+    Unknown,
+    // a type the checker is still inferring, like an unannotated
+    // parameter's, by its number
+    Var(usize),
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "str"),
+            Type::Array(elem) => write!(f, "[{}]", elem),
+            Type::Struct(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Struct(name, args) => write!(f, "{}<{}>", name, join(args)),
+            Type::Enum(name) | Type::Param(name) => write!(f, "{}", name),
+            Type::Fn(params, ret) => write!(f, "fn({}) -> {}", join(params), ret),
+            Type::Generic(vars, t) => match &**t {
+                Type::Fn(params, ret) => {
+                    write!(f, "fn<{}>({}) -> {}", vars.join(", "), join(params), ret)
+                }
+                t => write!(f, "<{}> {}", vars.join(", "), t),
+            },
+            Type::Unknown | Type::Var(_) => write!(f, "?"),
+        }
+    }
+}
+
+impl Type {
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Int | Type::Float | Type::Unknown)
+    }
+
+    // replaces the type parameters that have a type in `args`
+    pub fn substitute(&self, args: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Param(name) => args.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Type::Array(elem) => Type::Array(Box::new(elem.substitute(args))),
+            Type::Struct(name, struct_args) => Type::Struct(
+                name.clone(),
+                struct_args.iter().map(|t| t.substitute(args)).collect(),
+            ),
+            Type::Fn(params, ret) => Type::Fn(
+                params.iter().map(|t| t.substitute(args)).collect(),
+                Box::new(ret.substitute(args)),
+            ),
+            // its own type parameters aren't replaced
+            Type::Generic(vars, t) => {
+                let mut args = args.clone();
+                args.retain(|name, _| !vars.contains(name));
+                Type::Generic(vars.clone(), Box::new(t.substitute(&args)))
+            }
+            t => t.clone(),
+        }
+    }
+
+    // whether the type is fully known, without unknowns or type parameters
+    pub fn is_concrete(&self) -> bool {
+        match self {
+            Type::Unknown | Type::Param(_) | Type::Generic(..) | Type::Var(_) => false,
+            Type::Array(elem) => elem.is_concrete(),
+            Type::Struct(_, args) => args.iter().all(Type::is_concrete),
+            Type::Fn(params, ret) => params.iter().all(Type::is_concrete) && ret.is_concrete(),
+            _ => true,
+        }
+    }
+
+    // adds the numbers of the type variables the type mentions to `vars`
+    fn vars(&self, vars: &mut Vec<usize>) {
+        match self {
+            Type::Var(var) if !vars.contains(var) => vars.push(*var),
+            Type::Array(elem) => elem.vars(vars),
+            Type::Struct(_, args) => args.iter().for_each(|t| t.vars(vars)),
+            Type::Fn(params, ret) => {
+                params.iter().for_each(|t| t.vars(vars));
+                ret.vars(vars);
+            }
+            Type::Generic(_, t) => t.vars(vars),
+            _ => {}
+        }
+    }
+
+    // adds the names of the type parameters the type mentions to `names`
+    fn params(&self, names: &mut Vec<String>) {
+        match self {
+            Type::Param(name) if !names.contains(name) => names.push(name.clone()),
+            Type::Array(elem) => elem.params(names),
+            Type::Struct(_, args) => args.iter().for_each(|t| t.params(names)),
+            Type::Fn(params, ret) => {
+                params.iter().for_each(|t| t.params(names));
+                ret.params(names);
+            }
+            Type::Generic(_, t) => t.params(names),
+            _ => {}
+        }
+    }
+}
+
+fn join(types: &[Type]) -> String {
+    let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+    types.join(", ")
+}
+
+#[derive(Debug)]
+pub struct StructDef {
+    pub name: String,
+    pub generics: Vec<String>,
+    // fields in declaration order, their types may use the type parameters
+    pub fields: Vec<(String, Type)>,
+}
+
+#[derive(Debug)]
+pub struct EnumDef {
+    pub name: String,
+    // variants in declaration order, a variant's index is its tag
+    pub variants: Vec<(String, Fields<Type>)>,
+}
+
+impl EnumDef {
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|(v, _)| v == name)
+    }
+}
+
+// every type declared at the top level
+pub struct TypeDefs {
+    pub structs: Vec<StructDef>,
+    pub enums: Vec<Rc<EnumDef>>,
+}
+
+// the type arguments the checker inferred for every call of a generic
+// function and every literal of a generic struct, by the expression's address
+pub type Instances = HashMap<*const SExpr, Vec<Type>>;
+
+type Vars = HashMap<String, Type>;
+
+// the names types can refer to, structs with their number of type parameters
+struct TypeNames<'a> {
+    structs: Vec<(&'a str, usize)>,
+    enums: Vec<&'a str>,
+    generics: &'a [String],
+}
+
+pub struct TypeChecker {
+    structs: HashMap<String, StructDef>,
+    enums: Vec<Rc<EnumDef>>,
+    // the type parameters of the declarations around the expression being
+    // checked
+    generics: Vec<String>,
+    instances: Instances,
+    globals: HashMap<String, Type>,
+    // the types of the built-in functions, which globals shadow
+    natives: HashMap<String, Type>,
+    // what the custom operators call
+    operators: OperatorTable,
+    // None while checking the top level, where every variable is a global
+    locals: Option<HashMap<String, Type>>,
+    // the names bound by the patterns of the match arms being checked,
+    // innermost last
+    scopes: Vec<HashMap<String, Type>>,
+    // the locals and scopes of the functions around the one being checked,
+    // which it can capture
+    enclosing: Vec<(Option<Vars>, Vec<Vars>)>,
+    // the locals of the function being checked that nothing has been
+    // assigned to yet, reading one would fail at runtime
+    unassigned: Vec<String>,
+    // the names the program assigns at the top level, which a function
+    // body can use before they're assigned
+    declared: Vec<String>,
+    // what every type variable stands for, by its number, None while it's
+    // unsolved
+    solved: Vec<Option<Type>>,
+}
+
+impl TypeChecker {
+    pub fn check(
+        program: &SExpr,
+        natives: &Natives,
+        operators: &OperatorTable,
+    ) -> Result<(Type, Instances), Error> {
+        let mut checker = Self::new(program, natives, operators)?;
+        // the last statement's value is the program's
+        let t = checker.check_expr(program, true)?;
+        checker.solve();
+        Ok((t, checker.instances))
+    }
+
+    // the types of the program's globals once it's been checked
+    pub fn check_globals(
+        program: &SExpr,
+        natives: &Natives,
+        operators: &OperatorTable,
+    ) -> Result<HashMap<String, Type>, Error> {
+        let mut checker = Self::new(program, natives, operators)?;
+        checker.check_expr(program, true)?;
+        checker.solve();
+        Ok(checker.globals)
+    }
+
+    // checks one top level statement of a program whose type declarations
+    // are `types` and whose global_names are `names`, given the globals
+    // assigned before it. `last` says whether it's the program's last
+    // statement, whose value is the program's. Checking a program's
+    // statements one after the other this way is the same as checking it
+    // whole, the globals they leave behind are returned with the instances
+    // found in the statement
+    pub fn check_statement(
+        stmt: &SExpr,
+        last: bool,
+        types: &SExpr,
+        names: &[String],
+        globals: HashMap<String, Type>,
+        natives: &Natives,
+        operators: &OperatorTable,
+    ) -> Result<(HashMap<String, Type>, Instances), Error> {
+        let (_, globals, instances) =
+            Self::check_statement_type(stmt, last, types, names, globals, natives, operators)?;
+        Ok((globals, instances))
+    }
+
+    // like check_statement, with the statement's type as well
+    pub fn check_statement_type(
+        stmt: &SExpr,
+        last: bool,
+        types: &SExpr,
+        names: &[String],
+        globals: HashMap<String, Type>,
+        natives: &Natives,
+        operators: &OperatorTable,
+    ) -> Result<(Type, HashMap<String, Type>, Instances), Error> {
+        let mut checker = Self::new(types, natives, operators)?;
+        checker.declared = names.to_vec();
+        // the type variables the globals still mention keep their numbers
+        let mut vars = vec![];
+        globals.values().for_each(|t| t.vars(&mut vars));
+        checker.solved = vec![None; vars.iter().max().map_or(0, |var| var + 1)];
+        checker.globals = globals;
+        let t = checker.check_expr(stmt, last)?;
+        checker.solve();
+        Ok((t, checker.globals, checker.instances))
+    }
+
+    fn new(program: &SExpr, natives: &Natives, operators: &OperatorTable) -> Result<Self, Error> {
+        let defs = collect_types(program)?;
+        // rejects structs that contain themselves
+        layout::compute_layouts(&defs.structs)?;
+
+        Ok(Self {
+            structs: defs
+                .structs
+                .into_iter()
+                .map(|def| (def.name.clone(), def))
+                .collect(),
+            enums: defs.enums,
+            generics: vec![],
+            instances: HashMap::new(),
+            globals: HashMap::new(),
+            natives: natives
+                .iter()
+                .map(|native| (native.name.clone(), native.ty.clone()))
+                .collect(),
+            operators: operators.clone(),
+            locals: None,
+            scopes: vec![],
+            enclosing: vec![],
+            unassigned: vec![],
+            declared: match program {
+                SExpr::Block(stmts) => global_names(stmts),
+                _ => vec![],
+            },
+            solved: vec![],
+        })
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.solved.push(None);
+        Type::Var(self.solved.len() - 1)
+    }
+
+    // the type with the type variables solved so far replaced by what they
+    // stand for
+    fn prune(&self, t: &Type) -> Type {
+        match t {
+            Type::Var(var) => match &self.solved[*var] {
+                Some(t) => self.prune(t),
+                None => t.clone(),
+            },
+            Type::Array(elem) => Type::Array(Box::new(self.prune(elem))),
+            Type::Struct(name, args) => {
+                Type::Struct(name.clone(), args.iter().map(|t| self.prune(t)).collect())
+            }
+            Type::Fn(params, ret) => Type::Fn(
+                params.iter().map(|t| self.prune(t)).collect(),
+                Box::new(self.prune(ret)),
+            ),
+            Type::Generic(vars, t) => Type::Generic(vars.clone(), Box::new(self.prune(t))),
+            t => t.clone(),
+        }
+    }
+
+    // the globals and instances with every solved type variable replaced,
+    // once checking is done
+    fn solve(&mut self) {
+        let globals = std::mem::take(&mut self.globals);
+        self.globals = globals
+            .into_iter()
+            .map(|(name, t)| (name, self.prune(&t)))
+            .collect();
+        let instances = std::mem::take(&mut self.instances);
+        self.instances = instances
+            .into_iter()
+            .map(|(expr, args)| (expr, args.iter().map(|t| self.prune(t)).collect()))
+            .collect();
+    }
+
+    // the most specific type compatible with both, if there is one, solving
+    // the type variables it takes
+    fn unify(&mut self, a: &Type, b: &Type) -> Option<Type> {
+        let (a, b) = (self.prune(a), self.prune(b));
+        match (&a, &b) {
+            (Type::Unknown, t) | (t, Type::Unknown) => Some(t.clone()),
+            (Type::Var(a), Type::Var(b)) if a == b => Some(Type::Var(*a)),
+            (Type::Var(var), t) | (t, Type::Var(var)) => {
+                // a type can't contain itself
+                let mut vars = vec![];
+                t.vars(&mut vars);
+                if vars.contains(var) {
+                    return None;
+                }
+                self.solved[*var] = Some(t.clone());
+                Some(t.clone())
+            }
+            // a generic function passed where a function is expected works
+            // with any types
+            (Type::Generic(..), t) | (t, Type::Generic(..)) if !matches!(t, Type::Generic(..)) => {
+                let (a, b) = (self.instantiate(&a), self.instantiate(&b));
+                self.unify(&a, &b)
+            }
+            (Type::Array(a), Type::Array(b)) => Some(Type::Array(Box::new(self.unify(a, b)?))),
+            (Type::Fn(a_params, a_ret), Type::Fn(b_params, b_ret))
+                if a_params.len() == b_params.len() =>
+            {
+                let mut params = vec![];
+                for (a, b) in a_params.iter().zip(b_params) {
+                    params.push(self.unify(a, b)?);
+                }
+                Some(Type::Fn(params, Box::new(self.unify(a_ret, b_ret)?)))
+            }
+            (Type::Struct(a, a_args), Type::Struct(b, b_args)) if a == b => {
+                let mut args = vec![];
+                for (a, b) in a_args.iter().zip(b_args) {
+                    args.push(self.unify(a, b)?);
+                }
+                Some(Type::Struct(a.clone(), args))
+            }
+            (a, b) if a == b => Some(a.clone()),
+            _ => None,
+        }
+    }
+
+    // a generic function's type with a new type variable for every type
+    // parameter
+    fn instantiate(&mut self, t: &Type) -> Type {
+        match t {
+            Type::Generic(vars, t) => {
+                let fresh = vars.iter().map(|var| (var.clone(), self.fresh())).collect();
+                t.substitute(&fresh)
+            }
+            t => t.clone(),
+        }
+    }
+
+    // the type of a function declared with fn, whose type variables that
+    // nothing around it mentions become type parameters, so every call can
+    // give them different types
+    fn generalize(&mut self, t: &Type) -> Type {
+        let t = self.prune(t);
+        let mut free = vec![];
+        t.vars(&mut free);
+        let mut outer = vec![];
+        let frames = std::iter::once((&self.locals, &self.scopes))
+            .chain(self.enclosing.iter().map(|(l, s)| (l, s)));
+        for (locals, scopes) in frames {
+            for vars in locals.iter().chain(scopes) {
+                vars.values().for_each(|t| self.prune(t).vars(&mut outer));
+            }
+        }
+        self.globals
+            .values()
+            .for_each(|t| self.prune(t).vars(&mut outer));
+        free.retain(|var| !outer.contains(var));
+        if free.is_empty() {
+            return t;
+        }
+
+        // the names can't be written in a program, and skip the ones the
+        // type already uses
+        let mut taken = vec![];
+        t.params(&mut taken);
+        let mut names = vec![];
+        let mut letters = (0..).map(|i: usize| match i / 26 {
+            0 => format!("'{}", (b'a' + (i % 26) as u8) as char),
+            n => format!("'{}{}", (b'a' + (i % 26) as u8) as char, n),
+        });
+        for var in free {
+            let name = letters.find(|name| !taken.contains(name)).unwrap();
+            self.solved[var] = Some(Type::Param(name.clone()));
+            names.push(name);
+        }
+        match self.prune(&t) {
+            Type::Generic(mut vars, t) => {
+                vars.extend(names);
+                Type::Generic(vars, t)
+            }
+            t => Type::Generic(names, Box::new(t)),
+        }
+    }
+
+    fn resolve(&self, t: &TypeExpr) -> Result<Type, Error> {
+        let names = TypeNames {
+            structs: self
+                .structs
+                .values()
+                .map(|def| (def.name.as_str(), def.generics.len()))
+                .collect(),
+            enums: self.enums.iter().map(|def| def.name.as_str()).collect(),
+            generics: &self.generics,
+        };
+        resolve_type(t, &names)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Type, Error> {
+        let frames = std::iter::once((&self.locals, &self.scopes))
+            .chain(self.enclosing.iter().rev().map(|(l, s)| (l, s)));
+        for (i, (locals, scopes)) in frames.enumerate() {
+            if let Some(t) = scopes.iter().rev().find_map(|scope| scope.get(name)) {
+                return Ok(t.clone());
+            }
+            if let Some(t) = locals.as_ref().and_then(|locals| locals.get(name)) {
+                // the functions around it may have assigned theirs by the
+                // time it runs
+                if i == 0 && self.unassigned.iter().any(|u| u == name) {
+                    return Err(type_error(format!("{} is used before it's assigned", name)));
+                }
+                return Ok(t.clone());
+            }
+        }
+        self.lookup_global(name)
+    }
+
+    fn lookup_global(&self, name: &str) -> Result<Type, Error> {
+        match self.globals.get(name).or_else(|| self.natives.get(name)) {
+            Some(t) => Ok(t.clone()),
+            // a function body may run after the global is assigned
+            None if self.locals.is_some() && self.declared.iter().any(|d| d == name) => {
+                Ok(Type::Unknown)
+            }
+            None => Err(type_error(format!("{} is not defined", name))),
+        }
+    }
+
+    // an array variable whose elements' type isn't known yet, like one
+    // assigned [], gets it from the first use that says what it holds
+    fn refine(&mut self, var: &SExpr, t: &Type) -> Result<(), Error> {
+        if let SExpr::Atom(Token::IDENT(name)) = var {
+            let old = self.lookup(name)?;
+            if let (Type::Array(_), false) = (&old, old.is_concrete()) {
+                if let Some(t) = self.unify(&old, t) {
+                    self.assign(name, t)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // whether a function around the one being checked, or the top level's
+    // match arms, have a variable of the name
+    fn is_variable(&self, name: &str) -> bool {
+        std::iter::once((&self.locals, &self.scopes))
+            .chain(self.enclosing.iter().map(|(l, s)| (l, s)))
+            .any(|(locals, scopes)| {
+                scopes.iter().any(|scope| scope.contains_key(name))
+                    || locals
+                        .as_ref()
+                        .is_some_and(|locals| locals.contains_key(name))
+            })
+    }
+
+    // assigns the innermost variable of the name, which is a global if no
+    // function has one
+    fn assign(&mut self, name: &str, t: Type) -> Result<Type, Error> {
+        self.unassigned.retain(|u| u != name);
+        let t = match self.variables(name).get(name).cloned() {
+            Some(Type::Unknown) | None => t,
+            Some(old) => match self.unify(&old, &t) {
+                Some(t) => t,
+                None => {
+                    return Err(type_error(format!(
+                        "can't assign {} to {} of type {}",
+                        self.prune(&t),
+                        name,
+                        self.prune(&old)
+                    )))
+                }
+            },
+        };
+        self.variables(name).insert(name.to_string(), t.clone());
+        Ok(t)
+    }
+
+    // the innermost variables with one of the name, the globals if no
+    // function has one
+    fn variables(&mut self, name: &str) -> &mut Vars {
+        let frames = std::iter::once((&mut self.locals, &mut self.scopes))
+            .chain(self.enclosing.iter_mut().rev().map(|(l, s)| (l, s)));
+        for (locals, scopes) in frames {
+            if let Some(scope) = scopes.iter_mut().rev().find(|s| s.contains_key(name)) {
+                return scope;
+            }
+            if let Some(locals) = locals.as_mut().filter(|l| l.contains_key(name)) {
+                return locals;
+            }
+        }
+        &mut self.globals
+    }
+
+    // `used` says whether the value of expr is needed, statements whose
+    // value is thrown away don't need matching branch types. The type has
+    // the type variables solved so far replaced
+    fn check_expr(&mut self, expr: &SExpr, used: bool) -> Result<Type, Error> {
+        let t = self.check_node(expr, used)?;
+        Ok(self.prune(&t))
+    }
+
+    fn check_node(&mut self, expr: &SExpr, used: bool) -> Result<Type, Error> {
+        match expr {
+            SExpr::Atom(token) => match token {
+                Token::INT(_) => Ok(Type::Int),
+                Token::FLOAT(_) => Ok(Type::Float),
+                Token::STR(_) => Ok(Type::Str),
+                Token::BOOL(_) => Ok(Type::Bool),
+                Token::VOID => Ok(Type::Void),
+                Token::IDENT(name) => self.lookup(name),
+                _ => Err(type_error(format!("unexpected {}", expr))),
+            },
+
+            SExpr::Fn(name, sig, body) => {
+                let t = self.check_fn(name.as_deref(), sig, body)?;
+                match name {
+                    Some(name) => self.assign(name, t).map(|_| Type::Void),
+                    None => Ok(t),
+                }
+            }
+
+            SExpr::Call(callee, args, _) => {
+                let callee = self.check_expr(callee, true)?;
+                self.check_call(expr, callee, args)
+            }
+
+            // a custom operator calls its function, which is always a global
+            SExpr::Cons(op @ Token::OP(_), cons, _) => {
+                let callee = match self.operators.function(op, cons.len()) {
+                    Some(function) => self.lookup_global(function)?,
+                    None => {
+                        let symbol = op.symbol().unwrap_or_default();
+                        return Err(type_error(format!("operator {} has no function", symbol)));
+                    }
+                };
+                self.check_call(expr, callee, cons)
+            }
+
+            SExpr::Cons(Token::ASSIGN, cons, _) => {
+                let value = self.check_expr(&cons[1], true)?;
+                match &cons[0] {
+                    SExpr::Atom(Token::IDENT(name)) => self.assign(name, value),
+                    SExpr::Index(array, index, _) => {
+                        let elem = self.check_index(array, index)?;
+                        let elem = self.unify(&elem, &value).ok_or_else(|| {
+                            type_error(format!("can't store {} in an array of {}", value, elem))
+                        })?;
+                        self.refine(array, &Type::Array(Box::new(elem.clone())))?;
+                        Ok(elem)
+                    }
+                    SExpr::Field(target, field, _) => {
+                        let field_type = self.check_field(target, field)?;
+                        self.unify(&field_type, &value).ok_or_else(|| {
+                            type_error(format!(
+                                "can't assign {} to field {} of type {}",
+                                value, field, field_type
+                            ))
+                        })
+                    }
+                    _ => Err(type_error(format!("can't assign to {}", cons[0]))),
+                }
+            }
+
+            SExpr::Cons(op, cons, _) if op.compound_op().is_some() => {
+                let bin_op = op.compound_op().unwrap();
+                let target = match &cons[0] {
+                    SExpr::Atom(Token::IDENT(name)) => self.lookup(name)?,
+                    SExpr::Index(array, index, _) => self.check_index(array, index)?,
+                    SExpr::Field(target, field, _) => self.check_field(target, field)?,
+                    _ => return Err(type_error(format!("can't assign to {}", cons[0]))),
+                };
+                let value = self.check_expr(&cons[1], true)?;
+                let result = self.binary(&bin_op, &target, &value).ok_or_else(|| {
+                    type_error(format!("can't apply {:?} to {} and {}", op, target, value))
+                })?;
+                // x += 0.5 would turn an int x into a float
+                self.unify(&target, &result).ok_or_else(|| {
+                    type_error(format!(
+                        "can't apply {:?} to {} and {}, the result {} doesn't fit in {}",
+                        op, target, value, result, target
+                    ))
+                })
+            }
+
+            SExpr::Cons(op @ Token::AND, cons, _) | SExpr::Cons(op @ Token::OR, cons, _) => {
+                for operand in cons {
+                    let t = self.check_expr(operand, true)?;
+                    self.expect(&t, &Type::Bool, op)?;
+                }
+                Ok(Type::Bool)
+            }
+
+            SExpr::Cons(Token::TAIL, cons, _) => self.check_expr(&cons[0], used),
+
+            SExpr::Cons(op, cons, _) if cons.len() == 1 => {
+                let t = self.check_expr(&cons[0], true)?;
+                match op {
+                    Token::NOT => self.expect(&t, &Type::Bool, op).map(|_| Type::Bool),
+                    Token::ADD | Token::SUB if t.is_numeric() || matches!(t, Type::Var(_)) => Ok(t),
+                    Token::BITNOT => self.expect(&t, &Type::Int, op).map(|_| Type::Int),
+                    _ => Err(type_error(format!("can't apply {:?} to {}", op, t))),
+                }
+            }
+
+            SExpr::Cons(op, cons, _) => {
+                let lhs = self.check_expr(&cons[0], true)?;
+                let rhs = self.check_expr(&cons[1], true)?;
+                self.binary(op, &lhs, &rhs).ok_or_else(|| {
+                    type_error(format!("can't apply {:?} to {} and {}", op, lhs, rhs))
+                })
+            }
+
+            SExpr::Block(stmts) => {
+                let mut t = Type::Void;
+                for (i, stmt) in stmts.iter().enumerate() {
+                    t = self.check_expr(stmt, used && i == stmts.len() - 1)?;
+                }
+                Ok(t)
+            }
+
+            SExpr::If(cond, then, els, _) => {
+                let cond = self.check_expr(cond, true)?;
+                self.expect(&cond, &Type::Bool, &Token::IF)?;
+
+                let then = self.check_expr(then, used)?;
+                let els = match els {
+                    Some(els) => self.check_expr(els, used)?,
+                    None => Type::Void,
+                };
+
+                if !used {
+                    return Ok(Type::Void);
+                }
+                self.unify(&then, &els).ok_or_else(|| {
+                    type_error(format!(
+                        "if and else have incompatible types {} and {}",
+                        then, els
+                    ))
+                })
+            }
+
+            SExpr::While(cond, body, _) => {
+                let cond = self.check_expr(cond, true)?;
+                self.expect(&cond, &Type::Bool, &Token::WHILE)?;
+                self.check_expr(body, false)?;
+                Ok(Type::Void)
+            }
+
+            SExpr::Array(elems) => {
+                let mut t = Type::Unknown;
+                for elem in elems {
+                    let elem = self.check_expr(elem, true)?;
+                    t = self.unify(&t, &elem).ok_or_else(|| {
+                        type_error(format!(
+                            "array elements have mixed types {} and {}",
+                            t, elem
+                        ))
+                    })?;
+                }
+                Ok(Type::Array(Box::new(t)))
+            }
+
+            SExpr::Index(array, index, _) => self.check_index(array, index),
+
+            // already collected by collect_types
+            SExpr::StructDef(..) => Ok(Type::Void),
+
+            SExpr::StructLit(name, values) => self.check_struct_lit(expr, name, values),
+
+            SExpr::Field(target, field, _) => self.check_field(target, field),
+
+            // already collected by collect_types
+            SExpr::EnumDef(..) => Ok(Type::Void),
+
+            SExpr::Variant(name, variant, values, _) => self.check_variant(name, variant, values),
+
+            SExpr::Match(value, arms, _) => self.check_match(value, arms, used),
+
+            // the module loader resolves these before checking
+            SExpr::Mod(..) | SExpr::Import(..) | SExpr::Pub(_) => {
+                Err(type_error(format!("unresolved module item {}", expr)))
+            }
+
+            SExpr::Slice(array, lo, hi, _) => {
+                let mut t = self.check_expr(array, true)?;
+                if let Type::Var(_) = t {
+                    let elem = self.fresh();
+                    t = self.unify(&t, &Type::Array(Box::new(elem))).unwrap();
+                }
+                if !matches!(t, Type::Array(_) | Type::Unknown) {
+                    return Err(type_error(format!("can't slice a value of type {}", t)));
+                }
+                for bound in [lo, hi].iter().copied().flatten() {
+                    let bound = self.check_expr(bound, true)?;
+                    self.expect(&bound, &Type::Int, &Token::DOTDOT)?;
+                }
+                Ok(t)
+            }
+        }
+    }
+
+    fn check_field(&mut self, target: &SExpr, field: &str) -> Result<Type, Error> {
+        let mut t = self.check_expr(target, true)?;
+        // a value whose type is being inferred is the only struct with the
+        // field, if there's one
+        if let Type::Var(_) = t {
+            let mut defs = self
+                .structs
+                .values()
+                .filter(|def| def.fields.iter().any(|(f, _)| f == field));
+            if let (Some(def), None) = (defs.next(), defs.next()) {
+                let (name, count) = (def.name.clone(), def.generics.len());
+                let args = (0..count).map(|_| self.fresh()).collect();
+                t = self.unify(&t, &Type::Struct(name, args)).unwrap();
+            }
+        }
+        match t {
+            Type::Struct(name, args) => {
+                let def = &self.structs[&name];
+                let args = def.generics.iter().cloned().zip(args).collect();
+                def.fields
+                    .iter()
+                    .find(|(f, _)| f == field)
+                    .map(|(_, t)| t.substitute(&args))
+                    .ok_or_else(|| type_error(format!("struct {} has no field {}", name, field)))
+            }
+            Type::Unknown | Type::Var(_) => Ok(Type::Unknown),
+            t => Err(type_error(format!("{} has no field {}", t, field))),
+        }
+    }
+
+    // a call of a function of type `callee`, or of a custom operator's
+    // function, with the arguments `args`
+    fn check_call(&mut self, expr: &SExpr, callee: Type, args: &[SExpr]) -> Result<Type, Error> {
+        let mut arg_types = vec![];
+        for arg in args {
+            arg_types.push(self.check_expr(arg, true)?);
+        }
+        let (vars, callee) = match callee {
+            Type::Generic(vars, t) => (vars, *t),
+            // a value whose type is being inferred is a function of the
+            // arguments
+            Type::Var(_) => {
+                let ret = self.fresh();
+                let t = Type::Fn(arg_types.clone(), Box::new(ret));
+                match self.unify(&callee, &t) {
+                    Some(t) => (vec![], t),
+                    None => return Err(type_error("can't call a function with itself".into())),
+                }
+            }
+            t => (vec![], t),
+        };
+        match callee {
+            Type::Fn(params, ret) => {
+                if params.len() != args.len() {
+                    return Err(type_error(format!(
+                        "function takes {} arguments but {} were given",
+                        params.len(),
+                        args.len()
+                    )));
+                }
+                // the type arguments of a generic function are
+                // inferred from the arguments, the ones that can't
+                // be yet are new type variables
+                let mut bound = HashMap::new();
+                for (param, arg) in params.iter().zip(&arg_types) {
+                    self.bind(param, arg, &vars, &mut bound)?;
+                }
+                for var in &vars {
+                    if !bound.contains_key(var) {
+                        let t = self.fresh();
+                        bound.insert(var.clone(), t);
+                    }
+                }
+                for (param, arg) in params.iter().zip(&arg_types) {
+                    let param = param.substitute(&bound);
+                    if self.unify(&param, arg).is_none() {
+                        return Err(type_error(format!(
+                            "expected {} for an argument but found {}",
+                            self.prune(&param),
+                            self.prune(arg)
+                        )));
+                    }
+                }
+                for (param, arg) in params.iter().zip(args) {
+                    self.refine(arg, &param.substitute(&bound))?;
+                }
+                if !vars.is_empty() {
+                    let args = vars.iter().map(|var| bound[var].clone()).collect();
+                    self.instances.insert(expr as *const SExpr, args);
+                }
+                Ok(ret.substitute(&bound))
+            }
+            Type::Unknown => Ok(Type::Unknown),
+            t => Err(type_error(format!("can't call a value of type {}", t))),
+        }
+    }
+
+    fn check_struct_lit(
+        &mut self,
+        expr: &SExpr,
+        name: &str,
+        values: &[(String, SExpr)],
+    ) -> Result<Type, Error> {
+        let (generics, fields) = match self.structs.get(name) {
+            Some(def) => (def.generics.clone(), def.fields.clone()),
+            None => return Err(type_error(format!("{} is not a struct", name))),
+        };
+
+        // the type arguments are inferred from the fields' values
+        let mut bound = HashMap::new();
+        let what = format!("struct {}", name);
+        self.check_fields(&what, &fields, &generics, &mut bound, values)?;
+        let args: Vec<Type> = generics
+            .iter()
+            .map(|var| bound.remove(var).unwrap_or(Type::Unknown))
+            .collect();
+        if !args.is_empty() {
+            self.instances.insert(expr as *const SExpr, args.clone());
+        }
+        Ok(Type::Struct(name.to_string(), args))
+    }
+
+    // checks the values given for every field of a struct literal or a
+    // struct variant, `what` names it in errors. The types the values give
+    // the type parameters in `generics` are added to `bound`
+    fn check_fields(
+        &mut self,
+        what: &str,
+        fields: &[(String, Type)],
+        generics: &[String],
+        bound: &mut Vars,
+        values: &[(String, SExpr)],
+    ) -> Result<(), Error> {
+        for (i, (field, value)) in values.iter().enumerate() {
+            if values[..i].iter().any(|(f, _)| f == field) {
+                return Err(type_error(format!("field {} is given twice", field)));
+            }
+            let field_type = match fields.iter().find(|(f, _)| f == field) {
+                Some((_, t)) => t,
+                None => return Err(type_error(format!("{} has no field {}", what, field))),
+            };
+            let value = self.check_expr(value, true)?;
+            self.bind(field_type, &value, generics, bound)?;
+            let field_type = field_type.substitute(bound);
+            if self.unify(&field_type, &value).is_none() {
+                return Err(type_error(format!(
+                    "expected {} for field {} but found {}",
+                    field_type, field, value
+                )));
+            }
+        }
+
+        for (field, _) in fields {
+            if !values.iter().any(|(f, _)| f == field) {
+                return Err(type_error(format!("missing field {} for {}", field, what)));
+            }
+        }
+        Ok(())
+    }
+
+    fn enum_def(&self, name: &str) -> Result<Rc<EnumDef>, Error> {
+        match self.enums.iter().find(|def| def.name == name) {
+            Some(def) => Ok(def.clone()),
+            None => Err(type_error(format!("{} is not an enum", name))),
+        }
+    }
+
+    fn check_variant(
+        &mut self,
+        name: &str,
+        variant: &str,
+        values: &Fields<SExpr>,
+    ) -> Result<Type, Error> {
+        let def = self.enum_def(name)?;
+        let fields = match def.variant(variant) {
+            Some(i) => &def.variants[i].1,
+            None => {
+                return Err(type_error(format!(
+                    "enum {} has no variant {}",
+                    name, variant
+                )))
+            }
+        };
+
+        match (fields, values) {
+            (Fields::Unit, Fields::Unit) => {}
+            (Fields::Tuple(types), Fields::Tuple(values)) if types.len() == values.len() => {
+                for (i, (field_type, value)) in types.iter().zip(values).enumerate() {
+                    let value = self.check_expr(value, true)?;
+                    if self.unify(field_type, &value).is_none() {
+                        return Err(type_error(format!(
+                            "expected {} for field {} of {}::{} but found {}",
+                            field_type, i, name, variant, value
+                        )));
+                    }
+                }
+            }
+            (Fields::Struct(fields), Fields::Struct(values)) => {
+                let lit = format!("{}::{}", name, variant);
+                self.check_fields(&lit, fields, &[], &mut HashMap::new(), values)?;
+            }
+            _ => {
+                return Err(type_error(format!(
+                    "{}::{} doesn't take fields like that",
+                    name, variant
+                )))
+            }
+        }
+
+        Ok(Type::Enum(name.to_string()))
+    }
+
+    fn check_match(
+        &mut self,
+        value: &SExpr,
+        arms: &[(Pattern, SExpr)],
+        used: bool,
+    ) -> Result<Type, Error> {
+        // patterns can narrow down a value whose type is unknown
+        let mut value = self.check_expr(value, true)?;
+        let mut result = Type::Unknown;
+        for (pattern, arm) in arms {
+            let mut bindings = HashMap::new();
+            value = self.check_pattern(pattern, &value, &mut bindings)?;
+
+            self.scopes.push(bindings);
+            let arm = self.check_expr(arm, used);
+            self.scopes.pop();
+
+            let arm = arm?;
+            if used {
+                result = self.unify(&result, &arm).ok_or_else(|| {
+                    type_error(format!(
+                        "match arms have incompatible types {} and {}",
+                        result, arm
+                    ))
+                })?;
+            }
+        }
+
+        let mut rows = vec![];
+        for (pattern, _) in arms {
+            let row = patterns::normalize(pattern, &self.enums).unwrap();
+            if !patterns::is_useful(&rows, &row, &self.enums) {
+                return Err(type_error(format!("unreachable pattern {}", pattern)));
+            }
+            rows.push(row);
+        }
+        if let Some(missing) = patterns::missing_pattern(&rows, &self.enums) {
+            return Err(type_error(format!(
+                "match isn't exhaustive, {} is not covered",
+                missing
+            )));
+        }
+
+        Ok(if used { result } else { Type::Void })
+    }
+
+    // checks that the pattern can match a value of type `expected` and
+    // returns the type narrowed down by the pattern
+    fn check_pattern(
+        &mut self,
+        pattern: &Pattern,
+        expected: &Type,
+        bindings: &mut HashMap<String, Type>,
+    ) -> Result<Type, Error> {
+        let (found, narrowed) = match pattern {
+            Pattern::Wildcard => return Ok(expected.clone()),
+            Pattern::Binding(name) => {
+                if bindings.contains_key(name) {
+                    return Err(type_error(format!(
+                        "{} is bound more than once in the same pattern",
+                        name
+                    )));
+                }
+                bindings.insert(name.clone(), expected.clone());
+                return Ok(expected.clone());
+            }
+            Pattern::Literal(token) => {
+                let found = match token {
+                    Token::INT(_) => Type::Int,
+                    Token::FLOAT(_) => Type::Float,
+                    Token::STR(_) => Type::Str,
+                    _ => Type::Bool,
+                };
+                (found.clone(), self.unify(expected, &found))
+            }
+            Pattern::Variant(name, variant, fields, rest) => {
+                let found = Type::Enum(name.clone());
+                let narrowed = self.unify(expected, &found);
+                if narrowed.is_some() {
+                    self.check_variant_pattern(name, variant, fields, *rest, bindings)?;
+                }
+                (found, narrowed)
+            }
+        };
+
+        narrowed.ok_or_else(|| {
+            type_error(format!(
+                "pattern {} has type {} but the value is {}",
+                pattern, found, expected
+            ))
+        })
+    }
+
+    fn check_variant_pattern(
+        &mut self,
+        name: &str,
+        variant: &str,
+        patterns: &Fields<Pattern>,
+        rest: bool,
+        bindings: &mut HashMap<String, Type>,
+    ) -> Result<(), Error> {
+        let def = self.enum_def(name)?;
+        let fields = match def.variant(variant) {
+            Some(i) => &def.variants[i].1,
+            None => {
+                return Err(type_error(format!(
+                    "enum {} has no variant {}",
+                    name, variant
+                )))
+            }
+        };
+
+        match (fields, patterns) {
+            (Fields::Unit, Fields::Unit) => Ok(()),
+            (Fields::Tuple(types), Fields::Tuple(patterns)) if types.len() == patterns.len() => {
+                for (t, pattern) in types.iter().zip(patterns) {
+                    self.check_pattern(pattern, t, bindings)?;
+                }
+                Ok(())
+            }
+            (Fields::Struct(fields), Fields::Struct(patterns)) => {
+                for (i, (field, pattern)) in patterns.iter().enumerate() {
+                    if patterns[..i].iter().any(|(f, _)| f == field) {
+                        return Err(type_error(format!("field {} is matched twice", field)));
+                    }
+                    match fields.iter().find(|(f, _)| f == field) {
+                        Some((_, t)) => self.check_pattern(pattern, t, bindings)?,
+                        None => {
+                            return Err(type_error(format!(
+                                "{}::{} has no field {}",
+                                name, variant, field
+                            )))
+                        }
+                    };
+                }
+                if !rest {
+                    if let Some((field, _)) = fields
+                        .iter()
+                        .find(|(field, _)| !patterns.iter().any(|(f, _)| f == field))
+                    {
+                        return Err(type_error(format!(
+                            "pattern for {}::{} doesn't mention field {}, use .. to ignore it",
+                            name, variant, field
+                        )));
+                    }
+                }
+                Ok(())
+            }
+            _ => Err(type_error(format!(
+                "pattern for {}::{} doesn't match its fields",
+                name, variant
+            ))),
+        }
+    }
+
+    fn check_index(&mut self, array: &SExpr, index: &SExpr) -> Result<Type, Error> {
+        let array = self.check_expr(array, true)?;
+        let index = self.check_expr(index, true)?;
+        self.expect(&index, &Type::Int, &Token::LBRACE)?;
+        let array = match array {
+            Type::Var(_) => {
+                let elem = self.fresh();
+                self.unify(&array, &Type::Array(Box::new(elem))).unwrap()
+            }
+            t => t,
+        };
+        match array {
+            Type::Array(elem) => Ok(*elem),
+            Type::Unknown => Ok(Type::Unknown),
+            t => Err(type_error(format!(
+                "can't index into a value of type {}",
+                t
+            ))),
+        }
+    }
+
+    fn check_fn(&mut self, name: Option<&str>, sig: &FnSig, body: &SExpr) -> Result<Type, Error> {
+        let depth = self.generics.len();
+        self.generics.extend(sig.generics.iter().cloned());
+        let result = self.check_fn_body(name, sig, body);
+        self.generics.truncate(depth);
+
+        let mut t = result?;
+        if !sig.generics.is_empty() {
+            t = Type::Generic(sig.generics.clone(), Box::new(t));
+        }
+        match name {
+            Some(_) => Ok(self.generalize(&t)),
+            None => Ok(t),
+        }
+    }
+
+    fn check_fn_body(
+        &mut self,
+        name: Option<&str>,
+        sig: &FnSig,
+        body: &SExpr,
+    ) -> Result<Type, Error> {
+        let params = sig.param_names();
+        let names = local_names(&params, body, &|name| self.is_variable(name));
+        let unassigned = names[params.len()..].to_vec();
+
+        // locals without a type start out unknown until their first
+        // assignment is checked, parameters without one are inferred from
+        // how the body uses them
+        let mut locals: Vars = names
+            .into_iter()
+            .map(|name| (name, Type::Unknown))
+            .collect();
+        for (param, t) in &sig.params {
+            let t = match t {
+                Some(t) => self.resolve(t)?,
+                None => self.fresh(),
+            };
+            locals.insert(param.clone(), t);
+        }
+        let ret = match &sig.ret {
+            Some(t) => Some(self.resolve(t)?),
+            None => None,
+        };
+
+        let outer = self.locals.replace(locals);
+        let outer_scopes = std::mem::take(&mut self.scopes);
+        self.enclosing.push((outer, outer_scopes));
+        let outer_unassigned = std::mem::replace(&mut self.unassigned, unassigned);
+
+        let result = self.check_expr(body, true);
+
+        let (outer, outer_scopes) = self.enclosing.pop().unwrap();
+        let locals = std::mem::replace(&mut self.locals, outer).unwrap();
+        self.scopes = outer_scopes;
+        self.unassigned = outer_unassigned;
+
+        let result = result?;
+        let ret = match ret {
+            Some(ret) => {
+                if self.unify(&ret, &result).is_none() {
+                    return Err(type_error(format!(
+                        "{} should return {} but returns {}",
+                        name.unwrap_or("lambda"),
+                        ret,
+                        result
+                    )));
+                }
+                ret
+            }
+            None => result,
+        };
+        let params = params.iter().map(|param| locals[param].clone()).collect();
+        Ok(Type::Fn(params, Box::new(ret)))
+    }
+
+    // binds the type parameters in `vars` that `param` mentions to the parts
+    // of `arg` they stand for
+    fn bind(
+        &mut self,
+        param: &Type,
+        arg: &Type,
+        vars: &[String],
+        bound: &mut Vars,
+    ) -> Result<(), Error> {
+        match (param, self.prune(arg)) {
+            (Type::Param(name), arg) if vars.contains(name) => {
+                let t = match bound.get(name).cloned() {
+                    Some(old) => self.unify(&old, &arg).ok_or_else(|| {
+                        type_error(format!(
+                            "{} can't be both {} and {}",
+                            name,
+                            self.prune(&old),
+                            arg
+                        ))
+                    })?,
+                    None => arg,
+                };
+                bound.insert(name.clone(), t);
+                Ok(())
+            }
+            (Type::Array(param), Type::Array(arg)) => self.bind(param, &arg, vars, bound),
+            (param, arg @ Type::Generic(..)) => {
+                let arg = self.instantiate(&arg);
+                self.bind(param, &arg, vars, bound)
+            }
+            (Type::Struct(a, params), Type::Struct(b, args)) if *a == b => {
+                for (param, arg) in params.iter().zip(&args) {
+                    self.bind(param, arg, vars, bound)?;
+                }
+                Ok(())
+            }
+            (Type::Fn(params, ret), Type::Fn(args, arg_ret)) if params.len() == args.len() => {
+                for (param, arg) in params.iter().zip(&args) {
+                    self.bind(param, arg, vars, bound)?;
+                }
+                self.bind(ret, &arg_ret, vars, bound)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn binary(&mut self, op: &Token, lhs: &Type, rhs: &Type) -> Option<Type> {
+        let (mut lhs, mut rhs) = (self.prune(lhs), self.prune(rhs));
+        // an operand whose type is being inferred takes the other one's
+        if matches!(lhs, Type::Var(_)) || matches!(rhs, Type::Var(_)) {
+            lhs = self.unify(&lhs, &rhs)?;
+            rhs = lhs.clone();
+        }
+        match op {
+            Token::EQ | Token::NEQ => {
+                if lhs.is_numeric() && rhs.is_numeric() {
+                    return Some(Type::Bool);
+                }
+                self.unify(&lhs, &rhs).map(|_| Type::Bool)
+            }
+            Token::LESS | Token::LEQ | Token::GREATER | Token::GEQ => match (&lhs, &rhs) {
+                (Type::Str, Type::Str)
+                | (Type::Str, Type::Unknown)
+                | (Type::Unknown, Type::Str) => Some(Type::Bool),
+                (Type::Var(_), _) => Some(Type::Bool),
+                (a, b) if a.is_numeric() && b.is_numeric() => Some(Type::Bool),
+                _ => None,
+            },
+            Token::BITAND | Token::BITOR | Token::XOR | Token::SHL | Token::SHR => {
+                self.unify(&lhs, &Type::Int)?;
+                self.unify(&rhs, &Type::Int)?;
+                Some(Type::Int)
+            }
+            Token::ADD | Token::SUB | Token::MUL | Token::DIV | Token::EXP | Token::MOD => {
+                match (&lhs, &rhs) {
+                    (Type::Str, Type::Str) if matches!(op, Token::ADD) => Some(Type::Str),
+                    (Type::Int, Type::Int) => Some(Type::Int),
+                    // both are the same variable by now
+                    (Type::Var(_), _) => Some(lhs.clone()),
+                    (Type::Unknown, _) | (_, Type::Unknown)
+                        if lhs.is_numeric() && rhs.is_numeric() =>
+                    {
+                        Some(Type::Unknown)
+                    }
+                    // ints are promoted when mixed with floats
+                    (a, b) if a.is_numeric() && b.is_numeric() => Some(Type::Float),
+                    (Type::Unknown, t) | (t, Type::Unknown) if matches!(op, Token::ADD) => {
+                        let t = t.clone();
+                        self.unify(&t, &Type::Str)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, found: &Type, expected: &Type, context: &Token) -> Result<(), Error> {
+        match self.unify(found, expected) {
+            Some(_) => Ok(()),
+            None => Err(type_error(format!(
+                "expected {} for {:?} but found {}",
+                expected,
+                context,
+                self.prune(found)
+            ))),
+        }
+    }
+}
+
+// the names a program assigns at the top level, its globals
+pub fn global_names(stmts: &[SExpr]) -> Vec<String> {
+    let mut names = vec![];
+    for stmt in stmts {
+        assigned_names(stmt, &mut names);
+    }
+    names
+}
+
+// the structs and enums declared at the top level, with their fields' types
+pub fn collect_types(program: &SExpr) -> Result<TypeDefs, Error> {
+    let stmts = match program {
+        SExpr::Block(stmts) => &stmts[..],
+        _ => &[],
+    };
+
+    // every type name is known before any field types are resolved, so
+    // types can refer to the ones declared after them
+    let mut names = TypeNames {
+        structs: vec![],
+        enums: vec![],
+        generics: &[],
+    };
+    for stmt in stmts {
+        let (kind, name) = match stmt {
+            SExpr::StructDef(name, ..) => ("struct", name.as_str()),
+            SExpr::EnumDef(name, _) => ("enum", name.as_str()),
+            _ => continue,
+        };
+        if names.structs.iter().any(|(s, _)| *s == name) || names.enums.contains(&name) {
+            return Err(type_error(format!("{} {} is declared twice", kind, name)));
+        }
+        match stmt {
+            SExpr::StructDef(_, generics, _) => names.structs.push((name, generics.len())),
+            _ => names.enums.push(name),
+        }
+    }
+
+    let mut defs = TypeDefs {
+        structs: vec![],
+        enums: vec![],
+    };
+    for stmt in stmts {
+        match stmt {
+            SExpr::StructDef(name, generics, fields) => {
+                names.generics = generics;
+                let mut resolved = vec![];
+                for (field, t) in fields {
+                    resolved.push((field.clone(), resolve_type(t, &names)?));
+                }
+                defs.structs.push(StructDef {
+                    name: name.clone(),
+                    generics: generics.clone(),
+                    fields: resolved,
+                });
+            }
+            SExpr::EnumDef(name, variants) => {
+                names.generics = &[];
+                let mut resolved = vec![];
+                for (variant, fields) in variants {
+                    let fields = match fields {
+                        Fields::Unit => Fields::Unit,
+                        Fields::Tuple(types) => Fields::Tuple(
+                            types
+                                .iter()
+                                .map(|t| resolve_type(t, &names))
+                                .collect::<Result<_, _>>()?,
+                        ),
+                        Fields::Struct(fields) => Fields::Struct(
+                            fields
+                                .iter()
+                                .map(|(f, t)| Ok((f.clone(), resolve_type(t, &names)?)))
+                                .collect::<Result<_, Error>>()?,
+                        ),
+                    };
+                    resolved.push((variant.clone(), fields));
+                }
+                defs.enums.push(Rc::new(EnumDef {
+                    name: name.clone(),
+                    variants: resolved,
+                }));
+            }
+            _ => {}
+        }
+    }
+    Ok(defs)
+}
+
+fn resolve_type(t: &TypeExpr, names: &TypeNames) -> Result<Type, Error> {
+    let (name, args) = match t {
+        TypeExpr::Array(elem) => return Ok(Type::Array(Box::new(resolve_type(elem, names)?))),
+        TypeExpr::Fn(params, ret) => {
+            let params = params
+                .iter()
+                .map(|t| resolve_type(t, names))
+                .collect::<Result<_, _>>()?;
+            return Ok(Type::Fn(params, Box::new(resolve_type(ret, names)?)));
+        }
+        TypeExpr::Named(name, args) => (name.as_str(), args),
+    };
+
+    let expected = match names.structs.iter().find(|(s, _)| *s == name) {
+        _ if names.generics.iter().any(|g| g == name) => 0,
+        Some((_, generics)) => *generics,
+        None => 0,
+    };
+    if args.len() != expected {
+        return Err(type_error(format!(
+            "{} takes {} type arguments but {} were given",
+            name,
+            expected,
+            args.len()
+        )));
+    }
+
+    match name {
+        _ if names.generics.iter().any(|g| g == name) => Ok(Type::Param(name.to_string())),
+        "void" => Ok(Type::Void),
+        "bool" => Ok(Type::Bool),
+        "int" => Ok(Type::Int),
+        "float" => Ok(Type::Float),
+        "str" => Ok(Type::Str),
+        _ if names.structs.iter().any(|(s, _)| *s == name) => {
+            let args = args
+                .iter()
+                .map(|t| resolve_type(t, names))
+                .collect::<Result<_, _>>()?;
+            Ok(Type::Struct(name.to_string(), args))
+        }
+        _ if names.enums.contains(&name) => Ok(Type::Enum(name.to_string())),
+        _ => Err(type_error(format!("unknown type {}", name))),
+    }
+}
+
+fn type_error(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("TYPE ERROR: {}", message))
+}
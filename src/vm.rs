@@ -0,0 +1,892 @@
+use super::heap::{GcConfig, GcStats, Heap};
+use super::ir::*;
+use super::layout::*;
+use super::natives::{Native, Natives};
+use super::parser::Fields;
+use super::source::{SourceMap, Span};
+use super::typeck::EnumDef;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Void,
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    Str(Rc<str>),
+    Array(Rc<RefCell<Vec<Value>>>),
+    // fields are stored in slot order
+    Struct(Rc<StructLayout>, Rc<RefCell<Vec<Value>>>),
+    // the variant's index and its payload in slot order
+    Enum(Rc<EnumDef>, usize, Rc<Vec<Value>>),
+    Closure(Rc<Closure>),
+    // a built-in function implemented in Rust
+    Native(Rc<Native>),
+}
+
+// a variable, shared between the function it belongs to and the closures
+// capturing it, None until it's assigned
+pub type Cell = Rc<RefCell<Option<Value>>>;
+
+#[derive(Debug)]
+pub struct Closure {
+    pub func: usize,
+    pub name: String,
+    pub env: Vec<Cell>,
+}
+
+// strings inside other values are quoted
+fn fmt_nested(value: &Value, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match value {
+        Value::Str(s) => write!(f, "{:?}", s),
+        value => write!(f, "{}", value),
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Void => write!(f, "void"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(n) => write!(f, "{:?}", n),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Array(elems) => {
+                write!(f, "[")?;
+                for (i, elem) in elems.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    fmt_nested(elem, f)?;
+                }
+                write!(f, "]")
+            }
+            Value::Struct(layout, fields) => {
+                write!(f, "{} {{", layout.name)?;
+                for (i, (field, value)) in
+                    layout.fields.iter().zip(fields.borrow().iter()).enumerate()
+                {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}: ", field.name)?;
+                    fmt_nested(value, f)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.name),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Enum(def, index, payload) => {
+                let (variant, fields) = &def.variants[*index];
+                write!(f, "{}::{}", def.name, variant)?;
+                match fields {
+                    Fields::Unit => Ok(()),
+                    Fields::Tuple(_) => {
+                        write!(f, "(")?;
+                        for (i, value) in payload.iter().enumerate() {
+                            if i != 0 {
+                                write!(f, ", ")?;
+                            }
+                            fmt_nested(value, f)?;
+                        }
+                        write!(f, ")")
+                    }
+                    Fields::Struct(fields) => {
+                        write!(f, " {{")?;
+                        for (i, ((field, _), value)) in
+                            fields.iter().zip(payload.iter()).enumerate()
+                        {
+                            if i != 0 {
+                                write!(f, ",")?;
+                            }
+                            write!(f, " {}: ", field)?;
+                            fmt_nested(value, f)?;
+                        }
+                        write!(f, " }}")
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Void => "void",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::Array(_) => "array",
+            Value::Struct(..) => "struct",
+            Value::Enum(..) => "enum",
+            Value::Closure(_) | Value::Native(_) => "fn",
+        }
+    }
+
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Void, Value::Void) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(a), Value::Float(b)) | (Value::Float(b), Value::Int(a)) => *a as f32 == *b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.equals(b))
+            }
+            // a generic struct's values can have layouts for different type
+            // arguments, and the same slots either way
+            (Value::Struct(a_layout, a), Value::Struct(b_layout, b)) => {
+                let (a, b) = (a.borrow(), b.borrow());
+                a_layout.name == b_layout.name && a.iter().zip(b.iter()).all(|(a, b)| a.equals(b))
+            }
+            // by name too, a value an earlier REPL entry made has the enum of
+            // the module it ran in
+            (Value::Enum(a_def, a_index, a), Value::Enum(b_def, b_index, b)) => {
+                a_def.name == b_def.name
+                    && a_index == b_index
+                    && a.iter().zip(b.iter()).all(|(a, b)| a.equals(b))
+            }
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl From<&Const> for Value {
+    fn from(c: &Const) -> Self {
+        match c {
+            Const::Void => Value::Void,
+            Const::Bool(b) => Value::Bool(*b),
+            Const::Int(n) => Value::Int(*n),
+            Const::Float(n) => Value::Float(*n),
+            Const::Str(s) => Value::Str(Rc::from(s.as_str())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    // the calls being run when it happened, innermost first. Tail calls
+    // leave no frame behind
+    pub trace: Vec<TraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    // the call the function was making, None if the error was its own
+    pub site: Option<Span>,
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RUNTIME ERROR: {}", self.message)
+    }
+}
+
+impl RuntimeError {
+    // the error with its backtrace, each call site shown in the source.
+    // Frames of the same call repeated, as recursion makes, are shown once
+    pub fn render(&self, map: &SourceMap) -> String {
+        let mut out = self.to_string();
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut repeats = 0;
+            while frames.peek() == Some(&frame) {
+                frames.next();
+                repeats += 1;
+            }
+            out.push('\n');
+            match frame.site {
+                Some(site) => out.push_str(&map.render(site, &format!("in {}", frame.function))),
+                None => out.push_str(&format!("  in {}", frame.function)),
+            }
+            if repeats > 0 {
+                out.push_str(&format!("\n  ... {} more times", repeats));
+            }
+        }
+        out
+    }
+}
+
+fn error<T>(message: String) -> Result<T, RuntimeError> {
+    Err(RuntimeError {
+        message,
+        trace: vec![],
+    })
+}
+
+// bounds on how much a program may run before the VM stops it, None for no
+// bound
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_instructions: Option<u64>,
+    // how many calls deep the program may go
+    pub max_depth: Option<usize>,
+    // how many bytes the program's objects may take up after a collection
+    pub max_heap_bytes: Option<usize>,
+}
+
+// calls take heap rather than Rust stack, the default depth only stops
+// runaway recursion before it uses up the memory
+pub const DEFAULT_MAX_DEPTH: usize = 100_000;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_instructions: None,
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_heap_bytes: None,
+        }
+    }
+}
+
+enum Callee {
+    Closure(Rc<Closure>, Vec<Value>),
+    // what the native called returned
+    Returned(Value),
+}
+
+// a call being run, its variables are roots for the collector
+struct Frame {
+    func: usize,
+    locals: Rc<Vec<Cell>>,
+    closure: Option<Rc<Closure>>,
+    block: BlockId,
+    // the next instruction to run, the block's terminator after the last
+    index: usize,
+}
+
+pub struct VM<'a> {
+    module: &'a Module,
+    globals: Vec<Option<Value>>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    heap: Heap,
+    limits: Limits,
+    instructions: u64,
+}
+
+impl<'a> VM<'a> {
+    // the natives start out in the globals of the same name, which the
+    // program may reassign
+    pub fn new(module: &'a Module, natives: &Natives) -> Self {
+        let globals = module
+            .globals
+            .iter()
+            .map(|name| {
+                natives
+                    .get(name)
+                    .map(|native| Value::Native(native.clone()))
+            })
+            .collect();
+        Self {
+            module,
+            globals,
+            stack: vec![],
+            frames: vec![],
+            heap: Heap::new(GcConfig::default()),
+            limits: Limits::default(),
+            instructions: 0,
+        }
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // starts the globals with the values of the same name, which an earlier
+    // program left behind
+    pub fn with_globals(mut self, values: &HashMap<String, Value>) -> Self {
+        for (slot, name) in self.module.globals.iter().enumerate() {
+            if let Some(value) = values.get(name) {
+                self.globals[slot] = Some(value.clone());
+            }
+        }
+        self
+    }
+
+    // the globals that have a value, by name
+    pub fn globals(&self) -> HashMap<String, Value> {
+        self.module
+            .globals
+            .iter()
+            .zip(&self.globals)
+            .filter_map(|(name, value)| Some((name.clone(), value.clone()?)))
+            .collect()
+    }
+
+    pub fn with_gc(mut self, config: GcConfig) -> Self {
+        self.heap = Heap::new(config);
+        self
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        let mut at = None;
+        let result = self
+            .enter(self.module.main, None, vec![])
+            .and_then(|_| self.run_frames(&mut at));
+        result.map_err(|err| self.unwind(err, at))
+    }
+
+    // starts a call, on top of the calls being run
+    fn enter(
+        &mut self,
+        func: usize,
+        closure: Option<Rc<Closure>>,
+        args: Vec<Value>,
+    ) -> Result<(), RuntimeError> {
+        if let Some(max) = self.limits.max_depth {
+            if self.frames.len() >= max {
+                return error(format!("recursion depth limit of {} exceeded", max));
+            }
+        }
+        let mut locals: Vec<Cell> = args
+            .into_iter()
+            .map(|arg| Rc::new(RefCell::new(Some(arg))))
+            .collect();
+        locals.resize_with(self.module.functions[func].locals.len(), || {
+            Rc::new(RefCell::new(None))
+        });
+        self.frames.push(Frame {
+            func,
+            locals: Rc::new(locals),
+            closure,
+            block: 0,
+            index: 0,
+        });
+        Ok(())
+    }
+
+    // Runs the calls on the frame stack until the first one returns. A call
+    // pushes a frame rather than recursing, so however deep the program
+    // goes the Rust stack doesn't grow. A tail call replaces the frame it's
+    // made from. `at` is the instruction being run in the innermost call,
+    // None between instructions
+    fn run_frames(&mut self, at: &mut Option<(BlockId, usize)>) -> Result<Value, RuntimeError> {
+        let module = self.module;
+        loop {
+            let frame = self.frames.last().unwrap();
+            let func = &module.functions[frame.func];
+            let (block, index) = (frame.block, frame.index);
+            *at = Some((block, index));
+
+            if let Some(instr) = func.blocks[block].instrs.get(index) {
+                self.count_instruction()?;
+                let over_limit = self
+                    .limits
+                    .max_heap_bytes
+                    .is_some_and(|max| self.heap.bytes() > max);
+                if over_limit || self.heap.wants_collection() {
+                    self.collect()?;
+                }
+                let frame = self.frames.last_mut().unwrap();
+                frame.index += 1;
+                match instr {
+                    Instr::Call(argc) => match self.callee(*argc)? {
+                        Callee::Closure(closure, args) => {
+                            self.enter(closure.func, Some(closure), args)?
+                        }
+                        Callee::Returned(value) => self.stack.push(value),
+                    },
+                    _ => {
+                        let locals = frame.locals.clone();
+                        let closure = frame.closure.clone();
+                        let env = closure.as_ref().map_or(&[][..], |closure| &closure.env[..]);
+                        self.execute(instr, func, &locals, env)?;
+                    }
+                }
+                continue;
+            }
+
+            let value = match func.blocks[block].terminator {
+                Terminator::Jump(target) => {
+                    self.jump(target);
+                    continue;
+                }
+                Terminator::Branch(then, els) => {
+                    match self.pop() {
+                        Value::Bool(true) => self.jump(then),
+                        Value::Bool(false) => self.jump(els),
+                        other => {
+                            return error(format!(
+                                "condition must be a bool, not {}",
+                                other.type_name()
+                            ))
+                        }
+                    }
+                    continue;
+                }
+                Terminator::Return => self.pop(),
+                Terminator::Fail(ref message) => return error(message.clone()),
+                // counted like the call it replaced
+                Terminator::TailCall(argc) => {
+                    self.count_instruction()?;
+                    match self.callee(argc)? {
+                        Callee::Closure(closure, args) => {
+                            self.frames.pop();
+                            self.enter(closure.func, Some(closure), args)?;
+                            continue;
+                        }
+                        Callee::Returned(value) => value,
+                    }
+                }
+            };
+
+            self.frames.pop();
+            if self.frames.is_empty() {
+                return Ok(value);
+            }
+            self.stack.push(value);
+        }
+    }
+
+    fn jump(&mut self, target: BlockId) {
+        let frame = self.frames.last_mut().unwrap();
+        frame.block = target;
+        frame.index = 0;
+    }
+
+    // Gives the error a frame for each call being run, innermost first. The
+    // innermost is at the instruction that failed, if one did, and the
+    // others at the call they were making
+    fn unwind(&mut self, mut err: RuntimeError, at: Option<(BlockId, usize)>) -> RuntimeError {
+        let mut at = at;
+        while let Some(frame) = self.frames.pop() {
+            let func = &self.module.functions[frame.func];
+            err.trace.push(TraceFrame {
+                function: func.name.clone(),
+                site: at.and_then(|(block, index)| func.site(block, index)),
+            });
+            at = self
+                .frames
+                .last()
+                .map(|caller| (caller.block, caller.index - 1));
+        }
+        self.stack.clear();
+        err
+    }
+
+    // the stack, the globals and the variables of every call being run are
+    // the roots, all the program can still use is reachable from them
+    // between instructions
+    fn collect(&mut self) -> Result<(), RuntimeError> {
+        let mut roots: Vec<Value> = self.stack.clone();
+        roots.extend(self.globals.iter().flatten().cloned());
+        let mut cells = vec![];
+        for frame in &self.frames {
+            cells.extend(frame.locals.iter().cloned());
+            roots.extend(frame.closure.clone().map(Value::Closure));
+        }
+        self.heap.collect(roots, &cells);
+        match self.limits.max_heap_bytes {
+            Some(max) if self.heap.bytes() > max => {
+                error(format!("heap limit of {} bytes exceeded", max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn count_instruction(&mut self) -> Result<(), RuntimeError> {
+        self.instructions += 1;
+        match self.limits.max_instructions {
+            Some(max) if self.instructions > max => {
+                error(format!("instruction limit of {} exceeded", max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // pops the arguments and the callee of a call, a native is called right
+    // away
+    fn callee(&mut self, argc: usize) -> Result<Callee, RuntimeError> {
+        let args = self.stack.split_off(self.stack.len() - argc);
+        let closure = match self.pop() {
+            Value::Closure(closure) => closure,
+            Value::Native(native) => {
+                if native.arity() != argc {
+                    return error(format!(
+                        "{} takes {} arguments but {} were given",
+                        native.name,
+                        native.arity(),
+                        argc
+                    ));
+                }
+                let result = (native.func)(&args).map_err(|message| RuntimeError {
+                    message,
+                    trace: vec![],
+                })?;
+                for arg in &args {
+                    self.heap.resize(arg);
+                }
+                self.heap.track_all(&result);
+                return Ok(Callee::Returned(result));
+            }
+            other => return error(format!("can't call a value of type {}", other.type_name())),
+        };
+        let params = self.module.functions[closure.func].params;
+        if params != argc {
+            return error(format!(
+                "{} takes {} arguments but {} were given",
+                closure.name, params, argc
+            ));
+        }
+        Ok(Callee::Closure(closure, args))
+    }
+
+    fn execute(
+        &mut self,
+        instr: &Instr,
+        func: &Function,
+        locals: &[Cell],
+        env: &[Cell],
+    ) -> Result<(), RuntimeError> {
+        match instr {
+            Instr::Const(c) => self.alloc(Value::from(c)),
+            Instr::LoadLocal(slot) => match &*locals[*slot].borrow() {
+                Some(value) => self.stack.push(value.clone()),
+                None => return error(format!("{} is not defined", func.locals[*slot])),
+            },
+            Instr::StoreLocal(slot) => *locals[*slot].borrow_mut() = Some(self.pop()),
+            Instr::LoadEnv(i) => match &*env[*i].borrow() {
+                Some(value) => self.stack.push(value.clone()),
+                None => return error(format!("{} is not defined", func.captures[*i])),
+            },
+            Instr::StoreEnv(i) => *env[*i].borrow_mut() = Some(self.pop()),
+            Instr::LoadGlobal(slot) => match &self.globals[*slot] {
+                Some(value) => self.stack.push(value.clone()),
+                None => return error(format!("{} is not defined", self.module.globals[*slot])),
+            },
+            Instr::StoreGlobal(slot) => self.globals[*slot] = Some(self.pop()),
+            Instr::Dup => {
+                let top = self.stack.last().unwrap().clone();
+                self.stack.push(top);
+            }
+            Instr::Dup2 => {
+                let top = self.stack[self.stack.len() - 2..].to_vec();
+                self.stack.extend(top);
+            }
+            Instr::Pop => {
+                self.pop();
+            }
+            Instr::Unary(op) => {
+                let value = self.pop();
+                let result = unary(*op, value)?;
+                self.stack.push(result);
+            }
+            Instr::Binary(op) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let result = binary(*op, lhs, rhs)?;
+                self.alloc(result);
+            }
+            Instr::MakeArray(len) => {
+                let elems = self.stack.split_off(self.stack.len() - len);
+                self.alloc(Value::Array(Rc::new(RefCell::new(elems))));
+            }
+            Instr::Index => {
+                let index = self.pop();
+                let array = self.pop();
+                let elems = as_array(&array)?;
+                let elems = elems.borrow();
+                let i = check_index(&index, elems.len())?;
+                self.stack.push(elems[i].clone());
+            }
+            Instr::StoreIndex => {
+                let value = self.pop();
+                let index = self.pop();
+                let array = self.pop();
+                let elems = as_array(&array)?;
+                let mut elems = elems.borrow_mut();
+                let i = check_index(&index, elems.len())?;
+                elems[i] = value.clone();
+                self.stack.push(value);
+            }
+            Instr::Slice(has_lo, has_hi) => {
+                let hi = if *has_hi { Some(self.pop()) } else { None };
+                let lo = if *has_lo { Some(self.pop()) } else { None };
+                let array = self.pop();
+                let elems = as_array(&array)?;
+                let elems = elems.borrow();
+
+                let lo = match lo {
+                    Some(lo) => check_bound(&lo, elems.len())?,
+                    None => 0,
+                };
+                let hi = match hi {
+                    Some(hi) => check_bound(&hi, elems.len())?,
+                    None => elems.len(),
+                };
+                if lo > hi {
+                    return error(format!("slice index starts at {} but ends at {}", lo, hi));
+                }
+
+                let slice = elems[lo..hi].to_vec();
+                self.alloc(Value::Array(Rc::new(RefCell::new(slice))));
+            }
+            Instr::MakeStruct(id, slots) => {
+                let layout = self.module.structs[*id].clone();
+                let values = self.stack.split_off(self.stack.len() - slots.len());
+                let mut fields = vec![Value::Void; layout.fields.len()];
+                for (slot, value) in slots.iter().zip(values) {
+                    fields[*slot] = value;
+                }
+                self.alloc(Value::Struct(layout, Rc::new(RefCell::new(fields))));
+            }
+            Instr::GetField(name) => {
+                let target = self.pop();
+                let (slot, fields) = field_slot(&target, name)?;
+                let value = fields.borrow()[slot].clone();
+                self.stack.push(value);
+            }
+            Instr::SetField(name) => {
+                let value = self.pop();
+                let target = self.pop();
+                let (slot, fields) = field_slot(&target, name)?;
+                fields.borrow_mut()[slot] = value.clone();
+                self.stack.push(value);
+            }
+            Instr::MakeVariant(id, index, slots) => {
+                let def = self.module.enums[*id].clone();
+                let values = self.stack.split_off(self.stack.len() - slots.len());
+                let mut payload = vec![Value::Void; slots.len()];
+                for (slot, value) in slots.iter().zip(values) {
+                    payload[*slot] = value;
+                }
+                self.stack.push(Value::Enum(def, *index, Rc::new(payload)));
+            }
+            Instr::Tag(id) => {
+                let def = &self.module.enums[*id];
+                match self.pop() {
+                    Value::Enum(found, index, _) if found.name == def.name => {
+                        self.stack.push(Value::Int(index as i32))
+                    }
+                    other => {
+                        return error(format!(
+                            "can't match {} against a pattern for {}",
+                            other, def.name
+                        ))
+                    }
+                }
+            }
+            Instr::MakeClosure(id, captures) => {
+                let mut cells = vec![];
+                for capture in captures {
+                    cells.push(match capture {
+                        Capture::Local(slot) => {
+                            self.heap.track_cell(&locals[*slot]);
+                            locals[*slot].clone()
+                        }
+                        // tracked by the closure that captured it first
+                        Capture::Env(i) => env[*i].clone(),
+                    });
+                }
+                let closure = Closure {
+                    func: *id,
+                    name: self.module.functions[*id].name.clone(),
+                    env: cells,
+                };
+                self.alloc(Value::Closure(Rc::new(closure)));
+            }
+            // run_frames makes calls, it pushes a frame for them
+            Instr::Call(_) => unreachable!(),
+            Instr::VariantField(slot) => match self.pop() {
+                Value::Enum(_, _, payload) => self.stack.push(payload[*slot].clone()),
+                other => return error(format!("{} has no payload", other.type_name())),
+            },
+        }
+        Ok(())
+    }
+
+    // pushes a value that was just made, tracking the object it points to
+    fn alloc(&mut self, value: Value) {
+        self.heap.track(&value);
+        self.stack.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("ERROR: VM stack underflow")
+    }
+}
+
+fn as_array(value: &Value) -> Result<&Rc<RefCell<Vec<Value>>>, RuntimeError> {
+    match value {
+        Value::Array(elems) => Ok(elems),
+        other => error(format!(
+            "can't index into a value of type {}",
+            other.type_name()
+        )),
+    }
+}
+
+type Slots = Rc<RefCell<Vec<Value>>>;
+
+fn field_slot<'v>(target: &'v Value, name: &str) -> Result<(usize, &'v Slots), RuntimeError> {
+    match target {
+        Value::Struct(layout, fields) => match layout.field(name) {
+            Some(field) => Ok((field.slot, fields)),
+            None => error(format!("struct {} has no field {}", layout.name, name)),
+        },
+        other => error(format!("{} has no field {}", other.type_name(), name)),
+    }
+}
+
+// an index must be in 0..len
+fn check_index(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Int(i) if *i >= 0 && (*i as usize) < len => Ok(*i as usize),
+        Value::Int(i) => error(format!(
+            "index out of bounds: the len is {} but the index is {}",
+            len, i
+        )),
+        other => error(format!(
+            "array index must be an int, not {}",
+            other.type_name()
+        )),
+    }
+}
+
+// a slice bound must be in 0..=len
+fn check_bound(bound: &Value, len: usize) -> Result<usize, RuntimeError> {
+    match bound {
+        Value::Int(i) if *i >= 0 && (*i as usize) <= len => Ok(*i as usize),
+        Value::Int(i) => error(format!(
+            "slice index out of bounds: the len is {} but the index is {}",
+            len, i
+        )),
+        other => error(format!(
+            "slice index must be an int, not {}",
+            other.type_name()
+        )),
+    }
+}
+
+fn unary(op: UnOp, value: Value) -> Result<Value, RuntimeError> {
+    match (op, value) {
+        (UnOp::Neg, Value::Int(n)) => match n.checked_neg() {
+            Some(n) => Ok(Value::Int(n)),
+            None => error(String::from("integer overflow")),
+        },
+        (UnOp::Neg, Value::Float(n)) => Ok(Value::Float(-n)),
+        (UnOp::Plus, value @ Value::Int(_)) | (UnOp::Plus, value @ Value::Float(_)) => Ok(value),
+        (UnOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (UnOp::BitNot, Value::Int(n)) => Ok(Value::Int(!n)),
+        (op, value) => error(format!("can't apply {:?} to {}", op, value.type_name())),
+    }
+}
+
+fn binary(op: BinOp, lhs: Value, rhs: Value) -> Result<Value, RuntimeError> {
+    let compare = |ordering: Option<std::cmp::Ordering>| -> Result<Value, RuntimeError> {
+        use std::cmp::Ordering::*;
+        let result = match (op, ordering) {
+            (_, None) => {
+                return error(format!(
+                    "can't compare {} with {}",
+                    lhs.type_name(),
+                    rhs.type_name()
+                ))
+            }
+            (BinOp::Less, Some(o)) => o == Less,
+            (BinOp::Leq, Some(o)) => o != Greater,
+            (BinOp::Greater, Some(o)) => o == Greater,
+            (BinOp::Geq, Some(o)) => o != Less,
+            _ => unreachable!(),
+        };
+        Ok(Value::Bool(result))
+    };
+
+    match op {
+        BinOp::Eq => return Ok(Value::Bool(lhs.equals(&rhs))),
+        BinOp::Neq => return Ok(Value::Bool(!lhs.equals(&rhs))),
+        BinOp::Less | BinOp::Leq | BinOp::Greater | BinOp::Geq => {
+            return match (&lhs, &rhs) {
+                (Value::Int(a), Value::Int(b)) => compare(a.partial_cmp(b)),
+                (Value::Str(a), Value::Str(b)) => compare(a.partial_cmp(b)),
+                _ => match (as_float(&lhs), as_float(&rhs)) {
+                    (Some(a), Some(b)) => compare(a.partial_cmp(&b)),
+                    _ => compare(None),
+                },
+            };
+        }
+        _ => {}
+    }
+
+    match (&lhs, &rhs) {
+        (Value::Int(a), Value::Int(b)) => {
+            let (a, b) = (*a, *b);
+            let result = match op {
+                BinOp::Add => a.checked_add(b),
+                BinOp::Sub => a.checked_sub(b),
+                BinOp::Mul => a.checked_mul(b),
+                BinOp::Div if b == 0 => return error(String::from("division by zero")),
+                BinOp::Div => a.checked_div(b),
+                BinOp::Exp if b < 0 => return error(format!("negative exponent {} for an int", b)),
+                BinOp::Exp => a.checked_pow(b as u32),
+                BinOp::Mod if b == 0 => return error(String::from("division by zero")),
+                BinOp::Mod => a.checked_rem(b),
+                BinOp::BitAnd => Some(a & b),
+                BinOp::BitOr => Some(a | b),
+                BinOp::Xor => Some(a ^ b),
+                BinOp::Shl | BinOp::Shr if !(0..32).contains(&b) => {
+                    return error(format!("shift amount {} is out of range", b))
+                }
+                BinOp::Shl => Some(a << b),
+                BinOp::Shr => Some(a >> b),
+                _ => unreachable!(),
+            };
+            match result {
+                Some(n) => Ok(Value::Int(n)),
+                None => error(String::from("integer overflow")),
+            }
+        }
+        (Value::Str(a), Value::Str(b)) if op == BinOp::Add => {
+            Ok(Value::Str(Rc::from(format!("{}{}", a, b).as_str())))
+        }
+        _ => match (as_float(&lhs), as_float(&rhs)) {
+            (Some(a), Some(b)) => Ok(Value::Float(match op {
+                BinOp::Add => a + b,
+                BinOp::Sub => a - b,
+                BinOp::Mul => a * b,
+                BinOp::Div => a / b,
+                BinOp::Exp => a.powf(b),
+                BinOp::Mod => a % b,
+                _ => {
+                    return error(format!(
+                        "can't apply {:?} to {} and {}",
+                        op,
+                        lhs.type_name(),
+                        rhs.type_name()
+                    ))
+                }
+            })),
+            _ => error(format!(
+                "can't apply {:?} to {} and {}",
+                op,
+                lhs.type_name(),
+                rhs.type_name()
+            )),
+        },
+    }
+}
+
+fn as_float(value: &Value) -> Option<f32> {
+    match value {
+        Value::Int(n) => Some(*n as f32),
+        Value::Float(n) => Some(*n),
+        _ => None,
+    }
+}
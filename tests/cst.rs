@@ -0,0 +1,128 @@
+use compiler::cst::{self, AstNode, BinExpr, FnDecl, NodeKind, SourceFile, SyntaxNode, TokenKind};
+use compiler::lexer::Token;
+use std::fs;
+use std::path::PathBuf;
+
+fn corpus() -> Vec<String> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fmt");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    let mut texts: Vec<String> = files
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect();
+    texts.extend(
+        [
+            "",
+            "\n\n",
+            "// only a comment",
+            "  x=1;;  y = (( x ))  // no line break at the end",
+            "struct P<T> { x: Pair<Pair<int>> }\n\n\nfn f(){}",
+            "match  v {\n  E::A { x, .. } => -1 ,\n _=>{ } }\n",
+        ]
+        .iter()
+        .map(|text| text.to_string()),
+    );
+    // the same files as written on windows and indented with tabs
+    let crlf: Vec<String> = texts
+        .iter()
+        .map(|text| text.replace('\n', "\r\n"))
+        .collect();
+    let tabs: Vec<String> = texts
+        .iter()
+        .map(|text| text.replace("    ", "\t"))
+        .collect();
+    texts.extend(crlf);
+    texts.extend(tabs);
+    texts
+}
+
+#[test]
+fn printing_the_tree_gives_the_text_back() {
+    for text in corpus() {
+        let root = cst::parse(&text).unwrap();
+        assert_eq!(root.to_string(), text);
+        assert_eq!(root.span().end, text.chars().count());
+    }
+}
+
+#[test]
+fn every_node_spans_its_own_text() {
+    for text in corpus() {
+        let chars: Vec<char> = text.chars().collect();
+        let root = cst::parse(&text).unwrap();
+        for node in root.descendants() {
+            let span = node.span();
+            let expected: String = chars[span.start..span.end].iter().collect();
+            assert_eq!(node.text(), expected, "{:?}", node);
+        }
+        let mut offset = 0;
+        for token in root.tokens() {
+            assert_eq!(token.span().start, offset);
+            offset = token.span().end;
+        }
+    }
+}
+
+#[test]
+fn trivia_is_kept_as_tokens() {
+    let root = cst::parse("x = 1; // one\n").unwrap();
+    let kinds: Vec<TokenKind> = root.tokens().iter().map(|t| t.kind().clone()).collect();
+    assert!(kinds.contains(&TokenKind::Comment));
+    assert_eq!(root.tokens().last().unwrap().text(), "\n");
+}
+
+#[test]
+fn crlf_and_tabs_are_whitespace() {
+    let text = "fn f(x) {\r\n\tx + 1 // one\r\n}\r\nf(2)\r\n";
+    let root = cst::parse(text).unwrap();
+    assert_eq!(root.to_string(), text);
+    let tokens = root.tokens();
+    let comment = tokens.iter().find(|t| t.kind() == &TokenKind::Comment);
+    assert_eq!(comment.unwrap().text(), "// one");
+    let trivia: Vec<&str> = tokens
+        .iter()
+        .filter(|t| t.kind() == &TokenKind::Whitespace)
+        .map(|t| t.text())
+        .collect();
+    assert!(trivia.contains(&"\r\n\t"));
+
+    // the same tree as with \n line endings and spaces
+    let plain = cst::parse("fn f(x) {\n    x + 1 // one\n}\nf(2)\n").unwrap();
+    let kinds = |root: &SyntaxNode| -> Vec<NodeKind> {
+        root.descendants().iter().map(|n| n.kind()).collect()
+    };
+    assert_eq!(kinds(&root), kinds(&plain));
+}
+
+#[test]
+fn typed_views_find_the_parts_of_a_node() {
+    let root = cst::parse("fn add(a: int, b) -> int { a + b }\n").unwrap();
+    let file = SourceFile::cast(root).unwrap();
+    let f = FnDecl::cast(file.statements()[0].clone()).unwrap();
+    assert_eq!(f.name().unwrap().text(), "add");
+    let params: Vec<String> = f
+        .params()
+        .iter()
+        .map(|p| p.name().unwrap().text().to_string())
+        .collect();
+    assert_eq!(params, ["a", "b"]);
+    assert_eq!(f.params()[0].ty().unwrap().syntax().text(), "int");
+    assert!(f.params()[1].ty().is_none());
+    assert_eq!(f.ret_type().unwrap().syntax().text(), "int");
+
+    let body = f.body().unwrap();
+    let sum = BinExpr::cast(body.statements()[0].clone()).unwrap();
+    assert_eq!(sum.op().unwrap().kind(), &TokenKind::Token(Token::ADD));
+    assert_eq!(sum.lhs().unwrap().kind(), NodeKind::Name);
+    assert_eq!(sum.rhs().unwrap().text(), "b");
+}
+
+#[test]
+fn syntax_errors_are_returned() {
+    assert!(cst::parse("x = (1 + ;").is_err());
+    assert!(cst::parse("x = \"open").is_err());
+}
@@ -0,0 +1,64 @@
+// Compares the drawings of the dot module with the files in tests/dot.
+// `BLESS=1 cargo test --test dot` writes the files from what the drawings
+// are instead.
+use compiler::automata::{Dfa, Nfa};
+use compiler::ir::{Lowerer, Module};
+use compiler::modules;
+use compiler::natives::Natives;
+use compiler::operators::OperatorTable;
+use compiler::source::SourceMap;
+use compiler::typeck::TypeChecker;
+use compiler::{cst, dot};
+use std::fs;
+use std::path::PathBuf;
+
+fn snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from("tests/dot").join(format!("{}.dot", name));
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert_eq!(expected, actual, "{} is out of date", path.display());
+}
+
+fn lower(text: &str) -> Module {
+    let natives = Natives::prelude();
+    let operators = OperatorTable::new();
+    let mut map = SourceMap::new();
+    let program = modules::load_source("main", text.to_string(), None, &operators, &mut map);
+    let program = program.unwrap();
+    let (_, instances) = TypeChecker::check(&program, &natives, &operators).unwrap();
+    Lowerer::lower(&program, &instances, &operators).unwrap()
+}
+
+#[test]
+fn syntax_tree() {
+    let tree = cst::parse("x = -(1 + y) * 2; // c\nf(x)\n").unwrap();
+    snapshot("syntax_tree", &dot::syntax_tree(&tree));
+}
+
+#[test]
+fn cfg_with_a_loop_and_its_dominators() {
+    let module = lower(
+        "fn sum(n) {\n    total = 0;\n    while n > 0 {\n        if n % 2 == 0 { total += n; }\n        n -= 1;\n    }\n    total\n}\nsum(10)\n",
+    );
+    let func = module.functions.iter().find(|f| f.name == "sum").unwrap();
+
+    // the loop's header dominates its body and the exit, and the body
+    // jumps back to it
+    let header = func.blocks[0].terminator.successors()[0];
+    let idoms = func.dominators();
+    for succ in func.blocks[header].terminator.successors() {
+        assert_eq!(idoms[succ], Some(header));
+    }
+    snapshot("cfg_loop", &dot::cfg(func));
+    snapshot("dominators_loop", &dot::dominator_tree(func));
+}
+
+#[test]
+fn regex_nfa_and_dfa() {
+    let nfa = Nfa::from_regex("a(b|c)*").unwrap();
+    snapshot("nfa", &dot::nfa(&nfa));
+    snapshot("dfa", &dot::dfa(&Dfa::from_nfa(&nfa)));
+}
@@ -0,0 +1,175 @@
+use compiler::operators::{Assoc, OperatorTable};
+use compiler::typeck::Type;
+use compiler::{Engine, Limits};
+
+#[test]
+fn deep_recursion_doesnt_overflow_the_stack() {
+    // the test runs on a thread with a small stack
+    let program = "
+fn depth(n) {
+    if n == 0 { 0 } else { depth(n - 1) + 1 }
+}
+depth(50000)";
+    assert_eq!(Engine::new().eval(program).unwrap().to_string(), "50000");
+}
+
+#[test]
+fn runaway_recursion_is_a_runtime_error() {
+    let program = "
+fn forever(n) {
+    forever(n + 1) + 1
+}
+forever(0)";
+    let err = Engine::new().eval(program).unwrap_err().to_string();
+    assert!(
+        err.starts_with("RUNTIME ERROR: recursion depth limit of 100000 exceeded\n"),
+        "{}",
+        err
+    );
+    assert!(err.contains("... 99998 more times"), "{}", err);
+}
+
+#[test]
+fn syntax_errors_are_returned() {
+    let err = Engine::new().eval("x = (1 + ;").unwrap_err();
+    assert_eq!(err.to_string(), "<eval>:1:10: ERROR: Unexpected token SEMI");
+}
+
+#[test]
+fn the_instruction_limit_stops_a_loop() {
+    let mut engine = Engine::new();
+    engine.set_limits(Limits {
+        max_instructions: Some(1000),
+        ..Limits::default()
+    });
+    let err = engine.eval("i = 0;\nwhile true { i += 1; }").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "RUNTIME ERROR: instruction limit of 1000 exceeded\n<eval>:2:14: in main\n2 | while true { i += 1; }\n  |              ^^^^^^"
+    );
+    assert_eq!(engine.eval("1 + 2").unwrap().to_string(), "3");
+}
+
+fn with_operators() -> Engine {
+    let mut operators = OperatorTable::new();
+    operators
+        .define_infix("|>", 1, Assoc::Left, "apply")
+        .unwrap();
+    operators
+        .define_infix("<+>", 8, Assoc::Left, "vadd")
+        .unwrap();
+    operators.define_prefix("$", 11, "len").unwrap();
+    operators.define_postfix("!!", 12, "twice").unwrap();
+    operators.register_infix("<=>", 3, Assoc::Left).unwrap();
+    let mut engine = Engine::new();
+    engine.set_operators(operators);
+    engine
+}
+
+#[test]
+fn custom_operators_call_their_functions() {
+    let program = "
+fn apply(x, f) { f(x) }
+fn vadd(a: int, b: int) -> int { a * 10 + b }
+fn twice(n: int) -> int { n * 2 }
+fn inc(n: int) -> int { n + 1 }
+1 |> inc |> twice";
+    let mut engine = with_operators();
+    assert_eq!(engine.eval(program).unwrap().to_string(), "4");
+
+    // <+> binds as tightly as +, !! tighter than the prefix $
+    let program = "
+fn vadd(a: int, b: int) -> int { a * 10 + b }
+fn twice(n: int) -> int { n * 2 }
+[1 <+> 2 <+> 3, 2 * 3 <+> 4, $[1, 2, 3] <+> 3!!, $\"ab\"]";
+    assert_eq!(
+        engine.eval(program).unwrap().to_string(),
+        "[123, 64, 36, 2]"
+    );
+}
+
+#[test]
+fn custom_operators_are_checked_like_calls() {
+    let program = "
+fn vadd(a: int, b: int) -> int { a + b }
+\"one\" <+> 2";
+    let err = with_operators().eval(program).unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "<eval>:3:1: TYPE ERROR: expected int for an argument but found str"
+    );
+
+    let err = with_operators().eval("1 <+> 2").unwrap_err().to_string();
+    assert_eq!(err, "<eval>:1:1: TYPE ERROR: vadd is not defined");
+
+    let err = with_operators().eval("1 <=> 2").unwrap_err().to_string();
+    assert_eq!(err, "<eval>:1:1: TYPE ERROR: operator <=> has no function");
+}
+
+#[test]
+fn a_local_doesnt_hide_an_operators_function() {
+    let program = "
+fn vadd(a: int, b: int) -> int { a + b }
+fn f() {
+    vadd = 5;
+    1 <+> vadd
+}
+f()";
+    assert_eq!(with_operators().eval(program).unwrap().to_string(), "6");
+}
+
+#[test]
+fn generic_functions_can_implement_operators() {
+    let program = "
+fn apply<T, U>(x: T, f: fn(T) -> U) -> U { f(x) }
+fn show(n: int) -> str { to_string(n) }
+(41 + 1) |> show";
+    assert_eq!(with_operators().eval(program).unwrap().to_string(), "42");
+}
+
+#[test]
+fn the_value_returned_is_checked() {
+    let mut engine = Engine::new();
+    let err = engine.eval_as::<i32>("if 1 < 2 { 1 } else { \"a\" }");
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "<eval>:1:1: TYPE ERROR: if and else have incompatible types int and str"
+    );
+}
+
+#[test]
+fn read_line_reads_the_hosts_input() {
+    let program = "
+name = read_line();
+n = to_int(read_line());
+println(name + \"!\");
+[len(read_line()), n]";
+    let mut engine = Engine::new();
+    engine.push_input("ann\r\n7\n");
+    assert_eq!(engine.eval(program).unwrap().to_string(), "[0, 7]");
+    assert_eq!(engine.take_output(), "ann!\n");
+}
+
+#[test]
+fn host_functions_are_checked_and_called_like_natives() {
+    let mut engine = Engine::new();
+    engine.register_fn("scale", |xs: Vec<i32>, k: i32| {
+        xs.iter().map(|x| x * k).collect::<Vec<i32>>()
+    });
+    let scaled: Vec<i32> = engine.eval_as("scale([1, 2], 3)").unwrap();
+    assert_eq!(scaled, [3, 6]);
+    let err = engine.eval("scale(1, 2)").unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "<eval>:1:1: TYPE ERROR: expected [int] for an argument but found int"
+    );
+
+    engine.register_native("fail", Type::Fn(vec![], Box::new(Type::Int)), |_| {
+        Err(String::from("the host said no"))
+    });
+    let err = engine.eval("x = 1;\nfail() + x").unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "RUNTIME ERROR: the host said no\n<eval>:2:1: in main\n2 | fail() + x\n  | ^^^^^^"
+    );
+}
@@ -0,0 +1,152 @@
+use compiler::format::{format_source, format_with_operators, FormatOptions};
+use compiler::lexer::Lexer;
+use compiler::operators::{Assoc, OperatorTable};
+use compiler::parser::Parser;
+use std::fs;
+use std::path::PathBuf;
+
+// the programs the formatter is checked against, each with some of every
+// construct and comments in awkward places
+fn corpus() -> Vec<(PathBuf, String)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fmt");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lang"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty());
+    files
+        .into_iter()
+        .map(|path| {
+            let text = fs::read_to_string(&path).unwrap();
+            (path, text)
+        })
+        .collect()
+}
+
+fn options() -> Vec<FormatOptions> {
+    vec![
+        FormatOptions::default(),
+        FormatOptions {
+            indent: 2,
+            width: 30,
+        },
+    ]
+}
+
+// the syntax tree and the comments of a program
+fn parse(text: &str) -> (String, Vec<String>) {
+    let lexer = Lexer::scan_source(text, 0, &[]).unwrap();
+    let comments = lexer.comments().iter().map(|c| c.text.clone()).collect();
+    (Parser::new(lexer).parse().unwrap().to_string(), comments)
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    for (path, text) in corpus() {
+        for options in options() {
+            let once = format_source(&text, options).unwrap();
+            let twice = format_source(&once, options).unwrap();
+            assert_eq!(
+                once,
+                twice,
+                "{} isn't stable with {:?}",
+                path.display(),
+                options
+            );
+        }
+    }
+}
+
+#[test]
+fn formatting_keeps_the_program_and_its_comments() {
+    for (path, text) in corpus() {
+        let (tree, mut comments) = parse(&text);
+        comments.sort();
+        for options in options() {
+            let formatted = format_source(&text, options).unwrap();
+            let (formatted_tree, mut formatted_comments) = parse(&formatted);
+            formatted_comments.sort();
+            assert_eq!(tree, formatted_tree, "{} changed meaning", path.display());
+            assert_eq!(
+                comments,
+                formatted_comments,
+                "{} lost comments",
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn lines_stay_within_the_width() {
+    for (path, text) in corpus() {
+        let formatted = format_source(&text, FormatOptions::default()).unwrap();
+        for line in formatted.lines() {
+            assert!(
+                line.len() <= 100,
+                "{}: {:?} is too long",
+                path.display(),
+                line
+            );
+            assert_eq!(
+                line,
+                line.trim_end(),
+                "{}: trailing whitespace",
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn syntax_errors_are_returned() {
+    let err = format_source("x = (1 + ;", FormatOptions::default()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn custom_operators_are_formatted() {
+    let mut operators = OperatorTable::new();
+    operators
+        .define_infix("|>", 1, Assoc::Left, "apply")
+        .unwrap();
+    operators.define_prefix("$", 11, "len").unwrap();
+    let text = "x = (1+2)|>f|>( g );\ny = ($ [1]) |> (1 |> h);\n";
+    let formatted = format_with_operators(text, FormatOptions::default(), &operators).unwrap();
+    assert_eq!(formatted, "x = 1 + 2 |> f |> g;\ny = $[1] |> (1 |> h);\n");
+    assert!(format_source(text, FormatOptions::default()).is_err());
+}
+
+fn format(text: &str) -> String {
+    let formatted = format_source(text, FormatOptions::default()).unwrap();
+    assert_eq!(
+        format_source(&formatted, FormatOptions::default()).unwrap(),
+        formatted
+    );
+    formatted
+}
+
+#[test]
+fn comments_in_a_list_stay_after_their_item() {
+    assert_eq!(format("f(1, // c\n 2)\n"), "f(\n    1, // c\n    2,\n);\n");
+    assert_eq!(
+        format("x = [g(1, // inner\n 2), // outer\n // own line\n 3 // last\n];\n"),
+        "x = [\n    g(\n        1, // inner\n        2,\n    ), // outer\n    // own line\n    3, // last\n];\n"
+    );
+    assert_eq!(
+        format("fn f(a, // first\n b) { a }\n"),
+        "fn f(\n    a, // first\n    b,\n) {\n    a\n}\n"
+    );
+}
+
+#[test]
+fn a_comment_before_else_stays_after_the_brace() {
+    let text = "if x {\n    a\n} // then\nelse {\n    b\n}\n";
+    assert_eq!(format(text), text);
+    assert_eq!(
+        format("if x { a } // one\n// two\nelse if y { b } else { c }\n"),
+        "if x {\n    a\n} // one\n// two\nelse if y {\n    b\n} else {\n    c\n}\n"
+    );
+}
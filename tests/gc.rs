@@ -0,0 +1,137 @@
+use compiler::{Engine, GcConfig, Limits};
+use std::fs;
+use std::path::PathBuf;
+
+fn stress() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_gc(GcConfig { stress: true });
+    engine
+}
+
+// what the program printed and the value or error it ended with
+fn run(engine: &mut Engine, text: &str) -> String {
+    let result = match engine.eval(text) {
+        Ok(value) => value.to_string(),
+        Err(err) => err.to_string(),
+    };
+    format!("{}{}", engine.take_output(), result)
+}
+
+#[test]
+fn golden_programs_run_the_same_when_collecting_all_the_time() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "src") {
+            continue;
+        }
+        let text = fs::read_to_string(&path).unwrap();
+        let mut engine = stress();
+        let stressed = run(&mut engine, text.trim_end_matches('\n'));
+        let normal = run(&mut Engine::new(), text.trim_end_matches('\n'));
+        assert_eq!(stressed, normal, "{}", path.display());
+    }
+}
+
+#[test]
+fn cycles_are_collected() {
+    // the local function captures the variable it's stored in
+    let program = "
+fn make() {
+    fn again() { again() }
+    again
+}
+i = 0;
+while i < 100 {
+    make();
+    i += 1;
+}
+i";
+    let mut engine = stress();
+    assert_eq!(run(&mut engine, program), "100");
+    let stats = engine.gc_stats();
+    assert!(stats.collections > 0);
+    // the last one is only collected at the next allocation
+    assert!(stats.swept >= 99, "{:?}", stats);
+    assert!(stats.live < 10, "{:?}", stats);
+}
+
+#[test]
+fn reachable_objects_survive() {
+    let program = "
+struct Node { value: int, next: [Node] }
+fn cons(value, next) { Node { value: value, next: [next] } }
+list = Node { value: 0, next: [] };
+i = 1;
+while i <= 50 {
+    list = cons(i, list);
+    i += 1;
+}
+total = 0;
+while len(list.next) > 0 {
+    total += list.value;
+    list = list.next[0];
+}
+total";
+    assert_eq!(run(&mut stress(), program), "1275");
+}
+
+#[test]
+fn heap_limit() {
+    let program = "
+xs = [];
+i = 0;
+while i < 10000 {
+    push(xs, \"item \" + to_string(i));
+    i += 1;
+}
+len(xs)";
+    let mut engine = Engine::new();
+    engine.set_limits(Limits {
+        max_heap_bytes: Some(20000),
+        ..Limits::default()
+    });
+    assert_eq!(
+        run(&mut engine, program),
+        "RUNTIME ERROR: heap limit of 20000 bytes exceeded\n<eval>:4:1: in main\n4 | while i < 10000 {\n  | ^^^^^^^^^^^^^^^"
+    );
+    assert_eq!(run(&mut Engine::new(), program), "10000");
+}
+
+#[test]
+fn growing_an_array_counts_against_the_heap_limit() {
+    let program = "
+xs = [];
+i = 0;
+while i < 1000000 {
+    push(xs, i);
+    i += 1;
+}
+len(xs)";
+    let mut engine = Engine::new();
+    engine.set_limits(Limits {
+        max_heap_bytes: Some(20000),
+        ..Limits::default()
+    });
+    assert_eq!(
+        run(&mut engine, program),
+        "RUNTIME ERROR: heap limit of 20000 bytes exceeded\n<eval>:4:1: in main\n4 | while i < 1000000 {\n  | ^^^^^^^^^^^^^^^^^"
+    );
+}
+
+#[test]
+fn objects_natives_make_are_tracked() {
+    let program = "
+i = 0;
+while i < 100 {
+    to_string(i);
+    i += 1;
+}";
+    let mut engine = Engine::new();
+    run(&mut engine, program);
+    assert!(
+        engine.gc_stats().allocated >= 100,
+        "{:?}",
+        engine.gc_stats()
+    );
+}
@@ -0,0 +1,45 @@
+use compiler::ir::{Lowerer, Module};
+use compiler::modules;
+use compiler::natives::Natives;
+use compiler::operators::OperatorTable;
+use compiler::source::SourceMap;
+use compiler::typeck::TypeChecker;
+
+fn lower(text: &str) -> Module {
+    let natives = Natives::prelude();
+    let operators = OperatorTable::new();
+    let mut map = SourceMap::new();
+    let program = modules::load_source("main", text.to_string(), None, &operators, &mut map);
+    let program = program.unwrap();
+    let (_, instances) = TypeChecker::check(&program, &natives, &operators).unwrap();
+    Lowerer::lower(&program, &instances, &operators).unwrap()
+}
+
+// every type a generic function is called at gets a copy of its own, made
+// once however often it's called, and generic calls inside a copy are
+// specialised too
+#[test]
+fn generic_functions_are_specialised_per_type() {
+    let text = "fn id<T>(x: T) -> T { x }
+fn twice<T>(x: T) -> [T] { [id(x), id(x)] }
+a = id(1);
+b = id(\"s\");
+c = id(2);
+d = twice(true);
+";
+    let module = lower(text);
+    let mut names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "id",
+            "id<bool>",
+            "id<int>",
+            "id<str>",
+            "main",
+            "twice",
+            "twice<bool>"
+        ]
+    );
+}
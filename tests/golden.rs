@@ -0,0 +1,162 @@
+// Runs every program in tests/golden through the compiler's phases and
+// compares what each phase gives with the files next to it:
+//
+//   name.src     the program
+//   name.tokens  its tokens, one per line with where they start
+//   name.ast     its top level statements, one per line
+//   name.stdout  what it printed and the value it ended with
+//   name.stderr  the first error of any phase
+//
+// A file that's missing means the phase gives nothing, a lexer error has no
+// .ast for example. `cargo test --test golden -- --bless` writes the files
+// from what the phases give instead, other arguments pick the programs
+// whose names contain them.
+use compiler::lexer::Lexer;
+use compiler::parser::{Parser, SExpr};
+use compiler::source::SourceMap;
+use compiler::Engine;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const EXTENSIONS: [&str; 4] = ["tokens", "ast", "stdout", "stderr"];
+
+// what each phase gave, in the order of EXTENSIONS
+fn run(text: &str) -> [Option<String>; 4] {
+    let mut tokens = None;
+    let mut ast = None;
+    let mut stdout = None;
+    let mut stderr = None;
+
+    match Lexer::scan_source(text, 0, &[]) {
+        Err(err) => stderr = Some(format!("{}\n", err)),
+        Ok(lexer) => {
+            tokens = Some(list_tokens(text, lexer.clone()));
+            match parse(lexer) {
+                Err(err) => stderr = Some(format!("{}\n", err)),
+                Ok(SExpr::Block(stmts)) => {
+                    ast = Some(stmts.iter().map(|stmt| format!("{}\n", stmt)).collect());
+                    let mut engine = Engine::new();
+                    let result = engine.eval(text);
+                    let mut output = engine.take_output();
+                    match result {
+                        Ok(value) => output.push_str(&format!("{}\n", value)),
+                        Err(err) => stderr = Some(format!("{}\n", err)),
+                    }
+                    stdout = Some(output).filter(|output| !output.is_empty());
+                }
+                Ok(_) => unreachable!(),
+            }
+        }
+    }
+    [tokens, ast, stdout, stderr]
+}
+
+fn list_tokens(text: &str, mut lexer: Lexer) -> String {
+    let mut map = SourceMap::new();
+    map.add(String::new(), text.to_string());
+    let mut list = String::new();
+    while let Some(span) = lexer.span() {
+        let token = lexer.next().unwrap();
+        let location = map.location(span.start).unwrap();
+        list.push_str(&format!(
+            "{}:{} {:?}\n",
+            location.line, location.column, token
+        ));
+    }
+    list
+}
+
+fn parse(lexer: Lexer) -> Result<SExpr, String> {
+    Parser::new(lexer).parse().map_err(|err| err.to_string())
+}
+
+// the expected and the actual text a line at a time, with where the first
+// difference is
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let first = (0..expected.len().max(actual.len()))
+        .find(|&i| expected.get(i) != actual.get(i))
+        .unwrap_or(0);
+    let mut text = format!("    first difference at line {}\n", first + 1);
+    for line in &expected[first.min(expected.len())..] {
+        text.push_str(&format!("    - {}\n", line));
+    }
+    for line in &actual[first.min(actual.len())..] {
+        text.push_str(&format!("    + {}\n", line));
+    }
+    text
+}
+
+// the differences between a program's files and what its phases gave, the
+// files are written instead when blessing
+fn check(program: &Path, bless: bool) -> Vec<String> {
+    let text = fs::read_to_string(program).unwrap();
+    let mut problems = vec![];
+    for (extension, actual) in EXTENSIONS.iter().zip(run(&text).iter()) {
+        let path = program.with_extension(extension);
+        let expected = fs::read_to_string(&path).ok();
+        if &expected == actual {
+            continue;
+        }
+        if bless {
+            match actual {
+                Some(actual) => fs::write(&path, actual).unwrap(),
+                None => fs::remove_file(&path).unwrap(),
+            }
+            continue;
+        }
+        problems.push(match (expected, actual) {
+            (Some(expected), Some(actual)) => {
+                format!(".{} differs\n{}", extension, diff(&expected, actual))
+            }
+            (Some(_), None) => format!(".{} is expected but there's none", extension),
+            (None, Some(actual)) => format!(".{} is missing\n{}", extension, diff("", actual)),
+            (None, None) => unreachable!(),
+        });
+    }
+    problems
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let bless = args.iter().any(|arg| arg == "--bless");
+    // cargo passes on flags meant for the standard test harness
+    let filters: Vec<&String> = args.iter().filter(|arg| !arg.starts_with('-')).collect();
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut programs: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "src"))
+        .collect();
+    programs.sort();
+
+    let mut failed = 0;
+    let mut ran = 0;
+    for program in &programs {
+        let name = program.file_stem().unwrap().to_string_lossy();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        ran += 1;
+        let problems = check(program, bless);
+        if !problems.is_empty() {
+            failed += 1;
+            println!("{} FAILED", name);
+            for problem in problems {
+                println!("  {}", problem);
+            }
+        }
+    }
+
+    match bless {
+        true => println!("blessed {} programs", ran),
+        false => println!("{} programs, {} failed", ran, failed),
+    }
+    if failed > 0 {
+        println!("run `cargo test --test golden -- --bless` to accept the new output");
+        process::exit(1);
+    }
+}
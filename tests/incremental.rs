@@ -0,0 +1,200 @@
+use compiler::analysis::Analysis;
+use compiler::cst::{self, NodeKind, SyntaxNode};
+use compiler::lexer::Lexer;
+use compiler::natives::Natives;
+use compiler::query::Database;
+use compiler::source::{Edit, Span};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+fn corpus() -> Vec<String> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fmt");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    files
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect()
+}
+
+// xorshift, so every run makes the same edits
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+const SNIPPETS: [&str; 24] = [
+    "", "x", "y1", " ", "\n", "1", "2.5", "+", "-", "*", "**", "/", "//", "=", ";", ",", "(", ")",
+    "{", "}", "\"", "fn", "a + b;", "{ x }",
+];
+
+fn random_edit(rng: &mut Rng, text: &str) -> Edit {
+    let len = text.chars().count();
+    let start = rng.below(len + 1);
+    let end = (start + rng.below(4)).min(len);
+    Edit {
+        span: Span { start, end },
+        text: SNIPPETS[rng.below(SNIPPETS.len())].to_string(),
+    }
+}
+
+fn scan(text: &str) -> Result<Lexer, std::io::Error> {
+    Lexer::scan_source(text, 0, &[])
+}
+
+fn relex(old: &str, edit: &Edit) -> Result<(Lexer, usize), std::io::Error> {
+    let new = edit.apply(old);
+    scan(old).unwrap().relex(&new, 0, &[], edit)
+}
+
+#[test]
+fn random_edits_relex_like_a_full_scan() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for text in corpus() {
+        let mut text = text;
+        for _ in 0..300 {
+            let edit = random_edit(&mut rng, &text);
+            let new = edit.apply(&text);
+            match (relex(&text, &edit), scan(&new)) {
+                (Ok((relexed, _)), Ok(scanned)) => {
+                    assert_eq!(relexed, scanned, "{:?} on {:?}", edit, text);
+                    text = new;
+                }
+                (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+                (a, b) => panic!("{:?} on {:?}: {:?} and {:?}", edit, text, a, b),
+            }
+        }
+    }
+}
+
+// what an editor shows for the text: its errors and where they are
+fn diagnostics(analysis: &Analysis) -> Vec<String> {
+    analysis
+        .diagnostics
+        .iter()
+        .map(|d| format!("{}..{} {}", d.span.start, d.span.end, d.message))
+        .collect()
+}
+
+#[test]
+fn random_edits_analyse_like_a_fresh_analysis() {
+    let mut rng = Rng(0x6a09_e667_f3bc_c908);
+    for text in corpus() {
+        let mut text = text;
+        let mut lexer = scan(&text).unwrap();
+        // kept between edits, as the editor does
+        let mut db = Database::new("main.src", None, Natives::prelude());
+        for _ in 0..20 {
+            let edit = random_edit(&mut rng, &text);
+            let new = edit.apply(&text);
+            if let Ok((relexed, _)) = lexer.relex(&new, 0, &[], &edit) {
+                let analysed = Analysis::with_lexer("main.src", &new, &relexed, &mut db);
+                let fresh = Analysis::new("main.src", &new, None);
+                assert_eq!(diagnostics(&analysed), diagnostics(&fresh), "{:?}", new);
+                text = new;
+                lexer = relexed;
+            }
+        }
+    }
+}
+
+#[test]
+fn random_edits_reparse_like_a_full_parse() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for text in corpus() {
+        let mut root = cst::parse(&text).unwrap();
+        for _ in 0..300 {
+            let text = root.to_string();
+            let edit = random_edit(&mut rng, &text);
+            let new = edit.apply(&text);
+            match (cst::reparse(&root, &edit), cst::parse(&new)) {
+                (Ok(reparsed), Ok(parsed)) => {
+                    assert_eq!(reparsed.dump(), parsed.dump(), "{:?} on {:?}", edit, text);
+                    root = reparsed;
+                }
+                (Err(_), Err(_)) => {}
+                (a, b) => panic!("{:?} on {:?}: {:?} and {:?}", edit, text, a, b),
+            }
+        }
+    }
+}
+
+fn fn_body(root: &SyntaxNode, name: &str) -> SyntaxNode {
+    root.descendants()
+        .into_iter()
+        .find(|node| node.kind() == NodeKind::FnDecl && node.to_string().contains(name))
+        .and_then(|node| {
+            node.children()
+                .into_iter()
+                .find(|child| child.kind() == NodeKind::Block)
+        })
+        .unwrap()
+}
+
+#[test]
+fn untouched_fn_bodies_are_shared() {
+    let text = "fn first(a) {\n    a + 1\n}\n\nfn second(b) {\n    b * 2\n}\n";
+    let root = cst::parse(text).unwrap();
+    let at = text.find("b * 2").unwrap();
+
+    // inside the second body, which is parsed again on its own
+    let edit = Edit {
+        span: Span {
+            start: at,
+            end: at + 1,
+        },
+        text: String::from("(b - 1)"),
+    };
+    let new = cst::reparse(&root, &edit).unwrap();
+    assert_eq!(new.to_string(), edit.apply(text));
+    assert!(Rc::ptr_eq(
+        fn_body(&root, "first").green(),
+        fn_body(&new, "first").green()
+    ));
+
+    // one that doesn't fit in a block, the first body is still the old one
+    let edit = Edit {
+        span: Span {
+            start: at - 3,
+            end: at + 5,
+        },
+        text: String::from("\n}\n\nfn third() {"),
+    };
+    let new = cst::reparse(&root, &edit).unwrap();
+    assert_eq!(new.dump(), cst::parse(&edit.apply(text)).unwrap().dump());
+    assert!(Rc::ptr_eq(
+        fn_body(&root, "first").green(),
+        fn_body(&new, "first").green()
+    ));
+}
+
+#[test]
+fn small_edits_rescan_few_tokens() {
+    for text in corpus() {
+        let mut lexer = scan(&text).unwrap();
+        let mut total = 0;
+        while lexer.next().is_some() {
+            total += 1;
+        }
+        let middle = text.chars().count() / 2;
+        let edit = Edit {
+            span: Span {
+                start: middle,
+                end: middle,
+            },
+            text: String::from(" "),
+        };
+        let (_, rescanned) = relex(&text, &edit).unwrap();
+        assert!(rescanned < 8, "{} of {} tokens", rescanned, total);
+    }
+}
@@ -0,0 +1,75 @@
+use compiler::ir::{Lowerer, Module};
+use compiler::layout::StructLayout;
+use compiler::modules;
+use compiler::natives::Natives;
+use compiler::operators::OperatorTable;
+use compiler::source::SourceMap;
+use compiler::typeck::TypeChecker;
+
+fn lower(text: &str) -> Module {
+    let natives = Natives::prelude();
+    let operators = OperatorTable::new();
+    let mut map = SourceMap::new();
+    let program = modules::load_source("main", text.to_string(), None, &operators, &mut map);
+    let program = program.unwrap();
+    let (_, instances) = TypeChecker::check(&program, &natives, &operators).unwrap();
+    Lowerer::lower(&program, &instances, &operators).unwrap()
+}
+
+// every field as name, slot and offset, then the size and alignment
+fn describe(layout: &StructLayout) -> (Vec<(&str, usize, usize)>, usize, usize) {
+    let fields = layout
+        .fields
+        .iter()
+        .map(|field| (field.name.as_str(), field.slot, field.offset))
+        .collect();
+    (fields, layout.size, layout.align)
+}
+
+#[test]
+fn fields_are_padded_to_their_alignment() {
+    let module = lower("struct S { flag: bool, n: int, name: str, done: bool }\n");
+    assert_eq!(
+        describe(&module.structs[0]),
+        (
+            vec![("flag", 0, 0), ("n", 1, 4), ("name", 2, 8), ("done", 3, 24)],
+            32,
+            8
+        )
+    );
+}
+
+#[test]
+fn nested_and_generic_structs_are_laid_out_inline() {
+    let text = "struct Inner { a: bool, b: bool }
+struct Outer { x: int, inner: Inner, y: bool }
+struct Pair<T> { a: T, b: T }
+p = Pair { a: Pair { a: 1, b: 2 }, b: Pair { a: 3, b: 4 } };
+";
+    let module = lower(text);
+    let layout = |name: &str| {
+        let found = module.structs.iter().find(|layout| layout.name == name);
+        describe(found.unwrap())
+    };
+    assert_eq!(layout("Inner"), (vec![("a", 0, 0), ("b", 1, 1)], 2, 1));
+    assert_eq!(
+        layout("Outer"),
+        (vec![("x", 0, 0), ("inner", 1, 4), ("y", 2, 6)], 8, 4)
+    );
+
+    // the erased layout boxes its fields, the instances hold them inline
+    let pairs: Vec<String> = module
+        .structs
+        .iter()
+        .filter(|layout| layout.name == "Pair")
+        .map(|layout| format!("{:?} {} {}", layout.args, layout.size, layout.align))
+        .collect();
+    assert_eq!(
+        pairs,
+        [
+            "[Param(\"T\")] 16 8",
+            "[Struct(\"Pair\", [Int])] 16 4",
+            "[Int] 8 4"
+        ]
+    );
+}
@@ -0,0 +1,187 @@
+use compiler::json::Json;
+use compiler::lsp::Server;
+use std::io::Cursor;
+
+const URI: &str = "file:///tmp/main.src";
+
+const TEXT: &str = "fn add(a: int, b: int) -> int { a + b }
+total = add(1, 2);
+oops = \"s\" - 1
+";
+
+// a message framed the way a client sends it
+fn frame(message: Json) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: usize, method: &str, params: Json) -> String {
+    frame(Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("id", id.into()),
+        ("method", method.into()),
+        ("params", params),
+    ]))
+}
+
+fn notification(method: &str, params: Json) -> String {
+    frame(Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ]))
+}
+
+fn document() -> Json {
+    Json::object(vec![("uri", URI.into())])
+}
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn at(line: usize, character: usize) -> Json {
+    Json::object(vec![
+        ("textDocument", document()),
+        ("position", position(line, character)),
+    ])
+}
+
+// replaces the text from one position to another
+fn change(from: (usize, usize), to: (usize, usize), text: &str) -> Json {
+    Json::object(vec![
+        (
+            "range",
+            Json::object(vec![
+                ("start", position(from.0, from.1)),
+                ("end", position(to.0, to.1)),
+            ]),
+        ),
+        ("text", text.into()),
+    ])
+}
+
+// the messages of the diagnostics a publishDiagnostics notification holds
+fn diagnostics(published: &Json) -> Vec<&str> {
+    assert_eq!(
+        published.get("method").and_then(Json::as_str),
+        Some("textDocument/publishDiagnostics")
+    );
+    published
+        .get("params")
+        .and_then(|params| params.get("diagnostics"))
+        .and_then(Json::as_array)
+        .unwrap()
+        .iter()
+        .map(|diagnostic| diagnostic.get("message").and_then(Json::as_str).unwrap())
+        .collect()
+}
+
+// the messages the server wrote, in order
+fn replies(output: &[u8]) -> Vec<Json> {
+    let mut text = std::str::from_utf8(output).unwrap();
+    let mut messages = vec![];
+    while let Some(rest) = text.strip_prefix("Content-Length: ") {
+        let (length, rest) = rest.split_once("\r\n\r\n").unwrap();
+        let length: usize = length.parse().unwrap();
+        messages.push(Json::parse(&rest[..length]).unwrap());
+        text = &rest[length..];
+    }
+    assert!(text.is_empty(), "{:?}", text);
+    messages
+}
+
+#[test]
+fn a_session_from_initialize_to_exit() {
+    let open = Json::object(vec![(
+        "textDocument",
+        Json::object(vec![
+            ("uri", URI.into()),
+            ("languageId", "compiler".into()),
+            ("version", 1.into()),
+            ("text", TEXT.into()),
+        ]),
+    )]);
+    // the string operand is fixed, then a function using an undefined name
+    // is added after the last line, both edits in one notification
+    let edit = Json::object(vec![
+        ("textDocument", document()),
+        (
+            "contentChanges",
+            Json::Array(vec![
+                change((2, 7), (2, 14), "2 - 1;"),
+                change((3, 0), (3, 0), "fn f() { zzz }\n"),
+            ]),
+        ),
+    ]);
+    let input = [
+        request(1, "initialize", Json::object(vec![])),
+        notification("initialized", Json::object(vec![])),
+        notification("textDocument/didOpen", open),
+        request(2, "textDocument/hover", at(1, 1)),
+        request(3, "textDocument/definition", at(1, 9)),
+        request(4, "textDocument/completion", at(1, 9)),
+        request(
+            5,
+            "textDocument/documentSymbol",
+            Json::object(vec![("textDocument", document())]),
+        ),
+        notification("textDocument/didChange", edit),
+        request(6, "shutdown", Json::Null),
+        notification("exit", Json::Null),
+        // not read, the server stopped at exit
+        request(7, "textDocument/hover", at(1, 1)),
+    ]
+    .concat();
+
+    let mut output = vec![];
+    Server::new()
+        .serve(&mut Cursor::new(input), &mut output)
+        .unwrap();
+    let replies = replies(&output);
+    assert_eq!(replies.len(), 8, "{:?}", replies);
+
+    let capabilities = replies[0].get("result").unwrap().get("capabilities");
+    assert!(capabilities.and_then(|c| c.get("hoverProvider")).is_some());
+
+    let opened = diagnostics(&replies[1]);
+    assert_eq!(opened.len(), 1, "{:?}", opened);
+    assert!(opened[0].starts_with("TYPE ERROR"), "{}", opened[0]);
+
+    let hover = replies[2].get("result").unwrap();
+    let contents = hover.get("contents").and_then(|c| c.get("value"));
+    assert_eq!(contents.and_then(Json::as_str), Some("total: int"));
+
+    let definition = replies[3].get("result").unwrap();
+    assert_eq!(definition.get("uri").and_then(Json::as_str), Some(URI));
+    let start = definition.get("range").and_then(|range| range.get("start"));
+    assert_eq!(
+        start
+            .and_then(|start| start.get("line"))
+            .and_then(Json::as_usize),
+        Some(0)
+    );
+
+    let completions = replies[4].get("result").and_then(Json::as_array).unwrap();
+    let labels: Vec<&str> = completions
+        .iter()
+        .filter_map(|item| item.get("label").and_then(Json::as_str))
+        .collect();
+    assert!(labels.contains(&"add"), "{:?}", labels);
+    assert!(labels.contains(&"total"), "{:?}", labels);
+
+    let symbols = replies[5].get("result").and_then(Json::as_array).unwrap();
+    let names: Vec<&str> = symbols
+        .iter()
+        .filter_map(|symbol| symbol.get("name").and_then(Json::as_str))
+        .collect();
+    assert_eq!(names, ["add"]);
+    assert_eq!(symbols[0].get("kind").and_then(Json::as_usize), Some(12));
+
+    let changed = diagnostics(&replies[6]);
+    assert_eq!(changed.len(), 1, "{:?}", changed);
+    assert!(changed[0].contains("zzz is not defined"), "{}", changed[0]);
+
+    assert_eq!(replies[7].get("id").and_then(Json::as_usize), Some(6));
+    assert_eq!(replies[7].get("result"), Some(&Json::Null));
+}
@@ -0,0 +1,209 @@
+use compiler::ir::Lowerer;
+use compiler::lexer::Lexer;
+use compiler::modules::{self, Files};
+use compiler::natives::Natives;
+use compiler::operators::OperatorTable;
+use compiler::parser::{Parser, SExpr};
+use compiler::source::SourceMap;
+use compiler::typeck::TypeChecker;
+use compiler::vm::VM;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+// a project's files by their path, without touching the disk
+struct Project(HashMap<String, String>);
+
+impl Files for Project {
+    fn read(&mut self, name: &str) -> Result<String, Error> {
+        self.0
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No such file"))
+    }
+
+    fn parse(&mut self, name: &str, text: &str) -> Result<Vec<SExpr>, Error> {
+        match Parser::new(Lexer::scan_source(text, 0, &[])?).parse() {
+            Ok(SExpr::Block(stmts)) => Ok(stmts),
+            Ok(_) => unreachable!(),
+            Err(err) => Err(modules::syntax_error(name, text, err)),
+        }
+    }
+}
+
+// runs the project whose root is main.lang, the other files are given by
+// their path relative to the project's directory
+fn run(files: &[(&str, &str)]) -> Result<String, String> {
+    let dir = PathBuf::from("project");
+    let files = files
+        .iter()
+        .map(|(name, text)| (dir.join(name).display().to_string(), text.to_string()))
+        .collect();
+    let root = dir.join("main.lang").display().to_string();
+    let mut map = SourceMap::new();
+    let program = modules::load_files(&root, Some(dir), &mut map, &mut Project(files))
+        .map_err(|err| err.to_string())?;
+    let natives = Natives::prelude();
+    let operators = OperatorTable::new();
+    let (_, instances) =
+        TypeChecker::check(&program, &natives, &operators).map_err(|err| err.to_string())?;
+    let module = Lowerer::lower(&program, &instances, &operators).map_err(|err| err.to_string())?;
+    match VM::new(&module, &natives).run() {
+        Ok(value) => Ok(value.to_string()),
+        Err(err) => Err(err.render(&map)),
+    }
+}
+
+#[test]
+fn items_are_used_by_qualified_path_or_import() {
+    let files = [
+        (
+            "main.lang",
+            "mod math;\nimport math::square;\nimport math::shapes::Rect;\n\
+             r = Rect { w: square(2), h: math::two() };\n\
+             math::shapes::area(r) + math::shapes::perimeter(r)\n",
+        ),
+        (
+            "math.lang",
+            "pub mod shapes;\npub fn two() -> int { 2 }\npub fn square(x: int) -> int { x * x }\n",
+        ),
+        (
+            "math/shapes.lang",
+            "pub struct Rect { w: int, h: int }\n\
+             pub fn area(r: Rect) -> int { r.w * r.h }\n\
+             pub fn perimeter(r: Rect) -> int { double(r.w + r.h) }\n\
+             fn double(x: int) -> int { x * 2 }\n",
+        ),
+    ];
+    assert_eq!(run(&files).unwrap(), "20");
+}
+
+// app writes to the log before main runs, which needs log's lines to exist
+#[test]
+fn a_module_runs_before_the_modules_that_use_it() {
+    let files = [
+        ("main.lang", "mod app;\nmod log;\nlog::count()\n"),
+        ("app.lang", "import log::write;\nwrite(\"app\");\n"),
+        (
+            "log.lang",
+            "lines = [];\npub fn write(line: str) { push(lines, line); }\n\
+             pub fn count() -> int { len(lines) }\n",
+        ),
+    ];
+    assert_eq!(run(&files).unwrap(), "1");
+}
+
+#[test]
+fn private_items_stay_in_their_module() {
+    let files = [
+        ("main.lang", "mod math;\nmath::secret(1)\n"),
+        ("math.lang", "fn secret(x: int) -> int { x }\n"),
+    ];
+    assert_eq!(
+        run(&files).unwrap_err(),
+        "project/main.lang:2:1: MODULE ERROR: in module project/main.lang: secret is private to module math"
+    );
+
+    let files = [
+        ("main.lang", "mod math;\nimport math::inner::f;\n"),
+        ("math.lang", "mod inner;\n"),
+        ("math/inner.lang", "pub fn f() {}\n"),
+    ];
+    assert_eq!(
+        run(&files).unwrap_err(),
+        "project/main.lang:2:1: MODULE ERROR: inner is private to module math"
+    );
+}
+
+#[test]
+fn cyclic_imports_are_errors() {
+    let files = [
+        ("main.lang", "mod a;\nmod b;\n"),
+        ("a.lang", "import b::g;\npub fn f() { g() }\nx = g();\n"),
+        ("b.lang", "import a::f;\npub fn g() { f() }\ny = f();\n"),
+    ];
+    assert_eq!(
+        run(&files).unwrap_err(),
+        "MODULE ERROR: cyclic import a -> b -> a"
+    );
+}
+
+#[test]
+fn module_errors_say_where() {
+    let missing = [("main.lang", "x = 1;\nmod nope;\n")];
+    assert_eq!(
+        run(&missing).unwrap_err(),
+        "project/main.lang:2:1: MODULE ERROR: can't read module nope from project/nope.lang: No such file"
+    );
+
+    let twice = [("main.lang", "mod a;\nmod a;\n"), ("a.lang", "")];
+    assert_eq!(
+        run(&twice).unwrap_err(),
+        "project/main.lang:2:1: MODULE ERROR: module a is declared twice"
+    );
+
+    let unknown = [
+        ("main.lang", "mod a;\na::g()\n"),
+        ("a.lang", "pub fn f() {}\n"),
+    ];
+    assert_eq!(
+        run(&unknown).unwrap_err(),
+        "project/main.lang:2:1: MODULE ERROR: in module project/main.lang: module a has no item g"
+    );
+
+    let syntax = [
+        ("main.lang", "mod a;\n"),
+        ("a.lang", "pub fn f() {}\nx = (;\n"),
+    ];
+    assert_eq!(
+        run(&syntax).unwrap_err(),
+        "project/a.lang:2:6: ERROR: Unexpected token SEMI"
+    );
+}
+
+#[test]
+fn runtime_errors_point_into_the_module() {
+    let files = [
+        ("main.lang", "mod a;\na::f([1, 2])\n"),
+        (
+            "a.lang",
+            "// first line\npub fn f(xs: [int]) -> int { xs[2] }\n",
+        ),
+    ];
+    assert_eq!(
+        run(&files).unwrap_err(),
+        "RUNTIME ERROR: index out of bounds: the len is 2 but the index is 2
+project/a.lang:2:30: in a::f
+2 | pub fn f(xs: [int]) -> int { xs[2] }
+  |                              ^^^^^
+project/main.lang:2:1: in main
+2 | a::f([1, 2])
+  | ^^^^^^^^^^^^"
+    );
+}
+
+// the files on disk end without a line break, the loader reads them as they
+// are
+#[test]
+fn files_without_a_final_line_break_load_from_disk() {
+    let dir = std::env::temp_dir().join(format!("modules-{}", std::process::id()));
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::write(
+        dir.join("main.lang"),
+        "mod a;\nimport a::c::t;\nt() + a::one()",
+    )
+    .unwrap();
+    fs::write(dir.join("a.lang"), "pub mod c;\npub fn one() -> int { 1 }").unwrap();
+    fs::write(dir.join("a/c.lang"), "pub fn t() -> int { 7 }").unwrap();
+
+    let mut map = SourceMap::new();
+    let program = modules::load(&dir.join("main.lang"), &OperatorTable::new(), &mut map);
+    fs::remove_dir_all(&dir).unwrap();
+    let program = program.unwrap();
+    let natives = Natives::prelude();
+    let operators = OperatorTable::new();
+    let (_, instances) = TypeChecker::check(&program, &natives, &operators).unwrap();
+    let module = Lowerer::lower(&program, &instances, &operators).unwrap();
+    assert_eq!(VM::new(&module, &natives).run().unwrap().to_string(), "8");
+}
@@ -0,0 +1,130 @@
+use compiler::lexer::{Lexer, Token};
+use compiler::operators::{Assoc, Fixity, OperatorTable};
+use compiler::parser::Parser;
+
+fn parse(text: &str, operators: &OperatorTable) -> Result<String, String> {
+    let lexer = Lexer::scan_source(text, 0, &operators.custom_symbols()).unwrap();
+    Parser::with_operators(lexer, operators.clone())
+        .parse()
+        .map(|program| program.to_string())
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn built_in_levels_bind_as_documented() {
+    let operators = OperatorTable::new();
+    let parsed = parse("1 + 2 * 3 ** 2 - 4;", &operators).unwrap();
+    assert_eq!(
+        parsed,
+        "(BLOCK (SUB  (ADD  INT(1) (MUL  INT(2) (EXP  INT(3) INT(2)))) INT(4)))"
+    );
+    let parsed = parse("a = b += 1 || 2 && 3 == 4 | 5;", &operators).unwrap();
+    assert_eq!(
+        parsed,
+        "(BLOCK (ASSIGN  IDENT(\"a\") (ADDASSIGN  IDENT(\"b\") (OR  INT(1) (AND  INT(2) (EQ  INT(3) (BITOR  INT(4) INT(5))))))))"
+    );
+    let parsed = parse("-x[0] ** 2;", &operators).unwrap();
+    assert_eq!(
+        parsed,
+        "(BLOCK (EXP  (SUB  (INDEX IDENT(\"x\") INT(0))) INT(2)))"
+    );
+}
+
+#[test]
+fn registering_again_replaces_the_entry() {
+    let mut operators = OperatorTable::new();
+    operators.register_infix("**", 10, Assoc::Right).unwrap();
+    operators.register_infix("+", 9, Assoc::Left).unwrap();
+    let parsed = parse("2 ** 3 ** 2;", &operators).unwrap();
+    assert_eq!(parsed, "(BLOCK (EXP  INT(2) (EXP  INT(3) INT(2))))");
+    let parsed = parse("1 + 2 * 3;", &operators).unwrap();
+    assert_eq!(parsed, "(BLOCK (MUL  (ADD  INT(1) INT(2)) INT(3)))");
+
+    let plus = Token::from_symbol("+").unwrap();
+    assert_eq!(operators.infix_binding_power(&plus), Some((19, 20)));
+    assert_eq!(operators.prefix_binding_power(&plus), Some(23));
+    assert_eq!(
+        operators
+            .get(&plus, Fixity::Postfix)
+            .map(|op| op.precedence),
+        None
+    );
+}
+
+#[test]
+fn custom_operators_take_their_place_in_the_table() {
+    let mut operators = OperatorTable::new();
+    operators
+        .define_infix("<>", 8, Assoc::Right, "join")
+        .unwrap();
+    operators.define_prefix("$", 11, "len").unwrap();
+    operators.define_postfix("?", 12, "check").unwrap();
+    let parsed = parse("a <> b <> c + 1;", &operators).unwrap();
+    assert_eq!(
+        parsed,
+        "(BLOCK (OP(\"<>\")  IDENT(\"a\") (OP(\"<>\")  IDENT(\"b\") (ADD  IDENT(\"c\") INT(1)))))"
+    );
+    let parsed = parse("$x? * 2;", &operators).unwrap();
+    assert_eq!(
+        parsed,
+        "(BLOCK (MUL  (OP(\"$\")  (OP(\"?\")  IDENT(\"x\"))) INT(2)))"
+    );
+
+    let join = Token::OP("<>".to_string());
+    assert_eq!(operators.function(&join, 2), Some("join"));
+    assert_eq!(
+        operators.function(&Token::OP("$".to_string()), 1),
+        Some("len")
+    );
+    assert_eq!(
+        operators.function(&Token::OP("?".to_string()), 1),
+        Some("check")
+    );
+    let mut symbols = operators.custom_symbols();
+    symbols.sort();
+    assert_eq!(symbols, ["$", "<>", "?"]);
+}
+
+#[test]
+fn a_token_without_an_entry_is_a_syntax_error() {
+    let operators = OperatorTable::new();
+    assert_eq!(
+        parse("x = * 1;", &operators).unwrap_err(),
+        "ERROR: Unexpected token MUL"
+    );
+    assert!(parse("1 <> 2;", &operators).is_err());
+}
+
+#[test]
+fn invalid_operators_are_errors() {
+    let mut operators = OperatorTable::new();
+    let errors = [
+        operators.define_infix("+", 8, Assoc::Left, "add"),
+        // the slice's range can't be taken over
+        operators.define_infix("..", 5, Assoc::Left, "range"),
+        operators.register_infix("a", 8, Assoc::Left),
+        operators.define_postfix("()", 12, "call"),
+        operators.register_prefix("@", 127),
+    ];
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|result| result.as_ref().unwrap_err().to_string())
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            "ERROR: \"+\" is a built in symbol",
+            "ERROR: \"..\" is a built in symbol",
+            "ERROR: \"a\" can't be used as an operator",
+            "ERROR: \"()\" can't be used as an operator",
+            "ERROR: Precedence 127 is above the maximum of 126",
+        ]
+    );
+
+    // none of them changed the table
+    assert_eq!(operators.custom_symbols(), Vec::<String>::new());
+    assert_eq!(
+        parse("1 + 2 * 3;", &operators).unwrap(),
+        "(BLOCK (ADD  INT(1) (MUL  INT(2) INT(3))))"
+    );
+}
@@ -0,0 +1,365 @@
+use compiler::format::{format_source, FormatOptions};
+use compiler::lexer::Lexer;
+use compiler::parser::{sites_mut, Parser, SExpr};
+use compiler::source::Site;
+use proptest::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+
+// a syntax error comes back as an error, a panic is a crash
+fn parse(text: &str) -> Result<SExpr, String> {
+    let lexer = Lexer::scan_source(text, 0, &[]).map_err(|e| e.to_string())?;
+    Parser::new(lexer).parse().map_err(|err| err.to_string())
+}
+
+// the tree without where its expressions are, which formatting moves
+fn meaning(mut tree: SExpr) -> SExpr {
+    sites_mut(&mut tree, &mut |site| *site = Site::default());
+    tree
+}
+
+// the inputs that crashed the lexer or the parser once
+#[test]
+fn regressions_dont_crash() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/regressions");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    assert!(!files.is_empty());
+    for path in files {
+        let text = fs::read_to_string(&path).unwrap();
+        // some of them crashed without the line break the files end in
+        for text in [text.as_str(), text.trim_end_matches('\n')].iter() {
+            let _ = Lexer::scan_source(text, 0, &[]);
+            let _ = parse(text);
+        }
+    }
+}
+
+#[test]
+fn syntax_errors_say_where() {
+    let text = "x = 1;\ny = (2 + ;\n";
+    let lexer = Lexer::scan_source(text, 0, &[]).unwrap();
+    let err = Parser::new(lexer).parse().unwrap_err();
+    assert_eq!(err.to_string(), "ERROR: Unexpected token SEMI");
+    let span = err.span.unwrap();
+    assert_eq!(&text[span.start..span.end], ";");
+}
+
+// names that aren't keywords
+fn name() -> impl Strategy<Value = String> {
+    prop::sample::select(vec!["a", "b", "x", "count", "f", "total_2"]).prop_map(String::from)
+}
+
+fn literal() -> impl Strategy<Value = String> {
+    prop_oneof![
+        (0..100_000i32).prop_map(|n| n.to_string()),
+        (0..1000u32, 0..100u32).prop_map(|(a, b)| format!("{}.{}", a, b)),
+        prop::sample::select(vec!["\"\"", "\"s\"", "\"two words\"", "true", "false"])
+            .prop_map(String::from),
+    ]
+}
+
+fn ty() -> impl Strategy<Value = String> {
+    let leaf = prop::sample::select(vec!["int", "float", "str", "bool", "void", "T"])
+        .prop_map(String::from);
+    leaf.prop_recursive(3, 8, 2, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|t| format!("[{}]", t)),
+            (inner.clone(), inner.clone()).prop_map(|(a, b)| format!("fn({}) -> {}", a, b)),
+            (inner.clone(), inner).prop_map(|(a, b)| format!("Pair<{}, {}>", a, b)),
+        ]
+    })
+}
+
+fn pattern() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        Just(String::from("_")),
+        name(),
+        literal(),
+        (1..100i32).prop_map(|n| format!("-{}", n)),
+        Just(String::from("Shape::Empty")),
+    ];
+    leaf.prop_recursive(2, 6, 2, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|p| format!("Shape::Circle({})", p)),
+            (inner, any::<bool>()).prop_map(|(p, rest)| {
+                let rest = if rest { ", .." } else { "" };
+                format!("Shape::Rect {{ w, h: {}{} }}", p, rest)
+            }),
+        ]
+    })
+}
+
+// names that are all different
+fn params() -> impl Strategy<Value = Vec<String>> {
+    prop::sample::subsequence(vec!["a", "b", "x", "count", "f", "total_2"], 0..3)
+        .prop_map(|names| names.into_iter().map(String::from).collect())
+}
+
+fn list(items: Vec<String>) -> String {
+    items.join(", ")
+}
+
+// an expression that starts like an if, a while, a fn, a match or a block
+// would end at its closing brace in a statement or a match arm
+fn statement_position(expr: String) -> String {
+    let block_like = ["if ", "while ", "fn", "match ", "{"];
+    match block_like.iter().any(|start| expr.starts_with(start)) {
+        true => format!("({})", expr),
+        false => expr,
+    }
+}
+
+fn block(stmts: Vec<String>, last: Option<String>) -> String {
+    let mut text = String::from("{ ");
+    for stmt in stmts {
+        text.push_str(&stmt);
+        text.push(' ');
+    }
+    if let Some(last) = last {
+        text.push_str(&statement_position(last));
+        text.push(' ');
+    }
+    text.push('}');
+    text
+}
+
+// an expression that may go anywhere, including a condition, where a
+// struct literal would be taken for the block
+fn operand(expr: BoxedStrategy<String>) -> impl Strategy<Value = String> {
+    prop_oneof![name(), literal(), expr.prop_map(|e| format!("({})", e)),]
+}
+
+// what's called, indexed or has a field taken, the lexer doesn't allow a
+// number right before ( or [
+fn postfix_operand(expr: BoxedStrategy<String>) -> impl Strategy<Value = String> {
+    prop_oneof![name(), expr.prop_map(|e| format!("({})", e))]
+}
+
+fn expr() -> BoxedStrategy<String> {
+    let leaf = prop_oneof![name(), literal()];
+    leaf.prop_recursive(4, 48, 4, |inner| {
+        let op = prop::sample::select(vec![
+            "+", "-", "*", "/", "%", "**", "&", "|", "^", "<<", ">>", "==", "!=", "<", "<=", ">",
+            ">=", "&&", "||",
+        ]);
+        let stmt = simple_statement(inner.clone());
+        prop_oneof![
+            operand(inner.clone()),
+            (inner.clone(), op, inner.clone()).prop_map(|(a, op, b)| format!("{} {} {}", a, op, b)),
+            (
+                prop::sample::select(vec!["-", "!", "~"]),
+                operand(inner.clone())
+            )
+                .prop_map(|(op, e)| format!("{}{}", op, e)),
+            (
+                postfix_operand(inner.clone()),
+                prop::collection::vec(inner.clone(), 0..3)
+            )
+                .prop_map(|(f, args)| format!("{}({})", f, list(args))),
+            prop::collection::vec(inner.clone(), 0..4)
+                .prop_map(|elems| format!("[{}]", list(elems))),
+            (postfix_operand(inner.clone()), inner.clone())
+                .prop_map(|(a, i)| format!("{}[{}]", a, i)),
+            (
+                postfix_operand(inner.clone()),
+                prop::option::of(inner.clone()),
+                prop::option::of(inner.clone())
+            )
+                .prop_map(|(a, lo, hi)| format!(
+                    "{}[{}..{}]",
+                    a,
+                    lo.unwrap_or_default(),
+                    hi.unwrap_or_default()
+                )),
+            (postfix_operand(inner.clone()), name())
+                .prop_map(|(a, field)| format!("{}.{}", a, field)),
+            (
+                operand(inner.clone()),
+                prop::collection::vec(stmt.clone(), 0..2),
+                inner.clone(),
+                prop::option::of(inner.clone())
+            )
+                .prop_map(|(cond, stmts, then, other)| match other {
+                    Some(other) => format!(
+                        "if {} {} else {}",
+                        cond,
+                        block(stmts, Some(then)),
+                        block(vec![], Some(other))
+                    ),
+                    None => format!("if {} {}", cond, block(stmts, Some(then))),
+                }),
+            (
+                operand(inner.clone()),
+                prop::collection::vec((pattern(), inner.clone()), 1..4)
+            )
+                .prop_map(|(scrutinee, arms)| {
+                    let arms: Vec<String> = arms
+                        .into_iter()
+                        .map(|(p, e)| format!("{} => {}", p, statement_position(e)))
+                        .collect();
+                    format!("match {} {{ {} }}", scrutinee, list(arms))
+                }),
+            (params(), inner.clone()).prop_map(|(params, body)| format!(
+                "|{}| {}",
+                list(params),
+                body
+            )),
+            (params(), inner.clone()).prop_map(|(params, body)| format!(
+                "fn({}) {}",
+                list(params),
+                block(vec![], Some(body))
+            )),
+            (
+                prop::collection::vec(stmt, 0..3),
+                prop::option::of(inner.clone())
+            )
+                .prop_map(|(stmts, last)| block(stmts, last)),
+            (inner.clone(), inner.clone())
+                .prop_map(|(a, b)| format!("Point {{ x: {}, y: {} }}", a, b)),
+            prop_oneof![
+                inner.clone().prop_map(|e| format!("Shape::Circle({})", e)),
+                (inner.clone(), inner)
+                    .prop_map(|(w, h)| format!("Shape::Rect {{ w: {}, h: {} }}", w, h)),
+            ],
+        ]
+    })
+    .boxed()
+}
+
+// a statement that starts with neither a keyword nor a brace, so it ends
+// in a ;
+fn simple_statement(expr: BoxedStrategy<String>) -> BoxedStrategy<String> {
+    let assign = prop::sample::select(vec!["=", "+=", "-=", "*=", "**=", "<<=", "|="]);
+    prop_oneof![
+        expr.clone().prop_map(|e| format!("({});", e)),
+        (name(), assign, expr.clone()).prop_map(|(x, op, e)| format!("{} {} {};", x, op, e)),
+        (name(), expr.clone(), expr.clone()).prop_map(|(a, i, e)| format!("{}[{}] = {};", a, i, e)),
+        (name(), name(), expr).prop_map(|(a, field, e)| format!("{}.{} = {};", a, field, e)),
+    ]
+    .boxed()
+}
+
+fn typed_params() -> impl Strategy<Value = Vec<String>> {
+    (params(), prop::collection::vec(prop::option::of(ty()), 3)).prop_map(|(names, types)| {
+        names
+            .into_iter()
+            .zip(types)
+            .map(|(x, t)| match t {
+                Some(t) => format!("{}: {}", x, t),
+                None => x,
+            })
+            .collect()
+    })
+}
+
+fn statement() -> impl Strategy<Value = String> {
+    let stmt = simple_statement(expr());
+    prop_oneof![
+        4 => stmt.clone(),
+        1 => (operand(expr()), prop::collection::vec(stmt.clone(), 0..3))
+            .prop_map(|(cond, body)| format!("while {} {}", cond, block(body, None))),
+        1 => (
+            name(),
+            any::<bool>(),
+            typed_params(),
+            prop::option::of(ty()),
+            prop::collection::vec(stmt, 0..3),
+            prop::option::of(expr())
+        )
+            .prop_map(|(f, generic, params, ret, body, last)| {
+                let generics = if generic { "<T>" } else { "" };
+                let ret = ret.map(|t| format!(" -> {}", t)).unwrap_or_default();
+                format!(
+                    "fn {}{}({}){} {}",
+                    f,
+                    generics,
+                    list(params),
+                    ret,
+                    block(body, last)
+                )
+            }),
+        1 => (params(), prop::collection::vec(ty(), 3)).prop_map(|(names, types)| {
+            let fields: Vec<String> = names
+                .into_iter()
+                .zip(types)
+                .map(|(x, t)| format!("{}: {}", x, t))
+                .collect();
+            format!("struct Pair<A, B> {{ {} }}", list(fields))
+        }),
+        1 => (ty(), ty()).prop_map(|(a, b)| {
+            format!("enum Shape {{ Circle({}), Rect {{ w: {}, h: int }}, Empty }}", a, b)
+        }),
+    ]
+}
+
+fn program() -> impl Strategy<Value = String> {
+    (
+        prop::collection::vec(statement(), 0..6),
+        prop::option::of(expr()),
+    )
+        .prop_map(|(stmts, last)| {
+            let mut text = stmts.join("\n");
+            if let Some(last) = last {
+                text.push('\n');
+                text.push_str(&format!("({})", last));
+            }
+            text
+        })
+}
+
+// any text, weighted towards what the lexer has special cases for
+fn source_text() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[0-9a-z_.\"'/*\\\\ \n{}()\\[\\];:,=+\\-<>|&!^%~λ]{0,40}",
+    ]
+}
+
+proptest! {
+    // failures are added to tests/regressions by hand
+    #![proptest_config(ProptestConfig {
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn lexing_never_panics(text in source_text()) {
+        let _ = Lexer::scan_source(&text, 0, &[]);
+    }
+
+    #[test]
+    fn parsing_fails_with_a_syntax_error(text in source_text()) {
+        let _ = parse(&text);
+    }
+
+    // the last character is scanned like any other, a program means the
+    // same with or without a line break after it
+    #[test]
+    fn a_final_line_break_changes_nothing(text in program()) {
+        let text = text.trim_end();
+        let tokens = |text: &str| {
+            let mut lexer = Lexer::scan_source(text, 0, &[]).unwrap();
+            std::iter::from_fn(move || lexer.next()).collect::<Vec<_>>()
+        };
+        prop_assert_eq!(tokens(text), tokens(&format!("{}\n", text)));
+        let tree = meaning(parse(text).unwrap());
+        prop_assert_eq!(tree, meaning(parse(&format!("{}\n", text)).unwrap()));
+    }
+
+    #[test]
+    fn generated_programs_parse(text in program()) {
+        prop_assert!(parse(&text).is_ok(), "{}", parse(&text).unwrap_err());
+    }
+
+    #[test]
+    fn formatting_keeps_the_tree(text in program()) {
+        let tree = meaning(parse(&text).unwrap());
+        let formatted = format_source(&text, FormatOptions::default()).unwrap();
+        let formatted_tree = meaning(parse(&formatted).unwrap());
+        prop_assert_eq!(tree, formatted_tree, "formatted as {}", formatted);
+    }
+}
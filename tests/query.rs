@@ -0,0 +1,236 @@
+use compiler::ir::Lowerer;
+use compiler::modules;
+use compiler::natives::Natives;
+use compiler::operators::{Assoc, OperatorTable};
+use compiler::query::{Database, Query};
+use compiler::source::SourceMap;
+use compiler::typeck::TypeChecker;
+use compiler::vm::VM;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const PROGRAMS: [&str; 6] = [
+    "fn add(a: int, b: int) -> int { a + b }\nx = add(1, 2);\ny = add(x, x);\ny * 10\n",
+    "fn id<T>(x: T) -> T { x }\nstruct Pair<T> { a: T, b: T }\np = Pair { a: id(1), b: 2 };\nq = Pair { a: id(\"s\"), b: \"t\" };\n[p.a + p.b]\n",
+    "enum Shape { Circle(int), Rect { w: int, h: int } }\nfn area(s) {\n    match s {\n        Shape::Circle(r) => 3 * r * r,\n        Shape::Rect { w, h } => w * h,\n    }\n}\narea(Shape::Circle(2)) + area(Shape::Rect { w: 2, h: 5 })\n",
+    "fn counter() {\n    n = 0;\n    fn() {\n        n += 1;\n        n\n    }\n}\nc = counter();\nc();\nc();\nc()\n",
+    "total = 0;\ni = 0;\nwhile i < 10 {\n    if i % 2 == 0 { total += i; }\n    i += 1;\n}\ntotal\n",
+    "",
+];
+
+fn run_whole(text: &str, operators: &OperatorTable) -> String {
+    let natives = Natives::prelude();
+    let mut map = SourceMap::new();
+    let program =
+        modules::load_source("main", text.to_string(), None, operators, &mut map).unwrap();
+    let (_, instances) = TypeChecker::check(&program, &natives, operators).unwrap();
+    let module = Lowerer::lower(&program, &instances, operators).unwrap();
+    VM::new(&module, &natives).run().unwrap().to_string()
+}
+
+fn database(text: &str) -> Database {
+    let mut db = Database::new("main", None, Natives::prelude());
+    db.set_source("main", text.to_string());
+    db
+}
+
+fn run(db: &Database) -> String {
+    let module = db.module().unwrap();
+    VM::new(&module, db.natives()).run().unwrap().to_string()
+}
+
+#[test]
+fn linked_units_run_like_the_whole_program() {
+    for text in PROGRAMS.iter() {
+        let whole = run_whole(text, &OperatorTable::new());
+        assert_eq!(run(&database(text)), whole, "{}", text);
+    }
+}
+
+#[test]
+fn custom_operators_run_statement_by_statement() {
+    let mut operators = OperatorTable::new();
+    operators
+        .define_infix("|>", 1, Assoc::Left, "apply")
+        .unwrap();
+    let text = "fn apply(x, f) { f(x) }\nfn id<T>(x: T) -> T { x }\n3 |> id |> fn(n) { n * 2 }\n";
+    let mut db = Database::new("main", None, Natives::prelude()).with_operators(operators.clone());
+    db.set_source("main", text.to_string());
+    assert_eq!(run(&db), "6");
+    assert_eq!(run(&db), run_whole(text, &operators));
+
+    // without the operator the file doesn't scan
+    assert!(database(text).program().is_err());
+}
+
+const FNS: &str = "fn a(x) { x + 1 }\nfn b(x) { x * 2 }\nfn c(x) { b(x) - 1 }\nc(a(1))\n";
+
+#[test]
+fn editing_a_body_only_redoes_that_function() {
+    let mut db = database(FNS);
+    assert_eq!(run(&db), "3");
+    db.take_log();
+
+    db.set_source("main", FNS.replace("x * 2", "x * 3"));
+    assert_eq!(run(&db), "5");
+    let log = db.take_log();
+    assert!(log.contains(&Query::Checked(1)));
+    assert!(log.contains(&Query::Unit(1)));
+    for i in [0, 2, 3] {
+        assert!(!log.contains(&Query::Checked(i)), "{:?}", log);
+        assert!(!log.contains(&Query::Unit(i)), "{:?}", log);
+    }
+
+    // b's type changes, so c and the call after it are checked again
+    db.set_source("main", FNS.replace("x * 2", "x > 2"));
+    assert!(db.globals().is_err());
+    let log = db.take_log();
+    assert!(log.contains(&Query::Checked(2)));
+    assert!(!log.contains(&Query::Checked(0)));
+}
+
+#[test]
+fn nothing_is_redone_without_a_change() {
+    let mut db = database(FNS);
+    run(&db);
+    db.take_log();
+    db.set_source("main", FNS.to_string());
+    run(&db);
+    assert_eq!(db.take_log(), vec![]);
+
+    // a comment after them changes the tokens but not the statements, so
+    // the module is still the same one
+    let module = db.module().unwrap();
+    db.set_source("main", format!("{}// one\n", FNS));
+    assert!(Rc::ptr_eq(&module, &db.module().unwrap()));
+    let log = db.take_log();
+    assert!(log.contains(&Query::Program));
+    assert!(!log.contains(&Query::Module));
+
+    // one before them moves them, which only links the module again
+    db.set_source("main", format!("// one\n{}", FNS));
+    assert_eq!(run(&db), "3");
+    let log = db.take_log();
+    assert!(log.contains(&Query::Module));
+    for i in 0..4 {
+        assert!(!log.contains(&Query::Checked(i)), "{:?}", log);
+        assert!(!log.contains(&Query::Unit(i)), "{:?}", log);
+    }
+}
+
+#[test]
+fn runtime_errors_follow_text_moved_above_them() {
+    let text = "fn f(x) { 1 / x }\nf(0)\n";
+    let mut db = database(text);
+    let render = |db: &Database| {
+        let err = VM::new(&db.module().unwrap(), db.natives())
+            .run()
+            .unwrap_err();
+        err.render(&db.source_map())
+    };
+    render(&db);
+    db.take_log();
+
+    db.set_source("main", format!("// one\n// two\n\n\n{}", text));
+    assert_eq!(
+        render(&db),
+        "RUNTIME ERROR: division by zero
+main:5:11: in f
+5 | fn f(x) { 1 / x }
+  |           ^^^^^
+main:6:1: in main
+6 | f(0)
+  | ^^^^"
+    );
+    let log = db.take_log();
+    assert!(!log.contains(&Query::Unit(0)), "{:?}", log);
+    assert!(!log.contains(&Query::Unit(1)), "{:?}", log);
+
+    // a generic function's copy is lowered into the unit calling it, its
+    // sites move with the function rather than the call
+    let text = "fn at<T>(xs: [T], i: int) -> T { xs[i] }\nx = 1;\nat([1], 3)\n";
+    db.set_source("main", text.to_string());
+    render(&db);
+    db.set_source(
+        "main",
+        format!("\n\n{}", text.replace("x = 1;", "x = 1;\n")),
+    );
+    assert_eq!(
+        render(&db),
+        "RUNTIME ERROR: index out of bounds: the len is 1 but the index is 3
+main:3:34: in at<int>
+3 | fn at<T>(xs: [T], i: int) -> T { xs[i] }
+  |                                  ^^^^^
+main:6:1: in main
+6 | at([1], 3)
+  | ^^^^^^^^^^"
+    );
+}
+
+#[test]
+fn errors_are_kept_until_fixed() {
+    let mut db = database("x = 1;\ny = x + \"s\";\n");
+    let err = db.globals().unwrap_err();
+    assert!(err.to_string().contains("TYPE ERROR"), "{}", err);
+    assert!(db.globals_before(1).is_ok());
+    assert!(db.globals_before(2).is_err());
+
+    db.set_source("main", String::from("x = 1;\ny = x + (;\n"));
+    assert!(db.program().is_err());
+    db.set_source("main", String::from("x = 1;\ny = x + 2;\ny\n"));
+    assert_eq!(run(&db), "3");
+}
+
+#[test]
+fn modules_come_from_the_database() {
+    let dir = PathBuf::from("/nonexistent");
+    let module = dir.join("util.lang").display().to_string();
+    let mut db = Database::new("main", Some(dir), Natives::prelude());
+    db.set_source(
+        "main",
+        String::from("mod util;\nimport util::twice;\ntwice(4)\n"),
+    );
+    db.set_source(&module, String::from("pub fn twice(x) { x * 2 }\n"));
+    assert_eq!(run(&db), "8");
+    db.take_log();
+
+    db.set_source(&module, String::from("pub fn twice(x) { x + x + x }\n"));
+    assert_eq!(run(&db), "12");
+    let log = db.take_log();
+    assert!(log.contains(&Query::Ast(module)));
+    assert!(!log.contains(&Query::Ast(String::from("main"))));
+}
+
+#[test]
+fn runtime_errors_show_calls_in_the_file_they_are_in() {
+    let dir = PathBuf::from("/nonexistent");
+    let module = dir.join("util.lang").display().to_string();
+    let mut db = Database::new("main", Some(dir), Natives::prelude());
+    db.set_source(
+        "main",
+        String::from("mod util;\nimport util::half;\n\nhalf(0) + 1\n"),
+    );
+    db.set_source(
+        &module,
+        String::from("fn div(a, b) { a / b }\npub fn half(x) { div(2, x) + 0 }\n"),
+    );
+    let err = VM::new(&db.module().unwrap(), db.natives())
+        .run()
+        .unwrap_err();
+    assert_eq!(
+        err.render(&db.source_map()),
+        format!(
+            "RUNTIME ERROR: division by zero
+{0}:1:16: in util::div
+1 | fn div(a, b) {{ a / b }}
+  |                ^^^^^
+{0}:2:18: in util::half
+2 | pub fn half(x) {{ div(2, x) + 0 }}
+  |                  ^^^^^^^^^
+main:4:1: in main
+4 | half(0) + 1
+  | ^^^^^^^",
+            module
+        )
+    );
+}
@@ -0,0 +1,113 @@
+use compiler::natives::{Io, Natives};
+use compiler::repl::Repl;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+// what the session's programs printed
+#[derive(Default)]
+struct Output(String);
+
+impl Io for Output {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.0.push_str(text);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        Ok(String::new())
+    }
+}
+
+fn session() -> (Repl, Rc<RefCell<Output>>) {
+    let output = Rc::new(RefCell::new(Output::default()));
+    let repl = Repl::with_natives(Natives::prelude_with(output.clone()));
+    (repl, output)
+}
+
+fn eval(repl: &mut Repl, entry: &str) -> String {
+    match repl.eval(entry) {
+        Ok(value) => value.to_string(),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[test]
+fn bindings_and_functions_outlive_their_entry() {
+    let (mut repl, _) = session();
+    eval(&mut repl, "count = 1");
+    eval(&mut repl, "fn double(n) { n * 2 }");
+    assert_eq!(eval(&mut repl, "count = double(count); count"), "2");
+    assert_eq!(eval(&mut repl, "double(count) + count"), "6");
+    assert_eq!(eval(&mut repl, "count += 1; count"), "3");
+}
+
+#[test]
+fn earlier_entries_dont_run_again() {
+    let (mut repl, output) = session();
+    eval(&mut repl, "println(\"once\")");
+    eval(&mut repl, "x = 1");
+    eval(&mut repl, "x + 1");
+    assert_eq!(output.borrow().0, "once\n");
+}
+
+#[test]
+fn a_failed_entry_leaves_nothing_behind() {
+    let (mut repl, _) = session();
+    eval(&mut repl, "x = 1");
+    assert!(eval(&mut repl, "x = \"one\"").starts_with("TYPE ERROR"));
+    assert_eq!(eval(&mut repl, "x"), "1");
+    assert!(eval(&mut repl, "y = 2; [1][5]").starts_with("RUNTIME ERROR"));
+    assert!(eval(&mut repl, "y").contains("ERROR"));
+}
+
+// the value of an entry's last statement is printed, so its branches have
+// to agree
+#[test]
+fn an_entrys_value_is_checked() {
+    let (mut repl, _) = session();
+    let err = eval(&mut repl, "x = 1; if x < 2 { 1 } else { \"a\" }");
+    assert_eq!(
+        err,
+        "TYPE ERROR: if and else have incompatible types int and str"
+    );
+    assert_eq!(eval(&mut repl, "if 1 < 2 { 1 } else { \"a\" }; 3"), "3");
+}
+
+#[test]
+fn closures_types_and_generics_carry_over() {
+    let (mut repl, _) = session();
+    eval(
+        &mut repl,
+        "fn counter() { n = 0; fn next() { n += 1; n } next }",
+    );
+    eval(&mut repl, "c = counter()");
+    eval(&mut repl, "c()");
+    assert_eq!(eval(&mut repl, "c()"), "2");
+    eval(&mut repl, "enum Shape { Dot, Square(int) }");
+    eval(&mut repl, "s = Shape::Square(3)");
+    assert_eq!(
+        eval(
+            &mut repl,
+            "match s { Shape::Dot => 0, Shape::Square(n) => n * n }"
+        ),
+        "9"
+    );
+    eval(&mut repl, "struct Point { x: int, y: int }");
+    eval(&mut repl, "p = Point { x: 1, y: 2 }");
+    eval(&mut repl, "struct Size { w: int }");
+    assert_eq!(eval(&mut repl, "p.y + Size { w: 3 }.w"), "5");
+    eval(&mut repl, "fn first<T>(xs: [T]) -> T { xs[0] }");
+    assert_eq!(eval(&mut repl, "first([\"a\", \"b\"])"), "a");
+    assert_eq!(eval(&mut repl, "first([4, 5])"), "4");
+}
+
+#[test]
+fn positions_are_within_the_entry() {
+    let (mut repl, _) = session();
+    eval(&mut repl, "fn boom() { [1][5] }");
+    let err = eval(&mut repl, "1 + boom()");
+    assert!(err.contains("<entry 2>:1:5: in main"), "{}", err);
+    let err = eval(&mut repl, "x = (1 + ;");
+    assert!(err.starts_with("<entry 3>:1:10: ERROR"), "{}", err);
+}
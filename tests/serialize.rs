@@ -0,0 +1,149 @@
+use compiler::json::Json;
+use compiler::lexer::Lexer;
+use compiler::parser::{Parser, SExpr};
+use compiler::serialize::{ast_from_json, ast_to_json, tokens_to_json, VERSION};
+use std::fs;
+use std::io::ErrorKind;
+
+fn parse(text: &str) -> Option<SExpr> {
+    let lexer = Lexer::scan_source(text, 0, &[]).ok()?;
+    Parser::new(lexer).parse().ok()
+}
+
+fn read(text: &str) -> Result<SExpr, String> {
+    let json = Json::parse(text).map_err(|err| err.to_string())?;
+    ast_from_json(&json).map_err(|err| {
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        err.to_string()
+    })
+}
+
+#[test]
+fn golden_programs_round_trip() {
+    let mut read_back = 0;
+    for entry in fs::read_dir("tests/golden").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("src".as_ref()) {
+            continue;
+        }
+        let program = match parse(&fs::read_to_string(&path).unwrap()) {
+            Some(program) => program,
+            None => continue,
+        };
+        // through the text too, so the document is what another tool reads
+        let text = ast_to_json(&program).to_string();
+        let json = Json::parse(&text).unwrap();
+        assert_eq!(ast_from_json(&json).unwrap(), program, "{}", path.display());
+        assert_eq!(ast_to_json(&ast_from_json(&json).unwrap()), json);
+        read_back += 1;
+    }
+    assert!(read_back > 10, "only {} programs parsed", read_back);
+}
+
+#[test]
+fn documents_carry_the_version() {
+    let program = parse("x = 1").unwrap();
+    let version = Some(VERSION);
+    let ast = ast_to_json(&program);
+    assert_eq!(ast.get("version").and_then(Json::as_usize), version);
+    let tokens = tokens_to_json(Lexer::scan_source("x = 1\n", 0, &[]).unwrap());
+    assert_eq!(tokens.get("version").and_then(Json::as_usize), version);
+}
+
+#[test]
+fn other_versions_are_rejected() {
+    let ast = r#"{"node": "Block", "statements": []}"#;
+    let doc = |version: &str| format!(r#"{{"version": {}, "ast": {}}}"#, version, ast);
+    assert_eq!(read(&doc("1")), Ok(SExpr::Block(vec![])));
+    assert_eq!(
+        read(&doc("2")),
+        Err(String::from("AST JSON ERROR: unsupported version 2"))
+    );
+    assert_eq!(
+        read(&doc("\"1\"")),
+        Err(String::from("AST JSON ERROR: missing version"))
+    );
+    assert_eq!(
+        read(&format!(r#"{{"ast": {}}}"#, ast)),
+        Err(String::from("AST JSON ERROR: missing version"))
+    );
+}
+
+#[test]
+fn malformed_documents_are_rejected() {
+    let bad = [
+        r#"{"version": 1}"#,
+        r#"{"version": 1, "ast": {"node": "Nope"}}"#,
+        r#"{"version": 1, "ast": {"node": "Block"}}"#,
+        r#"{"version": 1, "ast": {"node": "Block", "statements": {}}}"#,
+        r#"{"version": 1, "ast": {"node": "Atom", "token": {"kind": "INT"}}}"#,
+        r#"{"version": 1, "ast": {"node": "Atom", "token": {"kind": "IDENT", "value": 3}}}"#,
+        r#"{"version": 1, "ast": {"node": "Cons", "op": {"kind": "ADD"}, "operands": [7]}}"#,
+        r#"[1, 2]"#,
+    ];
+    for text in bad.iter() {
+        let err = read(text).unwrap_err();
+        assert!(err.starts_with("AST JSON ERROR: "), "{}: {}", text, err);
+    }
+    // text that isn't JSON fails before the tree is read
+    assert!(read(r#"{"version": 1, "ast": "#).is_err());
+}
+
+fn cons(op: &str, operands: &str) -> String {
+    format!(
+        r#"{{"version": 1, "ast": {{"node": "Cons", "op": {{"kind": "{}"}}, "operands": [{}]}}}}"#,
+        op, operands
+    )
+}
+
+#[test]
+fn operators_need_their_operands() {
+    let one = r#"{"node": "Atom", "token": {"kind": "INT", "value": 1}}"#;
+    let two = format!("{}, {}", one, one);
+    assert_eq!(
+        read(&cons("ADD", "")),
+        Err(String::from(
+            "AST JSON ERROR: ADD takes 1 or 2 operands but has 0"
+        ))
+    );
+    assert_eq!(
+        read(&cons("MUL", one)),
+        Err(String::from(
+            "AST JSON ERROR: MUL takes 2 operands but has 1"
+        ))
+    );
+    assert_eq!(
+        read(&cons("ASSIGN", one)),
+        Err(String::from(
+            "AST JSON ERROR: ASSIGN takes 2 operands but has 1"
+        ))
+    );
+    assert_eq!(
+        read(&cons("NOT", &two)),
+        Err(String::from(
+            "AST JSON ERROR: NOT takes 1 operands but has 2"
+        ))
+    );
+    assert!(read(&cons("SUB", one)).is_ok());
+    assert!(read(&cons("SUB", &two)).is_ok());
+}
+
+#[test]
+fn deep_nesting_is_an_error_not_an_overflow() {
+    let err = Json::parse(&"[".repeat(200000)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "JSON ERROR: nested more than 512 deep at 512"
+    );
+    let err = read(&"{\"a\": ".repeat(200000)).unwrap_err();
+    assert!(err.starts_with("JSON ERROR: nested more than"), "{}", err);
+
+    // the deepest programs the parser takes still fit
+    let arrays = format!("x = {}1{};", "[".repeat(61), "]".repeat(61));
+    let matches = format!("{}1{}", "match 1 { _ => ".repeat(30), " }".repeat(30));
+    for text in [arrays, matches].iter() {
+        let program = parse(text).unwrap();
+        let json = Json::parse(&ast_to_json(&program).to_string()).unwrap();
+        assert_eq!(ast_from_json(&json).unwrap(), program);
+    }
+}